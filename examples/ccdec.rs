@@ -18,14 +18,12 @@ use std::path::PathBuf;
 use std::str::FromStr;
 
 use argh::FromArgs;
+use cros_codecs::apply_film_grain;
+use cros_codecs::backend::capabilities::Requirements;
 use cros_codecs::codec::h264::parser::Nalu as H264Nalu;
 use cros_codecs::codec::h265::parser::Nalu as H265Nalu;
-use cros_codecs::decoder::stateless::av1::Av1;
-use cros_codecs::decoder::stateless::h264::H264;
-use cros_codecs::decoder::stateless::h265::H265;
-use cros_codecs::decoder::stateless::vp8::Vp8;
-use cros_codecs::decoder::stateless::vp9::Vp9;
-use cros_codecs::decoder::stateless::StatelessDecoder;
+use cros_codecs::container::mp4::Mp4Demuxer;
+use cros_codecs::decoder::stateless::new_dyn_decoder;
 use cros_codecs::decoder::stateless::StatelessVideoDecoder;
 use cros_codecs::decoder::BlockingMode;
 use cros_codecs::decoder::DecodedHandle;
@@ -35,11 +33,13 @@ use cros_codecs::multiple_desc_type;
 use cros_codecs::utils::simple_playback_loop;
 use cros_codecs::utils::simple_playback_loop_owned_frames;
 use cros_codecs::utils::simple_playback_loop_userptr_frames;
+use cros_codecs::utils::sniff_encoded_format;
 use cros_codecs::utils::DmabufFrame;
 use cros_codecs::utils::IvfIterator;
 use cros_codecs::utils::NalIterator;
 use cros_codecs::utils::UserPtrFrame;
 use cros_codecs::DecodedFormat;
+use cros_codecs::EncodedFormat;
 use cros_codecs::Fourcc;
 use cros_codecs::FrameLayout;
 use cros_codecs::PlaneLayout;
@@ -114,30 +114,6 @@ fn simple_playback_loop_prime_frames<D: AsFd>(
         .collect()
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-enum EncodedFormat {
-    H264,
-    H265,
-    VP8,
-    VP9,
-    AV1,
-}
-
-impl FromStr for EncodedFormat {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "h264" | "H264" => Ok(EncodedFormat::H264),
-            "h265" | "H265" => Ok(EncodedFormat::H265),
-            "vp8" | "VP8" => Ok(EncodedFormat::VP8),
-            "vp9" | "VP9" => Ok(EncodedFormat::VP9),
-            "av1" | "AV1" => Ok(EncodedFormat::AV1),
-            _ => Err("unrecognized input format. Valid values: h264, h265, vp8, vp9, av1"),
-        }
-    }
-}
-
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 enum FrameMemoryType {
     Managed,
@@ -225,9 +201,9 @@ struct Args {
     #[argh(switch)]
     multiple_output_files: bool,
 
-    /// input format to decode from.
+    /// input format to decode from. Guessed from the input if not specified.
     #[argh(option)]
-    input_format: EncodedFormat,
+    input_format: Option<EncodedFormat>,
 
     /// pixel format to decode into. Default: i420
     #[argh(option, default = "DecodedFormat::I420")]
@@ -260,6 +236,18 @@ fn create_vpx_frame_iterator(input: &[u8]) -> Box<dyn Iterator<Item = Cow<[u8]>>
     }
 }
 
+/// Returns an iterator over the samples of the video track of `input` if it is an MP4 file.
+fn create_mp4_frame_iterator(input: &[u8]) -> Option<Box<dyn Iterator<Item = Cow<[u8]>> + '_>> {
+    if input.get(4..8) != Some(b"ftyp") {
+        return None;
+    }
+
+    let demuxer = Mp4Demuxer::new(input).expect("failed to parse MP4 input");
+    Some(Box::new(demuxer.map(|sample| {
+        sample.expect("failed to read MP4 sample").data
+    })))
+}
+
 /// Decide the output file name when multiple_output_files is set
 fn decide_output_file_name<'a>(output: &'a Path, index: i32) -> PathBuf {
     let extract_str = |s: Option<&'a OsStr>| s.and_then(|s| s.to_str()).expect("malformed file");
@@ -339,72 +327,58 @@ fn main() {
         }
     };
 
-    let display = libva::Display::open().expect("failed to open libva display");
-
-    // The created `decoder` is turned into a `DynStatelessVideoDecoder` trait object. This allows
-    // the same code to control the decoder no matter what codec or backend we are using.
-    let (mut decoder, frame_iter) = match args.input_format {
-        EncodedFormat::H264 => {
-            let frame_iter = Box::new(NalIterator::<H264Nalu>::new(&input))
-                as Box<dyn Iterator<Item = Cow<[u8]>>>;
-
-            let decoder = StatelessDecoder::<H264, _>::new_vaapi(display, blocking_mode)
-                .unwrap()
-                .into_trait_object();
-
-            (decoder, frame_iter)
-        }
-        EncodedFormat::VP8 => {
-            let frame_iter = create_vpx_frame_iterator(&input);
-
-            let decoder = StatelessDecoder::<Vp8, _>::new_vaapi(display, blocking_mode)
-                .unwrap()
-                .into_trait_object();
-
-            (decoder, frame_iter)
-        }
-        EncodedFormat::VP9 => {
-            let frame_iter = create_vpx_frame_iterator(&input);
-
-            let decoder = StatelessDecoder::<Vp9, _>::new_vaapi(display, blocking_mode)
-                .unwrap()
-                .into_trait_object();
-
-            (decoder, frame_iter)
-        }
-        EncodedFormat::H265 => {
-            let frame_iter = Box::new(NalIterator::<H265Nalu>::new(&input))
-                as Box<dyn Iterator<Item = Cow<[u8]>>>;
-
-            let decoder = StatelessDecoder::<H265, _>::new_vaapi(display, blocking_mode)
-                .unwrap()
-                .into_trait_object();
-
-            (decoder, frame_iter)
-        }
-        EncodedFormat::AV1 => {
-            let frame_iter = create_vpx_frame_iterator(&input);
-
-            let decoder = StatelessDecoder::<Av1, _>::new_vaapi(display, blocking_mode)
-                .unwrap()
-                .into_trait_object();
-
-            (decoder, frame_iter)
-        }
+    let input_format = args
+        .input_format
+        .or_else(|| sniff_encoded_format(&input))
+        .expect("cannot guess the input format, please specify it with --input-format");
+
+    // MP4 files can contain any codec, so they are handled separately from the codec-specific
+    // formats.
+    let frame_iter: Box<dyn Iterator<Item = Cow<[u8]>>> = match create_mp4_frame_iterator(&input) {
+        Some(frame_iter) => frame_iter,
+        None => match input_format {
+            EncodedFormat::H264 => Box::new(NalIterator::<H264Nalu>::new(&input)),
+            EncodedFormat::H265 => Box::new(NalIterator::<H265Nalu>::new(&input)),
+            EncodedFormat::VP8 | EncodedFormat::VP9 | EncodedFormat::AV1 => {
+                create_vpx_frame_iterator(&input)
+            }
+        },
     };
 
+    // The created `decoder` is a `DynStatelessVideoDecoder` trait object. This allows the same code
+    // to control the decoder no matter what codec or backend we are using.
+    let mut decoder = new_dyn_decoder::<BufferDescriptor>(
+        &Requirements::new(input_format, Resolution::default()),
+        blocking_mode,
+    )
+    .expect("failed to create decoder");
+
     let mut md5_context = md5::Context::new();
     let mut output_filename_idx = 0;
 
     let mut on_new_frame = |handle: DynDecodedHandle<BufferDescriptor>| {
         if args.output.is_some() || args.compute_md5.is_some() {
             handle.sync().unwrap();
+            let film_grain = handle.pending_film_grain();
+            let resolution = handle.display_resolution();
             let picture = handle.dyn_picture();
             let mut handle = picture.dyn_mappable_handle().unwrap();
             let buffer_size = handle.image_size();
             let mut frame_data = vec![0; buffer_size];
             handle.read(&mut frame_data).unwrap();
 
+            // Apply the film grain the backend left out so the output matches reference decoders.
+            if let Some(film_grain) = film_grain {
+                apply_film_grain(
+                    &film_grain,
+                    args.output_format,
+                    &mut frame_data,
+                    resolution.width as usize,
+                    resolution.height as usize,
+                )
+                .unwrap();
+            }
+
             if args.multiple_output_files {
                 let file_name = decide_output_file_name(
                     args.output
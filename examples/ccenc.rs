@@ -13,6 +13,8 @@ use std::str::FromStr;
 use argh::FromArgs;
 use cros_codecs::backend::vaapi::surface_pool::PooledVaSurface;
 use cros_codecs::backend::vaapi::surface_pool::VaSurfacePool;
+use cros_codecs::container::fmp4::Fmp4Muxer;
+use cros_codecs::container::fmp4::SampleEntryType;
 use cros_codecs::decoder::FramePool;
 use cros_codecs::encoder::av1::EncoderConfig as AV1EncoderConfig;
 use cros_codecs::encoder::h264::EncoderConfig as H264EncoderConfig;
@@ -20,6 +22,7 @@ use cros_codecs::encoder::stateless::av1;
 use cros_codecs::encoder::stateless::h264;
 use cros_codecs::encoder::stateless::vp9;
 use cros_codecs::encoder::vp9::EncoderConfig as VP9EncoderConfig;
+use cros_codecs::encoder::CodedBitstreamBuffer;
 use cros_codecs::encoder::FrameMetadata;
 use cros_codecs::encoder::VideoEncoder;
 use cros_codecs::utils::IvfFileHeader;
@@ -78,7 +81,8 @@ struct Args {
     #[argh(option)]
     framerate: Option<u32>,
 
-    /// output file to write the decoded frames to
+    /// output file to write the encoded frames to. Files with an .mp4
+    /// extension are written as fragmented MP4
     #[argh(option)]
     output: Option<PathBuf>,
 
@@ -87,6 +91,34 @@ struct Args {
     low_power: bool,
 }
 
+/// Destination of the encoded frames.
+enum Output {
+    /// Raw bitstream, or IVF for VP9.
+    Raw(File),
+    /// Fragmented MP4.
+    Fmp4(Fmp4Muxer<File>),
+}
+
+impl Output {
+    fn write(&mut self, codec: Codec, coded: &CodedBitstreamBuffer) {
+        match self {
+            Output::Raw(file) => {
+                if codec == Codec::VP9 {
+                    let hdr = IvfFrameHeader {
+                        timestamp: coded.metadata.timestamp,
+                        frame_size: coded.bitstream.len() as u32,
+                    };
+
+                    hdr.writo_into(file).unwrap();
+                }
+
+                file.write_all(&coded.bitstream).unwrap();
+            }
+            Output::Fmp4(muxer) => muxer.write(coded).unwrap(),
+        }
+    }
+}
+
 fn upload_img<M: libva::SurfaceMemoryDescriptor>(
     display: &Rc<libva::Display>,
     surface: &libva::Surface<M>,
@@ -280,9 +312,29 @@ fn main() {
 
     let frame_size: usize = (args.width * args.height + args.width * args.height / 2) as usize;
 
-    let mut output = args.output.map(|output| File::create(output).unwrap());
+    let mut output = args.output.as_ref().map(|path| {
+        let mut file = File::create(path).unwrap();
+
+        if path.extension().is_some_and(|ext| ext == "mp4") {
+            let sample_entry_type = match codec {
+                Codec::H264 => SampleEntryType::Avc1,
+                Codec::VP9 => SampleEntryType::Vp09,
+                Codec::AV1 => SampleEntryType::Av01,
+            };
+
+            // Timestamps are frame numbers, so use the framerate as timescale.
+            return Output::Fmp4(Fmp4Muxer::new(
+                file,
+                sample_entry_type,
+                Resolution {
+                    width: args.width,
+                    height: args.height,
+                },
+                args.framerate.unwrap_or(30),
+                1,
+            ));
+        }
 
-    if let Some(ref mut output) = output {
         if codec == Codec::VP9 {
             let hdr = IvfFileHeader::new(
                 IvfFileHeader::CODEC_VP9,
@@ -291,9 +343,11 @@ fn main() {
                 30,
                 args.count as u32,
             );
-            hdr.writo_into(output).unwrap();
+            hdr.writo_into(&mut file).unwrap();
         }
-    }
+
+        Output::Raw(file)
+    });
 
     let mut buf = vec![0u8; frame_size];
     for i in 0..args.count {
@@ -310,16 +364,7 @@ fn main() {
         encoder.encode(input_frame, handle).unwrap();
         while let Some(coded) = encoder.poll().unwrap() {
             if let Some(ref mut output) = output {
-                if codec == Codec::VP9 {
-                    let hdr = IvfFrameHeader {
-                        timestamp: coded.metadata.timestamp,
-                        frame_size: coded.bitstream.len() as u32,
-                    };
-
-                    hdr.writo_into(output).unwrap();
-                }
-
-                output.write_all(&coded.bitstream).unwrap();
+                output.write(codec, &coded);
             }
         }
     }
@@ -327,16 +372,7 @@ fn main() {
     encoder.drain().unwrap();
     while let Some(coded) = encoder.poll().unwrap() {
         if let Some(ref mut output) = output {
-            if codec == Codec::VP9 {
-                let hdr = IvfFrameHeader {
-                    timestamp: coded.metadata.timestamp,
-                    frame_size: coded.bitstream.len() as u32,
-                };
-
-                hdr.writo_into(output).unwrap();
-            }
-
-            output.write_all(&coded.bitstream).unwrap();
+            output.write(codec, &coded);
         }
     }
 }
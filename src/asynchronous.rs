@@ -0,0 +1,705 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Asynchronous wrappers around decoders and encoders.
+//!
+//! [`AsyncDecoder`] and [`AsyncEncoder`] provide `async` versions of the input methods of a
+//! decoder or encoder, and return their output as a [`Stream`]. They do not depend on any
+//! particular async runtime: tasks waiting for events are woken by a helper thread watching the
+//! poll FD of the [decoder](StatelessVideoDecoder::poll_fd) or [encoder](VideoEncoder::poll_fd).
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::os::fd::AsFd;
+use std::os::fd::BorrowedFd;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::thread::JoinHandle;
+
+use futures_core::Stream;
+use nix::errno::Errno;
+use nix::sys::epoll::Epoll;
+use nix::sys::epoll::EpollCreateFlags;
+use nix::sys::epoll::EpollEvent;
+use nix::sys::epoll::EpollFlags;
+use nix::sys::epoll::EpollTimeout;
+use nix::sys::eventfd::EventFd;
+
+use crate::decoder::stateless::DecodeError;
+use crate::decoder::stateless::StatelessVideoDecoder;
+use crate::decoder::DecodedHandle;
+use crate::decoder::DecoderEvent;
+use crate::decoder::DecoderFormatNegotiator;
+use crate::decoder::StreamInfo;
+use crate::encoder::CodedBitstreamBuffer;
+use crate::encoder::EncodeResult;
+use crate::encoder::FrameMetadata;
+use crate::encoder::VideoEncoder;
+
+/// Epoll data of the watched file descriptor.
+const FD_READY: u64 = 0;
+/// Epoll data of the event stopping the helper thread.
+const STOP: u64 = 1;
+
+/// Wakes tasks once a file descriptor becomes readable. The waiting happens on a helper thread, so
+/// no particular async runtime is required.
+struct FdWaker {
+    /// Sends the wakers of the tasks waiting for the file descriptor to the helper thread.
+    wakers: Option<mpsc::Sender<Waker>>,
+    /// Signaled to stop the helper thread.
+    stop: EventFd,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FdWaker {
+    fn new(fd: BorrowedFd) -> anyhow::Result<Self> {
+        let stop = EventFd::new()?;
+        let epoll = Epoll::new(EpollCreateFlags::empty())?;
+        epoll.add(fd, EpollEvent::new(EpollFlags::EPOLLIN, FD_READY))?;
+        epoll.add(stop.as_fd(), EpollEvent::new(EpollFlags::EPOLLIN, STOP))?;
+
+        let (wakers, receiver) = mpsc::channel::<Waker>();
+        let thread = std::thread::Builder::new()
+            .name("cros-codecs fd waker".into())
+            .spawn(move || {
+                while let Ok(waker) = receiver.recv() {
+                    let mut events = [EpollEvent::empty()];
+                    match epoll.wait(&mut events, EpollTimeout::NONE) {
+                        Ok(_) if events[0].data() == STOP => break,
+                        // Spurious wake-ups are harmless, the task will just register again.
+                        Ok(_) | Err(Errno::EINTR) => waker.wake(),
+                        Err(e) => {
+                            log::error!("failed to wait for file descriptor: {}", e);
+                            waker.wake();
+                            break;
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            wakers: Some(wakers),
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Wakes the task of `cx` once the file descriptor is readable, or right away if the helper
+    /// thread is not running anymore.
+    fn wake_when_readable(&self, cx: &Context) {
+        let registered = self
+            .wakers
+            .as_ref()
+            .map(|wakers| wakers.send(cx.waker().clone()).is_ok())
+            .unwrap_or(false);
+
+        if !registered {
+            cx.waker().wake_by_ref();
+        }
+    }
+}
+
+impl Drop for FdWaker {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop.write(1) {
+            log::error!("failed to stop fd waker thread: {}", e);
+        }
+        // Unblocks the helper thread if it is waiting for a waker.
+        self.wakers.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Events returned by the stream of an [`AsyncDecoder`].
+pub enum AsyncDecoderEvent<H: DecodedHandle> {
+    /// The next frame has been decoded.
+    FrameReady(H),
+    /// The format of the stream has changed and has been negotiated by the callback passed to
+    /// [`AsyncDecoder::new`].
+    FormatChanged(StreamInfo),
+}
+
+/// Asynchronous wrapper around a [`StatelessVideoDecoder`].
+///
+/// The events of the decoder are returned as a [`Stream`]. As format change events borrow the
+/// decoder, they cannot be returned by the stream: the format is negotiated by a callback instead,
+/// and the stream only reports the new [`StreamInfo`].
+pub struct AsyncDecoder<D, F>
+where
+    D: StatelessVideoDecoder,
+{
+    decoder: D,
+    /// Callback negotiating the format of the decoder when the stream requires it, e.g. by adding
+    /// frames to its pools.
+    negotiate_format: F,
+    waker: FdWaker,
+    /// Events retrieved from the decoder while decoding, waiting to be returned by the stream.
+    pending_events: VecDeque<AsyncDecoderEvent<D::Handle>>,
+    /// Whether the decoder has been drained. The stream ends once all the events preceding the
+    /// drain have been returned.
+    drained: bool,
+}
+
+// Pinning is never projected to the fields.
+impl<D, F> Unpin for AsyncDecoder<D, F> where D: StatelessVideoDecoder {}
+
+impl<D, F> AsyncDecoder<D, F>
+where
+    D: StatelessVideoDecoder,
+    F: FnMut(
+        &mut dyn DecoderFormatNegotiator<Descriptor = <D::Handle as DecodedHandle>::Descriptor>,
+    ) -> anyhow::Result<()>,
+{
+    /// Wraps `decoder`, using `negotiate_format` to negotiate its format whenever the stream
+    /// requires it.
+    pub fn new(decoder: D, negotiate_format: F) -> anyhow::Result<Self> {
+        let waker = FdWaker::new(decoder.poll_fd())?;
+
+        Ok(Self {
+            decoder,
+            negotiate_format,
+            waker,
+            pending_events: Default::default(),
+            drained: false,
+        })
+    }
+
+    /// Returns a reference to the wrapped decoder.
+    pub fn decoder(&self) -> &D {
+        &self.decoder
+    }
+
+    /// Returns a mutable reference to the wrapped decoder, e.g. to change its settings.
+    pub fn decoder_mut(&mut self) -> &mut D {
+        &mut self.decoder
+    }
+
+    /// Decodes `bitstream`, see [`StatelessVideoDecoder::decode`].
+    ///
+    /// If the decoder cannot accept input until its pending events are processed, the events are
+    /// queued to be returned by the stream, format changes being negotiated right away, and
+    /// decoding is attempted again. If the decoder has no event to report yet, this waits on its
+    /// [poll FD](StatelessVideoDecoder::poll_fd).
+    ///
+    /// [`DecodeError::NotEnoughOutputBuffers`] is only returned if decoded frames are waiting to
+    /// be returned by the stream: some of them must be dropped before retrying.
+    pub async fn decode(&mut self, timestamp: u64, bitstream: &[u8]) -> Result<usize, DecodeError> {
+        std::future::poll_fn(|cx| self.poll_decoder(cx, |d| d.decode(timestamp, bitstream))).await
+    }
+
+    /// Flushes the decoder, see [`StatelessVideoDecoder::flush`]. The stream ends once all the
+    /// frames decoded so far have been returned.
+    ///
+    /// Pending events are handled the same way as in [`AsyncDecoder::decode`].
+    pub async fn drain(&mut self) -> Result<(), DecodeError> {
+        std::future::poll_fn(|cx| self.poll_decoder(cx, |d| d.flush())).await?;
+        self.drained = true;
+
+        Ok(())
+    }
+
+    /// Calls `f` on the decoder until it does not need its pending events to be processed. The
+    /// events are queued to be returned by the stream, and the task is woken once the decoder
+    /// signals new ones if there are none yet.
+    fn poll_decoder<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut(&mut D) -> Result<T, DecodeError>,
+    ) -> Poll<Result<T, DecodeError>> {
+        loop {
+            let err = match f(&mut self.decoder) {
+                Err(e @ (DecodeError::CheckEvents | DecodeError::NotEnoughOutputBuffers(_))) => e,
+                res => return Poll::Ready(res),
+            };
+
+            let num_pending_events = self.pending_events.len();
+            loop {
+                match self.next_decoder_event() {
+                    Ok(Some(event)) => self.pending_events.push_back(event),
+                    Ok(None) => break,
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+
+            if self.pending_events.len() > num_pending_events {
+                continue;
+            }
+
+            // Only the client can release the output buffers of frames waiting in the stream.
+            let frames_pending = self
+                .pending_events
+                .iter()
+                .any(|event| matches!(event, AsyncDecoderEvent::FrameReady(_)));
+            if matches!(err, DecodeError::NotEnoughOutputBuffers(_)) && frames_pending {
+                return Poll::Ready(Err(err));
+            }
+
+            self.waker.wake_when_readable(cx);
+            return Poll::Pending;
+        }
+    }
+
+    /// Returns the next event of the decoder, negotiating its format if needed.
+    fn next_decoder_event(&mut self) -> Result<Option<AsyncDecoderEvent<D::Handle>>, DecodeError> {
+        let event = match self.decoder.next_event() {
+            None => return Ok(None),
+            Some(DecoderEvent::FrameReady(handle)) => AsyncDecoderEvent::FrameReady(handle),
+            Some(DecoderEvent::FormatChanged(mut negotiator)) => {
+                (self.negotiate_format)(negotiator.as_mut())?;
+                AsyncDecoderEvent::FormatChanged(negotiator.stream_info().clone())
+            }
+        };
+
+        Ok(Some(event))
+    }
+}
+
+impl<D, F> AsyncDecoder<D, F>
+where
+    D: StatelessVideoDecoder,
+    F: FnMut(
+        &mut dyn DecoderFormatNegotiator<Descriptor = <D::Handle as DecodedHandle>::Descriptor>,
+    ) -> anyhow::Result<()>,
+{
+    /// Returns the next item of the stream, for clients that do not use a `Stream` extension
+    /// trait.
+    pub fn next_event(
+        &mut self,
+    ) -> impl Future<Output = Option<Result<AsyncDecoderEvent<D::Handle>, DecodeError>>> + '_ {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx))
+    }
+}
+
+impl<D, F> Stream for AsyncDecoder<D, F>
+where
+    D: StatelessVideoDecoder,
+    F: FnMut(
+        &mut dyn DecoderFormatNegotiator<Descriptor = <D::Handle as DecodedHandle>::Descriptor>,
+    ) -> anyhow::Result<()>,
+{
+    type Item = Result<AsyncDecoderEvent<D::Handle>, DecodeError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(event) = this.pending_events.pop_front() {
+            return Poll::Ready(Some(Ok(event)));
+        }
+
+        match this.next_decoder_event() {
+            Ok(Some(event)) => Poll::Ready(Some(Ok(event))),
+            Err(e) => Poll::Ready(Some(Err(e))),
+            Ok(None) if this.drained => {
+                this.drained = false;
+                Poll::Ready(None)
+            }
+            Ok(None) => {
+                this.waker.wake_when_readable(cx);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Asynchronous wrapper around a [`VideoEncoder`].
+///
+/// The coded buffers of the encoder are returned as a [`Stream`]. While frames are being encoded,
+/// the stream waits on the [poll FD](VideoEncoder::poll_fd) of the encoder. Encoders without a
+/// poll FD must complete their frames synchronously, e.g. stateless encoders using
+/// [`BlockingMode::Blocking`](crate::decoder::BlockingMode::Blocking): frames they hold back are
+/// only waited for until more frames are submitted or the encoder is drained.
+pub struct AsyncEncoder<E, H>
+where
+    E: VideoEncoder<H>,
+{
+    encoder: E,
+    /// Wakes the task polling the stream while frames are being encoded, if the encoder has a
+    /// poll FD.
+    fd_waker: Option<FdWaker>,
+    /// Number of frames submitted for which no coded buffer has been returned yet.
+    num_frames_in_flight: usize,
+    /// Waker of the task waiting for a frame to be submitted.
+    waker: Option<Waker>,
+    /// Whether the encoder has been drained. The stream ends once all the coded buffers preceding
+    /// the drain have been returned.
+    drained: bool,
+    _phantom: PhantomData<fn(H)>,
+}
+
+// Pinning is never projected to the fields.
+impl<E, H> Unpin for AsyncEncoder<E, H> where E: VideoEncoder<H> {}
+
+impl<E, H> AsyncEncoder<E, H>
+where
+    E: VideoEncoder<H>,
+{
+    /// Wraps `encoder`.
+    pub fn new(encoder: E) -> anyhow::Result<Self> {
+        let fd_waker = encoder.poll_fd().map(FdWaker::new).transpose()?;
+
+        Ok(Self {
+            encoder,
+            fd_waker,
+            num_frames_in_flight: 0,
+            waker: None,
+            drained: false,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns a reference to the wrapped encoder.
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    /// Returns a mutable reference to the wrapped encoder, e.g. to change its tunings.
+    pub fn encoder_mut(&mut self) -> &mut E {
+        &mut self.encoder
+    }
+
+    /// Enqueues a frame for encoding, see [`VideoEncoder::encode`].
+    pub async fn encode(&mut self, meta: FrameMetadata, handle: H) -> EncodeResult<()> {
+        self.encoder.encode(meta, handle)?;
+        self.num_frames_in_flight += 1;
+        self.wake();
+
+        Ok(())
+    }
+
+    /// Drains the encoder, see [`VideoEncoder::drain`]. The stream ends once all the coded buffers
+    /// of the frames submitted so far have been returned.
+    pub async fn drain(&mut self) -> EncodeResult<()> {
+        self.encoder.drain()?;
+        self.drained = true;
+        self.wake();
+
+        Ok(())
+    }
+
+    /// Wakes the task waiting on the stream, if any.
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<E, H> AsyncEncoder<E, H>
+where
+    E: VideoEncoder<H>,
+{
+    /// Returns the next item of the stream, for clients that do not use a `Stream` extension
+    /// trait.
+    pub fn next_bitstream(
+        &mut self,
+    ) -> impl Future<Output = Option<EncodeResult<CodedBitstreamBuffer>>> + '_ {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx))
+    }
+}
+
+impl<E, H> Stream for AsyncEncoder<E, H>
+where
+    E: VideoEncoder<H>,
+{
+    type Item = EncodeResult<CodedBitstreamBuffer>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        match this.encoder.poll() {
+            Ok(Some(coded)) => {
+                this.num_frames_in_flight = this.num_frames_in_flight.saturating_sub(1);
+                Poll::Ready(Some(Ok(coded)))
+            }
+            Err(e) => Poll::Ready(Some(Err(e))),
+            Ok(None) => match &this.fd_waker {
+                // The encoder signals its poll FD once one of the frames in flight is encoded.
+                Some(fd_waker) if this.num_frames_in_flight > 0 => {
+                    fd_waker.wake_when_readable(cx);
+                    Poll::Pending
+                }
+                _ if this.drained => {
+                    this.drained = false;
+                    Poll::Ready(None)
+                }
+                // The encoder is holding frames back until more are submitted.
+                _ => {
+                    this.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::future::Future;
+    use std::os::fd::AsFd;
+    use std::os::fd::BorrowedFd;
+    use std::pin::Pin;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::task::Context;
+    use std::task::Poll;
+    use std::task::Wake;
+    use std::task::Waker;
+    use std::time::Duration;
+
+    use futures_core::Stream;
+    use nix::sys::eventfd::EfdFlags;
+    use nix::sys::eventfd::EventFd;
+
+    use crate::asynchronous::AsyncDecoder;
+    use crate::asynchronous::AsyncDecoderEvent;
+    use crate::asynchronous::AsyncEncoder;
+    use crate::codec::h264::parser::Nalu;
+    use crate::decoder::stateless::h264::tests::DECODE_64X64_PROGRESSIVE_I_P_B_P;
+    use crate::decoder::stateless::h264::H264;
+    use crate::decoder::stateless::StatelessDecoder;
+    use crate::decoder::stateless::StatelessVideoDecoder;
+    use crate::decoder::BlockingMode;
+    use crate::encoder::CodedBitstreamBuffer;
+    use crate::encoder::EncodeResult;
+    use crate::encoder::FrameMetadata;
+    use crate::encoder::Tunings;
+    use crate::encoder::VideoEncoder;
+    use crate::utils::NalIterator;
+    use crate::FrameLayout;
+    use crate::Resolution;
+
+    /// Waker sending a message every time it is woken.
+    struct ChannelWaker(mpsc::SyncSender<()>);
+
+    impl Wake for ChannelWaker {
+        fn wake(self: Arc<Self>) {
+            let _ = self.0.try_send(());
+        }
+    }
+
+    /// Minimal executor running `future` to completion on the current thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let waker = Waker::from(Arc::new(ChannelWaker(sender)));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => receiver.recv().unwrap(),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_stream() {
+        let decoder = StatelessDecoder::<H264, _>::new_dummy(BlockingMode::NonBlocking).unwrap();
+        let mut decoder = AsyncDecoder::new(decoder, |_| Ok(())).unwrap();
+
+        let (num_frames, num_format_changes) = block_on(async {
+            for nalu in NalIterator::<Nalu>::new(DECODE_64X64_PROGRESSIVE_I_P_B_P.stream) {
+                decoder.decode(0, nalu.as_ref()).await.unwrap();
+            }
+            decoder.drain().await.unwrap();
+
+            let mut num_frames = 0;
+            let mut num_format_changes = 0;
+            while let Some(event) = decoder.next_event().await {
+                match event.unwrap() {
+                    AsyncDecoderEvent::FrameReady(_) => num_frames += 1,
+                    AsyncDecoderEvent::FormatChanged(_) => num_format_changes += 1,
+                }
+            }
+
+            (num_frames, num_format_changes)
+        });
+
+        assert_eq!(num_frames, 3);
+        assert_eq!(num_format_changes, 1);
+    }
+
+    #[test]
+    fn wake_on_decoder_event() {
+        let decoder = StatelessDecoder::<H264, _>::new_dummy(BlockingMode::NonBlocking).unwrap();
+        let mut decoder = AsyncDecoder::new(decoder, |_| Ok(())).unwrap();
+
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let waker = Waker::from(Arc::new(ChannelWaker(sender)));
+        let mut cx = Context::from_waker(&waker);
+
+        // No event is pending before decoding.
+        assert!(Pin::new(&mut decoder).poll_next(&mut cx).is_pending());
+
+        // Submitting the first picture makes the decoder wait for its format to be negotiated,
+        // which must wake the task.
+        for nalu in NalIterator::<Nalu>::new(DECODE_64X64_PROGRESSIVE_I_P_B_P.stream).take(3) {
+            let _ = decoder.decoder_mut().decode(0, nalu.as_ref());
+        }
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            Pin::new(&mut decoder).poll_next(&mut cx),
+            Poll::Ready(Some(Ok(AsyncDecoderEvent::FormatChanged(_))))
+        ));
+    }
+
+    /// Encoder holding one frame back, as encoders using B frames would.
+    #[derive(Default)]
+    struct DelayingEncoder {
+        frames: VecDeque<FrameMetadata>,
+        coded: VecDeque<CodedBitstreamBuffer>,
+    }
+
+    impl VideoEncoder<()> for DelayingEncoder {
+        fn tune(&mut self, _: Tunings) -> EncodeResult<()> {
+            Ok(())
+        }
+
+        fn encode(&mut self, meta: FrameMetadata, _: ()) -> EncodeResult<()> {
+            self.frames.push_back(meta);
+            if self.frames.len() > 1 {
+                let meta = self.frames.pop_front().unwrap();
+                self.coded
+                    .push_back(CodedBitstreamBuffer::new(meta, vec![0]));
+            }
+
+            Ok(())
+        }
+
+        fn drain(&mut self) -> EncodeResult<()> {
+            while let Some(meta) = self.frames.pop_front() {
+                self.coded
+                    .push_back(CodedBitstreamBuffer::new(meta, vec![0]));
+            }
+
+            Ok(())
+        }
+
+        fn poll(&mut self) -> EncodeResult<Option<CodedBitstreamBuffer>> {
+            Ok(self.coded.pop_front())
+        }
+    }
+
+    #[test]
+    fn encode_stream() {
+        let mut encoder = AsyncEncoder::new(DelayingEncoder::default()).unwrap();
+
+        let timestamps = block_on(async {
+            for timestamp in 0..3 {
+                let meta = FrameMetadata {
+                    timestamp,
+                    layout: FrameLayout {
+                        format: (b"NV12".into(), 0),
+                        size: Resolution::from((64, 64)),
+                        planes: vec![],
+                    },
+                    force_keyframe: false,
+                };
+                encoder.encode(meta, ()).await.unwrap();
+            }
+            encoder.drain().await.unwrap();
+
+            let mut timestamps = vec![];
+            while let Some(coded) = encoder.next_bitstream().await {
+                timestamps.push(coded.unwrap().metadata.timestamp);
+            }
+
+            timestamps
+        });
+
+        assert_eq!(timestamps, vec![0, 1, 2]);
+    }
+
+    /// Encoder completing its frames on another thread and signaling them through its poll FD, as
+    /// hardware encoders would.
+    struct SlowEncoder {
+        coded: Arc<Mutex<VecDeque<CodedBitstreamBuffer>>>,
+        ready: Arc<EventFd>,
+    }
+
+    impl SlowEncoder {
+        fn new() -> Self {
+            Self {
+                coded: Default::default(),
+                ready: Arc::new(EventFd::from_flags(EfdFlags::EFD_NONBLOCK).unwrap()),
+            }
+        }
+    }
+
+    impl VideoEncoder<()> for SlowEncoder {
+        fn tune(&mut self, _: Tunings) -> EncodeResult<()> {
+            Ok(())
+        }
+
+        fn encode(&mut self, meta: FrameMetadata, _: ()) -> EncodeResult<()> {
+            let coded = Arc::clone(&self.coded);
+            let ready = Arc::clone(&self.ready);
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                let mut coded = coded.lock().unwrap();
+                coded.push_back(CodedBitstreamBuffer::new(meta, vec![0]));
+                ready.write(1).unwrap();
+            });
+
+            Ok(())
+        }
+
+        fn drain(&mut self) -> EncodeResult<()> {
+            Ok(())
+        }
+
+        fn poll(&mut self) -> EncodeResult<Option<CodedBitstreamBuffer>> {
+            let mut coded = self.coded.lock().unwrap();
+            let buffer = coded.pop_front();
+            if coded.is_empty() {
+                // Nothing left to signal, the read fails if the counter is already zero.
+                let _ = self.ready.read();
+            }
+
+            Ok(buffer)
+        }
+
+        fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+            Some(self.ready.as_fd())
+        }
+    }
+
+    #[test]
+    fn encode_stream_waits_for_encoder() {
+        let mut encoder = AsyncEncoder::new(SlowEncoder::new()).unwrap();
+
+        let timestamps = block_on(async {
+            let meta = FrameMetadata {
+                timestamp: 7,
+                layout: FrameLayout {
+                    format: (b"NV12".into(), 0),
+                    size: Resolution::from((64, 64)),
+                    planes: vec![],
+                },
+                force_keyframe: false,
+            };
+            encoder.encode(meta, ()).await.unwrap();
+            encoder.drain().await.unwrap();
+
+            // The frame is still in flight after the drain, so the stream must wait for the
+            // encoder to signal it before ending.
+            let mut timestamps = vec![];
+            while let Some(coded) = encoder.next_bitstream().await {
+                timestamps.push(coded.unwrap().metadata.timestamp);
+            }
+
+            timestamps
+        });
+
+        assert_eq!(timestamps, vec![7]);
+    }
+}
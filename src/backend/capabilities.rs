@@ -0,0 +1,362 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Backend-neutral description of what a backend can decode and encode.
+//!
+//! Backends implement [`CapabilityQuery`] to report the codecs, profiles, bit depths, decoded
+//! formats, maximum resolutions and rate-control modes they support. Clients can then use
+//! [`Capabilities::supports_decoding`] and [`Capabilities::supports_encoding`] to check whether a
+//! given session is possible, or [`select_backend`] to pick the first suitable backend among
+//! several. Since [`Capabilities`] itself implements [`CapabilityQuery`], it can be used in place
+//! of a real backend when testing selection logic.
+
+use crate::codec::av1::parser::Profile as Av1Profile;
+use crate::codec::h264::parser::Profile as H264Profile;
+use crate::codec::h265::parser::Profile as H265Profile;
+use crate::codec::vp9::parser::Profile as Vp9Profile;
+use crate::encoder::RateControl;
+use crate::DecodedFormat;
+use crate::EncodedFormat;
+use crate::Resolution;
+
+/// A codec profile.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Profile {
+    H264(H264Profile),
+    H265(H265Profile),
+    /// VP8 does not define profiles, only versions that all decoders must support.
+    Vp8,
+    Vp9(Vp9Profile),
+    Av1(Av1Profile),
+}
+
+impl Profile {
+    /// Returns the codec this profile belongs to.
+    pub fn codec(&self) -> EncodedFormat {
+        match self {
+            Profile::H264(_) => EncodedFormat::H264,
+            Profile::H265(_) => EncodedFormat::H265,
+            Profile::Vp8 => EncodedFormat::VP8,
+            Profile::Vp9(_) => EncodedFormat::VP9,
+            Profile::Av1(_) => EncodedFormat::AV1,
+        }
+    }
+}
+
+/// Rate-control mode of an encoder, i.e. a [`RateControl`] without its parameter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RateControlMode {
+    ConstantBitrate,
+    ConstantQuality,
+}
+
+impl From<&RateControl> for RateControlMode {
+    fn from(rate_control: &RateControl) -> Self {
+        match rate_control {
+            RateControl::ConstantBitrate(_) => RateControlMode::ConstantBitrate,
+            RateControl::ConstantQuality(_) => RateControlMode::ConstantQuality,
+        }
+    }
+}
+
+/// Capabilities of a backend for one codec.
+///
+/// A backend can report several entries for the same codec, e.g. one per hardware profile. Empty
+/// lists and `None` mean that the backend cannot report the corresponding information, not that
+/// nothing is supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodecCapabilities {
+    /// Codec these capabilities apply to.
+    pub codec: EncodedFormat,
+    /// Supported profiles of `codec`.
+    pub profiles: Vec<Profile>,
+    /// Supported bit depths of the decoded frames.
+    pub bit_depths: Vec<u32>,
+    /// Formats that decoded frames can be read in, or encoder input frames provided in.
+    pub formats: Vec<DecodedFormat>,
+    /// Largest supported resolution.
+    pub max_resolution: Option<Resolution>,
+    /// Supported rate-control modes. Always empty for decoders.
+    pub rate_control_modes: Vec<RateControlMode>,
+}
+
+impl CodecCapabilities {
+    /// Returns whether these capabilities satisfy `requirements`.
+    fn satisfies(&self, requirements: &Requirements) -> bool {
+        // Unreported information is assumed to be supported.
+        fn contains<T: PartialEq>(list: &[T], item: Option<T>) -> bool {
+            match item {
+                Some(item) if !list.is_empty() => list.contains(&item),
+                _ => true,
+            }
+        }
+
+        self.codec == requirements.codec
+            && contains(&self.profiles, requirements.profile)
+            && contains(&self.bit_depths, requirements.bit_depth)
+            && contains(&self.formats, requirements.format)
+            && self
+                .max_resolution
+                .map(|max| max.can_contain(requirements.resolution))
+                .unwrap_or(true)
+            && contains(&self.rate_control_modes, requirements.rate_control)
+    }
+}
+
+/// Decoding and encoding capabilities of a backend.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub decode: Vec<CodecCapabilities>,
+    pub encode: Vec<CodecCapabilities>,
+}
+
+impl Capabilities {
+    /// Returns whether the backend can decode a stream matching `requirements`. The
+    /// `rate_control` member of `requirements` is ignored.
+    pub fn supports_decoding(&self, requirements: &Requirements) -> bool {
+        let requirements = Requirements {
+            rate_control: None,
+            ..requirements.clone()
+        };
+
+        self.decode.iter().any(|caps| caps.satisfies(&requirements))
+    }
+
+    /// Returns whether the backend can encode a stream matching `requirements`.
+    pub fn supports_encoding(&self, requirements: &Requirements) -> bool {
+        self.encode.iter().any(|caps| caps.satisfies(requirements))
+    }
+}
+
+/// Properties of a decoding or encoding session, used to check whether a backend supports it.
+///
+/// Members set to `None` are not checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Requirements {
+    pub codec: EncodedFormat,
+    pub profile: Option<Profile>,
+    pub bit_depth: Option<u32>,
+    pub format: Option<DecodedFormat>,
+    pub resolution: Resolution,
+    /// Only checked when encoding.
+    pub rate_control: Option<RateControlMode>,
+}
+
+impl Requirements {
+    /// Returns requirements for a `resolution` stream of `codec`, with all other properties left
+    /// unchecked.
+    pub fn new(codec: EncodedFormat, resolution: Resolution) -> Self {
+        Self {
+            codec,
+            profile: None,
+            bit_depth: None,
+            format: None,
+            resolution,
+            rate_control: None,
+        }
+    }
+}
+
+/// Whether a session decodes or encodes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Decode,
+    Encode,
+}
+
+/// Trait for backends that can report their [`Capabilities`].
+pub trait CapabilityQuery {
+    /// Queries the decoding and encoding capabilities of the backend.
+    fn capabilities(&self) -> anyhow::Result<Capabilities>;
+}
+
+impl CapabilityQuery for Capabilities {
+    fn capabilities(&self) -> anyhow::Result<Capabilities> {
+        Ok(self.clone())
+    }
+}
+
+/// Returns the first backend of `backends` that supports a session in `direction` matching
+/// `requirements`, or `None` if there is no such backend.
+///
+/// Backends whose capabilities cannot be queried are skipped.
+pub fn select_backend<'a, Q: CapabilityQuery + ?Sized>(
+    backends: impl IntoIterator<Item = &'a Q>,
+    direction: Direction,
+    requirements: &Requirements,
+) -> Option<&'a Q> {
+    backends.into_iter().find(|backend| {
+        let Ok(capabilities) = backend.capabilities() else {
+            return false;
+        };
+
+        match direction {
+            Direction::Decode => capabilities.supports_decoding(requirements),
+            Direction::Encode => capabilities.supports_encoding(requirements),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use crate::backend::capabilities::select_backend;
+    use crate::backend::capabilities::Capabilities;
+    use crate::backend::capabilities::CapabilityQuery;
+    use crate::backend::capabilities::CodecCapabilities;
+    use crate::backend::capabilities::Direction;
+    use crate::backend::capabilities::Profile;
+    use crate::backend::capabilities::RateControlMode;
+    use crate::backend::capabilities::Requirements;
+    use crate::codec::h264::parser::Profile as H264Profile;
+    use crate::codec::vp9::parser::Profile as Vp9Profile;
+    use crate::DecodedFormat;
+    use crate::EncodedFormat;
+    use crate::Resolution;
+
+    fn h264_capabilities() -> Capabilities {
+        Capabilities {
+            decode: vec![CodecCapabilities {
+                codec: EncodedFormat::H264,
+                profiles: vec![
+                    Profile::H264(H264Profile::ConstrainedBaseline),
+                    Profile::H264(H264Profile::Main),
+                    Profile::H264(H264Profile::High),
+                ],
+                bit_depths: vec![8],
+                formats: vec![DecodedFormat::NV12, DecodedFormat::I420],
+                max_resolution: Some(Resolution::from((1920, 1088))),
+                rate_control_modes: vec![],
+            }],
+            encode: vec![CodecCapabilities {
+                codec: EncodedFormat::H264,
+                profiles: vec![Profile::H264(H264Profile::Main)],
+                bit_depths: vec![8],
+                formats: vec![DecodedFormat::NV12],
+                max_resolution: Some(Resolution::from((1920, 1088))),
+                rate_control_modes: vec![RateControlMode::ConstantBitrate],
+            }],
+        }
+    }
+
+    fn vp9_capabilities() -> Capabilities {
+        Capabilities {
+            decode: vec![CodecCapabilities {
+                codec: EncodedFormat::VP9,
+                profiles: vec![
+                    Profile::Vp9(Vp9Profile::Profile0),
+                    Profile::Vp9(Vp9Profile::Profile2),
+                ],
+                bit_depths: vec![8, 10],
+                formats: vec![],
+                max_resolution: None,
+                rate_control_modes: vec![],
+            }],
+            encode: vec![],
+        }
+    }
+
+    /// A backend whose capabilities cannot be queried.
+    struct FailingBackend;
+
+    impl CapabilityQuery for FailingBackend {
+        fn capabilities(&self) -> anyhow::Result<Capabilities> {
+            Err(anyhow!("no device"))
+        }
+    }
+
+    #[test]
+    fn supports_decoding() {
+        let caps = h264_capabilities();
+        let requirements = Requirements::new(EncodedFormat::H264, Resolution::from((1280, 720)));
+
+        assert!(caps.supports_decoding(&requirements));
+        assert!(caps.supports_decoding(&Requirements {
+            profile: Some(Profile::H264(H264Profile::High)),
+            format: Some(DecodedFormat::I420),
+            bit_depth: Some(8),
+            ..requirements.clone()
+        }));
+        // Rate control is not relevant to decoding.
+        assert!(caps.supports_decoding(&Requirements {
+            rate_control: Some(RateControlMode::ConstantQuality),
+            ..requirements.clone()
+        }));
+
+        assert!(!caps.supports_decoding(&Requirements {
+            codec: EncodedFormat::VP8,
+            ..requirements.clone()
+        }));
+        assert!(!caps.supports_decoding(&Requirements {
+            profile: Some(Profile::H264(H264Profile::High10)),
+            ..requirements.clone()
+        }));
+        assert!(!caps.supports_decoding(&Requirements {
+            bit_depth: Some(10),
+            ..requirements.clone()
+        }));
+        assert!(!caps.supports_decoding(&Requirements {
+            format: Some(DecodedFormat::P010),
+            ..requirements.clone()
+        }));
+        assert!(!caps.supports_decoding(&Requirements {
+            resolution: Resolution::from((3840, 2160)),
+            ..requirements
+        }));
+    }
+
+    #[test]
+    fn supports_encoding() {
+        let caps = h264_capabilities();
+        let requirements = Requirements {
+            profile: Some(Profile::H264(H264Profile::Main)),
+            rate_control: Some(RateControlMode::ConstantBitrate),
+            ..Requirements::new(EncodedFormat::H264, Resolution::from((1280, 720)))
+        };
+
+        assert!(caps.supports_encoding(&requirements));
+        assert!(!caps.supports_encoding(&Requirements {
+            rate_control: Some(RateControlMode::ConstantQuality),
+            ..requirements.clone()
+        }));
+        assert!(!caps.supports_encoding(&Requirements {
+            profile: Some(Profile::H264(H264Profile::High)),
+            ..requirements
+        }));
+        assert!(!vp9_capabilities().supports_encoding(&Requirements::new(
+            EncodedFormat::VP9,
+            Resolution::from((320, 240))
+        )));
+    }
+
+    #[test]
+    fn unreported_capabilities() {
+        // VP9 capabilities report neither formats nor a maximum resolution.
+        let requirements = Requirements {
+            format: Some(DecodedFormat::P010),
+            ..Requirements::new(EncodedFormat::VP9, Resolution::from((7680, 4320)))
+        };
+
+        assert!(vp9_capabilities().supports_decoding(&requirements));
+    }
+
+    #[test]
+    fn select() {
+        let h264 = h264_capabilities();
+        let vp9 = vp9_capabilities();
+        let backends: [&dyn CapabilityQuery; 3] = [&FailingBackend, &h264, &vp9];
+
+        let requirements = Requirements::new(EncodedFormat::VP9, Resolution::from((1280, 720)));
+        let selected = select_backend(backends, Direction::Decode, &requirements).unwrap();
+        assert_eq!(selected.capabilities().unwrap(), vp9);
+
+        let requirements = Requirements::new(EncodedFormat::H264, Resolution::from((1280, 720)));
+        let selected = select_backend(backends, Direction::Encode, &requirements).unwrap();
+        assert_eq!(selected.capabilities().unwrap(), h264);
+
+        let requirements = Requirements::new(EncodedFormat::AV1, Resolution::from((1280, 720)));
+        assert!(select_backend(backends, Direction::Decode, &requirements).is_none());
+    }
+}
@@ -3,11 +3,13 @@
 // found in the LICENSE file.
 
 //! This file contains a dummy backend whose only purpose is to let the decoder
-//! run so we can test it in isolation.
+//! run so we can test it in isolation. It is also exposed through
+//! [`crate::decoder::stateless::new_dummy_dyn_decoder`].
 
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use crate::codec::av1::film_grain::FilmGrain;
 use crate::decoder::stateless::PoolLayer;
 use crate::decoder::stateless::StatelessCodec;
 use crate::decoder::stateless::StatelessDecoderBackend;
@@ -22,7 +24,11 @@ use crate::DecodedFormat;
 use crate::Resolution;
 
 #[derive(Default)]
-pub struct BackendHandle(());
+pub struct BackendHandle {
+    resource: (),
+    corrupted: bool,
+    film_grain: Option<FilmGrain>,
+}
 
 impl MappableHandle for BackendHandle {
     fn read(&mut self, _: &mut [u8]) -> anyhow::Result<()> {
@@ -80,7 +86,23 @@ impl DecodedHandle for Handle {
     }
 
     fn resource(&self) -> std::cell::Ref<()> {
-        std::cell::Ref::map(self.handle.borrow(), |h| &h.0)
+        std::cell::Ref::map(self.handle.borrow(), |h| &h.resource)
+    }
+
+    fn is_corrupted(&self) -> bool {
+        self.handle.borrow().corrupted
+    }
+
+    fn set_corrupted(&self) {
+        self.handle.borrow_mut().corrupted = true;
+    }
+
+    fn pending_film_grain(&self) -> Option<FilmGrain> {
+        self.handle.borrow().film_grain.clone()
+    }
+
+    fn set_pending_film_grain(&self, film_grain: Option<FilmGrain>) {
+        self.handle.borrow_mut().film_grain = film_grain;
     }
 }
 
@@ -4,6 +4,7 @@
 
 //! V4L2 backend for stateful encoders.
 
+mod capabilities;
 pub mod encoder;
 
 impl From<v4l2r::PixelFormat> for crate::Fourcc {
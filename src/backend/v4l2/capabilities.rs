@@ -0,0 +1,119 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Implementation of [`CapabilityQuery`] for V4L2 stateful encoder devices.
+//!
+//! The capabilities are obtained through format and frame size enumeration. Profiles and
+//! rate-control modes are exposed by V4L2 as controls rather than formats, and are not reported.
+
+use std::sync::Arc;
+
+use v4l2r::bindings::v4l2_frmsizeenum;
+use v4l2r::device::queue::Queue;
+use v4l2r::device::Device;
+use v4l2r::ioctl;
+use v4l2r::ioctl::FrmSizeTypes;
+use v4l2r::PixelFormat;
+
+use crate::backend::capabilities::Capabilities;
+use crate::backend::capabilities::CapabilityQuery;
+use crate::backend::capabilities::CodecCapabilities;
+use crate::DecodedFormat;
+use crate::EncodedFormat;
+use crate::Resolution;
+
+/// Maximum number of frame sizes we enumerate for a given pixel format.
+const MAX_FRAME_SIZES: u32 = 64;
+
+fn pixel_format_to_codec(pixel_format: PixelFormat) -> Option<EncodedFormat> {
+    match &pixel_format.to_fourcc() {
+        b"H264" => Some(EncodedFormat::H264),
+        b"HEVC" => Some(EncodedFormat::H265),
+        b"VP80" => Some(EncodedFormat::VP8),
+        b"VP90" => Some(EncodedFormat::VP9),
+        _ => None,
+    }
+}
+
+fn pixel_format_to_decoded_format(pixel_format: PixelFormat) -> Option<DecodedFormat> {
+    match &pixel_format.to_fourcc() {
+        b"NV12" | b"NM12" => Some(DecodedFormat::NV12),
+        _ => None,
+    }
+}
+
+/// Returns the largest frame size supported by `device` for `pixel_format`, or `None` if frame
+/// sizes cannot be enumerated.
+fn max_frame_size(device: &Device, pixel_format: PixelFormat) -> Option<Resolution> {
+    let mut max_resolution: Option<Resolution> = None;
+
+    for index in 0..MAX_FRAME_SIZES {
+        let Ok(frame_size) =
+            ioctl::enum_frame_sizes::<v4l2_frmsizeenum>(device, index, pixel_format)
+        else {
+            break;
+        };
+
+        let resolution = match frame_size.size() {
+            Some(FrmSizeTypes::Discrete(size)) => Resolution::from((size.width, size.height)),
+            Some(FrmSizeTypes::StepWise(size)) => {
+                Resolution::from((size.max_width, size.max_height))
+            }
+            None => continue,
+        };
+
+        max_resolution = Some(match max_resolution {
+            Some(max) if max.can_contain(resolution) => max,
+            _ => resolution,
+        });
+    }
+
+    max_resolution
+}
+
+impl CapabilityQuery for Arc<Device> {
+    fn capabilities(&self) -> anyhow::Result<Capabilities> {
+        let capture_queue = Queue::get_capture_mplane_queue(self.clone())?;
+        let output_queue = Queue::get_output_mplane_queue(self.clone())?;
+
+        // Input formats are common to all the coded formats of the device.
+        let mut formats = vec![];
+        let mut max_input_resolution: Option<Resolution> = None;
+        for fmt in output_queue.format_iter() {
+            let Some(format) = pixel_format_to_decoded_format(fmt.pixelformat) else {
+                continue;
+            };
+
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+
+            if let Some(resolution) = max_frame_size(self, fmt.pixelformat) {
+                max_input_resolution = Some(match max_input_resolution {
+                    Some(max) if max.can_contain(resolution) => max,
+                    _ => resolution,
+                });
+            }
+        }
+
+        let mut capabilities = Capabilities::default();
+        for fmt in capture_queue.format_iter() {
+            let Some(codec) = pixel_format_to_codec(fmt.pixelformat) else {
+                continue;
+            };
+
+            capabilities.encode.push(CodecCapabilities {
+                codec,
+                profiles: vec![],
+                // All the input formats we support have 8 bits per sample.
+                bit_depths: if formats.is_empty() { vec![] } else { vec![8] },
+                formats: formats.clone(),
+                max_resolution: max_frame_size(self, fmt.pixelformat).or(max_input_resolution),
+                rate_control_modes: vec![],
+            });
+        }
+
+        Ok(capabilities)
+    }
+}
@@ -2,10 +2,13 @@
 // Use of this source code is governed by a BSD-style license that can be
 
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
 use std::sync::Arc;
+use std::sync::Mutex;
 
 use nix::sys::stat::fstat;
 use thiserror::Error;
@@ -26,6 +29,7 @@ use v4l2r::device::queue::dqbuf::DqBuffer;
 use v4l2r::device::queue::qbuf::get_free::GetFreeBufferError;
 use v4l2r::device::queue::qbuf::get_free::GetFreeCaptureBuffer;
 use v4l2r::device::queue::qbuf::get_free::GetFreeOutputBuffer;
+use v4l2r::device::queue::qbuf::get_indexed::GetOutputBufferByIndex;
 use v4l2r::device::queue::qbuf::OutputQueueable;
 use v4l2r::device::queue::qbuf::OutputQueueableProvider;
 use v4l2r::device::queue::qbuf::QBuffer;
@@ -33,6 +37,7 @@ use v4l2r::device::queue::BuffersAllocated;
 use v4l2r::device::queue::CreateQueueError;
 use v4l2r::device::queue::Queue;
 use v4l2r::device::queue::RequestBuffersError;
+use v4l2r::device::queue::TryGetBufferError;
 use v4l2r::device::AllocatedQueue;
 use v4l2r::device::Device;
 use v4l2r::device::Stream;
@@ -40,6 +45,7 @@ use v4l2r::device::TryDequeue;
 use v4l2r::ioctl;
 use v4l2r::ioctl::BufferFlags;
 use v4l2r::ioctl::EncoderCommand;
+use v4l2r::ioctl::PlaneMapping;
 use v4l2r::ioctl::StreamOnError;
 use v4l2r::ioctl::V4l2BufferFromError;
 use v4l2r::memory::BufferHandles;
@@ -66,9 +72,12 @@ use crate::encoder::EncodeError;
 use crate::encoder::FrameMetadata;
 use crate::encoder::RateControl;
 use crate::encoder::Tunings;
+use crate::encoder::WritableFrame;
+use crate::encoder::WriteFrameFn;
 use crate::utils::DmabufFrame;
 use crate::utils::UserPtrFrame;
 use crate::Fourcc;
+use crate::FrameLayout;
 use crate::Resolution;
 
 #[derive(Debug, Error)]
@@ -148,6 +157,12 @@ pub enum BackendError {
     #[error(transparent)]
     GetFreeBufferError(#[from] GetFreeBufferError),
 
+    #[error(transparent)]
+    GetBufferError(#[from] TryGetBufferError),
+
+    #[error(transparent)]
+    MapFrame(anyhow::Error),
+
     #[error(transparent)]
     QueueBitstreamBuffer(anyhow::Error),
 
@@ -218,14 +233,33 @@ pub type OutputBuffer<'a, P> =
 pub trait OutputBufferHandle {
     type PrimitiveBufferHandles: PrimitiveBufferHandles;
 
+    /// Returns the index of the OUTPUT buffer the handle must be queued into.
+    ///
+    /// The default implementation is for handles that can be queued into any free buffer, and
+    /// returns `None`.
+    fn buffer_index(&self) -> Option<usize> {
+        None
+    }
+
     fn queue(self, buffer: OutputBuffer<'_, Self::PrimitiveBufferHandles>) -> anyhow::Result<()>;
 }
 
-pub trait AlwaysEntireBufferUsed {}
+pub trait AlwaysEntireBufferUsed {
+    /// Checks that the handle can be queued.
+    ///
+    /// The default implementation is for handles that are always valid, and does nothing.
+    fn validate(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
 
 impl AlwaysEntireBufferUsed for UserPtrFrame {}
 
-impl AlwaysEntireBufferUsed for DmabufFrame {}
+impl AlwaysEntireBufferUsed for DmabufFrame {
+    fn validate(&self) -> anyhow::Result<()> {
+        DmabufFrame::validate(self)
+    }
+}
 
 impl<T> OutputBufferHandle for T
 where
@@ -234,6 +268,8 @@ where
     type PrimitiveBufferHandles = Self;
 
     fn queue(self, buffer: OutputBuffer<'_, Self>) -> anyhow::Result<()> {
+        AlwaysEntireBufferUsed::validate(&self)?;
+
         let mut bytes_used = Vec::new();
         for i in 0..self.len() {
             let mut plane = v4l2r::bindings::v4l2_plane::default();
@@ -250,16 +286,15 @@ where
 impl BufferHandles for UserPtrFrame {
     type SupportedMemoryType = v4l2r::memory::MemoryType;
 
+    // V4L2 planes are memory planes, i.e. buffers, which may contain several planes of the frame.
     fn fill_v4l2_plane(&self, index: usize, plane: &mut v4l2r::bindings::v4l2_plane) {
-        let plane_layout = &self.layout.planes[index];
-
-        plane.m.userptr = self.buffers[plane_layout.buffer_index] as _;
-        plane.data_offset = plane_layout.offset as _;
+        plane.m.userptr = self.buffers[index] as _;
+        plane.data_offset = self.layout.buffer_offset(index) as _;
         plane.length = self.mem_layout.size() as _;
     }
 
     fn len(&self) -> usize {
-        self.layout.planes.len()
+        self.layout.num_buffers()
     }
 }
 
@@ -278,11 +313,10 @@ impl BufferHandles for DmabufFrame {
     type SupportedMemoryType = v4l2r::memory::MemoryType;
 
     fn fill_v4l2_plane(&self, index: usize, plane: &mut v4l2r::bindings::v4l2_plane) {
-        let plane_layout = &self.layout.planes[index];
-        let fd = &self.fds[plane_layout.buffer_index];
+        let fd = &self.fds[index];
 
         plane.m.fd = fd.as_raw_fd();
-        plane.data_offset = plane_layout.offset as u32;
+        plane.data_offset = self.layout.buffer_offset(index) as u32;
         plane.length = fstat(fd.as_raw_fd())
             .map(|stat| stat.st_size as u32)
             .unwrap_or(0);
@@ -293,7 +327,7 @@ impl BufferHandles for DmabufFrame {
     }
 
     fn len(&self) -> usize {
-        self.layout.planes.len()
+        self.layout.num_buffers()
     }
 }
 
@@ -302,6 +336,109 @@ impl PrimitiveBufferHandles for DmabufFrame {
     const MEMORY_TYPE: Self::SupportedMemoryType = v4l2r::memory::MemoryType::DmaBuf;
 }
 
+/// Returns the layout of the frames of the OUTPUT queue `format`. See [`copy_packed_frame`] for
+/// the formats supported.
+///
+/// [`copy_packed_frame`]: crate::encoder::copy_packed_frame
+pub fn output_frame_layout(format: &Format) -> anyhow::Result<FrameLayout> {
+    let plane = |buffer_index, offset, stride: u32| crate::PlaneLayout {
+        buffer_index,
+        offset,
+        stride: stride as usize,
+    };
+
+    let fourcc = Fourcc::from(format.pixelformat.to_u32());
+    let planes = match (&format.pixelformat.to_fourcc(), format.plane_fmt.as_slice()) {
+        // Both planes in a single buffer, the chroma one right after the luma one.
+        (b"NV12" | b"P010", [buffer]) => vec![
+            plane(0, 0, buffer.bytesperline),
+            plane(
+                0,
+                buffer.bytesperline as usize * format.height as usize,
+                buffer.bytesperline,
+            ),
+        ],
+        (b"NM12", [luma, chroma]) => vec![
+            plane(0, 0, luma.bytesperline),
+            plane(1, 0, chroma.bytesperline),
+        ],
+        _ => {
+            return Err(anyhow::anyhow!(
+                "unsupported OUTPUT format {} with {} buffers",
+                fourcc,
+                format.plane_fmt.len()
+            ))
+        }
+    };
+
+    Ok(FrameLayout {
+        format: (fourcc, 0),
+        size: Resolution::from((format.width, format.height)),
+        planes,
+    })
+}
+
+/// Encoder input frame written by the CPU directly into a MMAP buffer of the OUTPUT queue, see
+/// [`V4L2Backend::mmap_frame`].
+pub struct MmapFrame {
+    /// Index of the OUTPUT buffer.
+    index: usize,
+    /// Mappings of the memory planes of the buffer.
+    mappings: Vec<PlaneMapping>,
+    /// Layout of the frame, from the format of the OUTPUT queue.
+    layout: FrameLayout,
+    /// Indices of the OUTPUT buffers lent as frames, shared with the backend.
+    lent_buffers: Arc<Mutex<BTreeSet<usize>>>,
+}
+
+impl MmapFrame {
+    /// Returns the layout of the frame.
+    pub fn layout(&self) -> &FrameLayout {
+        &self.layout
+    }
+}
+
+impl WritableFrame for MmapFrame {
+    fn write_with(&mut self, f: &mut WriteFrameFn) -> anyhow::Result<()> {
+        let mut buffers = self
+            .mappings
+            .iter_mut()
+            .map(|mapping| mapping.as_mut())
+            .collect::<Vec<_>>();
+
+        f(&self.layout, &mut buffers)
+    }
+}
+
+impl OutputBufferHandle for MmapFrame {
+    type PrimitiveBufferHandles = Vec<MmapHandle>;
+
+    fn buffer_index(&self) -> Option<usize> {
+        Some(self.index)
+    }
+
+    fn queue(self, buffer: OutputBuffer<'_, Self::PrimitiveBufferHandles>) -> anyhow::Result<()> {
+        let bytes_used = self
+            .mappings
+            .iter()
+            .map(|mapping| mapping.len())
+            .collect::<Vec<_>>();
+
+        buffer.queue(&bytes_used)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for MmapFrame {
+    fn drop(&mut self) {
+        // The buffer is either queued or free again, so it can be lent once dequeued.
+        if let Ok(mut lent_buffers) = self.lent_buffers.lock() {
+            lent_buffers.remove(&self.index);
+        }
+    }
+}
+
 /// Encoder's coded specific trait enabling setting codec specific tunings
 pub trait EncoderCodec {
     /// Set's [`Tunings`] for the [`v4l2r::device::Device`]
@@ -382,6 +519,9 @@ where
     /// Device poller for implementing [`StatefulVideoEncoderBackend::sync`]
     poller: Poller,
 
+    /// Indices of the OUTPUT buffers currently lent as [`MmapFrame`]s
+    lent_buffers: Arc<Mutex<BTreeSet<usize>>>,
+
     _phantom: PhantomData<(Handle, Codec)>,
 }
 
@@ -697,6 +837,7 @@ where
             currently_processed: Default::default(),
             current_tunings: tunings,
             poller,
+            lent_buffers: Default::default(),
             _phantom: Default::default(),
         })
     }
@@ -831,7 +972,10 @@ where
             self.current_tunings = request.tunings;
         }
 
-        let buffer = self.output_queue.try_get_free_buffer()?;
+        let buffer = match request.handle.buffer_index() {
+            Some(index) => self.output_queue.try_get_buffer(index)?,
+            None => self.output_queue.try_get_free_buffer()?,
+        };
 
         let timestamp = Timestamp(request.meta.timestamp);
         let buffer = buffer.set_timestamp(TimeVal::from(&timestamp));
@@ -884,6 +1028,52 @@ where
     }
 }
 
+impl<CaptureBufferz, Codec> V4L2Backend<MmapFrame, CaptureBufferz, Codec>
+where
+    CaptureBufferz: CaptureBuffers,
+    Self: EncoderCodec,
+{
+    /// Maps a free OUTPUT buffer as a frame, which can be written using [`WritableFrame`] and
+    /// then encoded. Returns `None` if all the OUTPUT buffers are in use.
+    pub fn mmap_frame(&self) -> BackendResult<Option<MmapFrame>> {
+        let format: Format = self.output_queue.get_format()?;
+        let layout = output_frame_layout(&format).map_err(BackendError::MapFrame)?;
+
+        let mut lent_buffers = self.lent_buffers.lock().unwrap();
+        for index in 0..self.output_queue.num_buffers() {
+            if lent_buffers.contains(&index) {
+                continue;
+            }
+
+            // Fails if the buffer is queued.
+            let Ok(buffer) = self.output_queue.try_get_buffer(index) else {
+                continue;
+            };
+
+            let mappings = (0..format.plane_fmt.len())
+                .map(|i| {
+                    buffer
+                        .get_plane_mapping(i)
+                        .ok_or_else(|| anyhow::anyhow!("failed to map plane {}", i))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+                .map_err(BackendError::MapFrame)?;
+
+            log::trace!("OUTPUT: Lending buffer index={index} as a frame");
+            lent_buffers.insert(index);
+
+            return Ok(Some(MmapFrame {
+                index,
+                mappings,
+                layout,
+                lent_buffers: Arc::clone(&self.lent_buffers),
+            }));
+        }
+
+        Ok(None)
+    }
+}
+
 impl<Handle, CaptureBufferz, Codec> StatefulVideoEncoderBackend<Handle>
     for V4L2Backend<Handle, CaptureBufferz, Codec>
 where
@@ -920,6 +1110,11 @@ where
         Ok(self.dequeue_capture()?)
     }
 
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        // SAFETY: the device file descriptor stays open as long as `self.device` is alive.
+        Some(unsafe { BorrowedFd::borrow_raw(self.device.as_raw_fd()) })
+    }
+
     fn drain(&mut self) -> StatefulBackendResult<Vec<BackendOutput>> {
         if self.currently_processed.is_empty() {
             log::info!("Skipping drain sequence, nothing to drain.");
@@ -1418,4 +1613,60 @@ pub(crate) mod tests {
 
         simple_encode_loop(&mut encoder, &mut frame_producer, coded_consumer).expect("encode loop");
     }
+
+    #[test]
+    fn test_output_frame_layout() {
+        let nv12 = Format {
+            width: 64,
+            height: 48,
+            pixelformat: PixelFormat::from_fourcc(b"NV12"),
+            plane_fmt: vec![v4l2r::PlaneLayout {
+                sizeimage: 128 * 72,
+                bytesperline: 128,
+            }],
+        };
+        let layout = output_frame_layout(&nv12).unwrap();
+        assert_eq!(layout.size, Resolution::from((64, 48)));
+        assert_eq!(
+            layout.planes,
+            vec![
+                crate::PlaneLayout {
+                    buffer_index: 0,
+                    offset: 0,
+                    stride: 128,
+                },
+                crate::PlaneLayout {
+                    buffer_index: 0,
+                    offset: 128 * 48,
+                    stride: 128,
+                },
+            ]
+        );
+
+        let nm12 = Format {
+            width: 64,
+            height: 48,
+            pixelformat: PixelFormat::from_fourcc(b"NM12"),
+            plane_fmt: vec![
+                v4l2r::PlaneLayout {
+                    sizeimage: 128 * 48,
+                    bytesperline: 128,
+                },
+                v4l2r::PlaneLayout {
+                    sizeimage: 128 * 24,
+                    bytesperline: 128,
+                },
+            ],
+        };
+        let layout = output_frame_layout(&nm12).unwrap();
+        assert_eq!(layout.planes[1].buffer_index, 1);
+        assert_eq!(layout.planes[1].offset, 0);
+
+        // A single plane NM12 format is inconsistent.
+        let nm12_single = Format {
+            pixelformat: PixelFormat::from_fourcc(b"NM12"),
+            ..nv12
+        };
+        assert!(output_frame_layout(&nm12_single).is_err());
+    }
 }
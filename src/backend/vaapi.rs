@@ -19,6 +19,7 @@ use crate::utils::DmabufFrame;
 use crate::utils::UserPtrFrame;
 use crate::DecodedFormat;
 
+mod capabilities;
 pub mod decoder;
 pub mod encoder;
 pub mod surface_pool;
@@ -47,7 +48,11 @@ struct FormatMap {
 
 /// Maps a given VA_RT_FORMAT to a compatible decoded format in an arbitrary
 /// preferred order.
-const FORMAT_MAP: [FormatMap; 10] = [
+///
+/// Several decoded formats can be read from the same image format, e.g. both `I010` and `P010`
+/// from a `P010` image: the former is converted on the CPU, while the latter is copied as-is.
+/// RGB formats rely on the driver converting the surface when it is mapped.
+const FORMAT_MAP: [FormatMap; 20] = [
     FormatMap {
         rt_format: libva::constants::VA_RT_FORMAT_YUV420,
         va_fourcc: libva::constants::VA_FOURCC_NV12,
@@ -98,6 +103,36 @@ const FORMAT_MAP: [FormatMap; 10] = [
         va_fourcc: libva::constants::VA_FOURCC_Y412,
         decoded_format: DecodedFormat::I412,
     },
+    FormatMap {
+        rt_format: libva::constants::VA_RT_FORMAT_YUV420_10,
+        va_fourcc: libva::constants::VA_FOURCC_P010,
+        decoded_format: DecodedFormat::P010,
+    },
+    FormatMap {
+        rt_format: libva::constants::VA_RT_FORMAT_YUV420_12,
+        va_fourcc: libva::constants::VA_FOURCC_P012,
+        decoded_format: DecodedFormat::P012,
+    },
+    FormatMap {
+        rt_format: libva::constants::VA_RT_FORMAT_YUV422,
+        va_fourcc: libva::constants::VA_FOURCC_YUY2,
+        decoded_format: DecodedFormat::YUY2,
+    },
+    FormatMap {
+        rt_format: libva::constants::VA_RT_FORMAT_YUV422_10,
+        va_fourcc: libva::constants::VA_FOURCC_Y210,
+        decoded_format: DecodedFormat::Y210,
+    },
+    FormatMap {
+        rt_format: libva::constants::VA_RT_FORMAT_YUV444,
+        va_fourcc: libva::constants::VA_FOURCC_AYUV,
+        decoded_format: DecodedFormat::AYUV,
+    },
+    FormatMap {
+        rt_format: libva::constants::VA_RT_FORMAT_YUV444_10,
+        va_fourcc: libva::constants::VA_FOURCC_Y410,
+        decoded_format: DecodedFormat::Y410,
+    },
 ];
 
 /// Returns a set of supported decoded formats given `rt_format`
@@ -146,24 +181,6 @@ fn supported_formats_for_rt_format(
     Ok(supported_formats)
 }
 
-impl TryFrom<&libva::VAImageFormat> for DecodedFormat {
-    type Error = anyhow::Error;
-
-    fn try_from(value: &libva::VAImageFormat) -> Result<Self, Self::Error> {
-        match value.fourcc {
-            libva::constants::VA_FOURCC_I420 => Ok(DecodedFormat::I420),
-            libva::constants::VA_FOURCC_NV12 => Ok(DecodedFormat::NV12),
-            libva::constants::VA_FOURCC_P010 => Ok(DecodedFormat::I010),
-            libva::constants::VA_FOURCC_P012 => Ok(DecodedFormat::I012),
-            libva::constants::VA_FOURCC_Y210 => Ok(DecodedFormat::I210),
-            libva::constants::VA_FOURCC_Y212 => Ok(DecodedFormat::I212),
-            libva::constants::VA_FOURCC_Y410 => Ok(DecodedFormat::I410),
-            libva::constants::VA_FOURCC_Y412 => Ok(DecodedFormat::I412),
-            _ => Err(anyhow!("Unsupported format")),
-        }
-    }
-}
-
 /// Copies `src` into `dst` removing all padding and converting from biplanar to triplanar format.
 ///
 /// `useful_pixels` is the number of useful pixels in each sample, e.g. `10` for `P010`, `12` for
@@ -385,6 +402,24 @@ impl libva::ExternalBufferDescriptor for DmabufFrame {
     type DescriptorAttribute = libva::VADRMPRIMESurfaceDescriptor;
 
     fn va_surface_attribute(&mut self) -> Self::DescriptorAttribute {
+        // A surface holds at most 4 objects. Invalid frames are described without any object or
+        // layer, so that importing them fails instead of reading past `fds`.
+        let valid = match self.validate() {
+            Ok(()) if self.fds.len() > 4 => {
+                log::error!(
+                    "DMA-BUF frame has {} fds but VA-API supports at most 4",
+                    self.fds.len()
+                );
+                false
+            }
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("{:#}", e);
+                false
+            }
+        };
+
+        // Each fd is a separate object, which the planes refer to through their buffer index.
         let objects = self
             .fds
             .iter()
@@ -395,7 +430,7 @@ impl libva::ExternalBufferDescriptor for DmabufFrame {
                     // If we don't have the information about the plane fd size, fallback to 0.
                     // Libva seems to be *sometimes* "happy" with zero.
                     .unwrap_or(0),
-                // TODO should the descriptor be moved to individual objects?
+                // DRM format modifiers apply to the whole frame, hence to all its objects.
                 drm_format_modifier: self.layout.format.1,
             })
             .chain(std::iter::repeat(Default::default()))
@@ -408,7 +443,16 @@ impl libva::ExternalBufferDescriptor for DmabufFrame {
             libva::VADRMPRIMESurfaceDescriptorLayer {
                 drm_format: self.layout.format.0.into(),
                 num_planes: self.layout.planes.len() as u32,
-                object_index: [0, 0, 0, 0],
+                object_index: self
+                    .layout
+                    .planes
+                    .iter()
+                    .map(|p| p.buffer_index as u32)
+                    .chain(std::iter::repeat(0))
+                    .take(4)
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .unwrap(),
                 offset: self
                     .layout
                     .planes
@@ -440,9 +484,9 @@ impl libva::ExternalBufferDescriptor for DmabufFrame {
             fourcc: self.layout.format.0.into(),
             width: self.layout.size.width,
             height: self.layout.size.height,
-            num_objects: 1,
+            num_objects: if valid { self.fds.len() as u32 } else { 0 },
             objects,
-            num_layers: 1,
+            num_layers: if valid { 1 } else { 0 },
             layers,
         }
     }
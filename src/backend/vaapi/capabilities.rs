@@ -0,0 +1,211 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Implementation of [`CapabilityQuery`] for VA-API displays.
+
+use libva::Display;
+use libva::VAConfigAttrib;
+use libva::VAConfigAttribType;
+
+use crate::backend::capabilities::Capabilities;
+use crate::backend::capabilities::CapabilityQuery;
+use crate::backend::capabilities::CodecCapabilities;
+use crate::backend::capabilities::Profile;
+use crate::backend::capabilities::RateControlMode;
+use crate::backend::vaapi::FORMAT_MAP;
+use crate::codec::av1::parser::Profile as Av1Profile;
+use crate::codec::h264::parser::Profile as H264Profile;
+use crate::codec::h265::parser::Profile as H265Profile;
+use crate::codec::vp9::parser::Profile as Vp9Profile;
+use crate::EncodedFormat;
+use crate::Resolution;
+
+/// Bit depth of the samples of each VA_RT_FORMAT.
+const RT_FORMAT_BIT_DEPTHS: [(u32, u32); 9] = [
+    (libva::constants::VA_RT_FORMAT_YUV420, 8),
+    (libva::constants::VA_RT_FORMAT_YUV422, 8),
+    (libva::constants::VA_RT_FORMAT_YUV444, 8),
+    (libva::constants::VA_RT_FORMAT_YUV420_10, 10),
+    (libva::constants::VA_RT_FORMAT_YUV422_10, 10),
+    (libva::constants::VA_RT_FORMAT_YUV444_10, 10),
+    (libva::constants::VA_RT_FORMAT_YUV420_12, 12),
+    (libva::constants::VA_RT_FORMAT_YUV422_12, 12),
+    (libva::constants::VA_RT_FORMAT_YUV444_12, 12),
+];
+
+/// Returns the codec and profiles of the VA profile `va_profile`, given the set of VA_RT_FORMATs
+/// `rt_formats` it supports, or `None` if this crate cannot use `va_profile`.
+///
+/// Only the profiles that our decoders and encoders map to `va_profile` are returned.
+fn va_profile_to_profiles(
+    va_profile: i32,
+    rt_formats: u32,
+) -> Option<(EncodedFormat, Vec<Profile>)> {
+    let has_rt_format = |rt_format| rt_formats & rt_format != 0;
+
+    let (codec, profiles) = match va_profile {
+        libva::VAProfile::VAProfileH264ConstrainedBaseline => (
+            EncodedFormat::H264,
+            vec![
+                Profile::H264(H264Profile::Baseline),
+                Profile::H264(H264Profile::ConstrainedBaseline),
+            ],
+        ),
+        libva::VAProfile::VAProfileH264Main => {
+            (EncodedFormat::H264, vec![Profile::H264(H264Profile::Main)])
+        }
+        libva::VAProfile::VAProfileH264High => {
+            // All the High profiles share the same VA profile, so rely on the RT formats to tell
+            // which ones are actually supported.
+            let mut profiles = vec![H264Profile::High, H264Profile::ConstrainedHigh];
+            if has_rt_format(libva::constants::VA_RT_FORMAT_YUV420_10) {
+                profiles.extend([H264Profile::High10, H264Profile::High10Intra]);
+            }
+            if has_rt_format(libva::constants::VA_RT_FORMAT_YUV422) {
+                profiles.extend([H264Profile::High422P, H264Profile::High422Intra]);
+            }
+            if has_rt_format(libva::constants::VA_RT_FORMAT_YUV444) {
+                profiles.extend([
+                    H264Profile::High444P,
+                    H264Profile::High444Intra,
+                    H264Profile::Cavlc444Intra,
+                ]);
+            }
+
+            (
+                EncodedFormat::H264,
+                profiles.into_iter().map(Profile::H264).collect(),
+            )
+        }
+        libva::VAProfile::VAProfileHEVCMain => (
+            EncodedFormat::H265,
+            vec![
+                Profile::H265(H265Profile::Main),
+                Profile::H265(H265Profile::MainStill),
+            ],
+        ),
+        libva::VAProfile::VAProfileHEVCMain10 => (
+            EncodedFormat::H265,
+            vec![Profile::H265(H265Profile::Main10)],
+        ),
+        libva::VAProfile::VAProfileVP8Version0_3 => (EncodedFormat::VP8, vec![Profile::Vp8]),
+        libva::VAProfile::VAProfileVP9Profile0 => {
+            (EncodedFormat::VP9, vec![Profile::Vp9(Vp9Profile::Profile0)])
+        }
+        libva::VAProfile::VAProfileVP9Profile1 => {
+            (EncodedFormat::VP9, vec![Profile::Vp9(Vp9Profile::Profile1)])
+        }
+        libva::VAProfile::VAProfileVP9Profile2 => {
+            (EncodedFormat::VP9, vec![Profile::Vp9(Vp9Profile::Profile2)])
+        }
+        libva::VAProfile::VAProfileVP9Profile3 => {
+            (EncodedFormat::VP9, vec![Profile::Vp9(Vp9Profile::Profile3)])
+        }
+        libva::VAProfile::VAProfileAV1Profile0 => {
+            (EncodedFormat::AV1, vec![Profile::Av1(Av1Profile::Profile0)])
+        }
+        libva::VAProfile::VAProfileAV1Profile1 => {
+            (EncodedFormat::AV1, vec![Profile::Av1(Av1Profile::Profile1)])
+        }
+        _ => return None,
+    };
+
+    Some((codec, profiles))
+}
+
+impl CapabilityQuery for Display {
+    fn capabilities(&self) -> anyhow::Result<Capabilities> {
+        let image_formats = self.query_image_formats()?;
+        let mut capabilities = Capabilities::default();
+
+        for va_profile in self.query_config_profiles()? {
+            for entrypoint in self.query_config_entrypoints(va_profile)? {
+                let decode = match entrypoint {
+                    libva::VAEntrypoint::VAEntrypointVLD => true,
+                    libva::VAEntrypoint::VAEntrypointEncSlice
+                    | libva::VAEntrypoint::VAEntrypointEncSliceLP => false,
+                    _ => continue,
+                };
+
+                let mut attrs = [
+                    VAConfigAttribType::VAConfigAttribRTFormat,
+                    VAConfigAttribType::VAConfigAttribMaxPictureWidth,
+                    VAConfigAttribType::VAConfigAttribMaxPictureHeight,
+                    VAConfigAttribType::VAConfigAttribRateControl,
+                ]
+                .map(|type_| VAConfigAttrib { type_, value: 0 });
+                self.get_config_attributes(va_profile, entrypoint, &mut attrs)?;
+                let [rt_formats, max_width, max_height, rate_control] = attrs.map(|attr| {
+                    Some(attr.value)
+                        .filter(|&value| value != libva::constants::VA_ATTRIB_NOT_SUPPORTED)
+                });
+                let rt_formats = rt_formats.unwrap_or(0);
+
+                let Some((codec, profiles)) = va_profile_to_profiles(va_profile, rt_formats) else {
+                    continue;
+                };
+
+                // We only have VA-API encoders for some codecs.
+                if !decode
+                    && !matches!(
+                        codec,
+                        EncodedFormat::H264 | EncodedFormat::VP9 | EncodedFormat::AV1
+                    )
+                {
+                    continue;
+                }
+
+                let mut bit_depths = vec![];
+                for (rt_format, bit_depth) in RT_FORMAT_BIT_DEPTHS {
+                    if rt_formats & rt_format != 0 && !bit_depths.contains(&bit_depth) {
+                        bit_depths.push(bit_depth);
+                    }
+                }
+
+                // Only report the formats that the hardware can actually map into.
+                let mut formats = vec![];
+                for map in FORMAT_MAP {
+                    if rt_formats & map.rt_format != 0
+                        && image_formats.iter().any(|fmt| fmt.fourcc == map.va_fourcc)
+                        && !formats.contains(&map.decoded_format)
+                    {
+                        formats.push(map.decoded_format);
+                    }
+                }
+
+                let max_resolution = max_width
+                    .zip(max_height)
+                    .map(|(width, height)| Resolution { width, height });
+
+                let mut rate_control_modes = vec![];
+                if !decode {
+                    let rate_control = rate_control.unwrap_or(0);
+                    if rate_control & libva::constants::VA_RC_CBR != 0 {
+                        rate_control_modes.push(RateControlMode::ConstantBitrate);
+                    }
+                    if rate_control & libva::constants::VA_RC_CQP != 0 {
+                        rate_control_modes.push(RateControlMode::ConstantQuality);
+                    }
+                }
+
+                let caps = CodecCapabilities {
+                    codec,
+                    profiles,
+                    bit_depths,
+                    formats,
+                    max_resolution,
+                    rate_control_modes,
+                };
+
+                if decode {
+                    capabilities.decode.push(caps);
+                } else {
+                    capabilities.encode.push(caps);
+                }
+            }
+        }
+
+        Ok(capabilities)
+    }
+}
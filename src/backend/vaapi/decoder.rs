@@ -26,6 +26,7 @@ use crate::backend::vaapi::va_rt_format_to_string;
 use crate::backend::vaapi::y21x_to_i21x;
 use crate::backend::vaapi::FormatMap;
 use crate::backend::vaapi::FORMAT_MAP;
+use crate::codec::av1::film_grain::FilmGrain;
 use crate::decoder::stateless::PoolLayer;
 use crate::decoder::stateless::StatelessBackendResult;
 use crate::decoder::stateless::StatelessCodec;
@@ -34,14 +35,22 @@ use crate::decoder::stateless::StatelessDecoderBackendPicture;
 use crate::decoder::stateless::TryFormat;
 use crate::decoder::DecodedHandle as DecodedHandleTrait;
 use crate::decoder::DynHandle;
+use crate::decoder::ExportedFrame;
 use crate::decoder::FramePool;
 use crate::decoder::MappableHandle;
+use crate::decoder::MappedPlanes;
 use crate::decoder::StreamInfo;
 use crate::i4xx_copy;
 use crate::nv12_copy;
+use crate::p01x_copy;
+use crate::packed_copy;
+use crate::packed_line_size;
+use crate::utils::DmabufFrame;
 use crate::y410_to_i410;
 use crate::DecodedFormat;
 use crate::Fourcc;
+use crate::FrameLayout;
+use crate::PlaneLayout;
 use crate::Resolution;
 
 use super::supported_formats_for_rt_format;
@@ -60,7 +69,7 @@ pub(crate) fn va_surface_id<M: SurfaceMemoryDescriptor>(
     }
 }
 
-impl<M: SurfaceMemoryDescriptor> DecodedHandleTrait for DecodedHandle<M> {
+impl<M: SurfaceMemoryDescriptor + 'static> DecodedHandleTrait for DecodedHandle<M> {
     type Descriptor = M;
 
     fn coded_resolution(&self) -> Resolution {
@@ -96,6 +105,65 @@ impl<M: SurfaceMemoryDescriptor> DecodedHandleTrait for DecodedHandle<M> {
             PictureState::Invalid => unreachable!(),
         })
     }
+
+    fn is_corrupted(&self) -> bool {
+        self.borrow().corrupted
+    }
+
+    fn set_corrupted(&self) {
+        self.borrow_mut().corrupted = true;
+    }
+
+    fn pending_film_grain(&self) -> Option<FilmGrain> {
+        self.borrow().film_grain.clone()
+    }
+
+    fn set_pending_film_grain(&self, film_grain: Option<FilmGrain>) {
+        self.borrow_mut().film_grain = film_grain;
+    }
+
+    fn export_dmabuf(&self) -> anyhow::Result<ExportedFrame> {
+        self.sync()?;
+
+        let descriptor = self
+            .borrow()
+            .surface()
+            .export_prime()
+            .context("while exporting surface")?;
+        // Surfaces are exported with composed layers, so all the planes are in the first one.
+        let layer = descriptor
+            .layers
+            .first()
+            .ok_or_else(|| anyhow!("exported surface has no layer"))?;
+        let modifier = descriptor
+            .objects
+            .first()
+            .map(|object| object.drm_format_modifier)
+            .unwrap_or(0);
+
+        let layout = FrameLayout {
+            format: (Fourcc::from(layer.drm_format), modifier),
+            size: Resolution::from((descriptor.width, descriptor.height)),
+            planes: (0..layer.num_planes as usize)
+                .map(|i| PlaneLayout {
+                    buffer_index: layer.object_index[i] as usize,
+                    offset: layer.offset[i] as usize,
+                    stride: layer.pitch[i] as usize,
+                })
+                .collect(),
+        };
+        let frame = DmabufFrame {
+            fds: descriptor
+                .objects
+                .into_iter()
+                .map(|object| object.fd)
+                .collect(),
+            layout,
+        };
+
+        // Keep the handle, and thus its pooled surface, alive while the frame is exported.
+        Ok(ExportedFrame::new(frame, Box::new(self.clone())))
+    }
 }
 
 /// A trait for providing the basic information needed to setup libva for decoding.
@@ -126,6 +194,8 @@ pub(crate) struct ParsedStreamMetadata {
     /// mapping in a different format if requested and if the VA-API driver can
     /// do it.
     map_format: Rc<libva::VAImageFormat>,
+    /// The format surfaces are read as once mapped using `map_format`.
+    decoded_format: DecodedFormat,
     /// The rt_format parsed from the stream.
     rt_format: u32,
     /// The profile parsed from the stream.
@@ -307,6 +377,7 @@ impl StreamMetadataState {
                     min_num_frames: min_num_surfaces,
                 },
                 map_format: Rc::new(map_format),
+                decoded_format: format_map.decoded_format,
                 rt_format,
                 profile: va_profile,
             }),
@@ -386,6 +457,12 @@ pub struct VaapiDecodedHandle<M: SurfaceMemoryDescriptor> {
     display_resolution: Resolution,
     /// Image format for this surface, taken from the pool it originates from.
     map_format: Rc<libva::VAImageFormat>,
+    /// Format the mapped image is read as.
+    decoded_format: DecodedFormat,
+    /// Whether errors have been concealed while decoding this surface.
+    corrupted: bool,
+    /// Film grain signaled by the stream that has not been applied to this surface.
+    film_grain: Option<FilmGrain>,
 }
 
 impl<M: SurfaceMemoryDescriptor> VaapiDecodedHandle<M> {
@@ -399,6 +476,9 @@ impl<M: SurfaceMemoryDescriptor> VaapiDecodedHandle<M> {
             state: PictureState::Pending(picture),
             display_resolution: metadata.stream_info.display_resolution,
             map_format: Rc::clone(&metadata.map_format),
+            decoded_format: metadata.decoded_format,
+            corrupted: false,
+            film_grain: None,
         })
     }
 
@@ -451,16 +531,30 @@ impl<M: SurfaceMemoryDescriptor> VaapiDecodedHandle<M> {
 
 impl<'a, M: SurfaceMemoryDescriptor> DynHandle for std::cell::Ref<'a, VaapiDecodedHandle<M>> {
     fn dyn_mappable_handle<'b>(&'b self) -> anyhow::Result<Box<dyn MappableHandle + 'b>> {
-        self.image().map(|i| Box::new(i) as Box<dyn MappableHandle>)
+        self.image().map(|image| {
+            Box::new(VaapiMappedImage {
+                image,
+                format: self.decoded_format,
+            }) as Box<dyn MappableHandle>
+        })
     }
 }
 
-impl<'a> MappableHandle for Image<'a> {
+/// A VA image mapped from a decoded surface, along with the format it should be read as.
+///
+/// This is needed because several decoded formats can be read from the same image format.
+pub struct VaapiMappedImage<'a> {
+    image: Image<'a>,
+    format: DecodedFormat,
+}
+
+impl<'a> MappableHandle for VaapiMappedImage<'a> {
     fn read(&mut self, buffer: &mut [u8]) -> anyhow::Result<()> {
         let image_size = self.image_size();
-        let image_inner = self.image();
+        let image_inner = self.image.image();
+        let src: &[u8] = self.image.as_ref();
 
-        let display_resolution = self.display_resolution();
+        let display_resolution = self.image.display_resolution();
         let width = display_resolution.0 as usize;
         let height = display_resolution.1 as usize;
 
@@ -475,66 +569,58 @@ impl<'a> MappableHandle for Image<'a> {
         let pitches = image_inner.pitches.map(|x| x as usize);
         let offsets = image_inner.offsets.map(|x| x as usize);
 
-        match image_inner.format.fourcc {
-            libva::constants::VA_FOURCC_NV12 => {
-                nv12_copy(self.as_ref(), buffer, width, height, pitches, offsets);
+        match self.format {
+            DecodedFormat::NV12 => {
+                nv12_copy(src, buffer, width, height, pitches, offsets);
             }
-            libva::constants::VA_FOURCC_I420 => {
-                i4xx_copy(
-                    self.as_ref(),
-                    buffer,
-                    width,
-                    height,
-                    pitches,
-                    offsets,
-                    (true, true),
-                );
+            DecodedFormat::I420 => {
+                i4xx_copy(src, buffer, width, height, pitches, offsets, (true, true));
             }
-            libva::constants::VA_FOURCC_422H => {
-                i4xx_copy(
-                    self.as_ref(),
-                    buffer,
-                    width,
-                    height,
-                    pitches,
-                    offsets,
-                    (true, false),
-                );
+            DecodedFormat::I422 => {
+                i4xx_copy(src, buffer, width, height, pitches, offsets, (true, false));
             }
-            libva::constants::VA_FOURCC_444P => {
-                i4xx_copy(
-                    self.as_ref(),
-                    buffer,
-                    width,
-                    height,
-                    pitches,
-                    offsets,
-                    (false, false),
-                );
+            DecodedFormat::I444 => {
+                i4xx_copy(src, buffer, width, height, pitches, offsets, (false, false));
             }
-            libva::constants::VA_FOURCC_P010 => {
-                p01x_to_i01x(self.as_ref(), buffer, 10, width, height, pitches, offsets);
+            DecodedFormat::I010 => {
+                p01x_to_i01x(src, buffer, 10, width, height, pitches, offsets);
             }
-            libva::constants::VA_FOURCC_P012 => {
-                p01x_to_i01x(self.as_ref(), buffer, 12, width, height, pitches, offsets);
+            DecodedFormat::I012 => {
+                p01x_to_i01x(src, buffer, 12, width, height, pitches, offsets);
             }
-            libva::constants::VA_FOURCC_Y210 => {
-                y21x_to_i21x(self.as_ref(), buffer, 10, width, height, pitches, offsets);
+            DecodedFormat::I210 => {
+                y21x_to_i21x(src, buffer, 10, width, height, pitches, offsets);
             }
-            libva::constants::VA_FOURCC_Y212 => {
-                y21x_to_i21x(self.as_ref(), buffer, 12, width, height, pitches, offsets);
+            DecodedFormat::I212 => {
+                y21x_to_i21x(src, buffer, 12, width, height, pitches, offsets);
             }
-            libva::constants::VA_FOURCC_Y410 => {
-                y410_to_i410(self.as_ref(), buffer, width, height, pitches, offsets);
+            DecodedFormat::I410 => {
+                y410_to_i410(src, buffer, width, height, pitches, offsets);
             }
-            libva::constants::VA_FOURCC_Y412 => {
-                y412_to_i412(self.as_ref(), buffer, width, height, pitches, offsets);
+            DecodedFormat::I412 => {
+                y412_to_i412(src, buffer, width, height, pitches, offsets);
             }
-            _ => {
+            DecodedFormat::P010 | DecodedFormat::P012 => {
+                p01x_copy(src, buffer, width, height, pitches, offsets);
+            }
+            DecodedFormat::YUY2
+            | DecodedFormat::Y210
+            | DecodedFormat::Y410
+            | DecodedFormat::AYUV => {
+                let line_size = packed_line_size(self.format, width)
+                    .ok_or_else(|| anyhow!("{:?} is not a single-plane format", self.format))?;
+                packed_copy(src, buffer, line_size, height, pitches[0], offsets[0]);
+            }
+            // These formats are not in the format map, as VA-API surfaces cannot be read as them
+            // without a post-processing step.
+            DecodedFormat::P016
+            | DecodedFormat::ARGB
+            | DecodedFormat::XRGB
+            | DecodedFormat::AB30 => {
                 return Err(anyhow!(
-                    "unsupported format 0x{:x}",
-                    image_inner.format.fourcc
-                ))
+                    "{:?} cannot be read from a VA-API surface",
+                    self.format
+                ));
             }
         }
 
@@ -542,14 +628,49 @@ impl<'a> MappableHandle for Image<'a> {
     }
 
     fn image_size(&mut self) -> usize {
-        let image = self.image();
-        let display_resolution = self.display_resolution();
+        let display_resolution = self.image.display_resolution();
         crate::decoded_frame_size(
-            (&image.format).try_into().unwrap(),
+            self.format,
             display_resolution.0 as usize,
             display_resolution.1 as usize,
         )
     }
+
+    fn map_planes(&self) -> anyhow::Result<MappedPlanes> {
+        let image = self.image.image();
+        let data: &[u8] = self.image.as_ref();
+        let num_planes = image.num_planes as usize;
+        let offsets = image.offsets.map(|x| x as usize);
+
+        let mut layout = FrameLayout {
+            format: (Fourcc::from(image.format.fourcc), 0),
+            size: Resolution::from((u32::from(image.width), u32::from(image.height))),
+            planes: Vec::with_capacity(num_planes),
+        };
+        let mut planes = Vec::with_capacity(num_planes);
+        for plane in 0..num_planes {
+            let start = offsets[plane];
+            // A plane extends until the start of the next one in memory, or the end of the image.
+            let end = offsets[..num_planes]
+                .iter()
+                .copied()
+                .filter(|&offset| offset > start)
+                .min()
+                .unwrap_or(data.len());
+
+            layout.planes.push(PlaneLayout {
+                buffer_index: 0,
+                offset: start,
+                stride: image.pitches[plane] as usize,
+            });
+            planes.push(
+                data.get(start..end)
+                    .ok_or_else(|| anyhow!("plane {} is outside of the mapped image", plane))?,
+            );
+        }
+
+        Ok(MappedPlanes { layout, planes })
+    }
 }
 
 pub struct VaapiBackend<M>
@@ -32,7 +32,11 @@ use crate::encoder::stateless::StatelessEncoderBackendImport;
 use crate::encoder::FrameMetadata;
 use crate::encoder::RateControl;
 use crate::encoder::Tunings;
+use crate::encoder::WritableFrame;
+use crate::encoder::WriteFrameFn;
 use crate::Fourcc;
+use crate::FrameLayout;
+use crate::PlaneLayout;
 use crate::Resolution;
 
 /// The number of frames that encoder backend should initialize scratch pool with.
@@ -410,6 +414,57 @@ where
     }
 }
 
+/// A VA surface that can be written from the CPU, e.g. to import a raw frame into a surface
+/// obtained from a [`VaSurfacePool`] before encoding it.
+pub struct WritableSurface<'a, M: SurfaceMemoryDescriptor> {
+    surface: &'a Surface<M>,
+    image_format: libva::VAImageFormat,
+}
+
+impl<'a, M: SurfaceMemoryDescriptor> WritableSurface<'a, M> {
+    /// Wraps `surface`, which will be mapped as `fourcc` when written.
+    pub fn new(display: &Display, surface: &'a Surface<M>, fourcc: Fourcc) -> anyhow::Result<Self> {
+        let image_format = display
+            .query_image_formats()?
+            .into_iter()
+            .find(|format| format.fourcc == fourcc.0)
+            .ok_or_else(|| anyhow::anyhow!("no image format for fourcc {}", fourcc))?;
+
+        Ok(Self {
+            surface,
+            image_format,
+        })
+    }
+}
+
+impl<'a, M: SurfaceMemoryDescriptor> WritableFrame for WritableSurface<'a, M> {
+    fn write_with(&mut self, f: &mut WriteFrameFn) -> anyhow::Result<()> {
+        let size = self.surface.size();
+        let mut image = libva::Image::create_from(self.surface, self.image_format, size, size)?;
+        let va_image = *image.image();
+
+        let layout = FrameLayout {
+            format: (Fourcc::from(va_image.format.fourcc), 0),
+            size: Resolution::from(size),
+            planes: (0..va_image.num_planes as usize)
+                .map(|i| PlaneLayout {
+                    buffer_index: 0,
+                    offset: va_image.offsets[i] as usize,
+                    stride: va_image.pitches[i] as usize,
+                })
+                .collect(),
+        };
+
+        f(&layout, &mut [image.as_mut()])?;
+
+        // Dropping the image writes it back into the surface if it was not derived from it.
+        drop(image);
+        self.surface.sync()?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use std::borrow::Borrow;
@@ -11,8 +11,10 @@
 //! There shall be no dependencies from other modules of this crate to this module, so that it
 //! can be turned into a crate of its own if needed in the future.
 
+pub mod access_unit;
 pub mod av1;
 pub mod h264;
 pub mod h265;
+pub mod rtp;
 pub mod vp8;
 pub mod vp9;
@@ -0,0 +1,357 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Splitting of Annex B elementary streams into access units.
+//!
+//! Demuxers often deliver H.264 and H.265 elementary streams in chunks that
+//! do not match picture boundaries, and
+//! [`StatelessVideoDecoder::decode`](crate::decoder::stateless::StatelessVideoDecoder::decode)
+//! associates a single timestamp to each call. [`AccessUnitSplitter`] groups
+//! the NAL units of such streams into access units, i.e. the NAL units of one
+//! picture, each tagged with the timestamp of the input chunk it started in.
+//! The codec-specific detection of access unit boundaries lives in the
+//! `access_unit` module of each codec.
+
+use std::collections::VecDeque;
+
+/// The role of a NAL unit regarding access unit boundaries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NaluKind {
+    /// A non-VCL NAL unit that starts a new access unit if it follows a VCL
+    /// NAL unit of the current one, e.g. an access unit delimiter, a parameter
+    /// set or a prefix SEI. As some of these may also precede the non-first
+    /// VCL NAL units of a picture, the boundary is only placed before them if
+    /// the next VCL NAL unit is a [`NaluKind::FirstVcl`].
+    Prefix,
+    /// The first VCL NAL unit of a new primary coded picture.
+    FirstVcl,
+    /// Any other VCL NAL unit of the current picture.
+    Vcl,
+    /// A NAL unit that belongs to the same access unit as the preceding NAL
+    /// unit, e.g. a suffix SEI, filler data or an end of sequence.
+    Suffix,
+}
+
+/// Codec-specific detection of access unit boundaries.
+pub trait AccessUnitBoundary {
+    /// Returns the kind of `nalu`, which is given without its start code.
+    /// NAL units are passed in stream order, so implementations can keep
+    /// track of the state needed to detect the first VCL NAL unit of a
+    /// picture.
+    fn nalu_kind(&mut self, nalu: &[u8]) -> NaluKind;
+}
+
+/// A complete access unit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessUnit {
+    /// The timestamp of the input chunk the access unit started in.
+    pub timestamp: u64,
+    /// The Annex B encoded NAL units of the access unit, start codes
+    /// included.
+    pub data: Vec<u8>,
+}
+
+/// Groups the NAL units of an Annex B stream into access units.
+///
+/// Input can be chunked arbitrarily: a NAL unit is only processed once the
+/// start code of the next one, or the end of the stream signaled with
+/// [`AccessUnitSplitter::flush`], has been seen.
+pub struct AccessUnitSplitter<D> {
+    /// Codec-specific boundary detection.
+    detector: D,
+    /// Input that has not been assigned to an access unit yet.
+    input: Vec<u8>,
+    /// Stream offset of the first byte of `input`.
+    input_offset: u64,
+    /// Stream offset and timestamp of the input chunks still referenced by
+    /// `input`.
+    chunks: VecDeque<(u64, u64)>,
+    /// Position in `input` of the start code of the NAL unit being received.
+    nalu_start: Option<usize>,
+    /// Position in `input` from which to look for the next start code.
+    scan_pos: usize,
+    /// The access unit being assembled.
+    current: Option<AccessUnit>,
+    /// Whether the access unit being assembled contains a VCL NAL unit.
+    has_vcl: bool,
+    /// NAL units received after the last VCL NAL unit of the access unit
+    /// being assembled, that may start the next access unit.
+    pending: Option<AccessUnit>,
+}
+
+impl<D: AccessUnitBoundary + Default> AccessUnitSplitter<D> {
+    pub fn new() -> Self {
+        Self::with_detector(Default::default())
+    }
+}
+
+impl<D: AccessUnitBoundary + Default> Default for AccessUnitSplitter<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: AccessUnitBoundary> AccessUnitSplitter<D> {
+    /// Creates a splitter detecting access unit boundaries with `detector`.
+    pub fn with_detector(detector: D) -> Self {
+        Self {
+            detector,
+            input: Default::default(),
+            input_offset: 0,
+            chunks: Default::default(),
+            nalu_start: None,
+            scan_pos: 0,
+            current: None,
+            has_vcl: false,
+            pending: None,
+        }
+    }
+
+    /// Processes the next `chunk` of the stream, which has `timestamp`.
+    /// Returns the access units completed by this chunk, if any.
+    ///
+    /// Data preceding the first start code of the stream is discarded.
+    pub fn push(&mut self, chunk: &[u8], timestamp: u64) -> Vec<AccessUnit> {
+        let mut access_units = Vec::new();
+
+        if chunk.is_empty() {
+            return access_units;
+        }
+
+        self.chunks
+            .push_back((self.input_offset + self.input.len() as u64, timestamp));
+        self.input.extend_from_slice(chunk);
+
+        while let Some(pos) = self.input[self.scan_pos..]
+            .windows(3)
+            .position(|window| window == [0x00, 0x00, 0x01])
+        {
+            let start_code = self.scan_pos + pos;
+            let mut start = start_code;
+
+            // Include the zero_byte of a 4-byte start code, unless it belongs
+            // to the start code of the NAL unit being received.
+            if start > self.nalu_start.map_or(0, |s| s + 3) && self.input[start - 1] == 0x00 {
+                start -= 1;
+            }
+
+            if let Some(nalu_start) = self.nalu_start {
+                access_units.extend(self.process_nalu(nalu_start, start));
+            }
+
+            self.nalu_start = Some(start);
+            self.scan_pos = start_code + 3;
+        }
+
+        // A start code, including the zero_byte of a 4-byte one, may straddle
+        // this chunk and the next one.
+        self.scan_pos = std::cmp::max(self.scan_pos, self.input.len().saturating_sub(3));
+        self.discard_processed_input();
+
+        access_units
+    }
+
+    /// Signals the end of the stream, returning the access units that were
+    /// still pending. The splitter can be reused afterwards.
+    pub fn flush(&mut self) -> Vec<AccessUnit> {
+        let mut access_units = Vec::new();
+
+        if let Some(nalu_start) = self.nalu_start.take() {
+            access_units.extend(self.process_nalu(nalu_start, self.input.len()));
+        }
+        if let Some(pending) = self.pending.take() {
+            /* Not followed by any picture, keep them with the last one. */
+            match &mut self.current {
+                Some(current) => current.data.extend(pending.data),
+                None => self.current = Some(pending),
+            }
+        }
+        access_units.extend(self.current.take());
+
+        self.input.clear();
+        self.input_offset = 0;
+        self.chunks.clear();
+        self.scan_pos = 0;
+        self.has_vcl = false;
+
+        access_units
+    }
+
+    /// Adds the NAL unit at `input[start..end]` to the access unit being
+    /// assembled, returning the previous access unit if the NAL unit starts a
+    /// new one.
+    fn process_nalu(&mut self, start: usize, end: usize) -> Option<AccessUnit> {
+        let nalu = &self.input[start..end];
+        // The first 0x01 byte ends the start code.
+        let header = nalu
+            .iter()
+            .position(|&b| b == 0x01)
+            .map_or(nalu.len(), |p| p + 1);
+        let payload = &nalu[header..];
+        let trailing_zeros = payload.iter().rev().take_while(|&&b| b == 0x00).count();
+        let payload = &payload[..payload.len() - trailing_zeros];
+
+        if payload.is_empty() {
+            return None;
+        }
+
+        let kind = self.detector.nalu_kind(payload);
+        let timestamp = self.timestamp_at(start);
+        let nalu = &self.input[start..end];
+
+        let mut finished = None;
+        match kind {
+            // Whether these start a new access unit depends on the next VCL
+            // NAL unit, so keep them aside until then.
+            NaluKind::Prefix | NaluKind::Suffix if self.pending.is_some() => {
+                self.pending.as_mut().unwrap().data.extend_from_slice(nalu);
+                return None;
+            }
+            NaluKind::Prefix if self.has_vcl => {
+                self.pending = Some(AccessUnit {
+                    timestamp,
+                    data: nalu.to_vec(),
+                });
+                return None;
+            }
+            NaluKind::FirstVcl if self.has_vcl => {
+                finished = self.current.take();
+                self.has_vcl = false;
+            }
+            _ => (),
+        }
+
+        if matches!(kind, NaluKind::FirstVcl | NaluKind::Vcl) {
+            self.has_vcl = true;
+        }
+
+        // Pending NAL units precede this one in its access unit.
+        let pending = self.pending.take();
+        let current = self.current.get_or_insert_with(|| AccessUnit {
+            timestamp: pending.as_ref().map_or(timestamp, |p| p.timestamp),
+            data: Vec::new(),
+        });
+        if let Some(pending) = pending {
+            current.data.extend(pending.data);
+        }
+        current.data.extend_from_slice(nalu);
+
+        finished
+    }
+
+    /// Returns the timestamp of the chunk containing `input[pos]`.
+    fn timestamp_at(&self, pos: usize) -> u64 {
+        let offset = self.input_offset + pos as u64;
+
+        self.chunks
+            .iter()
+            .rev()
+            .find(|(chunk_offset, _)| *chunk_offset <= offset)
+            .or(self.chunks.front())
+            .map_or(0, |(_, timestamp)| *timestamp)
+    }
+
+    /// Drops the input and chunks that are not needed anymore.
+    fn discard_processed_input(&mut self) {
+        let keep_from = self.nalu_start.unwrap_or(self.scan_pos);
+
+        self.input.drain(..keep_from);
+        self.input_offset += keep_from as u64;
+        self.nalu_start = self.nalu_start.map(|s| s - keep_from);
+        self.scan_pos -= keep_from;
+
+        while self.chunks.len() > 1 && self.chunks[1].0 <= self.input_offset {
+            self.chunks.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::access_unit::AccessUnitBoundary;
+    use crate::codec::access_unit::AccessUnitSplitter;
+    use crate::codec::access_unit::NaluKind;
+
+    /// Boundary detector using the first payload byte as the kind of the NAL
+    /// unit.
+    #[derive(Default)]
+    struct TestDetector;
+
+    impl AccessUnitBoundary for TestDetector {
+        fn nalu_kind(&mut self, nalu: &[u8]) -> NaluKind {
+            match nalu[0] {
+                b'P' => NaluKind::Prefix,
+                b'F' => NaluKind::FirstVcl,
+                b'V' => NaluKind::Vcl,
+                _ => NaluKind::Suffix,
+            }
+        }
+    }
+
+    const STREAM: &[u8] = b"\x00\x00\x00\x01P1\x00\x00\x01F1\x00\x00\x01V1\x00\x00\x01S1\
+        \x00\x00\x00\x01F2\x00\x00\x01P3\x00\x00\x01F3\x00\x00\x01V3";
+
+    #[test]
+    fn split_whole_stream() {
+        let mut splitter = AccessUnitSplitter::<TestDetector>::new();
+
+        let aus = splitter.push(STREAM, 7);
+        assert_eq!(aus.len(), 2);
+        assert_eq!(
+            aus[0].data,
+            b"\x00\x00\x00\x01P1\x00\x00\x01F1\x00\x00\x01V1\x00\x00\x01S1"
+        );
+        assert_eq!(aus[1].data, b"\x00\x00\x00\x01F2");
+        assert!(aus.iter().all(|au| au.timestamp == 7));
+
+        let aus = splitter.flush();
+        assert_eq!(aus.len(), 1);
+        assert_eq!(aus[0].data, b"\x00\x00\x01P3\x00\x00\x01F3\x00\x00\x01V3");
+        assert!(splitter.flush().is_empty());
+    }
+
+    #[test]
+    fn split_byte_by_byte() {
+        let mut splitter = AccessUnitSplitter::<TestDetector>::new();
+
+        let mut aus = Vec::new();
+        for (i, byte) in STREAM.iter().enumerate() {
+            aus.extend(splitter.push(std::slice::from_ref(byte), i as u64));
+        }
+        aus.extend(splitter.flush());
+
+        let whole: Vec<u8> = aus.iter().flat_map(|au| au.data.clone()).collect();
+        assert_eq!(whole, STREAM);
+
+        // Each access unit gets the timestamp of its first byte.
+        let timestamps: Vec<u64> = aus.iter().map(|au| au.timestamp).collect();
+        assert_eq!(timestamps, vec![0, 21, 27]);
+    }
+
+    #[test]
+    fn leading_garbage() {
+        let mut splitter = AccessUnitSplitter::<TestDetector>::new();
+
+        assert!(splitter.push(b"garbage\x00\x00\x01F1", 0).is_empty());
+        assert_eq!(splitter.flush()[0].data, b"\x00\x00\x01F1");
+    }
+
+    #[test]
+    fn prefix_before_non_first_vcl() {
+        let mut splitter = AccessUnitSplitter::<TestDetector>::new();
+
+        let aus = splitter.push(
+            b"\x00\x00\x01F1\x00\x00\x01P1\x00\x00\x01V1\x00\x00\x01P2\x00\x00\x01S2\x00\x00\x01F2",
+            0,
+        );
+        // The last NAL unit is only known to be complete at the end of the
+        // stream.
+        assert!(aus.is_empty());
+
+        let aus = splitter.flush();
+        assert_eq!(aus.len(), 2);
+        assert_eq!(aus[0].data, b"\x00\x00\x01F1\x00\x00\x01P1\x00\x00\x01V1");
+        assert_eq!(aus[1].data, b"\x00\x00\x01P2\x00\x00\x01S2\x00\x00\x01F2");
+    }
+}
@@ -2,8 +2,12 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+pub mod annexb;
+pub mod av1c;
+pub mod film_grain;
 mod helpers;
 pub mod parser;
 pub mod reader;
+pub mod rtp;
 pub mod synthesizer;
 pub mod writer;
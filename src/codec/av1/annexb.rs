@@ -0,0 +1,326 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Conversion between the "low-overhead" bitstream format of section 5 and the
+//! length-delimited bitstream format of Annex B.
+
+use anyhow::anyhow;
+
+use crate::codec::av1::parser::ObuType;
+
+/// Reads a leb128() value from the start of `data`, returning it along with
+/// the number of bytes it was coded with. See 4.10.5.
+pub(crate) fn read_leb128(data: &[u8]) -> anyhow::Result<(u32, usize)> {
+    let mut value = 0u64;
+
+    for (i, byte) in data.iter().take(8).enumerate() {
+        value |= u64::from(byte & 0x7f) << (i * 7);
+
+        if byte & 0x80 == 0 {
+            return Ok((u32::try_from(value)?, i + 1));
+        }
+    }
+
+    Err(anyhow!("Invalid or truncated leb128 value"))
+}
+
+/// Appends `value` to `out` as a leb128() value using the minimum number of
+/// bytes.
+pub(crate) fn write_leb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            out.push(byte);
+            break;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+/// A bare view of an OBU, enough to move it between bitstream formats.
+pub(crate) struct RawObu<'a> {
+    pub(crate) obu_type: ObuType,
+    /// The obu_header(), without the obu_has_size_field bit.
+    pub(crate) header: [u8; 2],
+    pub(crate) header_len: usize,
+    /// The OBU payload, i.e. what follows obu_header() and obu_size.
+    pub(crate) payload: &'a [u8],
+}
+
+impl<'a> RawObu<'a> {
+    /// Parses an OBU from the start of `data`. If the OBU has no obu_size
+    /// field, its payload is assumed to span the whole of `data`. Returns the
+    /// OBU along with the number of bytes it occupies.
+    pub(crate) fn parse(data: &'a [u8]) -> anyhow::Result<(Self, usize)> {
+        let first = *data.first().ok_or_else(|| anyhow!("Empty OBU"))?;
+        let obu_type = ObuType::n((first >> 3) & 0xf).ok_or_else(|| anyhow!("Invalid OBU type"))?;
+        let extension_flag = first & 0x4 != 0;
+        let has_size_field = first & 0x2 != 0;
+
+        let header_len = 1 + usize::from(extension_flag);
+        if data.len() < header_len {
+            return Err(anyhow!("Truncated OBU header"));
+        }
+
+        let mut header = [first & !0x2, 0];
+        if extension_flag {
+            header[1] = data[1];
+        }
+
+        let (payload_start, payload_len) = if has_size_field {
+            let (obu_size, leb128_len) = read_leb128(&data[header_len..])?;
+            (header_len + leb128_len, obu_size as usize)
+        } else {
+            (header_len, data.len() - header_len)
+        };
+
+        let end = payload_start
+            .checked_add(payload_len)
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| anyhow!("OBU size exceeds the available data"))?;
+
+        Ok((
+            Self {
+                obu_type,
+                header,
+                header_len,
+                payload: &data[payload_start..end],
+            },
+            end,
+        ))
+    }
+
+    /// Writes the OBU in low-overhead format, i.e. with an obu_size field.
+    pub(crate) fn write_sized(&self, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        out.push(self.header[0] | 0x2);
+        out.extend_from_slice(&self.header[1..self.header_len]);
+        write_leb128(out, u32::try_from(self.payload.len())?);
+        out.extend_from_slice(self.payload);
+        Ok(())
+    }
+
+    /// Writes the OBU in Annex B format, i.e. preceded by obu_length and
+    /// without an obu_size field.
+    fn write_length_delimited(&self, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        write_leb128(out, u32::try_from(self.header_len + self.payload.len())?);
+        out.extend_from_slice(&self.header[..self.header_len]);
+        out.extend_from_slice(self.payload);
+        Ok(())
+    }
+}
+
+/// Converts one temporal unit in low-overhead format into an Annex B
+/// temporal_unit(), including its temporal_unit_size.
+///
+/// Each frame header or frame OBU, together with its tile groups and the
+/// OBUs preceding it, makes up a frame_unit().
+pub fn low_overhead_to_annexb(temporal_unit: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut frame_units = vec![];
+    let mut frame_unit = vec![];
+    let mut frame_unit_has_frame = false;
+
+    let mut consumed = 0;
+    while consumed < temporal_unit.len() {
+        let (obu, len) = RawObu::parse(&temporal_unit[consumed..])?;
+        consumed += len;
+
+        // Anything but the tile groups of the current frame starts a new
+        // frame unit once a frame has been seen. Temporal delimiters, sequence
+        // headers and metadata thus belong to the frame that follows them.
+        let starts_frame_unit = frame_unit_has_frame
+            && !matches!(
+                obu.obu_type,
+                ObuType::TileGroup
+                    | ObuType::TileList
+                    | ObuType::RedundantFrameHeader
+                    | ObuType::Padding
+            );
+
+        if starts_frame_unit {
+            frame_units.push(std::mem::take(&mut frame_unit));
+            frame_unit_has_frame = false;
+        }
+
+        if matches!(obu.obu_type, ObuType::FrameHeader | ObuType::Frame) {
+            frame_unit_has_frame = true;
+        }
+
+        obu.write_length_delimited(&mut frame_unit)?;
+    }
+
+    if !frame_unit.is_empty() {
+        frame_units.push(frame_unit);
+    }
+
+    let mut tu_payload = vec![];
+    for frame_unit in frame_units {
+        write_leb128(&mut tu_payload, u32::try_from(frame_unit.len())?);
+        tu_payload.extend_from_slice(&frame_unit);
+    }
+
+    let mut out = Vec::with_capacity(tu_payload.len() + 4);
+    write_leb128(&mut out, u32::try_from(tu_payload.len())?);
+    out.extend_from_slice(&tu_payload);
+
+    Ok(out)
+}
+
+/// Converts Annex B data made of one or more temporal_unit()s into
+/// low-overhead format. Each returned buffer holds one temporal unit.
+pub fn annexb_to_low_overhead(data: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    /// Splits `data` into a leading leb128()-sized unit and the rest.
+    fn split_unit(data: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+        let (size, leb128_len) = read_leb128(data)?;
+        let end = leb128_len
+            .checked_add(size as usize)
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| anyhow!("Unit size exceeds the available data"))?;
+
+        Ok((&data[leb128_len..end], &data[end..]))
+    }
+
+    let mut temporal_units = vec![];
+
+    let mut remaining = data;
+    while !remaining.is_empty() {
+        let (mut temporal_unit, rest) = split_unit(remaining)?;
+        remaining = rest;
+
+        let mut out = vec![];
+        while !temporal_unit.is_empty() {
+            let (mut frame_unit, rest) = split_unit(temporal_unit)?;
+            temporal_unit = rest;
+
+            while !frame_unit.is_empty() {
+                let (obu_data, rest) = split_unit(frame_unit)?;
+                frame_unit = rest;
+
+                let (obu, _) = RawObu::parse(obu_data)?;
+                obu.write_sized(&mut out)?;
+            }
+        }
+
+        temporal_units.push(out);
+    }
+
+    Ok(temporal_units)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::av1::annexb::annexb_to_low_overhead;
+    use crate::codec::av1::annexb::low_overhead_to_annexb;
+    use crate::codec::av1::annexb::read_leb128;
+    use crate::codec::av1::annexb::write_leb128;
+    use crate::codec::av1::annexb::RawObu;
+    use crate::codec::av1::parser::ObuType;
+    use crate::codec::av1::parser::ParsedObu;
+    use crate::codec::av1::parser::Parser;
+    use crate::utils::IvfIterator;
+
+    const STREAM_TEST_25_FPS: &[u8] = include_bytes!("test_data/test-25fps.ivf.av1");
+    const STREAM_ANNEXB: &[u8] = include_bytes!("test_data/av1-annexb.ivf.av1");
+
+    /// Returns the type and payload of all OBUs in `data` as identified by the
+    /// parser.
+    fn parse_all(data: &[u8]) -> Vec<(ObuType, Vec<u8>)> {
+        let mut parser = Parser::default();
+        let mut consumed = 0;
+        let mut obus = vec![];
+
+        while let Ok(obu) = parser.parse_obu(&data[consumed..]) {
+            let obu = match obu {
+                ParsedObu::Process(obu) => obu,
+                ParsedObu::Drop(length) => {
+                    consumed += usize::try_from(length).unwrap();
+                    continue;
+                }
+            };
+
+            consumed += obu.data.len();
+            obus.push((obu.header.obu_type, obu.as_ref().to_vec()));
+        }
+
+        obus
+    }
+
+    #[test]
+    fn leb128() {
+        for value in [0, 1, 127, 128, 300, 16383, 16384, u32::MAX] {
+            let mut out = vec![];
+            write_leb128(&mut out, value);
+            assert_eq!(read_leb128(&out).unwrap(), (value, out.len()));
+        }
+
+        // Non-minimal encodings are valid too.
+        assert_eq!(read_leb128(&[0x85, 0x80, 0x00]).unwrap(), (5, 3));
+        assert!(read_leb128(&[0x80]).is_err());
+    }
+
+    #[test]
+    fn low_overhead_roundtrip() {
+        for packet in IvfIterator::new(STREAM_TEST_25_FPS) {
+            let annexb = low_overhead_to_annexb(packet).unwrap();
+            let low_overhead = annexb_to_low_overhead(&annexb).unwrap();
+            assert_eq!(low_overhead.len(), 1);
+
+            let original = parse_all(packet);
+            assert_eq!(parse_all(&low_overhead[0]), original);
+
+            // The parser must detect and properly read the Annex B output.
+            if original
+                .iter()
+                .any(|(obu_type, _)| *obu_type == ObuType::SequenceHeader)
+            {
+                assert_eq!(parse_all(&annexb), original);
+            }
+        }
+    }
+
+    #[test]
+    fn annexb_roundtrip() {
+        let packet = IvfIterator::new(STREAM_ANNEXB).next().unwrap();
+        let original = parse_all(packet);
+        assert_eq!(original.len(), 3);
+
+        let low_overhead = annexb_to_low_overhead(packet).unwrap();
+        assert_eq!(low_overhead.len(), 1);
+        assert_eq!(parse_all(&low_overhead[0]), original);
+
+        let annexb = low_overhead_to_annexb(&low_overhead[0]).unwrap();
+        assert_eq!(annexb, packet);
+    }
+
+    #[test]
+    fn frame_units() {
+        // TD, sequence header, frame, then a frame header and its tile group.
+        let temporal_unit = [
+            0x12, 0x00, 0x0a, 0x01, 0xaa, 0x32, 0x01, 0xbb, 0x1a, 0x01, 0xcc, 0x22, 0x01, 0xdd,
+        ];
+
+        let annexb = low_overhead_to_annexb(&temporal_unit).unwrap();
+        assert_eq!(
+            annexb,
+            [
+                0x10, // temporal_unit_size
+                0x08, // frame_unit_size
+                0x01, 0x10, // TD
+                0x02, 0x08, 0xaa, // Sequence header
+                0x02, 0x30, 0xbb, // Frame
+                0x06, // frame_unit_size
+                0x02, 0x18, 0xcc, // Frame header
+                0x02, 0x20, 0xdd, // Tile group
+            ]
+        );
+
+        let (obu, len) = RawObu::parse(&annexb[3..4]).unwrap();
+        assert_eq!(obu.obu_type, ObuType::TemporalDelimiter);
+        assert_eq!(len, 1);
+
+        assert_eq!(annexb_to_low_overhead(&annexb).unwrap(), [temporal_unit]);
+    }
+}
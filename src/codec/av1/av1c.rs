@@ -0,0 +1,198 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Support for the AV1CodecConfigurationRecord (`av1C`) defined in the "AV1
+//! Codec ISO Media File Format Binding" specification, which is used by the
+//! ISO BMFF (MP4) and Matroska containers.
+
+use anyhow::anyhow;
+
+use crate::codec::av1::parser::ObuType;
+use crate::codec::av1::parser::ParsedObu;
+use crate::codec::av1::parser::Parser;
+use crate::codec::av1::parser::SequenceHeaderObu;
+
+/// The AV1CodecConfigurationRecord, as per section 2.3.3 of the AV1 ISOBMFF
+/// binding.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Av1CodecConfigurationRecord {
+    /// Always 1 for this version of the specification.
+    pub version: u8,
+    /// Same as `seq_profile` in the sequence header.
+    pub seq_profile: u8,
+    /// Same as `seq_level_idx[0]` in the sequence header.
+    pub seq_level_idx_0: u8,
+    /// Same as `seq_tier[0]` in the sequence header.
+    pub seq_tier_0: bool,
+    /// Same as `high_bitdepth` in the sequence header.
+    pub high_bitdepth: bool,
+    /// Same as `twelve_bit` in the sequence header.
+    pub twelve_bit: bool,
+    /// Same as `mono_chrome` in the sequence header.
+    pub monochrome: bool,
+    /// Same as `subsampling_x` in the sequence header.
+    pub chroma_subsampling_x: bool,
+    /// Same as `subsampling_y` in the sequence header.
+    pub chroma_subsampling_y: bool,
+    /// Same as `chroma_sample_position` in the sequence header.
+    pub chroma_sample_position: u8,
+    /// The number of temporal units to buffer before presenting the first
+    /// frame, minus one, if known.
+    pub initial_presentation_delay_minus_one: Option<u8>,
+    /// Zero or more OBUs in low-overhead format. If present, the first one is
+    /// the sequence header.
+    pub config_obus: Vec<u8>,
+}
+
+impl Av1CodecConfigurationRecord {
+    /// Parses an AV1CodecConfigurationRecord, e.g. the payload of an `av1C`
+    /// box or the Matroska `CodecPrivate` element.
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < 4 {
+            return Err(anyhow!("AV1CodecConfigurationRecord is too short"));
+        }
+
+        let marker = data[0] >> 7;
+        let version = data[0] & 0x7f;
+        if marker != 1 || version != 1 {
+            return Err(anyhow!(
+                "Unsupported marker {} or version {}",
+                marker,
+                version
+            ));
+        }
+
+        let initial_presentation_delay_present = (data[3] >> 4) & 0x1 != 0;
+
+        Ok(Self {
+            version,
+            seq_profile: data[1] >> 5,
+            seq_level_idx_0: data[1] & 0x1f,
+            seq_tier_0: data[2] >> 7 != 0,
+            high_bitdepth: (data[2] >> 6) & 0x1 != 0,
+            twelve_bit: (data[2] >> 5) & 0x1 != 0,
+            monochrome: (data[2] >> 4) & 0x1 != 0,
+            chroma_subsampling_x: (data[2] >> 3) & 0x1 != 0,
+            chroma_subsampling_y: (data[2] >> 2) & 0x1 != 0,
+            chroma_sample_position: data[2] & 0x3,
+            initial_presentation_delay_minus_one: initial_presentation_delay_present
+                .then_some(data[3] & 0xf),
+            config_obus: data[4..].to_vec(),
+        })
+    }
+
+    /// Builds a record from a parsed sequence header. `config_obus` should
+    /// contain the sequence header OBU in low-overhead format, optionally
+    /// followed by metadata OBUs.
+    pub fn from_sequence_header(seq: &SequenceHeaderObu, config_obus: &[u8]) -> Self {
+        let op = &seq.operating_points[0];
+        let cc = &seq.color_config;
+
+        let initial_presentation_delay_minus_one = (seq.initial_display_delay_present_flag
+            && op.initial_display_delay_present_for_this_op)
+            .then_some(op.initial_display_delay_minus_1.min(0xf) as u8);
+
+        Self {
+            version: 1,
+            seq_profile: seq.seq_profile as u8,
+            seq_level_idx_0: op.seq_level_idx as u8,
+            seq_tier_0: op.seq_tier != 0,
+            high_bitdepth: cc.high_bitdepth,
+            twelve_bit: cc.twelve_bit,
+            monochrome: cc.mono_chrome,
+            chroma_subsampling_x: cc.subsampling_x,
+            chroma_subsampling_y: cc.subsampling_y,
+            chroma_sample_position: cc.chroma_sample_position as u8,
+            initial_presentation_delay_minus_one,
+            config_obus: config_obus.to_vec(),
+        }
+    }
+
+    /// Builds a record from the first sequence header found in `data`, a
+    /// temporal unit in low-overhead format, e.g. the first keyframe produced
+    /// by an encoder.
+    pub fn from_temporal_unit(data: &[u8]) -> anyhow::Result<Self> {
+        let mut parser = Parser::default();
+        let mut consumed = 0;
+
+        while consumed < data.len() {
+            let obu = match parser.parse_obu(&data[consumed..])? {
+                ParsedObu::Process(obu) => obu,
+                ParsedObu::Drop(length) => {
+                    consumed += usize::try_from(length)?;
+                    continue;
+                }
+            };
+
+            consumed += obu.data.len();
+
+            if obu.header.obu_type == ObuType::SequenceHeader {
+                let seq = parser.parse_sequence_header_obu(&obu)?;
+                return Ok(Self::from_sequence_header(&seq, &obu.data));
+            }
+        }
+
+        Err(anyhow!("No sequence header found in the temporal unit"))
+    }
+
+    /// Serializes the record, e.g. to use as the payload of an `av1C` box.
+    pub fn write(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(4 + self.config_obus.len());
+
+        out.push(0x80 | (self.version & 0x7f));
+        out.push((self.seq_profile << 5) | (self.seq_level_idx_0 & 0x1f));
+        out.push(
+            (u8::from(self.seq_tier_0) << 7)
+                | (u8::from(self.high_bitdepth) << 6)
+                | (u8::from(self.twelve_bit) << 5)
+                | (u8::from(self.monochrome) << 4)
+                | (u8::from(self.chroma_subsampling_x) << 3)
+                | (u8::from(self.chroma_subsampling_y) << 2)
+                | (self.chroma_sample_position & 0x3),
+        );
+        out.push(match self.initial_presentation_delay_minus_one {
+            Some(delay) => 0x10 | (delay & 0xf),
+            None => 0,
+        });
+        out.extend_from_slice(&self.config_obus);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::av1::av1c::Av1CodecConfigurationRecord;
+    use crate::utils::IvfIterator;
+
+    const STREAM_TEST_25_FPS: &[u8] = include_bytes!("test_data/test-25fps.ivf.av1");
+
+    #[test]
+    fn av1c_roundtrip() {
+        let packet = IvfIterator::new(STREAM_TEST_25_FPS).next().unwrap();
+        let record = Av1CodecConfigurationRecord::from_temporal_unit(packet).unwrap();
+
+        assert_eq!(record.version, 1);
+        assert_eq!(record.seq_profile, 0);
+        assert!(!record.high_bitdepth);
+        assert!(record.chroma_subsampling_x);
+        assert!(record.chroma_subsampling_y);
+        // The sequence header OBU, with obu_has_size_field set.
+        assert_eq!(record.config_obus[0], 0x0a);
+
+        let bytes = record.write();
+        assert_eq!(bytes[0], 0x81);
+        assert_eq!(Av1CodecConfigurationRecord::parse(&bytes).unwrap(), record);
+
+        // The config OBUs must be parseable on their own.
+        let record2 = Av1CodecConfigurationRecord::from_temporal_unit(&record.config_obus).unwrap();
+        assert_eq!(record2, record);
+    }
+
+    #[test]
+    fn av1c_invalid() {
+        assert!(Av1CodecConfigurationRecord::parse(&[0x81, 0x00]).is_err());
+        assert!(Av1CodecConfigurationRecord::parse(&[0x01, 0x00, 0x0c, 0x00]).is_err());
+    }
+}
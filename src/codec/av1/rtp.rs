@@ -0,0 +1,301 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! RTP payload format for AV1, as per the "RTP Payload Format For AV1"
+//! specification of the Alliance for Open Media.
+//!
+//! The payloader aggregates and fragments OBUs into OBU elements, each
+//! preceded by its length (i.e. with W=0). The depayloader accepts any value
+//! of W and produces temporal units in low-overhead format.
+
+use anyhow::anyhow;
+
+use crate::codec::av1::annexb::read_leb128;
+use crate::codec::av1::annexb::write_leb128;
+use crate::codec::av1::annexb::RawObu;
+use crate::codec::av1::parser::ObuType;
+use crate::codec::rtp::AccessUnitAssembler;
+use crate::codec::rtp::RtpDepayloader;
+use crate::codec::rtp::RtpPacket;
+use crate::codec::rtp::RtpPayloader;
+
+/// Z: the first OBU element continues an OBU fragment of the previous packet.
+const AGGREGATION_HEADER_Z: u8 = 0x80;
+/// Y: the last OBU element continues in the next packet.
+const AGGREGATION_HEADER_Y: u8 = 0x40;
+/// N: the packet is the first one of a coded video sequence.
+const AGGREGATION_HEADER_N: u8 = 0x08;
+
+/// A temporal delimiter OBU in low-overhead format.
+const TEMPORAL_DELIMITER: [u8; 2] = [0x12, 0x00];
+
+/// Returns the number of bytes needed to code `value` as a leb128().
+fn leb128_size(value: usize) -> usize {
+    let mut size = 1;
+    let mut value = value >> 7;
+
+    while value != 0 {
+        size += 1;
+        value >>= 7;
+    }
+
+    size
+}
+
+/// Splits temporal units in low-overhead format into RTP payloads.
+pub struct Payloader {
+    max_payload_size: usize,
+}
+
+impl Payloader {
+    /// Creates a payloader producing payloads of at most `max_payload_size`
+    /// bytes.
+    pub fn new(max_payload_size: usize) -> Self {
+        Self { max_payload_size }
+    }
+}
+
+impl RtpPayloader for Payloader {
+    fn payload(&mut self, frame: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+        // A leb128() length and at least one byte of OBU data must fit after
+        // the aggregation header.
+        if self.max_payload_size < 3 {
+            return Err(anyhow!("maximum payload size is too small"));
+        }
+
+        // OBU elements are OBUs without their obu_size field. Temporal
+        // delimiters, tile lists and padding are not transmitted.
+        let mut elements = vec![];
+        let mut new_sequence = false;
+        let mut consumed = 0;
+
+        while consumed < frame.len() {
+            let (obu, len) = RawObu::parse(&frame[consumed..])?;
+            consumed += len;
+
+            match obu.obu_type {
+                ObuType::TemporalDelimiter | ObuType::TileList | ObuType::Padding => continue,
+                ObuType::SequenceHeader => new_sequence = true,
+                _ => (),
+            }
+
+            let mut element = Vec::with_capacity(obu.header_len + obu.payload.len());
+            element.extend_from_slice(&obu.header[..obu.header_len]);
+            element.extend_from_slice(obu.payload);
+            elements.push(element);
+        }
+
+        let mut packets = vec![];
+        let mut packet = vec![if new_sequence {
+            AGGREGATION_HEADER_N
+        } else {
+            0
+        }];
+
+        for element in &elements {
+            let mut remaining = &element[..];
+
+            loop {
+                let available = self.max_payload_size - packet.len();
+                if leb128_size(remaining.len()) + remaining.len() <= available {
+                    write_leb128(&mut packet, u32::try_from(remaining.len())?);
+                    packet.extend_from_slice(remaining);
+                    break;
+                }
+
+                // Fragment the OBU if at least one byte of it fits in this
+                // packet.
+                let fragment_size = available.saturating_sub(leb128_size(available));
+                let continues = fragment_size > 0;
+                if continues {
+                    write_leb128(&mut packet, u32::try_from(fragment_size)?);
+                    packet.extend_from_slice(&remaining[..fragment_size]);
+                    remaining = &remaining[fragment_size..];
+                    packet[0] |= AGGREGATION_HEADER_Y;
+                }
+
+                packets.push(std::mem::replace(
+                    &mut packet,
+                    vec![if continues { AGGREGATION_HEADER_Z } else { 0 }],
+                ));
+            }
+        }
+
+        if packet.len() > 1 {
+            packets.push(packet);
+        }
+
+        Ok(packets)
+    }
+}
+
+/// Reassembles RTP payloads into temporal units in low-overhead format.
+#[derive(Default)]
+pub struct Depayloader {
+    assembler: AccessUnitAssembler,
+    /// The OBU element being reassembled from fragments, if any.
+    fragment: Option<Vec<u8>>,
+}
+
+impl Depayloader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+/// Appends the OBU element `element` to `out` in low-overhead format.
+fn write_element(element: &[u8], out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let (obu, len) = RawObu::parse(element)?;
+    if len != element.len() {
+        return Err(anyhow!("OBU element with trailing data"));
+    }
+
+    obu.write_sized(out)
+}
+
+impl RtpDepayloader for Depayloader {
+    fn depayload(&mut self, packet: &RtpPacket) -> anyhow::Result<Option<Vec<u8>>> {
+        let payload = packet.payload;
+        let header = *payload
+            .first()
+            .ok_or_else(|| anyhow!("empty RTP payload"))?;
+        let continues_fragment = header & AGGREGATION_HEADER_Z != 0;
+        let ends_with_fragment = header & AGGREGATION_HEADER_Y != 0;
+        let num_elements = (header >> 4) & 0x3;
+
+        if !continues_fragment {
+            self.fragment = None;
+        }
+
+        let fragment = &mut self.fragment;
+        self.assembler
+            .process(packet, !continues_fragment, |out| {
+                if out.is_empty() {
+                    out.extend_from_slice(&TEMPORAL_DELIMITER);
+                }
+
+                let mut data = &payload[1..];
+                let mut index = 0;
+
+                while !data.is_empty() {
+                    index += 1;
+
+                    // With W != 0, the last element has no length field.
+                    let element_len = if num_elements != 0 && index == num_elements {
+                        data.len()
+                    } else {
+                        let (len, leb128_len) = read_leb128(data)?;
+                        data = &data[leb128_len..];
+                        len as usize
+                    };
+
+                    if element_len > data.len() {
+                        return Err(anyhow!("OBU element exceeds the RTP payload"));
+                    }
+
+                    let (element, rest) = data.split_at(element_len);
+                    data = rest;
+
+                    let mut element = element.to_vec();
+                    if index == 1 && continues_fragment {
+                        let mut first = fragment
+                            .take()
+                            .ok_or_else(|| anyhow!("OBU fragment continuation without a start"))?;
+                        first.extend_from_slice(&element);
+                        element = first;
+                    }
+
+                    if data.is_empty() && ends_with_fragment {
+                        *fragment = Some(element);
+                    } else {
+                        write_element(&element, out)?;
+                    }
+                }
+
+                Ok(())
+            })
+            .inspect_err(|_| self.fragment = None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::av1::rtp::leb128_size;
+    use crate::codec::av1::rtp::Depayloader;
+    use crate::codec::av1::rtp::Payloader;
+    use crate::codec::av1::rtp::AGGREGATION_HEADER_N;
+    use crate::codec::av1::rtp::AGGREGATION_HEADER_Y;
+    use crate::codec::av1::rtp::AGGREGATION_HEADER_Z;
+    use crate::codec::rtp::tests::roundtrip;
+    use crate::codec::rtp::RtpDepayloader;
+    use crate::codec::rtp::RtpPacket;
+    use crate::utils::IvfIterator;
+
+    const STREAM: &[u8] = include_bytes!("test_data/test-25fps.ivf.av1");
+
+    #[test]
+    fn rtp_roundtrip() {
+        let temporal_units = IvfIterator::new(STREAM)
+            .take(10)
+            .map(|f| f.to_vec())
+            .collect::<Vec<_>>();
+
+        for max_payload_size in [3, 100, 1200] {
+            let (packets, depayloaded) = roundtrip(
+                &mut Payloader::new(max_payload_size),
+                &mut Depayloader::new(),
+                &temporal_units,
+                &[],
+            );
+
+            assert!(packets.iter().all(|p| p.len() <= max_payload_size));
+            assert_eq!(depayloaded, temporal_units);
+        }
+
+        let (packets, _) = roundtrip(
+            &mut Payloader::new(100),
+            &mut Depayloader::new(),
+            &temporal_units,
+            &[],
+        );
+        // The first temporal unit starts a coded video sequence, and is
+        // fragmented.
+        assert_eq!(packets[0][0], AGGREGATION_HEADER_N | AGGREGATION_HEADER_Y);
+        assert_eq!(packets[1][0] & AGGREGATION_HEADER_Z, AGGREGATION_HEADER_Z);
+
+        // Losing a fragment drops the temporal unit.
+        let (_, depayloaded) = roundtrip(
+            &mut Payloader::new(100),
+            &mut Depayloader::new(),
+            &temporal_units,
+            &[1],
+        );
+        assert_eq!(depayloaded, temporal_units[1..]);
+    }
+
+    #[test]
+    fn rtp_obu_count() {
+        // W=2: a padding OBU with its length, then a frame header without.
+        let payload = [0x20, 0x02, 0x78, 0xaa, 0x18, 0xbb, 0xcc];
+        let packet = RtpPacket {
+            sequence_number: 0,
+            timestamp: 0,
+            marker: true,
+            payload: &payload,
+        };
+
+        assert_eq!(
+            Depayloader::new().depayload(&packet).unwrap(),
+            Some(vec![0x12, 0x00, 0x7a, 0x01, 0xaa, 0x1a, 0x02, 0xbb, 0xcc])
+        );
+    }
+
+    #[test]
+    fn leb128_sizes() {
+        assert_eq!(leb128_size(0), 1);
+        assert_eq!(leb128_size(127), 1);
+        assert_eq!(leb128_size(128), 2);
+        assert_eq!(leb128_size(16384), 3);
+    }
+}
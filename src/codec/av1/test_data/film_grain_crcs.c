@@ -0,0 +1,210 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+// Computes the CRCs expected by the tests of film_grain.rs by applying the same film grain to the
+// same frames with libaom's av1_add_film_grain. See gen_film_grain_crcs.sh.
+//
+// The film grain parameters and images are declared here, as libaom does not install the header
+// of the former. Both match libaom v3.6.
+
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+
+typedef struct {
+  int apply_grain;
+  int update_parameters;
+  int scaling_points_y[14][2];
+  int num_y_points;
+  int scaling_points_cb[10][2];
+  int num_cb_points;
+  int scaling_points_cr[10][2];
+  int num_cr_points;
+  int scaling_shift;
+  int ar_coeff_lag;
+  int ar_coeffs_y[24];
+  int ar_coeffs_cb[25];
+  int ar_coeffs_cr[25];
+  int ar_coeff_shift;
+  int cb_mult;
+  int cb_luma_mult;
+  int cb_offset;
+  int cr_mult;
+  int cr_luma_mult;
+  int cr_offset;
+  int overlap_flag;
+  int clip_to_restricted_range;
+  unsigned int bit_depth;
+  int chroma_scaling_from_luma;
+  int grain_scale_shift;
+  uint16_t random_seed;
+} aom_film_grain_t;
+
+typedef struct aom_image {
+  int fmt;
+  int cp;
+  int tc;
+  int mc;
+  int monochrome;
+  int csp;
+  int range;
+  unsigned int w;
+  unsigned int h;
+  unsigned int bit_depth;
+  unsigned int d_w;
+  unsigned int d_h;
+  unsigned int r_w;
+  unsigned int r_h;
+  unsigned int x_chroma_shift;
+  unsigned int y_chroma_shift;
+  unsigned char *planes[3];
+  int stride[3];
+  size_t sz;
+  int bps;
+  int temporal_id;
+  int spatial_id;
+  void *user_priv;
+  unsigned char *img_data;
+  int img_data_owner;
+  int self_allocd;
+  void *metadata;
+  void *fb_priv;
+} aom_image_t;
+
+#define AOM_IMG_FMT_PLANAR 0x100
+#define AOM_IMG_FMT_HIGHBITDEPTH 0x800
+#define AOM_IMG_FMT_I420 (AOM_IMG_FMT_PLANAR | 2)
+#define AOM_IMG_FMT_I444 (AOM_IMG_FMT_PLANAR | 6)
+#define AOM_CICP_MC_BT_709 1
+
+aom_image_t *aom_img_alloc(aom_image_t *img, int fmt, unsigned int d_w, unsigned int d_h,
+                           unsigned int align);
+void aom_img_free(aom_image_t *img);
+int av1_add_film_grain(const aom_film_grain_t *grain, const aom_image_t *src, aom_image_t *dst);
+
+// Same parameters as test_film_grain() in film_grain.rs.
+static aom_film_grain_t test_film_grain(int config, int bit_depth) {
+  aom_film_grain_t p = {0};
+  p.apply_grain = 1;
+  p.random_seed = config == 1 ? 1234 : 45231;
+  p.num_y_points = 3;
+  p.scaling_shift = 3 + 8;
+  p.ar_coeff_lag = config == 1 ? 2 : 3;
+  p.ar_coeff_shift = 1 + 6;
+  p.grain_scale_shift = config == 2;
+  p.cb_mult = 128 + 20;
+  p.cb_luma_mult = 192;
+  p.cb_offset = 256 + 10;
+  p.cr_mult = 128 - 10;
+  p.cr_luma_mult = 128 + 40;
+  p.cr_offset = 256 - 5;
+  p.overlap_flag = config != 1;
+  p.clip_to_restricted_range = config == 3;
+  p.bit_depth = bit_depth;
+
+  const int y_values[3] = {0, 128, 255};
+  const int y_scalings[3] = {20, 80, 40};
+  for (int i = 0; i < 3; i++) {
+    p.scaling_points_y[i][0] = y_values[i];
+    p.scaling_points_y[i][1] = y_scalings[i];
+  }
+  for (int i = 0; i < 24; i++) p.ar_coeffs_y[i] = (i * 37) % 41 - 20;
+  for (int i = 0; i < 25; i++) {
+    p.ar_coeffs_cb[i] = (i * 17) % 31 - 15;
+    p.ar_coeffs_cr[i] = (i * 11) % 29 - 14;
+  }
+  if (config == 1) {
+    p.chroma_scaling_from_luma = 1;
+  } else if (config == 4) {
+    // Monochrome streams have no chroma scaling points.
+  } else {
+    p.num_cb_points = 2;
+    p.scaling_points_cb[0][0] = 20;
+    p.scaling_points_cb[0][1] = 60;
+    p.scaling_points_cb[1][0] = 200;
+    p.scaling_points_cb[1][1] = 30;
+    p.num_cr_points = 1;
+    p.scaling_points_cr[0][0] = 100;
+    p.scaling_points_cr[0][1] = 50;
+  }
+
+  return p;
+}
+
+static uint32_t crc32(uint32_t crc, const uint8_t *data, size_t len) {
+  crc = ~crc;
+  for (size_t i = 0; i < len; i++) {
+    crc ^= data[i];
+    for (int k = 0; k < 8; k++) crc = (crc >> 1) ^ (0xedb88320 & -(crc & 1));
+  }
+  return ~crc;
+}
+
+static int pattern_8bit(int x, int y, int plane) { return (x * 3 + y * 5 + plane * 40) & 255; }
+
+static int pattern_10bit(int x, int y, int plane) {
+  return (x * 29 + y * 37 + plane * 300) & 1023;
+}
+
+// Applies the film grain of `config` to a frame filled with a gradient, and prints the CRC of its
+// planes, stored without padding one after the other. Only the luma plane is hashed for
+// monochrome frames.
+static void print_crc(const char *name, int config, int bit_depth, int subsampling, int monochrome,
+                      unsigned int width, unsigned int height) {
+  int high = bit_depth > 8;
+  int fmt = (subsampling ? AOM_IMG_FMT_I420 : AOM_IMG_FMT_I444) |
+            (high ? AOM_IMG_FMT_HIGHBITDEPTH : 0);
+  int (*pattern)(int, int, int) = high ? pattern_10bit : pattern_8bit;
+  aom_film_grain_t grain = test_film_grain(config, bit_depth);
+  aom_image_t src, dst;
+
+  aom_img_alloc(&src, fmt, width, height, 32);
+  aom_img_alloc(&dst, fmt, width, height, 32);
+  src.mc = AOM_CICP_MC_BT_709;
+  src.monochrome = monochrome;
+  src.bit_depth = bit_depth;
+
+  int num_planes = monochrome ? 1 : 3;
+  for (int plane = 0; plane < num_planes; plane++) {
+    unsigned int w = plane ? (width + subsampling) >> subsampling : width;
+    unsigned int h = plane ? (height + subsampling) >> subsampling : height;
+    for (unsigned int y = 0; y < h; y++) {
+      for (unsigned int x = 0; x < w; x++) {
+        uint8_t *line = src.planes[plane] + y * src.stride[plane];
+        if (high)
+          ((uint16_t *)line)[x] = pattern(x, y, plane);
+        else
+          line[x] = pattern(x, y, plane);
+      }
+    }
+  }
+
+  if (av1_add_film_grain(&grain, &src, &dst)) {
+    fprintf(stderr, "%s: av1_add_film_grain failed\n", name);
+    return;
+  }
+
+  uint32_t crc = 0;
+  for (int plane = 0; plane < num_planes; plane++) {
+    unsigned int w = plane ? (width + subsampling) >> subsampling : width;
+    unsigned int h = plane ? (height + subsampling) >> subsampling : height;
+    for (unsigned int y = 0; y < h; y++)
+      crc = crc32(crc, dst.planes[plane] + y * dst.stride[plane], w << high);
+  }
+  printf("%s: 0x%08x\n", name, crc);
+
+  aom_img_free(&src);
+  aom_img_free(&dst);
+}
+
+int main(void) {
+  print_crc("8-bit 4:2:0, config 0, 50x40", 0, 8, 1, 0, 50, 40);
+  print_crc("8-bit 4:2:0, config 1, 64x66", 1, 8, 1, 0, 64, 66);
+  print_crc("10-bit 4:2:0, config 2, 50x40", 2, 10, 1, 0, 50, 40);
+  print_crc("10-bit 4:2:0, config 3, 50x40", 3, 10, 1, 0, 50, 40);
+  print_crc("8-bit 4:4:4, config 0, 50x40", 0, 8, 0, 0, 50, 40);
+  print_crc("10-bit 4:4:4, config 1, 40x36", 1, 10, 0, 0, 40, 36);
+  print_crc("8-bit monochrome, config 4, 50x40", 4, 8, 1, 1, 50, 40);
+  return 0;
+}
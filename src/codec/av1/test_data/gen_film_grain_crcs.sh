@@ -0,0 +1,11 @@
+#!/bin/bash
+
+# Prints the CRCs expected by the film grain tests of film_grain.rs, computed using libaom's
+# av1_add_film_grain. Requires libaom.so.3 (tested with v3.6.0).
+
+set -e
+
+out=$(mktemp)
+gcc -o $out film_grain_crcs.c -l:libaom.so.3
+$out
+rm $out
@@ -2,10 +2,13 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+pub mod access_unit;
+pub mod avcc;
 pub mod dpb;
 pub mod nalu;
 pub mod nalu_reader;
 pub mod nalu_writer;
 pub mod parser;
 pub mod picture;
+pub mod rtp;
 pub mod synthesizer;
@@ -0,0 +1,197 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Access unit boundary detection for H.264 Annex B streams.
+
+use std::io::Cursor;
+
+use log::debug;
+
+use crate::codec::access_unit::AccessUnitBoundary;
+use crate::codec::access_unit::AccessUnitSplitter;
+use crate::codec::access_unit::NaluKind;
+use crate::codec::h264::parser::Nalu;
+use crate::codec::h264::parser::NaluType;
+use crate::codec::h264::parser::Parser;
+use crate::codec::h264::parser::Slice;
+
+/// Splits H.264 Annex B streams into access units.
+pub type Splitter = AccessUnitSplitter<AccessUnitDetector>;
+
+/// The slice header values that identify a primary coded picture, as listed
+/// in 7.4.1.2.4. Values that are not present in a slice header are zero, so
+/// they can be compared regardless of the active SPS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PictureId {
+    frame_num: u16,
+    pic_parameter_set_id: u8,
+    field_pic_flag: bool,
+    bottom_field_flag: bool,
+    is_reference: bool,
+    pic_order_cnt_lsb: u16,
+    delta_pic_order_cnt_bottom: i32,
+    delta_pic_order_cnt: [i32; 2],
+    idr_pic_flag: bool,
+    idr_pic_id: u16,
+}
+
+impl PictureId {
+    fn new(slice: &Slice) -> Self {
+        let hdr = &slice.header;
+
+        Self {
+            frame_num: hdr.frame_num,
+            pic_parameter_set_id: hdr.pic_parameter_set_id,
+            field_pic_flag: hdr.field_pic_flag,
+            bottom_field_flag: hdr.bottom_field_flag,
+            is_reference: slice.nalu.header.ref_idc != 0,
+            pic_order_cnt_lsb: hdr.pic_order_cnt_lsb,
+            delta_pic_order_cnt_bottom: hdr.delta_pic_order_cnt_bottom,
+            delta_pic_order_cnt: hdr.delta_pic_order_cnt,
+            idr_pic_flag: slice.nalu.header.idr_pic_flag,
+            idr_pic_id: hdr.idr_pic_id,
+        }
+    }
+}
+
+/// Detects the access unit boundaries of H.264 streams as per 7.4.1.2.3.
+///
+/// Parameter sets are parsed as they go by, so the first slice of each
+/// primary coded picture can be detected from its slice header. Slices whose
+/// parameter sets are unknown are assumed to start a picture if their
+/// `first_mb_in_slice` is 0.
+#[derive(Default)]
+pub struct AccessUnitDetector {
+    parser: Parser,
+    /// Identifies the picture of the last slice of the base view.
+    last_picture: Option<PictureId>,
+}
+
+impl AccessUnitDetector {
+    fn slice_kind(&mut self, nalu: &[u8]) -> NaluKind {
+        let mut data = vec![0x00, 0x00, 0x01];
+        data.extend_from_slice(nalu);
+
+        let slice = Nalu::next(&mut Cursor::new(&data))
+            .and_then(|nalu| self.parser.parse_slice_header(nalu));
+
+        match slice {
+            Ok(slice) => {
+                // Redundant coded pictures belong to the access unit of their
+                // primary coded picture.
+                if slice.header.redundant_pic_cnt > 0 {
+                    return NaluKind::Vcl;
+                }
+
+                let picture = Some(PictureId::new(&slice));
+                if std::mem::replace(&mut self.last_picture, picture) != picture {
+                    NaluKind::FirstVcl
+                } else {
+                    NaluKind::Vcl
+                }
+            }
+            Err(e) => {
+                debug!(
+                    "Cannot parse slice header, checking first_mb_in_slice: {:#}",
+                    e
+                );
+                self.last_picture = None;
+
+                // first_mb_in_slice is an ue(v), which is 0 if its first bit
+                // is set.
+                match nalu.get(1) {
+                    Some(byte) if byte & 0x80 != 0 => NaluKind::FirstVcl,
+                    _ => NaluKind::Vcl,
+                }
+            }
+        }
+    }
+
+    fn parse_parameter_set(&mut self, nalu: &[u8], type_: NaluType) {
+        let mut data = vec![0x00, 0x00, 0x01];
+        data.extend_from_slice(nalu);
+
+        let res = Nalu::next(&mut Cursor::new(&data)).and_then(|nalu| match type_ {
+            NaluType::Sps => self.parser.parse_sps(&nalu).map(|_| ()),
+            NaluType::Pps => self.parser.parse_pps(&nalu).map(|_| ()),
+            NaluType::SubsetSps => self.parser.parse_subset_sps(&nalu).map(|_| ()),
+            _ => Ok(()),
+        });
+
+        if let Err(e) = res {
+            debug!("Cannot parse {:?}: {:#}", type_, e);
+        }
+    }
+}
+
+impl AccessUnitBoundary for AccessUnitDetector {
+    fn nalu_kind(&mut self, nalu: &[u8]) -> NaluKind {
+        match nalu[0] & 0x1f {
+            // Coded slices of the base view and slice data partition A.
+            1 | 2 | 5 => self.slice_kind(nalu),
+            // Other slice data partitions, auxiliary and extension slices.
+            3 | 4 | 19..=21 => NaluKind::Vcl,
+            7 => {
+                self.parse_parameter_set(nalu, NaluType::Sps);
+                NaluKind::Prefix
+            }
+            8 => {
+                self.parse_parameter_set(nalu, NaluType::Pps);
+                NaluKind::Prefix
+            }
+            15 => {
+                self.parse_parameter_set(nalu, NaluType::SubsetSps);
+                NaluKind::Prefix
+            }
+            // SEI, access unit delimiter, prefix NAL unit and reserved types.
+            6 | 9 | 14 | 16..=18 => NaluKind::Prefix,
+            _ => NaluKind::Suffix,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::access_unit::Splitter;
+
+    const STREAM: &[u8] = include_bytes!("test_data/test-25fps.h264");
+    const STREAM_CRCS: &str = include_str!("test_data/test-25fps.h264.crc");
+
+    #[test]
+    fn split_access_units() {
+        let mut splitter = Splitter::new();
+        let mut aus = splitter.push(STREAM, 0);
+        aus.extend(splitter.flush());
+
+        assert_eq!(aus.len(), STREAM_CRCS.lines().count());
+        let data: Vec<u8> = aus.iter().flat_map(|au| au.data.clone()).collect();
+        assert_eq!(data, STREAM);
+    }
+
+    #[test]
+    fn split_chunked_access_units() {
+        let mut splitter = Splitter::new();
+        let expected = {
+            let mut aus = splitter.push(STREAM, 0);
+            aus.extend(splitter.flush());
+            aus
+        };
+
+        // Deliver the stream in chunks that do not match NAL units, each with
+        // the index of the chunk as timestamp.
+        let mut aus = Vec::new();
+        for (i, chunk) in STREAM.chunks(1000).enumerate() {
+            aus.extend(splitter.push(chunk, i as u64));
+        }
+        aus.extend(splitter.flush());
+
+        assert_eq!(aus.len(), expected.len());
+        let mut offset = 0;
+        for (au, expected) in aus.iter().zip(expected.iter()) {
+            assert_eq!(au.data, expected.data);
+            assert_eq!(au.timestamp, offset / 1000);
+            offset += au.data.len() as u64;
+        }
+    }
+}
@@ -0,0 +1,398 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Support for the AVCDecoderConfigurationRecord (`avcC`) defined in ISO/IEC
+//! 14496-15, which carries the parameter sets of length-prefixed H.264
+//! streams in the ISO BMFF (MP4) and Matroska containers.
+
+use std::io::Cursor;
+
+use anyhow::anyhow;
+use bytes::Buf;
+use bytes::BufMut;
+
+use crate::codec::h264::nalu::annexb_nalus;
+use crate::codec::h264::parser::Nalu;
+use crate::codec::h264::parser::NaluType;
+use crate::codec::h264::parser::Parser;
+
+/// The AVCDecoderConfigurationRecord as per ISO/IEC 14496-15, 5.3.3.1.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AvcDecoderConfigurationRecord {
+    /// Always 1 for this version of the specification.
+    pub configuration_version: u8,
+    /// Same as `profile_idc` in the SPS.
+    pub profile_indication: u8,
+    /// The byte between `profile_idc` and `level_idc` in the SPS, i.e. the
+    /// constraint set flags.
+    pub profile_compatibility: u8,
+    /// Same as `level_idc` in the SPS.
+    pub level_indication: u8,
+    /// The size in bytes of the NALU length field, minus one.
+    pub length_size_minus_one: u8,
+    /// The SPS NAL units, without start codes.
+    pub sps: Vec<Vec<u8>>,
+    /// The PPS NAL units, without start codes.
+    pub pps: Vec<Vec<u8>>,
+    /// The fields only present for the High profiles, if any.
+    pub high_profile_ext: Option<AvcHighProfileExt>,
+}
+
+/// The trailing part of the AVCDecoderConfigurationRecord, only present for
+/// the High, High 10, High 4:2:2 and High 4:4:4 profiles.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AvcHighProfileExt {
+    /// Same as `chroma_format_idc` in the SPS.
+    pub chroma_format: u8,
+    /// Same as `bit_depth_luma_minus8` in the SPS.
+    pub bit_depth_luma_minus8: u8,
+    /// Same as `bit_depth_chroma_minus8` in the SPS.
+    pub bit_depth_chroma_minus8: u8,
+    /// The SPS extension NAL units, without start codes.
+    pub sps_ext: Vec<Vec<u8>>,
+}
+
+/// Whether the trailing High profile fields are expected for `profile_idc`, i.e. for every
+/// profile but Baseline, Main and Extended.
+fn has_high_profile_ext(profile_idc: u8) -> bool {
+    !matches!(profile_idc, 66 | 77 | 88)
+}
+
+fn read_nalus(cursor: &mut Cursor<&[u8]>, count: usize) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut nalus = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        if cursor.remaining() < 2 {
+            return Err(anyhow!("Truncated parameter set length"));
+        }
+
+        let len = usize::from(cursor.get_u16());
+        if cursor.remaining() < len {
+            return Err(anyhow!("Truncated parameter set"));
+        }
+
+        let mut nalu = vec![0; len];
+        cursor.copy_to_slice(&mut nalu);
+        nalus.push(nalu);
+    }
+
+    Ok(nalus)
+}
+
+fn write_nalus(out: &mut Vec<u8>, nalus: &[Vec<u8>]) -> anyhow::Result<()> {
+    for nalu in nalus {
+        out.put_u16(u16::try_from(nalu.len())?);
+        out.extend_from_slice(nalu);
+    }
+
+    Ok(())
+}
+
+impl AvcDecoderConfigurationRecord {
+    /// Parses an AVCDecoderConfigurationRecord, e.g. the payload of an `avcC`
+    /// box or the Matroska `CodecPrivate` element.
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut cursor = Cursor::new(data);
+
+        if cursor.remaining() < 6 {
+            return Err(anyhow!("AVCDecoderConfigurationRecord is too short"));
+        }
+
+        let configuration_version = cursor.get_u8();
+        if configuration_version != 1 {
+            return Err(anyhow!(
+                "Unsupported configurationVersion {}",
+                configuration_version
+            ));
+        }
+
+        let profile_indication = cursor.get_u8();
+        let profile_compatibility = cursor.get_u8();
+        let level_indication = cursor.get_u8();
+        let length_size_minus_one = cursor.get_u8() & 0x3;
+        if length_size_minus_one == 2 {
+            return Err(anyhow!(
+                "Invalid lengthSizeMinusOne {}",
+                length_size_minus_one
+            ));
+        }
+
+        let num_sps = usize::from(cursor.get_u8() & 0x1f);
+        let sps = read_nalus(&mut cursor, num_sps)?;
+
+        if !cursor.has_remaining() {
+            return Err(anyhow!("Missing numOfPictureParameterSets"));
+        }
+        let num_pps = usize::from(cursor.get_u8());
+        let pps = read_nalus(&mut cursor, num_pps)?;
+
+        // Many muxers omit the trailing fields even for the High profiles, so
+        // only parse them if they are actually there.
+        let high_profile_ext =
+            if has_high_profile_ext(profile_indication) && cursor.remaining() >= 4 {
+                let chroma_format = cursor.get_u8() & 0x3;
+                let bit_depth_luma_minus8 = cursor.get_u8() & 0x7;
+                let bit_depth_chroma_minus8 = cursor.get_u8() & 0x7;
+                let num_sps_ext = usize::from(cursor.get_u8());
+                let sps_ext = read_nalus(&mut cursor, num_sps_ext)?;
+
+                Some(AvcHighProfileExt {
+                    chroma_format,
+                    bit_depth_luma_minus8,
+                    bit_depth_chroma_minus8,
+                    sps_ext,
+                })
+            } else {
+                None
+            };
+
+        Ok(Self {
+            configuration_version,
+            profile_indication,
+            profile_compatibility,
+            level_indication,
+            length_size_minus_one,
+            sps,
+            pps,
+            high_profile_ext,
+        })
+    }
+
+    /// Builds a record from the parameter sets found in the Annex B encoded
+    /// `data`, e.g. the first keyframe produced by an encoder. The NALU length
+    /// field of the described stream will be `length_size` bytes long.
+    pub fn from_annexb(data: &[u8], length_size: usize) -> anyhow::Result<Self> {
+        if !matches!(length_size, 1 | 2 | 4) {
+            return Err(anyhow!("Invalid NALU length size {}", length_size));
+        }
+
+        let mut sps = vec![];
+        let mut pps = vec![];
+        let mut sps_ext = vec![];
+
+        for nalu in annexb_nalus(data) {
+            let list = match NaluType::n(nalu[0] & 0x1f) {
+                Some(NaluType::Sps) => &mut sps,
+                Some(NaluType::Pps) => &mut pps,
+                Some(NaluType::SpsExt) => &mut sps_ext,
+                _ => continue,
+            };
+
+            // Parameter sets are usually repeated before each IDR picture.
+            if !list.iter().any(|n| n == nalu) {
+                list.push(nalu.to_vec());
+            }
+        }
+
+        let first_sps = sps
+            .first()
+            .ok_or_else(|| anyhow!("No SPS found in the stream"))?;
+        if first_sps.len() < 4 {
+            return Err(anyhow!("SPS is too short"));
+        }
+        if pps.is_empty() {
+            return Err(anyhow!("No PPS found in the stream"));
+        }
+
+        let profile_indication = first_sps[1];
+        let high_profile_ext = if has_high_profile_ext(profile_indication) {
+            let mut annexb = vec![0x00, 0x00, 0x01];
+            annexb.extend_from_slice(first_sps);
+            let mut cursor = Cursor::new(annexb.as_ref());
+            let nalu = Nalu::next(&mut cursor)?;
+            let mut parser = Parser::default();
+            let parsed = parser.parse_sps(&nalu)?;
+
+            Some(AvcHighProfileExt {
+                chroma_format: parsed.chroma_format_idc,
+                bit_depth_luma_minus8: parsed.bit_depth_luma_minus8,
+                bit_depth_chroma_minus8: parsed.bit_depth_chroma_minus8,
+                sps_ext,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            configuration_version: 1,
+            profile_indication,
+            profile_compatibility: first_sps[2],
+            level_indication: first_sps[3],
+            length_size_minus_one: (length_size - 1) as u8,
+            sps,
+            pps,
+            high_profile_ext,
+        })
+    }
+
+    /// Serializes the record, e.g. to use as the payload of an `avcC` box.
+    pub fn write(&self) -> anyhow::Result<Vec<u8>> {
+        let mut out = vec![
+            self.configuration_version,
+            self.profile_indication,
+            self.profile_compatibility,
+            self.level_indication,
+            0xfc | (self.length_size_minus_one & 0x3),
+        ];
+
+        if self.sps.len() > 0x1f {
+            return Err(anyhow!("Too many SPSs: {}", self.sps.len()));
+        }
+        out.put_u8(0xe0 | self.sps.len() as u8);
+        write_nalus(&mut out, &self.sps)?;
+
+        out.put_u8(u8::try_from(self.pps.len())?);
+        write_nalus(&mut out, &self.pps)?;
+
+        if let Some(ext) = &self.high_profile_ext {
+            out.put_u8(0xfc | (ext.chroma_format & 0x3));
+            out.put_u8(0xf8 | (ext.bit_depth_luma_minus8 & 0x7));
+            out.put_u8(0xf8 | (ext.bit_depth_chroma_minus8 & 0x7));
+            out.put_u8(u8::try_from(ext.sps_ext.len())?);
+            write_nalus(&mut out, &ext.sps_ext)?;
+        }
+
+        Ok(out)
+    }
+
+    /// The size in bytes of the NALU length field of the described stream.
+    pub fn length_size(&self) -> usize {
+        usize::from(self.length_size_minus_one) + 1
+    }
+
+    /// Returns the parameter sets of the record as an Annex B stream, suitable
+    /// for priming a decoder before sending it the converted samples.
+    pub fn to_annexb(&self) -> Vec<u8> {
+        let sps_ext = self
+            .high_profile_ext
+            .iter()
+            .flat_map(|ext| ext.sps_ext.iter());
+
+        let mut out = vec![];
+        for nalu in self.sps.iter().chain(sps_ext).chain(self.pps.iter()) {
+            out.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+            out.extend_from_slice(nalu);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::avcc::AvcDecoderConfigurationRecord;
+    use crate::codec::h264::nalu::annexb_nalus;
+    use crate::codec::h264::nalu::annexb_to_length_prefixed;
+    use crate::codec::h264::nalu::length_prefixed_to_annexb;
+    use crate::codec::h264::parser::Level;
+    use crate::codec::h264::parser::Sps;
+    use crate::codec::h264::synthesizer::Synthesizer;
+
+    const STREAM_TEST_25_FPS: &[u8] = include_bytes!("test_data/test-25fps.h264");
+    const STREAM_HIGH: &[u8] = include_bytes!("test_data/64x64-I-P-B-P-high.h264");
+
+    #[test]
+    fn avcc_from_annexb_roundtrip() {
+        let record = AvcDecoderConfigurationRecord::from_annexb(STREAM_TEST_25_FPS, 4).unwrap();
+        assert_eq!(record.configuration_version, 1);
+        assert_eq!(record.sps.len(), 1);
+        assert_eq!(record.pps.len(), 1);
+        assert_eq!(record.profile_indication, record.sps[0][1]);
+        assert_eq!(record.length_size(), 4);
+
+        let bytes = record.write().unwrap();
+        assert_eq!(
+            AvcDecoderConfigurationRecord::parse(&bytes).unwrap(),
+            record
+        );
+
+        // The parameter sets must come back as they were in the stream.
+        let nalus = annexb_nalus(STREAM_TEST_25_FPS)
+            .into_iter()
+            .filter(|n| matches!(n[0] & 0x1f, 7 | 8))
+            .take(2)
+            .collect::<Vec<_>>();
+        let annexb = record.to_annexb();
+        assert_eq!(annexb_nalus(&annexb), nalus);
+    }
+
+    #[test]
+    fn avcc_high_profile() {
+        let record = AvcDecoderConfigurationRecord::from_annexb(STREAM_HIGH, 4).unwrap();
+        assert_eq!(record.profile_indication, 100);
+
+        let ext = record.high_profile_ext.as_ref().unwrap();
+        assert_eq!(ext.chroma_format, 1);
+        assert_eq!(ext.bit_depth_luma_minus8, 0);
+        assert_eq!(ext.bit_depth_chroma_minus8, 0);
+
+        let bytes = record.write().unwrap();
+        assert_eq!(
+            AvcDecoderConfigurationRecord::parse(&bytes).unwrap(),
+            record
+        );
+
+        // Records without the trailing fields must be accepted as well.
+        let mut record = record;
+        record.high_profile_ext = None;
+        let bytes = record.write().unwrap();
+        assert_eq!(
+            AvcDecoderConfigurationRecord::parse(&bytes).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn avcc_high_444_profile() {
+        // 10-bit 4:4:4 SPS for a 64x64 stream.
+        let sps = Sps {
+            profile_idc: 244,
+            level_idc: Level::L3,
+            chroma_format_idc: 3,
+            bit_depth_luma_minus8: 2,
+            bit_depth_chroma_minus8: 2,
+            pic_width_in_mbs_minus1: 3,
+            pic_height_in_map_units_minus1: 3,
+            frame_mbs_only_flag: true,
+            ..Default::default()
+        };
+        let pps_nalu = annexb_nalus(STREAM_HIGH)
+            .into_iter()
+            .find(|n| n[0] & 0x1f == 8)
+            .unwrap();
+
+        let mut stream = vec![];
+        Synthesizer::<'_, Sps, _>::synthesize(0, &sps, &mut stream, false).unwrap();
+        stream.extend_from_slice(&[0, 0, 1]);
+        stream.extend_from_slice(pps_nalu);
+
+        let record = AvcDecoderConfigurationRecord::from_annexb(&stream, 4).unwrap();
+        assert_eq!(record.profile_indication, 244);
+        let ext = record.high_profile_ext.as_ref().unwrap();
+        assert_eq!(ext.chroma_format, 3);
+        assert_eq!(ext.bit_depth_luma_minus8, 2);
+        assert_eq!(ext.bit_depth_chroma_minus8, 2);
+
+        let bytes = record.write().unwrap();
+        assert_eq!(
+            AvcDecoderConfigurationRecord::parse(&bytes).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn avcc_invalid() {
+        assert!(AvcDecoderConfigurationRecord::parse(&[]).is_err());
+        assert!(AvcDecoderConfigurationRecord::parse(&[0, 66, 0, 30, 0xff, 0xe0, 0]).is_err());
+        // One SPS announced, but not present.
+        assert!(AvcDecoderConfigurationRecord::parse(&[1, 66, 0, 30, 0xff, 0xe1]).is_err());
+    }
+
+    #[test]
+    fn stream_roundtrip() {
+        let length_prefixed = annexb_to_length_prefixed(STREAM_TEST_25_FPS, 4).unwrap();
+        let annexb = length_prefixed_to_annexb(&length_prefixed, 4).unwrap();
+        assert_eq!(annexb_nalus(&annexb), annexb_nalus(STREAM_TEST_25_FPS));
+    }
+}
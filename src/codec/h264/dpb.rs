@@ -43,8 +43,9 @@ pub struct ReferencePicLists {
 //
 // The first member of the tuple is the `PictureData` for the frame.
 //
-// The second member is the backend handle of the frame. It can be `None` if the inserted picture
-// is non-existing (i.e. `nonexisting` is true on the `PictureData`).
+// The second member is the backend handle of the frame. It is `None` if the inserted picture is
+// non-existing (i.e. `nonexisting` is true on the `PictureData`). Pictures concealing lost frames
+// (i.e. `concealed` is true on the `PictureData`) use the handle of the frame replacing them.
 #[derive(Clone)]
 pub struct DpbEntry<T> {
     pub pic: RcPictureData,
@@ -54,6 +55,17 @@ pub struct DpbEntry<T> {
 }
 
 impl<T> DpbEntry<T> {
+    /// Creates an entry for an inter-view reference, i.e. a picture of another view of the same
+    /// access unit in a MVC stream. Such entries are never stored in the DPB, and thus never
+    /// output through it.
+    pub fn new_inter_view_ref(pic: RcPictureData, handle: T) -> Self {
+        Self {
+            pic,
+            handle: Some(handle),
+            needed_for_output: false,
+        }
+    }
+
     /// Returns `true` is the entry is eligible to be bumped.
     ///
     /// An entry can be bumped if its `needed_for_output` flag is true and it is the first field of
@@ -151,6 +163,12 @@ impl<T: Clone> Dpb<T> {
         self.max_num_pics
     }
 
+    /// Returns the maximum number of frames that can precede another one in
+    /// decoding order and follow it in output order.
+    pub fn max_num_reorder_frames(&self) -> usize {
+        self.max_num_reorder_frames
+    }
+
     // Returns the number of reference frames, counting the first field only if
     // dealing with interlaced content.
     pub fn num_ref_frames(&self) -> usize {
@@ -276,7 +294,7 @@ impl<T: Clone> Dpb<T> {
 
         // C.4.2. Decoding of gaps in frame_num and storage of "non-existing"
         // pictures
-        let needed_for_output = !pic.nonexisting;
+        let needed_for_output = !pic.nonexisting && !pic.concealed;
 
         debug!(
             "Stored picture POC {:?}, field {:?}, the DPB length is {:?}",
@@ -369,7 +387,7 @@ impl<T: Clone> Dpb<T> {
             return false;
         }
 
-        if to_insert.nonexisting {
+        if to_insert.nonexisting || to_insert.concealed {
             return true;
         }
 
@@ -521,6 +539,36 @@ impl<T: Clone> Dpb<T> {
         pics
     }
 
+    /// Bumps the DPB until no more than `max_num_reorder_frames` frames are waiting for output,
+    /// regardless of whether the DPB is full. This outputs frames as early as the reordering
+    /// constraints of the stream allow.
+    pub fn bump_to_reorder_limit(&mut self) -> Vec<Option<T>> {
+        let mut pics = vec![];
+        while self
+            .entries
+            .iter()
+            .filter(|entry| entry.is_bumpable())
+            .count()
+            > self.max_num_reorder_frames
+        {
+            match self.bump() {
+                Some(pic) => pics.push(pic),
+                None => return pics,
+            }
+            self.remove_unused();
+        }
+
+        pics
+    }
+
+    /// Marks all the pictures in the DPB as not needed for output, e.g. because they have already
+    /// been output in decoding order.
+    pub fn mark_all_as_output(&mut self) {
+        for entry in &mut self.entries {
+            entry.needed_for_output = false;
+        }
+    }
+
     // 8.2.5.3
     pub fn sliding_window_marking(
         &mut self,
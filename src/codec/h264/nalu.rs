@@ -8,6 +8,7 @@ use std::io::Cursor;
 
 use anyhow::anyhow;
 use bytes::Buf;
+use bytes::BufMut;
 
 #[allow(clippy::len_without_is_empty)]
 pub trait Header: Sized {
@@ -114,3 +115,149 @@ impl<'a, U> AsRef<[u8]> for Nalu<'a, U> {
         &self.data[self.offset..self.offset + self.size]
     }
 }
+
+/// Returns the NAL units contained in the Annex B encoded `data`, without their
+/// start codes and trailing zero bytes.
+pub fn annexb_nalus(data: &[u8]) -> Vec<&[u8]> {
+    let mut start_codes = data
+        .windows(3)
+        .enumerate()
+        .filter_map(|(i, window)| (window == [0x00, 0x00, 0x01]).then_some(i))
+        .peekable();
+
+    let mut nalus = Vec::new();
+    while let Some(start_code) = start_codes.next() {
+        let start = start_code + 3;
+        let mut end = start_codes.peek().copied().unwrap_or(data.len());
+
+        // Discard trailing_zero_8bits and the zero_byte of a 4-byte start code.
+        while end > start && data[end - 1] == 0x00 {
+            end -= 1;
+        }
+
+        if end > start {
+            nalus.push(&data[start..end]);
+        }
+    }
+
+    nalus
+}
+
+/// Returns the NAL units contained in `data`, where each NAL unit is preceded
+/// by its size as a big-endian integer of `length_size` bytes. This is the
+/// format used by the ISO BMFF (MP4) and Matroska containers.
+pub fn length_prefixed_nalus(data: &[u8], length_size: usize) -> anyhow::Result<Vec<&[u8]>> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err(anyhow!("Invalid NALU length size {}", length_size));
+    }
+
+    let mut cursor = Cursor::new(data);
+    let mut nalus = Vec::new();
+    while cursor.has_remaining() {
+        if cursor.remaining() < length_size {
+            return Err(anyhow!("Truncated NALU length"));
+        }
+
+        let len = cursor.get_uint(length_size) as usize;
+        if cursor.remaining() < len {
+            return Err(anyhow!(
+                "NALU length {} exceeds the {} remaining bytes",
+                len,
+                cursor.remaining()
+            ));
+        }
+
+        let start = usize::try_from(cursor.position())?;
+        nalus.push(&data[start..start + len]);
+        cursor.advance(len);
+    }
+
+    Ok(nalus)
+}
+
+/// Converts Annex B encoded `data` into length-prefixed NAL units, using
+/// `length_size` bytes for the size of each NAL unit.
+pub fn annexb_to_length_prefixed(data: &[u8], length_size: usize) -> anyhow::Result<Vec<u8>> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err(anyhow!("Invalid NALU length size {}", length_size));
+    }
+
+    let max_len = (1u64 << (8 * length_size)) - 1;
+    let mut out = Vec::with_capacity(data.len());
+    for nalu in annexb_nalus(data) {
+        if nalu.len() as u64 > max_len {
+            return Err(anyhow!(
+                "NALU of {} bytes does not fit in a {}-byte length field",
+                nalu.len(),
+                length_size
+            ));
+        }
+
+        out.put_uint(nalu.len() as u64, length_size);
+        out.extend_from_slice(nalu);
+    }
+
+    Ok(out)
+}
+
+/// Converts length-prefixed NAL units, where each size is stored in
+/// `length_size` bytes, into an Annex B stream using 4-byte start codes.
+pub fn length_prefixed_to_annexb(data: &[u8], length_size: usize) -> anyhow::Result<Vec<u8>> {
+    let nalus = length_prefixed_nalus(data, length_size)?;
+
+    let mut out = Vec::with_capacity(data.len() + nalus.len() * (4 - length_size.min(4)));
+    for nalu in nalus {
+        out.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+        out.extend_from_slice(nalu);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annexb_roundtrip() {
+        let annexb = [
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0x00, 0x00, 0x01, 0x68, 0xce, 0x00, 0x00, 0x00,
+            0x01, 0x65, 0x88, 0x84, 0x00,
+        ];
+
+        let nalus = annexb_nalus(&annexb);
+        assert_eq!(
+            nalus,
+            vec![
+                &[0x67, 0x42][..],
+                &[0x68, 0xce][..],
+                &[0x65, 0x88, 0x84][..]
+            ]
+        );
+
+        let length_prefixed = annexb_to_length_prefixed(&annexb, 4).unwrap();
+        assert_eq!(
+            length_prefixed,
+            [
+                0x00, 0x00, 0x00, 0x02, 0x67, 0x42, 0x00, 0x00, 0x00, 0x02, 0x68, 0xce, 0x00, 0x00,
+                0x00, 0x03, 0x65, 0x88, 0x84
+            ]
+        );
+        assert_eq!(length_prefixed_nalus(&length_prefixed, 4).unwrap(), nalus);
+
+        let annexb2 = length_prefixed_to_annexb(&length_prefixed, 4).unwrap();
+        assert_eq!(annexb_nalus(&annexb2), nalus);
+
+        let short = annexb_to_length_prefixed(&annexb, 2).unwrap();
+        assert_eq!(&short[..4], &[0x00, 0x02, 0x67, 0x42]);
+        assert_eq!(length_prefixed_to_annexb(&short, 2).unwrap(), annexb2);
+    }
+
+    #[test]
+    fn invalid_length_prefixed() {
+        assert!(length_prefixed_nalus(&[0x00, 0x00, 0x00, 0x05, 0x65], 4).is_err());
+        assert!(length_prefixed_nalus(&[0x00, 0x00], 4).is_err());
+        assert!(length_prefixed_nalus(&[0x01, 0x65], 3).is_err());
+        assert!(annexb_to_length_prefixed(&[0x00, 0x00, 0x01, 0x65], 0).is_err());
+    }
+}
@@ -487,15 +487,68 @@ impl Default for SliceType {
     }
 }
 
-#[derive(N, Clone, Copy)]
-#[repr(u8)]
+/// H.264 profiles. See Annex A of the specification.
+///
+/// Some profiles share the same `profile_idc` and are told apart by the constraint flags of the
+/// SPS, see [`Sps::profile`].
+///
+/// As a consequence, the variants no longer have their `profile_idc` as discriminant, and the
+/// enum does not provide `Profile::n` anymore: use [`Profile::from_profile_idc`] instead of
+/// `Profile::n(idc)`, and [`Profile::profile_idc`] instead of `profile as u8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Profile {
-    Baseline = 66,
-    Main = 77,
-    Extended = 88,
-    High = 100,
-    High10 = 110,
-    High422P = 122,
+    Baseline,
+    /// Baseline with `constraint_set1_flag` set.
+    ConstrainedBaseline,
+    Main,
+    Extended,
+    High,
+    /// High with `constraint_set4_flag` and `constraint_set5_flag` set.
+    ConstrainedHigh,
+    High10,
+    /// High 10 with `constraint_set3_flag` set.
+    High10Intra,
+    High422P,
+    /// High 4:2:2 with `constraint_set3_flag` set.
+    High422Intra,
+    High444P,
+    /// High 4:4:4 Predictive with `constraint_set3_flag` set.
+    High444Intra,
+    Cavlc444Intra,
+}
+
+impl Profile {
+    /// Returns the profile signaled by `profile_idc` when no constraint flag is set, or `None` if
+    /// `profile_idc` is not a known profile. This is what `Profile::n` used to return.
+    pub fn from_profile_idc(profile_idc: u8) -> Option<Self> {
+        let profile = match profile_idc {
+            66 => Profile::Baseline,
+            77 => Profile::Main,
+            88 => Profile::Extended,
+            100 => Profile::High,
+            110 => Profile::High10,
+            122 => Profile::High422P,
+            244 => Profile::High444P,
+            44 => Profile::Cavlc444Intra,
+            _ => return None,
+        };
+
+        Some(profile)
+    }
+
+    /// Returns the `profile_idc` signaling this profile.
+    pub fn profile_idc(&self) -> u8 {
+        match self {
+            Profile::Baseline | Profile::ConstrainedBaseline => 66,
+            Profile::Main => 77,
+            Profile::Extended => 88,
+            Profile::High | Profile::ConstrainedHigh => 100,
+            Profile::High10 | Profile::High10Intra => 110,
+            Profile::High422P | Profile::High422Intra => 122,
+            Profile::High444P | Profile::High444Intra => 244,
+            Profile::Cavlc444Intra => 44,
+        }
+    }
 }
 
 #[derive(N, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
@@ -722,6 +775,31 @@ impl Sps {
         1 << (self.log2_max_frame_num_minus4 + 4)
     }
 
+    /// Returns the profile the stream conforms to, as signaled by `profile_idc` and the
+    /// constraint flags, or `None` if it is not a profile of Annex A.
+    pub fn profile(&self) -> Option<Profile> {
+        let profile = match self.profile_idc {
+            66 if self.constraint_set1_flag => Profile::ConstrainedBaseline,
+            66 => Profile::Baseline,
+            77 => Profile::Main,
+            88 => Profile::Extended,
+            100 if self.constraint_set4_flag && self.constraint_set5_flag => {
+                Profile::ConstrainedHigh
+            }
+            100 => Profile::High,
+            110 if self.constraint_set3_flag => Profile::High10Intra,
+            110 => Profile::High10,
+            122 if self.constraint_set3_flag => Profile::High422Intra,
+            122 => Profile::High422P,
+            244 if self.constraint_set3_flag => Profile::High444Intra,
+            244 => Profile::High444P,
+            44 => Profile::Cavlc444Intra,
+            _ => return None,
+        };
+
+        Some(profile)
+    }
+
     pub fn visible_rectangle(&self) -> Rect<u32> {
         if !self.frame_cropping_flag {
             return Rect {
@@ -769,7 +847,8 @@ impl Sps {
         // A.3.1 and A.3.2: Level 1b for Baseline, Constrained Baseline and Main
         // profile if level_idc == 11 and constraint_set3_flag == 1
         if matches!(level, Level::L1_1)
-            && (profile == Profile::Baseline as u8 || profile == Profile::Main as u8)
+            && (profile == Profile::Baseline.profile_idc()
+                || profile == Profile::Main.profile_idc())
             && self.constraint_set3_flag
         {
             level = Level::L1B;
@@ -906,8 +985,24 @@ impl SpsBuilder {
         self
     }
 
+    /// Sets `profile_idc`, along with the constraint flags distinguishing `value` from the other
+    /// profiles using the same `profile_idc`.
     pub fn profile_idc(mut self, value: Profile) -> Self {
-        self.0.profile_idc = value as u8;
+        self.0.profile_idc = value.profile_idc();
+        match value {
+            Profile::ConstrainedBaseline => {
+                self.0.constraint_set0_flag = true;
+                self.0.constraint_set1_flag = true;
+            }
+            Profile::ConstrainedHigh => {
+                self.0.constraint_set4_flag = true;
+                self.0.constraint_set5_flag = true;
+            }
+            Profile::High10Intra | Profile::High422Intra | Profile::High444Intra => {
+                self.0.constraint_set3_flag = true;
+            }
+            _ => (),
+        }
         self
     }
 
@@ -1371,7 +1466,7 @@ impl Default for VuiParams {
 /// A H264 Picture Parameter Set. A syntax structure containing syntax elements
 /// that apply to zero or more entire coded pictures as determined by the
 /// `pic_parameter_set_id` syntax element found in each slice header.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Pps {
     /// Identifies the picture parameter set that is referred to in the slice header.
     pub pic_parameter_set_id: u8,
@@ -1563,10 +1658,111 @@ impl PpsBuilder {
     }
 }
 
+/// The `seq_parameter_set_svc_extension()` of a subset SPS. See G.7.3.2.1.4 in the
+/// specification.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpsSvcExtension {
+    pub inter_layer_deblocking_filter_control_present_flag: bool,
+    pub extended_spatial_scalability_idc: u8,
+    pub chroma_phase_x_plus1_flag: bool,
+    pub chroma_phase_y_plus1: u8,
+    pub seq_ref_layer_chroma_phase_x_plus1_flag: bool,
+    pub seq_ref_layer_chroma_phase_y_plus1: u8,
+    pub seq_scaled_ref_layer_left_offset: i32,
+    pub seq_scaled_ref_layer_top_offset: i32,
+    pub seq_scaled_ref_layer_right_offset: i32,
+    pub seq_scaled_ref_layer_bottom_offset: i32,
+    pub seq_tcoeff_level_prediction_flag: bool,
+    pub adaptive_tcoeff_level_prediction_flag: bool,
+    pub slice_header_restriction_flag: bool,
+}
+
+/// An operation point a MVC level applies to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MvcApplicableOp {
+    pub temporal_id: u8,
+    pub target_view_id: Vec<u16>,
+    pub num_views_minus1: u16,
+}
+
+/// A level signalled in the MVC extension of a subset SPS, along with the operation points it
+/// applies to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MvcLevelValue {
+    pub level_idc: u8,
+    pub applicable_ops: Vec<MvcApplicableOp>,
+}
+
+/// The `seq_parameter_set_mvc_extension()` of a subset SPS. See H.7.3.2.1.4 in the
+/// specification.
+///
+/// All the per-view vectors are indexed by view order index.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpsMvcExtension {
+    pub num_views_minus1: u16,
+    pub view_id: Vec<u16>,
+    /// view_id of the inter-view references of anchor view components, for list 0.
+    pub anchor_refs_l0: Vec<Vec<u16>>,
+    /// view_id of the inter-view references of anchor view components, for list 1.
+    pub anchor_refs_l1: Vec<Vec<u16>>,
+    /// view_id of the inter-view references of non-anchor view components, for list 0.
+    pub non_anchor_refs_l0: Vec<Vec<u16>>,
+    /// view_id of the inter-view references of non-anchor view components, for list 1.
+    pub non_anchor_refs_l1: Vec<Vec<u16>>,
+    pub level_values: Vec<MvcLevelValue>,
+}
+
+impl SpsMvcExtension {
+    /// Returns the view order index of the view with `view_id`, if it is part of the sequence.
+    pub fn view_order_idx(&self, view_id: u16) -> Option<usize> {
+        self.view_id.iter().position(|&id| id == view_id)
+    }
+
+    /// Returns the view_id of the inter-view references of the view component with view order
+    /// index `voidx`, for reference picture list 0 or 1 depending on `list1`.
+    pub fn inter_view_refs(&self, voidx: usize, anchor: bool, list1: bool) -> &[u16] {
+        let refs = match (anchor, list1) {
+            (true, false) => &self.anchor_refs_l0,
+            (true, true) => &self.anchor_refs_l1,
+            (false, false) => &self.non_anchor_refs_l0,
+            (false, true) => &self.non_anchor_refs_l1,
+        };
+
+        refs.get(voidx).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// A H264 subset Sequence Parameter Set, used by the non-base layers of SVC streams and the
+/// non-base views of MVC streams. See 7.3.2.1.3 in the specification.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SubsetSps {
+    /// The `seq_parameter_set_data()` part of the subset SPS.
+    pub sps: Rc<Sps>,
+    /// Present if `profile_idc` is one of the Scalable profiles.
+    pub svc_extension: Option<SpsSvcExtension>,
+    /// Present if `profile_idc` is one of the Multiview profiles.
+    pub mvc_extension: Option<SpsMvcExtension>,
+}
+
+/// A recovery point SEI message, signaling that decoding can start at the picture it is
+/// associated with. See D.1.8 and D.2.8 in the specification.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryPoint {
+    /// Number of frames in decoding order after which the output is correct.
+    pub recovery_frame_cnt: u32,
+    pub exact_match_flag: bool,
+    pub broken_link_flag: bool,
+    pub changing_slice_group_idc: u8,
+}
+
 #[derive(Debug, Default)]
 pub struct Parser {
     active_spses: BTreeMap<u8, Rc<Sps>>,
     active_ppses: BTreeMap<u8, Rc<Pps>>,
+    active_subset_spses: BTreeMap<u8, Rc<SubsetSps>>,
+    /// PPSs bound to the subset SPS with their `seq_parameter_set_id`, for use by the non-base
+    /// views of MVC streams.
+    active_mvc_ppses: BTreeMap<u8, Rc<Pps>>,
 }
 
 impl Parser {
@@ -1909,6 +2105,22 @@ impl Parser {
         let data = nalu.as_ref();
         // Skip the header
         let mut r = NaluReader::new(&data[nalu.header.len()..]);
+        let sps = Parser::parse_sps_data(&mut r)?;
+
+        let key = sps.seq_parameter_set_id;
+        self.active_spses.insert(key, Rc::new(sps));
+
+        if self.active_spses.keys().len() > MAX_SPS_COUNT as usize {
+            return Err(anyhow!(
+                "Broken data: Number of active SPSs > MAX_SPS_COUNT"
+            ));
+        }
+
+        Ok(self.get_sps(key).unwrap())
+    }
+
+    /// Parses the `seq_parameter_set_data()` syntax shared by SPSs and subset SPSs.
+    fn parse_sps_data(r: &mut NaluReader) -> anyhow::Result<Sps> {
         let mut sps = Sps {
             profile_idc: r.read_bits(8)?,
             constraint_set0_flag: r.read_bit()?,
@@ -1952,7 +2164,7 @@ impl Parser {
             sps.seq_scaling_matrix_present_flag = r.read_bit()?;
 
             if sps.seq_scaling_matrix_present_flag {
-                Parser::parse_sps_scaling_lists(&mut r, &mut sps)?;
+                Parser::parse_sps_scaling_lists(r, &mut sps)?;
             } else {
                 Parser::fill_scaling_list_flat(
                     &mut sps.scaling_lists_4x4,
@@ -2016,7 +2228,7 @@ impl Parser {
 
         sps.vui_parameters_present_flag = r.read_bit()?;
         if sps.vui_parameters_present_flag {
-            Parser::parse_vui(&mut r, &mut sps)?;
+            Parser::parse_vui(r, &mut sps)?;
         }
 
         let mut width = (sps.pic_width_in_mbs_minus1 + 1) * 16;
@@ -2054,16 +2266,181 @@ impl Parser {
             sps.crop_rect_y = sps.frame_crop_top_offset * crop_unit_y;
         }
 
-        let key = sps.seq_parameter_set_id;
-        self.active_spses.insert(key, Rc::new(sps));
+        Ok(sps)
+    }
 
-        if self.active_spses.keys().len() > MAX_SPS_COUNT as usize {
+    fn parse_sps_svc_extension(r: &mut NaluReader, sps: &Sps) -> anyhow::Result<SpsSvcExtension> {
+        let mut ext = SpsSvcExtension {
+            inter_layer_deblocking_filter_control_present_flag: r.read_bit()?,
+            extended_spatial_scalability_idc: r.read_bits(2)?,
+            ..Default::default()
+        };
+
+        if sps.chroma_array_type == 1 || sps.chroma_array_type == 2 {
+            ext.chroma_phase_x_plus1_flag = r.read_bit()?;
+        }
+
+        if sps.chroma_array_type == 1 {
+            ext.chroma_phase_y_plus1 = r.read_bits(2)?;
+        }
+
+        if ext.extended_spatial_scalability_idc == 1 {
+            if sps.chroma_array_type > 0 {
+                ext.seq_ref_layer_chroma_phase_x_plus1_flag = r.read_bit()?;
+                ext.seq_ref_layer_chroma_phase_y_plus1 = r.read_bits(2)?;
+            }
+
+            ext.seq_scaled_ref_layer_left_offset = r.read_se()?;
+            ext.seq_scaled_ref_layer_top_offset = r.read_se()?;
+            ext.seq_scaled_ref_layer_right_offset = r.read_se()?;
+            ext.seq_scaled_ref_layer_bottom_offset = r.read_se()?;
+        }
+
+        ext.seq_tcoeff_level_prediction_flag = r.read_bit()?;
+        if ext.seq_tcoeff_level_prediction_flag {
+            ext.adaptive_tcoeff_level_prediction_flag = r.read_bit()?;
+        }
+
+        ext.slice_header_restriction_flag = r.read_bit()?;
+
+        Ok(ext)
+    }
+
+    fn parse_view_refs(r: &mut NaluReader, num_views: usize) -> anyhow::Result<Vec<u16>> {
+        let num_refs = r.read_ue_max::<usize>(15)?;
+        if num_refs >= num_views {
+            return Err(anyhow!("Broken Data: {} inter-view references", num_refs));
+        }
+
+        (0..num_refs).map(|_| r.read_ue_max(1023)).collect()
+    }
+
+    fn parse_sps_mvc_extension(r: &mut NaluReader) -> anyhow::Result<SpsMvcExtension> {
+        let mut ext = SpsMvcExtension {
+            num_views_minus1: r.read_ue_max(1023)?,
+            ..Default::default()
+        };
+
+        let num_views = usize::from(ext.num_views_minus1) + 1;
+        for _ in 0..num_views {
+            ext.view_id.push(r.read_ue_max(1023)?);
+        }
+
+        // The base view has no inter-view references.
+        ext.anchor_refs_l0.push(vec![]);
+        ext.anchor_refs_l1.push(vec![]);
+        for _ in 1..num_views {
+            ext.anchor_refs_l0
+                .push(Parser::parse_view_refs(r, num_views)?);
+            ext.anchor_refs_l1
+                .push(Parser::parse_view_refs(r, num_views)?);
+        }
+
+        ext.non_anchor_refs_l0.push(vec![]);
+        ext.non_anchor_refs_l1.push(vec![]);
+        for _ in 1..num_views {
+            ext.non_anchor_refs_l0
+                .push(Parser::parse_view_refs(r, num_views)?);
+            ext.non_anchor_refs_l1
+                .push(Parser::parse_view_refs(r, num_views)?);
+        }
+
+        let num_level_values_signalled = r.read_ue_max::<usize>(63)? + 1;
+        for _ in 0..num_level_values_signalled {
+            let mut level_value = MvcLevelValue {
+                level_idc: r.read_bits(8)?,
+                ..Default::default()
+            };
+
+            let num_applicable_ops = r.read_ue_max::<usize>(1023)? + 1;
+            for _ in 0..num_applicable_ops {
+                let mut op = MvcApplicableOp {
+                    temporal_id: r.read_bits(3)?,
+                    ..Default::default()
+                };
+
+                let num_target_views = r.read_ue_max::<usize>(1023)? + 1;
+                for _ in 0..num_target_views {
+                    op.target_view_id.push(r.read_ue_max(1023)?);
+                }
+
+                op.num_views_minus1 = r.read_ue_max(1023)?;
+                level_value.applicable_ops.push(op);
+            }
+
+            ext.level_values.push(level_value);
+        }
+
+        Ok(ext)
+    }
+
+    /// Parses a subset SPS. Only the SVC and MVC extensions are parsed, the VUI extensions that
+    /// follow them are ignored.
+    pub fn parse_subset_sps(&mut self, nalu: &Nalu) -> anyhow::Result<&Rc<SubsetSps>> {
+        if !matches!(nalu.header.type_, NaluType::SubsetSps) {
             return Err(anyhow!(
-                "Broken data: Number of active SPSs > MAX_SPS_COUNT"
+                "Invalid NALU type, expected {:?}, got {:?}",
+                NaluType::SubsetSps,
+                nalu.header.type_
             ));
         }
 
-        Ok(self.get_sps(key).unwrap())
+        let data = nalu.as_ref();
+        // Skip the header
+        let mut r = NaluReader::new(&data[nalu.header.len()..]);
+        let sps = Parser::parse_sps_data(&mut r)?;
+
+        let mut svc_extension = None;
+        let mut mvc_extension = None;
+
+        match sps.profile_idc {
+            83 | 86 => svc_extension = Some(Parser::parse_sps_svc_extension(&mut r, &sps)?),
+            118 | 128 | 134 => {
+                if !r.read_bit()? {
+                    return Err(anyhow!("Broken Data: bit_equal_to_one is zero"));
+                }
+
+                mvc_extension = Some(Parser::parse_sps_mvc_extension(&mut r)?);
+            }
+            _ => (),
+        }
+
+        let key = sps.seq_parameter_set_id;
+        let subset_sps = Rc::new(SubsetSps {
+            sps: Rc::new(sps),
+            svc_extension,
+            mvc_extension,
+        });
+
+        // Rebind the PPSs referring to this subset SPS that have already been parsed.
+        let ppses = self
+            .active_ppses
+            .values()
+            .filter(|pps| pps.seq_parameter_set_id == key)
+            .map(|pps| Parser::bind_pps(pps, &subset_sps.sps))
+            .collect::<Vec<_>>();
+        for pps in ppses {
+            self.active_mvc_ppses.insert(pps.pic_parameter_set_id, pps);
+        }
+
+        self.active_subset_spses.insert(key, subset_sps);
+
+        Ok(self.get_subset_sps(key).unwrap())
+    }
+
+    /// Returns a copy of `pps` that refers to `sps`.
+    fn bind_pps(pps: &Pps, sps: &Rc<Sps>) -> Rc<Pps> {
+        let mut pps = Pps {
+            sps: Rc::clone(sps),
+            ..pps.clone()
+        };
+
+        if !pps.pic_scaling_matrix_present_flag {
+            pps.scaling_lists_4x4 = sps.scaling_lists_4x4;
+            pps.scaling_lists_8x8 = sps.scaling_lists_8x8;
+        }
+
+        Rc::new(pps)
     }
 
     pub fn parse_pps(&mut self, nalu: &Nalu) -> anyhow::Result<&Pps> {
@@ -2080,9 +2457,16 @@ impl Parser {
         let mut r = NaluReader::new(&data[nalu.header.len()..]);
         let pic_parameter_set_id = r.read_ue_max(MAX_PPS_COUNT as u32 - 1)?;
         let seq_parameter_set_id = r.read_ue_max(MAX_SPS_COUNT as u32 - 1)?;
-        let sps = self.get_sps(seq_parameter_set_id).context(
-            "Broken stream: stream references a SPS that has not been successfully parsed",
-        )?;
+        // The PPSs of the non-base views of MVC streams may only refer to a subset SPS.
+        let sps = self
+            .get_sps(seq_parameter_set_id)
+            .or_else(|| {
+                self.get_subset_sps(seq_parameter_set_id)
+                    .map(|subset_sps| &subset_sps.sps)
+            })
+            .context(
+                "Broken stream: stream references a SPS that has not been successfully parsed",
+            )?;
         let mut pps = Pps {
             pic_parameter_set_id,
             seq_parameter_set_id,
@@ -2156,6 +2540,12 @@ impl Parser {
         }
 
         let key = pps.pic_parameter_set_id;
+        if let Some(subset_sps) = self.active_subset_spses.get(&pps.seq_parameter_set_id) {
+            let mvc_pps = Parser::bind_pps(&pps, &subset_sps.sps);
+            self.active_mvc_ppses.insert(key, mvc_pps);
+        } else {
+            self.active_mvc_ppses.remove(&key);
+        }
         self.active_ppses.insert(key, Rc::new(pps));
 
         if self.active_ppses.keys().len() > MAX_PPS_COUNT as usize {
@@ -2167,18 +2557,23 @@ impl Parser {
         Ok(self.get_pps(key).unwrap())
     }
 
+    /// Parses `ref_pic_list_modification()`, or `ref_pic_list_mvc_modification()` if `mvc` is
+    /// set.
     fn parse_ref_pic_list_modification(
         r: &mut NaluReader,
         num_ref_idx_active_minus1: u8,
         ref_list_mods: &mut Vec<RefPicListModification>,
+        mvc: bool,
     ) -> anyhow::Result<()> {
         if num_ref_idx_active_minus1 >= 32 {
             return Err(anyhow!("Broken Data: num_ref_idx_active_minus1 >= 32"));
         }
 
+        let max_idc = if mvc { 5 } else { 3 };
+
         loop {
             let mut pic_num_mod = RefPicListModification {
-                modification_of_pic_nums_idc: r.read_ue_max(3)?,
+                modification_of_pic_nums_idc: r.read_ue_max(max_idc)?,
                 ..Default::default()
             };
 
@@ -2196,7 +2591,16 @@ impl Parser {
                     break;
                 }
 
-                _ => return Err(anyhow!("Broken Data: modification_of_pic_nums_idc > 3")),
+                4 | 5 => {
+                    pic_num_mod.abs_diff_view_idx_minus1 = r.read_ue()?;
+                }
+
+                _ => {
+                    return Err(anyhow!(
+                        "Broken Data: modification_of_pic_nums_idc > {}",
+                        max_idc
+                    ))
+                }
             }
 
             ref_list_mods.push(pic_num_mod);
@@ -2208,6 +2612,7 @@ impl Parser {
     fn parse_ref_pic_list_modifications(
         r: &mut NaluReader,
         header: &mut SliceHeader,
+        mvc: bool,
     ) -> anyhow::Result<()> {
         if !header.slice_type.is_i() && !header.slice_type.is_si() {
             header.ref_pic_list_modification_flag_l0 = r.read_bit()?;
@@ -2216,6 +2621,7 @@ impl Parser {
                     r,
                     header.num_ref_idx_l0_active_minus1,
                     &mut header.ref_pic_list_modification_l0,
+                    mvc,
                 )?;
             }
         }
@@ -2227,6 +2633,7 @@ impl Parser {
                     r,
                     header.num_ref_idx_l1_active_minus1,
                     &mut header.ref_pic_list_modification_l1,
+                    mvc,
                 )?;
             }
         }
@@ -2390,6 +2797,12 @@ impl Parser {
             ));
         }
 
+        if nalu.header.svc_extension().is_some() {
+            return Err(anyhow!(
+                "Stream contain unsupported/unimplemented NALs: SVC enhancement layer slice"
+            ));
+        }
+
         let data = nalu.as_ref();
         // Skip the header
         let mut r = NaluReader::new(&data[nalu.header.len()..]);
@@ -2405,9 +2818,11 @@ impl Parser {
 
         header.pic_parameter_set_id = r.read_ue()?;
 
-        let pps = self.get_pps(header.pic_parameter_set_id).context(
-            "Broken stream: slice references PPS that has not been successfully parsed.",
-        )?;
+        let pps = self
+            .get_slice_pps(&nalu.header, header.pic_parameter_set_id)
+            .context(
+                "Broken stream: slice references PPS that has not been successfully parsed.",
+            )?;
 
         let sps = &pps.sps;
 
@@ -2484,11 +2899,11 @@ impl Parser {
             return Err(anyhow!("Broken Data"));
         }
 
-        if let NaluType::SliceExt = nalu.header.type_ {
-            return Err(anyhow!("Stream contain unsupported/unimplemented NALs"));
-        }
-
-        Parser::parse_ref_pic_list_modifications(&mut r, &mut header)?;
+        Parser::parse_ref_pic_list_modifications(
+            &mut r,
+            &mut header,
+            nalu.header.mvc_extension().is_some(),
+        )?;
 
         if (pps.weighted_pred_flag && (header.slice_type.is_p() || header.slice_type.is_sp()))
             || (pps.weighted_bipred_idc == 1 && header.slice_type.is_b())
@@ -2535,6 +2950,56 @@ impl Parser {
         Ok(Slice { header, nalu })
     }
 
+    /// Returns the recovery point message of the SEI NAL unit `nalu`, if it contains one. See
+    /// 7.3.2.3 in the specification.
+    pub fn parse_recovery_point(&self, nalu: &Nalu) -> anyhow::Result<Option<RecoveryPoint>> {
+        if !matches!(nalu.header.type_, NaluType::Sei) {
+            return Err(anyhow!(
+                "Invalid NALU type, expected {:?}, got {:?}",
+                NaluType::Sei,
+                nalu.header.type_
+            ));
+        }
+
+        let data = nalu.as_ref();
+        // Skip the header
+        let mut r = NaluReader::new(&data[nalu.header.len()..]);
+
+        while r.has_more_rsbp_data() {
+            let mut payload_type = 0u32;
+            loop {
+                let byte: u32 = r.read_bits(8)?;
+                payload_type += byte;
+                if byte != 0xff {
+                    break;
+                }
+            }
+
+            let mut payload_size = 0usize;
+            loop {
+                let byte: usize = r.read_bits(8)?;
+                payload_size += byte;
+                if byte != 0xff {
+                    break;
+                }
+            }
+
+            // See D.1.8.
+            if payload_type == 6 {
+                return Ok(Some(RecoveryPoint {
+                    recovery_frame_cnt: r.read_ue()?,
+                    exact_match_flag: r.read_bit()?,
+                    broken_link_flag: r.read_bit()?,
+                    changing_slice_group_idc: r.read_bits(2)?,
+                }));
+            }
+
+            r.skip_bits(payload_size * 8)?;
+        }
+
+        Ok(None)
+    }
+
     pub fn get_sps(&self, sps_id: u8) -> Option<&Rc<Sps>> {
         self.active_spses.get(&sps_id)
     }
@@ -2542,6 +3007,98 @@ impl Parser {
     pub fn get_pps(&self, pps_id: u8) -> Option<&Rc<Pps>> {
         self.active_ppses.get(&pps_id)
     }
+
+    pub fn get_subset_sps(&self, sps_id: u8) -> Option<&Rc<SubsetSps>> {
+        self.active_subset_spses.get(&sps_id)
+    }
+
+    /// Returns the PPS with `pps_id` as seen by the slice with `nalu_header`, i.e. bound to a
+    /// subset SPS if the slice belongs to a non-base view.
+    pub fn get_slice_pps(&self, nalu_header: &NaluHeader, pps_id: u8) -> Option<&Rc<Pps>> {
+        if nalu_header.mvc_extension().is_some() {
+            self.active_mvc_ppses
+                .get(&pps_id)
+                .or_else(|| self.get_pps(pps_id))
+        } else {
+            self.get_pps(pps_id)
+        }
+    }
+
+    /// Returns the MVC extension of the subset SPSs, if any. All the subset SPSs of a MVC stream
+    /// describe the same views.
+    pub fn mvc_extension(&self) -> Option<(&Rc<SubsetSps>, &SpsMvcExtension)> {
+        self.active_subset_spses.values().find_map(|subset_sps| {
+            subset_sps
+                .mvc_extension
+                .as_ref()
+                .map(|ext| (subset_sps, ext))
+        })
+    }
+}
+
+/// The `nal_unit_header_svc_extension()` of prefix and coded slice extension NAL units. See
+/// G.7.3.1.1 in the specification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NaluHeaderSvcExtension {
+    pub idr_flag: bool,
+    pub priority_id: u8,
+    pub no_inter_layer_pred_flag: bool,
+    pub dependency_id: u8,
+    pub quality_id: u8,
+    pub temporal_id: u8,
+    pub use_ref_base_pic_flag: bool,
+    pub discardable_flag: bool,
+    pub output_flag: bool,
+}
+
+/// The `nal_unit_header_mvc_extension()` of prefix and coded slice extension NAL units. See
+/// H.7.3.1.1 in the specification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NaluHeaderMvcExtension {
+    pub non_idr_flag: bool,
+    pub priority_id: u8,
+    pub view_id: u16,
+    pub temporal_id: u8,
+    pub anchor_pic_flag: bool,
+    pub inter_view_flag: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NaluHeaderExtension {
+    Svc(NaluHeaderSvcExtension),
+    Mvc(NaluHeaderMvcExtension),
+}
+
+impl NaluHeaderExtension {
+    /// Size of the extension in bytes, including `svc_extension_flag`.
+    const LEN: usize = 3;
+
+    fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut r = NaluReader::new(data);
+
+        if r.read_bit()? {
+            Ok(NaluHeaderExtension::Svc(NaluHeaderSvcExtension {
+                idr_flag: r.read_bit()?,
+                priority_id: r.read_bits(6)?,
+                no_inter_layer_pred_flag: r.read_bit()?,
+                dependency_id: r.read_bits(3)?,
+                quality_id: r.read_bits(4)?,
+                temporal_id: r.read_bits(3)?,
+                use_ref_base_pic_flag: r.read_bit()?,
+                discardable_flag: r.read_bit()?,
+                output_flag: r.read_bit()?,
+            }))
+        } else {
+            Ok(NaluHeaderExtension::Mvc(NaluHeaderMvcExtension {
+                non_idr_flag: r.read_bit()?,
+                priority_id: r.read_bits(6)?,
+                view_id: r.read_bits(10)?,
+                temporal_id: r.read_bits(3)?,
+                anchor_pic_flag: r.read_bit()?,
+                inter_view_flag: r.read_bit()?,
+            }))
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -2549,6 +3106,24 @@ pub struct NaluHeader {
     pub ref_idc: u8,
     pub type_: NaluType,
     pub idr_pic_flag: bool,
+    /// The SVC or MVC extension of prefix and coded slice extension NAL units.
+    pub extension: Option<NaluHeaderExtension>,
+}
+
+impl NaluHeader {
+    pub fn svc_extension(&self) -> Option<&NaluHeaderSvcExtension> {
+        match &self.extension {
+            Some(NaluHeaderExtension::Svc(ext)) => Some(ext),
+            _ => None,
+        }
+    }
+
+    pub fn mvc_extension(&self) -> Option<&NaluHeaderMvcExtension> {
+        match &self.extension {
+            Some(NaluHeaderExtension::Mvc(ext)) => Some(ext),
+            _ => None,
+        }
+    }
 }
 
 impl Header for NaluHeader {
@@ -2561,17 +3136,32 @@ impl Header for NaluHeader {
 
         let type_ = NaluType::n(byte & 0x1f).ok_or(anyhow!("Broken Data"))?;
 
-        if let NaluType::SliceExt = type_ {
-            return Err(anyhow!("Stream contain unsupported/unimplemented NALs"));
-        }
-
         let ref_idc = (byte & 0x60) >> 5;
-        let idr_pic_flag = matches!(type_, NaluType::SliceIdr);
+        let mut idr_pic_flag = matches!(type_, NaluType::SliceIdr);
+
+        let extension = match type_ {
+            NaluType::PrefixUnit | NaluType::SliceExt => {
+                let data = cursor
+                    .chunk()
+                    .get(1..1 + NaluHeaderExtension::LEN)
+                    .ok_or(anyhow!("Broken Data"))?;
+                let extension = NaluHeaderExtension::parse(data)?;
+
+                idr_pic_flag = match &extension {
+                    NaluHeaderExtension::Svc(ext) => ext.idr_flag,
+                    NaluHeaderExtension::Mvc(ext) => !ext.non_idr_flag,
+                };
+
+                Some(extension)
+            }
+            _ => None,
+        };
 
         Ok(NaluHeader {
             ref_idc,
             type_,
             idr_pic_flag,
+            extension,
         })
     }
 
@@ -2580,19 +3170,29 @@ impl Header for NaluHeader {
     }
 
     fn len(&self) -> usize {
-        1
+        if self.extension.is_some() {
+            1 + NaluHeaderExtension::LEN
+        } else {
+            1
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
+    use std::rc::Rc;
 
+    use crate::codec::h264::nalu_writer::NaluWriter;
     use crate::codec::h264::parser::Level;
     use crate::codec::h264::parser::MaxLongTermFrameIdx;
     use crate::codec::h264::parser::Nalu;
+    use crate::codec::h264::parser::NaluHeaderMvcExtension;
     use crate::codec::h264::parser::NaluType;
     use crate::codec::h264::parser::Parser;
+    use crate::codec::h264::parser::Profile;
+    use crate::codec::h264::parser::RecoveryPoint;
+    use crate::codec::h264::parser::SpsBuilder;
 
     const STREAM_TEST_25_FPS: &[u8] = include_bytes!("test_data/test-25fps.h264");
     const STREAM_TEST_25_FPS_NUM_NALUS: usize = 759;
@@ -2973,4 +3573,252 @@ mod tests {
         assert_eq!(MaxLongTermFrameIdx::Idx(24), 24);
         assert!(MaxLongTermFrameIdx::Idx(24) < 25);
     }
+
+    fn write_rbsp_trailing_bits(w: &mut NaluWriter<&mut Vec<u8>>) {
+        w.write_u(1, 1u32).unwrap();
+        while !w.aligned() {
+            w.write_u(1, 0u32).unwrap();
+        }
+    }
+
+    /// Returns a Stereo High subset SPS, a PPS referring to it and the IDR slice of the second
+    /// view, which uses the base view as inter-view reference.
+    fn mvc_stream() -> Vec<u8> {
+        let mut stream = vec![];
+
+        let mut w = NaluWriter::new(&mut stream, true);
+        w.write_header(3, NaluType::SubsetSps as u8).unwrap();
+        // profile_idc, constraint flags and level_idc.
+        w.write_u(8, 128u32).unwrap();
+        w.write_u(8, 0u32).unwrap();
+        w.write_u(8, 30u32).unwrap();
+        // seq_parameter_set_id, chroma_format_idc, bit depths.
+        for value in [0u32, 1, 0, 0] {
+            w.write_ue(value).unwrap();
+        }
+        // qpprime_y_zero_transform_bypass_flag, seq_scaling_matrix_present_flag.
+        w.write_u(2, 0u32).unwrap();
+        // log2_max_frame_num_minus4, pic_order_cnt_type, max_num_ref_frames.
+        for value in [0u32, 2, 1] {
+            w.write_ue(value).unwrap();
+        }
+        w.write_u(1, 0u32).unwrap();
+        // 64x64.
+        w.write_ue(3u32).unwrap();
+        w.write_ue(3u32).unwrap();
+        // frame_mbs_only_flag, direct_8x8_inference_flag, frame_cropping_flag,
+        // vui_parameters_present_flag and bit_equal_to_one.
+        w.write_u(5, 0b11001u32).unwrap();
+        // num_views_minus1 and view_id.
+        for value in [1u32, 0, 5] {
+            w.write_ue(value).unwrap();
+        }
+        // Anchor, then non-anchor references of view 5: view 0 in list 0.
+        for value in [1u32, 0, 0, 1, 0, 0] {
+            w.write_ue(value).unwrap();
+        }
+        // One level value, for one operation point with both views.
+        w.write_ue(0u32).unwrap();
+        w.write_u(8, 30u32).unwrap();
+        w.write_ue(0u32).unwrap();
+        w.write_u(3, 0u32).unwrap();
+        for value in [1u32, 0, 5, 1] {
+            w.write_ue(value).unwrap();
+        }
+        // mvc_vui_parameters_present_flag, additional_extension2_flag.
+        w.write_u(2, 0u32).unwrap();
+        write_rbsp_trailing_bits(&mut w);
+
+        w.write_header(3, NaluType::Pps as u8).unwrap();
+        w.write_ue(0u32).unwrap();
+        w.write_ue(0u32).unwrap();
+        w.write_u(2, 0u32).unwrap();
+        for value in [0u32, 0, 0] {
+            w.write_ue(value).unwrap();
+        }
+        w.write_u(3, 0u32).unwrap();
+        for value in [0i32, 0, 0] {
+            w.write_se(value).unwrap();
+        }
+        // deblocking_filter_control_present_flag only.
+        w.write_u(3, 0b100u32).unwrap();
+        write_rbsp_trailing_bits(&mut w);
+
+        w.write_header(3, NaluType::SliceExt as u8).unwrap();
+        // svc_extension_flag, non_idr_flag, priority_id, view_id, temporal_id,
+        // anchor_pic_flag, inter_view_flag and reserved_one_bit.
+        w.write_u(2, 0u32).unwrap();
+        w.write_u(6, 0u32).unwrap();
+        w.write_u(10, 5u32).unwrap();
+        w.write_u(3, 0u32).unwrap();
+        w.write_u(3, 0b101u32).unwrap();
+        // first_mb_in_slice, slice_type (P), pic_parameter_set_id.
+        for value in [0u32, 5, 0] {
+            w.write_ue(value).unwrap();
+        }
+        // frame_num, idr_pic_id.
+        w.write_u(4, 0u32).unwrap();
+        w.write_ue(0u32).unwrap();
+        // One active reference, moved to the front by an inter-view modification.
+        w.write_u(1, 1u32).unwrap();
+        w.write_ue(0u32).unwrap();
+        w.write_u(1, 1u32).unwrap();
+        for value in [5u32, 0, 3] {
+            w.write_ue(value).unwrap();
+        }
+        // dec_ref_pic_marking().
+        w.write_u(2, 0u32).unwrap();
+        // slice_qp_delta, disable_deblocking_filter_idc.
+        w.write_se(0i32).unwrap();
+        w.write_ue(1u32).unwrap();
+        write_rbsp_trailing_bits(&mut w);
+        drop(w);
+
+        stream
+    }
+
+    #[test]
+    fn parse_mvc() {
+        let stream = mvc_stream();
+        let mut cursor = Cursor::new(stream.as_ref());
+        let mut parser = Parser::default();
+
+        let nalu = Nalu::next(&mut cursor).unwrap();
+        let subset_sps = parser.parse_subset_sps(&nalu).unwrap();
+        assert_eq!(subset_sps.sps.profile_idc, 128);
+        assert_eq!(subset_sps.sps.width, 64);
+        assert_eq!(subset_sps.sps.height, 64);
+        assert!(subset_sps.svc_extension.is_none());
+
+        let ext = subset_sps.mvc_extension.as_ref().unwrap();
+        assert_eq!(ext.view_id, vec![0, 5]);
+        assert_eq!(ext.view_order_idx(5), Some(1));
+        assert_eq!(ext.inter_view_refs(1, true, false), &[0]);
+        assert!(ext.inter_view_refs(1, true, true).is_empty());
+        assert_eq!(ext.inter_view_refs(1, false, false), &[0]);
+        assert!(ext.inter_view_refs(0, true, false).is_empty());
+        assert_eq!(ext.level_values.len(), 1);
+        assert_eq!(
+            ext.level_values[0].applicable_ops[0].target_view_id,
+            vec![0, 5]
+        );
+
+        // The PPS refers to the subset SPS, as no SPS with the same ID exists.
+        let nalu = Nalu::next(&mut cursor).unwrap();
+        let pps = parser.parse_pps(&nalu).unwrap();
+        assert_eq!(pps.sps.profile_idc, 128);
+
+        let nalu = Nalu::next(&mut cursor).unwrap();
+        assert_eq!(nalu.header.type_, NaluType::SliceExt);
+        assert!(nalu.header.idr_pic_flag);
+        assert_eq!(
+            nalu.header.mvc_extension(),
+            Some(&NaluHeaderMvcExtension {
+                non_idr_flag: false,
+                priority_id: 0,
+                view_id: 5,
+                temporal_id: 0,
+                anchor_pic_flag: true,
+                inter_view_flag: false,
+            })
+        );
+
+        let slice = parser.parse_slice_header(nalu).unwrap();
+        let hdr = &slice.header;
+        assert!(hdr.slice_type.is_p());
+        assert!(hdr.ref_pic_list_modification_flag_l0);
+        assert_eq!(hdr.ref_pic_list_modification_l0.len(), 2);
+        assert_eq!(
+            hdr.ref_pic_list_modification_l0[0].modification_of_pic_nums_idc,
+            5
+        );
+        assert_eq!(hdr.disable_deblocking_filter_idc, 1);
+    }
+
+    #[test]
+    fn parse_svc_prefix_nalu() {
+        // Prefix NAL unit with idr_flag, dependency_id 1, quality_id 2,
+        // temporal_id 3 and output_flag.
+        let data = [0x00, 0x00, 0x01, 0x6e, 0xc0, 0x12, 0x67, 0x80];
+        let mut cursor = Cursor::new(data.as_ref());
+        let nalu = Nalu::next(&mut cursor).unwrap();
+
+        assert_eq!(nalu.header.type_, NaluType::PrefixUnit);
+        assert!(nalu.header.idr_pic_flag);
+        assert!(nalu.header.mvc_extension().is_none());
+
+        let ext = nalu.header.svc_extension().unwrap();
+        assert!(ext.idr_flag);
+        assert_eq!(ext.dependency_id, 1);
+        assert_eq!(ext.quality_id, 2);
+        assert_eq!(ext.temporal_id, 3);
+        assert!(ext.output_flag);
+        assert!(!ext.discardable_flag);
+    }
+
+    #[test]
+    fn parse_recovery_point_sei() {
+        // SEI NAL unit with a user data unregistered message followed by a recovery point
+        // message with recovery_frame_cnt 2 and exact_match_flag.
+        let data = [
+            0x00, 0x00, 0x01, 0x06, 0x05, 0x02, 0xaa, 0xbb, 0x06, 0x01, 0x71, 0x80,
+        ];
+        let mut cursor = Cursor::new(data.as_ref());
+        let nalu = Nalu::next(&mut cursor).unwrap();
+
+        let parser = Parser::default();
+        assert_eq!(
+            parser.parse_recovery_point(&nalu).unwrap(),
+            Some(RecoveryPoint {
+                recovery_frame_cnt: 2,
+                exact_match_flag: true,
+                broken_link_flag: false,
+                changing_slice_group_idc: 0,
+            })
+        );
+
+        // SEI NAL unit with only a user data unregistered message.
+        let data = [0x00, 0x00, 0x01, 0x06, 0x05, 0x02, 0xaa, 0xbb, 0x80];
+        let mut cursor = Cursor::new(data.as_ref());
+        let nalu = Nalu::next(&mut cursor).unwrap();
+        assert_eq!(parser.parse_recovery_point(&nalu).unwrap(), None);
+    }
+
+    #[test]
+    fn sps_profile() {
+        let profiles = [
+            Profile::Baseline,
+            Profile::ConstrainedBaseline,
+            Profile::Main,
+            Profile::Extended,
+            Profile::High,
+            Profile::ConstrainedHigh,
+            Profile::High10,
+            Profile::High10Intra,
+            Profile::High422P,
+            Profile::High422Intra,
+            Profile::High444P,
+            Profile::High444Intra,
+            Profile::Cavlc444Intra,
+        ];
+        for profile in profiles {
+            let sps = SpsBuilder::new().profile_idc(profile).build();
+            assert_eq!(sps.profile(), Some(profile));
+
+            // Without constraint flags, the profile_idc maps to the unconstrained profile.
+            let unconstrained = Profile::from_profile_idc(profile.profile_idc()).unwrap();
+            assert_eq!(unconstrained.profile_idc(), profile.profile_idc());
+        }
+        assert_eq!(Profile::from_profile_idc(100), Some(Profile::High));
+        assert_eq!(Profile::from_profile_idc(118), None);
+
+        // Progressive High only sets constraint_set4_flag.
+        let mut sps = SpsBuilder::new().profile_idc(Profile::High).build();
+        Rc::get_mut(&mut sps).unwrap().constraint_set4_flag = true;
+        assert_eq!(sps.profile(), Some(Profile::High));
+
+        // Scalable and multiview profiles are not part of Annex A.
+        Rc::get_mut(&mut sps).unwrap().profile_idc = 118;
+        assert_eq!(sps.profile(), None);
+    }
 }
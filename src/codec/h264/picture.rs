@@ -102,6 +102,12 @@ pub struct PictureData {
     // Not for decode or output.
     pub nonexisting: bool,
 
+    // Created by the decoding process for gaps in frame_num to replace a lost
+    // frame when concealing errors. Contrary to non-existing pictures, it is
+    // backed by a substitute frame and used for inter prediction like any
+    // other reference picture. Not for output.
+    pub concealed: bool,
+
     pub field: Field,
 
     // Values from slice_hdr to be used during reference marking and
@@ -133,6 +139,16 @@ impl Deref for RcPictureData {
 }
 
 impl PictureData {
+    /// Returns a picture standing in for the lost frame `frame_num` while concealing errors. See
+    /// [`PictureData::concealed`].
+    pub fn new_concealed(frame_num: u32, timestamp: u64) -> Self {
+        PictureData {
+            nonexisting: false,
+            concealed: true,
+            ..Self::new_non_existing(frame_num, timestamp)
+        }
+    }
+
     pub fn new_non_existing(frame_num: u32, timestamp: u64) -> Self {
         PictureData {
             frame_num,
@@ -146,6 +162,29 @@ impl PictureData {
         }
     }
 
+    /// Returns a copy of this picture for use as inter-view reference by the other views of the
+    /// same access unit.
+    ///
+    /// The copy is not marked as reference, so the reference picture list modification and
+    /// marking processes of the view using it never confuse it with one of its own pictures.
+    pub fn new_inter_view_ref(&self) -> Self {
+        PictureData {
+            pic_order_cnt_type: self.pic_order_cnt_type,
+            top_field_order_cnt: self.top_field_order_cnt,
+            bottom_field_order_cnt: self.bottom_field_order_cnt,
+            pic_order_cnt: self.pic_order_cnt,
+            pic_num: self.pic_num,
+            frame_num: self.frame_num,
+            coded_resolution: self.coded_resolution,
+            display_resolution: self.display_resolution,
+            type_: self.type_,
+            nal_ref_idc: self.nal_ref_idc,
+            field: self.field,
+            timestamp: self.timestamp,
+            ..Default::default()
+        }
+    }
+
     /// Create a new picture from a `slice`, `sps`, and `timestamp`.
     ///
     /// `first_field` is set if this picture is the second field of a frame.
@@ -315,7 +354,7 @@ impl PictureData {
     }
 
     pub fn pic_num_f(&self, max_pic_num: i32) -> i32 {
-        if !matches!(self.reference(), Reference::LongTerm) {
+        if matches!(self.reference(), Reference::ShortTerm) {
             self.pic_num
         } else {
             max_pic_num
@@ -372,6 +411,7 @@ impl PictureData {
             frame_num: self.frame_num,
             reference: self.reference,
             nonexisting: self.nonexisting,
+            concealed: self.concealed,
             pic_order_cnt: second_pic_order_cnt,
             field: self.field.opposite(),
             ..Default::default()
@@ -433,6 +473,7 @@ impl std::fmt::Debug for PictureData {
             .field("abs_diff_pic_num_minus1", &self.abs_diff_pic_num_minus1)
             .field("has_mmco_5", &self.has_mmco_5)
             .field("nonexisting", &self.nonexisting)
+            .field("concealed", &self.concealed)
             .field("field", &self.field)
             .field("ref_pic_marking", &self.ref_pic_marking)
             .field("field_rank", &self.field_rank)
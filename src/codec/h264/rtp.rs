@@ -0,0 +1,316 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! RTP payload format for H.264, as per RFC 6184.
+//!
+//! Only the non-interleaved packetization mode (`packetization-mode=1`) is
+//! supported, i.e. single NAL unit packets, STAP-A and FU-A.
+
+use anyhow::anyhow;
+use bytes::Buf;
+
+use crate::codec::h264::nalu::annexb_nalus;
+use crate::codec::rtp::fragments;
+use crate::codec::rtp::AccessUnitAssembler;
+use crate::codec::rtp::RtpDepayloader;
+use crate::codec::rtp::RtpPacket;
+use crate::codec::rtp::RtpPayloader;
+
+/// NAL unit type of single-time aggregation packets.
+const STAP_A: u8 = 24;
+/// NAL unit type of fragmentation units.
+const FU_A: u8 = 28;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Splits Annex B access units into RTP payloads.
+pub struct Payloader {
+    max_payload_size: usize,
+}
+
+impl Payloader {
+    /// Creates a payloader producing payloads of at most `max_payload_size`
+    /// bytes.
+    pub fn new(max_payload_size: usize) -> Self {
+        Self { max_payload_size }
+    }
+
+    /// Appends the NAL units of `stap` to `out` as a single NAL unit packet or
+    /// a STAP-A, depending on their number.
+    fn flush_stap(stap: &mut Vec<&[u8]>, out: &mut Vec<Vec<u8>>) -> anyhow::Result<()> {
+        match stap.len() {
+            0 => (),
+            1 => out.push(stap[0].to_vec()),
+            _ => {
+                // F is the OR of the aggregated F bits and NRI their maximum.
+                let f = stap.iter().fold(0, |f, nalu| f | (nalu[0] & 0x80));
+                let nri = stap.iter().map(|nalu| nalu[0] & 0x60).max().unwrap_or(0);
+
+                let mut packet = vec![f | nri | STAP_A];
+                for nalu in stap.iter() {
+                    packet.extend_from_slice(&u16::try_from(nalu.len())?.to_be_bytes());
+                    packet.extend_from_slice(nalu);
+                }
+
+                out.push(packet);
+            }
+        }
+
+        stap.clear();
+
+        Ok(())
+    }
+}
+
+impl RtpPayloader for Payloader {
+    fn payload(&mut self, frame: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut packets = vec![];
+        let mut stap = vec![];
+        // Size of the STAP-A being built, including its NAL unit header.
+        let mut stap_size = 1;
+
+        for nalu in annexb_nalus(frame) {
+            if nalu.len() > self.max_payload_size {
+                Self::flush_stap(&mut stap, &mut packets)?;
+                stap_size = 1;
+
+                let indicator = (nalu[0] & 0xe0) | FU_A;
+                let nalu_type = nalu[0] & 0x1f;
+                let mut chunks = fragments(&nalu[1..], self.max_payload_size, 2)?.peekable();
+                let mut start = true;
+
+                while let Some(chunk) = chunks.next() {
+                    let mut header = nalu_type;
+                    if start {
+                        header |= 0x80;
+                    }
+                    if chunks.peek().is_none() {
+                        header |= 0x40;
+                    }
+
+                    let mut packet = Vec::with_capacity(chunk.len() + 2);
+                    packet.extend_from_slice(&[indicator, header]);
+                    packet.extend_from_slice(chunk);
+                    packets.push(packet);
+                    start = false;
+                }
+
+                continue;
+            }
+
+            // NAL units too large for the 16-bit size field of STAP-As are
+            // sent in single NAL unit packets.
+            if nalu.len() > usize::from(u16::MAX) {
+                Self::flush_stap(&mut stap, &mut packets)?;
+                stap_size = 1;
+                packets.push(nalu.to_vec());
+                continue;
+            }
+
+            if stap_size + 2 + nalu.len() > self.max_payload_size {
+                Self::flush_stap(&mut stap, &mut packets)?;
+                stap_size = 1;
+            }
+
+            stap.push(nalu);
+            stap_size += 2 + nalu.len();
+        }
+
+        Self::flush_stap(&mut stap, &mut packets)?;
+
+        Ok(packets)
+    }
+}
+
+/// Reassembles RTP payloads into Annex B access units.
+#[derive(Default)]
+pub struct Depayloader {
+    assembler: AccessUnitAssembler,
+    /// Whether a fragmented NAL unit is being reassembled.
+    in_fu: bool,
+}
+
+impl Depayloader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl RtpDepayloader for Depayloader {
+    fn depayload(&mut self, packet: &RtpPacket) -> anyhow::Result<Option<Vec<u8>>> {
+        let payload = packet.payload;
+        let indicator = *payload
+            .first()
+            .ok_or_else(|| anyhow!("empty RTP payload"))?;
+        let nalu_type = indicator & 0x1f;
+        let starts_frame = nalu_type != FU_A || payload.get(1).is_some_and(|h| h & 0x80 != 0);
+
+        let in_fu = &mut self.in_fu;
+        self.assembler.process(packet, starts_frame, |out| {
+            match nalu_type {
+                1..=23 => {
+                    *in_fu = false;
+                    out.extend_from_slice(&START_CODE);
+                    out.extend_from_slice(payload);
+                }
+                STAP_A => {
+                    *in_fu = false;
+                    let mut reader = &payload[1..];
+
+                    while reader.has_remaining() {
+                        if reader.remaining() < 2 {
+                            return Err(anyhow!("truncated STAP-A"));
+                        }
+
+                        let size = usize::from(reader.get_u16());
+                        if size == 0 || size > reader.remaining() {
+                            return Err(anyhow!("invalid STAP-A NAL unit size {}", size));
+                        }
+
+                        out.extend_from_slice(&START_CODE);
+                        out.extend_from_slice(&reader[..size]);
+                        reader.advance(size);
+                    }
+                }
+                FU_A => {
+                    let header = *payload.get(1).ok_or_else(|| anyhow!("truncated FU-A"))?;
+
+                    if header & 0x80 != 0 {
+                        *in_fu = true;
+                        out.extend_from_slice(&START_CODE);
+                        out.push((indicator & 0xe0) | (header & 0x1f));
+                    } else if !*in_fu {
+                        return Err(anyhow!("FU-A continuation without a start fragment"));
+                    }
+
+                    out.extend_from_slice(&payload[2..]);
+
+                    if header & 0x40 != 0 {
+                        *in_fu = false;
+                    }
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "unsupported NAL unit type {} in RTP payload",
+                        nalu_type
+                    ))
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::nalu::annexb_nalus;
+    use crate::codec::h264::parser::Nalu;
+    use crate::codec::h264::rtp::Depayloader;
+    use crate::codec::h264::rtp::Payloader;
+    use crate::codec::h264::rtp::FU_A;
+    use crate::codec::h264::rtp::STAP_A;
+    use crate::codec::rtp::tests::roundtrip;
+    use crate::codec::rtp::RtpDepayloader;
+    use crate::codec::rtp::RtpPacket;
+    use crate::utils::NalIterator;
+
+    const STREAM: &[u8] = include_bytes!("test_data/64x64-I-P-B-P.h264");
+
+    /// Returns the access units of `STREAM`, with 4-byte start codes.
+    fn access_units() -> Vec<Vec<u8>> {
+        let mut aus: Vec<Vec<u8>> = vec![];
+        let mut new_au = true;
+
+        for nalu in NalIterator::<Nalu>::new(STREAM) {
+            let nalu = annexb_nalus(&nalu)[0].to_vec();
+
+            if new_au {
+                aus.push(vec![]);
+            }
+
+            let au = aus.last_mut().unwrap();
+            au.extend_from_slice(&[0, 0, 0, 1]);
+            au.extend_from_slice(&nalu);
+            new_au = matches!(nalu[0] & 0x1f, 1 | 5);
+        }
+
+        aus
+    }
+
+    #[test]
+    fn rtp_roundtrip() {
+        let aus = access_units();
+        assert_eq!(aus.len(), 3);
+
+        for max_payload_size in [20, 100, 1200] {
+            let (packets, depayloaded) = roundtrip(
+                &mut Payloader::new(max_payload_size),
+                &mut Depayloader::new(),
+                &aus,
+                &[],
+            );
+
+            assert!(packets.iter().all(|p| p.len() <= max_payload_size));
+            assert_eq!(depayloaded, aus);
+        }
+
+        // SPS and PPS are aggregated, and larger NAL units fragmented.
+        let (packets, _) = roundtrip(&mut Payloader::new(100), &mut Depayloader::new(), &aus, &[]);
+        assert_eq!(packets[0][0] & 0x1f, STAP_A);
+        assert_eq!(packets[1][0] & 0x1f, FU_A);
+        assert_eq!(packets[1][1] & 0xc0, 0x80);
+    }
+
+    #[test]
+    fn rtp_large_nalu() {
+        // A NAL unit that fits in a payload but not in the 16-bit size field
+        // of a STAP-A, between two small ones.
+        let mut au = vec![];
+        for nalu in [&[0x67, 1, 2][..], &[0x65], &[0x68, 3]] {
+            au.extend_from_slice(&[0, 0, 0, 1]);
+            au.extend_from_slice(nalu);
+            if nalu.len() < 3 {
+                au.resize(au.len() + 70000, 0xaa);
+            }
+        }
+        let aus = [au];
+
+        let (packets, depayloaded) = roundtrip(
+            &mut Payloader::new(100_000),
+            &mut Depayloader::new(),
+            &aus,
+            &[],
+        );
+        assert_eq!(depayloaded, aus);
+        // It is sent alone, in a single NAL unit packet.
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[1][0], 0x65);
+        assert_eq!(packets[1].len(), 1 + 70000);
+    }
+
+    #[test]
+    fn rtp_packet_loss() {
+        let aus = access_units();
+
+        // Losing the second fragment of the IDR slice only drops the first
+        // access unit.
+        let (_, depayloaded) = roundtrip(
+            &mut Payloader::new(100),
+            &mut Depayloader::new(),
+            &aus,
+            &[2],
+        );
+        assert_eq!(depayloaded, aus[1..]);
+
+        // Interleaved packetization is not supported.
+        let packet = RtpPacket {
+            sequence_number: 0,
+            timestamp: 0,
+            marker: true,
+            payload: &[29, 0x85, 0],
+        };
+        assert!(Depayloader::new().depayload(&packet).is_err());
+    }
+}
@@ -489,7 +489,7 @@ mod tests {
     #[test]
     fn synthesize_sps_scaling_lists() {
         let sps = Sps {
-            profile_idc: Profile::High as u8,
+            profile_idc: Profile::High.profile_idc(),
             seq_scaling_matrix_present_flag: true,
             scaling_lists_4x4: [[
                 11, 20, 10, 20, 10, 22, 10, 20, 10, 20, 13, 20, 10, 20, 10, 24,
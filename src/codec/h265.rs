@@ -2,6 +2,9 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+pub mod access_unit;
 pub mod dpb;
+pub mod hvcc;
 pub mod parser;
 pub mod picture;
+pub mod rtp;
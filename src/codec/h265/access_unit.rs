@@ -0,0 +1,81 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Access unit boundary detection for H.265 Annex B streams.
+
+use crate::codec::access_unit::AccessUnitBoundary;
+use crate::codec::access_unit::AccessUnitSplitter;
+use crate::codec::access_unit::NaluKind;
+
+/// Splits H.265 Annex B streams into access units.
+pub type Splitter = AccessUnitSplitter<AccessUnitDetector>;
+
+/// Detects the access unit boundaries of H.265 streams as per 7.4.2.4.4.
+///
+/// Only NAL units with a `nuh_layer_id` of 0 can start an access unit, and the
+/// first VCL NAL unit of a picture is the one with
+/// `first_slice_segment_in_pic_flag` set, so no parameter set needs to be
+/// tracked.
+#[derive(Default)]
+pub struct AccessUnitDetector;
+
+impl AccessUnitBoundary for AccessUnitDetector {
+    fn nalu_kind(&mut self, nalu: &[u8]) -> NaluKind {
+        if nalu.len() < 2 {
+            return NaluKind::Suffix;
+        }
+
+        let nal_unit_type = (nalu[0] >> 1) & 0x3f;
+        let nuh_layer_id = ((nalu[0] & 0x1) << 5) | (nalu[1] >> 3);
+
+        match nal_unit_type {
+            0..=31 => {
+                let first_slice_segment_in_pic_flag = nalu.get(2).is_some_and(|b| b & 0x80 != 0);
+                if nuh_layer_id == 0 && first_slice_segment_in_pic_flag {
+                    NaluKind::FirstVcl
+                } else {
+                    NaluKind::Vcl
+                }
+            }
+            // VPS, SPS, PPS, access unit delimiter, prefix SEI and reserved
+            // types.
+            32..=35 | 39 | 41..=44 | 48..=55 if nuh_layer_id == 0 => NaluKind::Prefix,
+            _ => NaluKind::Suffix,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::nalu::annexb_nalus;
+    use crate::codec::h265::access_unit::Splitter;
+
+    const STREAM: &[u8] = include_bytes!("test_data/test-25fps.h265");
+    const STREAM_CRCS: &str = include_str!("test_data/test-25fps.h265.crc");
+
+    #[test]
+    fn split_access_units() {
+        let mut splitter = Splitter::new();
+
+        // Deliver the stream in chunks that do not match NAL units.
+        let mut aus = Vec::new();
+        for chunk in STREAM.chunks(1000) {
+            aus.extend(splitter.push(chunk, 0));
+        }
+        aus.extend(splitter.flush());
+
+        assert_eq!(aus.len(), STREAM_CRCS.lines().count());
+        let data: Vec<u8> = aus.iter().flat_map(|au| au.data.clone()).collect();
+        assert_eq!(data, STREAM);
+
+        // Each access unit contains exactly one first slice segment.
+        for au in &aus {
+            let first_slices = annexb_nalus(&au.data)
+                .iter()
+                .filter(|nalu| (nalu[0] >> 1) & 0x3f < 32 && nalu[2] & 0x80 != 0)
+                .count();
+            assert_eq!(first_slices, 1);
+        }
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Support for the HEVCDecoderConfigurationRecord (`hvcC`) defined in ISO/IEC
+//! 14496-15, which carries the parameter sets of length-prefixed H.265
+//! streams in the ISO BMFF (MP4) and Matroska containers.
+
+use std::io::Cursor;
+
+use anyhow::anyhow;
+use bytes::Buf;
+use bytes::BufMut;
+
+use crate::codec::h264::nalu::annexb_nalus;
+use crate::codec::h265::parser::Nalu;
+use crate::codec::h265::parser::NaluType;
+use crate::codec::h265::parser::Parser;
+
+/// An array of NAL units of the same type in the HEVCDecoderConfigurationRecord.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HvccNaluArray {
+    /// Whether all NAL units of this type are in this array and none are in
+    /// the stream itself.
+    pub array_completeness: bool,
+    /// The type of the NAL units in this array.
+    pub nal_unit_type: NaluType,
+    /// The NAL units, without start codes.
+    pub nalus: Vec<Vec<u8>>,
+}
+
+/// The HEVCDecoderConfigurationRecord as per ISO/IEC 14496-15, 8.3.3.1.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HevcDecoderConfigurationRecord {
+    /// Always 1 for this version of the specification.
+    pub configuration_version: u8,
+    /// Same as `general_profile_space` in the SPS.
+    pub general_profile_space: u8,
+    /// Same as `general_tier_flag` in the SPS.
+    pub general_tier_flag: bool,
+    /// Same as `general_profile_idc` in the SPS.
+    pub general_profile_idc: u8,
+    /// The 32 `general_profile_compatibility_flag`s of the SPS, the first one
+    /// being the most significant bit.
+    pub general_profile_compatibility_flags: u32,
+    /// The 48 bits following `general_profile_compatibility_flag` in the SPS,
+    /// starting with `general_progressive_source_flag`.
+    pub general_constraint_indicator_flags: u64,
+    /// Same as `general_level_idc` in the SPS.
+    pub general_level_idc: u8,
+    /// Same as `min_spatial_segmentation_idc` in the VUI.
+    pub min_spatial_segmentation_idc: u16,
+    /// The type of parallelism used by the stream. 0 if unknown.
+    pub parallelism_type: u8,
+    /// Same as `chroma_format_idc` in the SPS.
+    pub chroma_format_idc: u8,
+    /// Same as `bit_depth_luma_minus8` in the SPS.
+    pub bit_depth_luma_minus8: u8,
+    /// Same as `bit_depth_chroma_minus8` in the SPS.
+    pub bit_depth_chroma_minus8: u8,
+    /// The average frame rate in units of frames/(256 seconds). 0 if unknown.
+    pub avg_frame_rate: u16,
+    /// Whether the stream has a constant frame rate. 0 if unknown.
+    pub constant_frame_rate: u8,
+    /// Same as `sps_max_sub_layers_minus1` plus 1. 0 if unknown.
+    pub num_temporal_layers: u8,
+    /// Same as `sps_temporal_id_nesting_flag`.
+    pub temporal_id_nested: bool,
+    /// The size in bytes of the NALU length field, minus one.
+    pub length_size_minus_one: u8,
+    /// The arrays of parameter sets and SEI NAL units.
+    pub arrays: Vec<HvccNaluArray>,
+}
+
+/// Returns the RBSP of `nalu`, i.e. the NAL unit without its emulation
+/// prevention bytes.
+fn rbsp(nalu: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nalu.len());
+    let mut zeros = 0;
+
+    for &byte in nalu {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0x00 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+
+    out
+}
+
+impl HevcDecoderConfigurationRecord {
+    /// Parses an HEVCDecoderConfigurationRecord, e.g. the payload of an `hvcC`
+    /// box or the Matroska `CodecPrivate` element.
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut cursor = Cursor::new(data);
+
+        if cursor.remaining() < 23 {
+            return Err(anyhow!("HEVCDecoderConfigurationRecord is too short"));
+        }
+
+        let configuration_version = cursor.get_u8();
+        if configuration_version != 1 {
+            return Err(anyhow!(
+                "Unsupported configurationVersion {}",
+                configuration_version
+            ));
+        }
+
+        let byte = cursor.get_u8();
+        let general_profile_space = byte >> 6;
+        let general_tier_flag = (byte >> 5) & 0x1 != 0;
+        let general_profile_idc = byte & 0x1f;
+        let general_profile_compatibility_flags = cursor.get_u32();
+        let general_constraint_indicator_flags = cursor.get_uint(6);
+        let general_level_idc = cursor.get_u8();
+        let min_spatial_segmentation_idc = cursor.get_u16() & 0xfff;
+        let parallelism_type = cursor.get_u8() & 0x3;
+        let chroma_format_idc = cursor.get_u8() & 0x3;
+        let bit_depth_luma_minus8 = cursor.get_u8() & 0x7;
+        let bit_depth_chroma_minus8 = cursor.get_u8() & 0x7;
+        let avg_frame_rate = cursor.get_u16();
+
+        let byte = cursor.get_u8();
+        let constant_frame_rate = byte >> 6;
+        let num_temporal_layers = (byte >> 3) & 0x7;
+        let temporal_id_nested = (byte >> 2) & 0x1 != 0;
+        let length_size_minus_one = byte & 0x3;
+        if length_size_minus_one == 2 {
+            return Err(anyhow!(
+                "Invalid lengthSizeMinusOne {}",
+                length_size_minus_one
+            ));
+        }
+
+        let num_arrays = cursor.get_u8();
+        let mut arrays = Vec::with_capacity(usize::from(num_arrays));
+        for _ in 0..num_arrays {
+            if cursor.remaining() < 3 {
+                return Err(anyhow!("Truncated NALU array"));
+            }
+
+            let byte = cursor.get_u8();
+            let array_completeness = byte >> 7 != 0;
+            let nal_unit_type = NaluType::n(u32::from(byte & 0x3f))
+                .ok_or_else(|| anyhow!("Invalid NALU type {}", byte & 0x3f))?;
+
+            let num_nalus = cursor.get_u16();
+            let mut nalus = Vec::with_capacity(usize::from(num_nalus));
+            for _ in 0..num_nalus {
+                if cursor.remaining() < 2 {
+                    return Err(anyhow!("Truncated NALU length"));
+                }
+
+                let len = usize::from(cursor.get_u16());
+                if cursor.remaining() < len {
+                    return Err(anyhow!("Truncated NALU"));
+                }
+
+                let mut nalu = vec![0; len];
+                cursor.copy_to_slice(&mut nalu);
+                nalus.push(nalu);
+            }
+
+            arrays.push(HvccNaluArray {
+                array_completeness,
+                nal_unit_type,
+                nalus,
+            });
+        }
+
+        Ok(Self {
+            configuration_version,
+            general_profile_space,
+            general_tier_flag,
+            general_profile_idc,
+            general_profile_compatibility_flags,
+            general_constraint_indicator_flags,
+            general_level_idc,
+            min_spatial_segmentation_idc,
+            parallelism_type,
+            chroma_format_idc,
+            bit_depth_luma_minus8,
+            bit_depth_chroma_minus8,
+            avg_frame_rate,
+            constant_frame_rate,
+            num_temporal_layers,
+            temporal_id_nested,
+            length_size_minus_one,
+            arrays,
+        })
+    }
+
+    /// Builds a record from the parameter sets found in the Annex B encoded
+    /// `data`, e.g. the first keyframe produced by an encoder. The NALU length
+    /// field of the described stream will be `length_size` bytes long.
+    ///
+    /// Only the VPS, SPS and PPS NAL units are put in the record, as SEI NAL
+    /// units may differ for every frame.
+    pub fn from_annexb(data: &[u8], length_size: usize) -> anyhow::Result<Self> {
+        if !matches!(length_size, 1 | 2 | 4) {
+            return Err(anyhow!("Invalid NALU length size {}", length_size));
+        }
+
+        let mut arrays: Vec<HvccNaluArray> = vec![];
+        for nalu in annexb_nalus(data) {
+            let nal_unit_type = match NaluType::n(u32::from((nalu[0] >> 1) & 0x3f)) {
+                Some(type_ @ (NaluType::VpsNut | NaluType::SpsNut | NaluType::PpsNut)) => type_,
+                _ => continue,
+            };
+
+            match arrays.iter_mut().find(|a| a.nal_unit_type == nal_unit_type) {
+                // Parameter sets are usually repeated before each IRAP picture.
+                Some(array) if array.nalus.iter().any(|n| n == nalu) => (),
+                Some(array) => array.nalus.push(nalu.to_vec()),
+                None => arrays.push(HvccNaluArray {
+                    array_completeness: true,
+                    nal_unit_type,
+                    nalus: vec![nalu.to_vec()],
+                }),
+            }
+        }
+
+        // Parameter sets come first and in VPS, SPS, PPS order.
+        arrays.sort_by_key(|a| a.nal_unit_type);
+
+        let first_sps = arrays
+            .iter()
+            .find(|a| a.nal_unit_type == NaluType::SpsNut)
+            .and_then(|a| a.nalus.first())
+            .ok_or_else(|| anyhow!("No SPS found in the stream"))?;
+
+        let mut annexb = vec![0x00, 0x00, 0x01];
+        annexb.extend_from_slice(first_sps);
+        let mut cursor = Cursor::new(annexb.as_ref());
+        let nalu = Nalu::next(&mut cursor)?;
+        let mut parser = Parser::default();
+        let sps = parser.parse_sps(&nalu)?;
+
+        // The general part of profile_tier_level() is byte-aligned and starts
+        // right after the 2-byte NALU header and the first byte of the SPS.
+        let ptl = rbsp(first_sps);
+        if ptl.len() < 15 {
+            return Err(anyhow!("SPS is too short"));
+        }
+        let mut ptl = Cursor::new(&ptl[3..15]);
+        let byte = ptl.get_u8();
+
+        let min_spatial_segmentation_idc = if sps.vui_parameters_present_flag {
+            sps.vui_parameters.min_spatial_segmentation_idc as u16
+        } else {
+            0
+        };
+
+        Ok(Self {
+            configuration_version: 1,
+            general_profile_space: byte >> 6,
+            general_tier_flag: (byte >> 5) & 0x1 != 0,
+            general_profile_idc: byte & 0x1f,
+            general_profile_compatibility_flags: ptl.get_u32(),
+            general_constraint_indicator_flags: ptl.get_uint(6),
+            general_level_idc: ptl.get_u8(),
+            min_spatial_segmentation_idc,
+            parallelism_type: 0,
+            chroma_format_idc: sps.chroma_format_idc,
+            bit_depth_luma_minus8: sps.bit_depth_luma_minus8,
+            bit_depth_chroma_minus8: sps.bit_depth_chroma_minus8,
+            avg_frame_rate: 0,
+            constant_frame_rate: 0,
+            num_temporal_layers: sps.max_sub_layers_minus1 + 1,
+            temporal_id_nested: sps.temporal_id_nesting_flag,
+            length_size_minus_one: (length_size - 1) as u8,
+            arrays,
+        })
+    }
+
+    /// Serializes the record, e.g. to use as the payload of an `hvcC` box.
+    pub fn write(&self) -> anyhow::Result<Vec<u8>> {
+        let mut out = vec![
+            self.configuration_version,
+            (self.general_profile_space << 6)
+                | (u8::from(self.general_tier_flag) << 5)
+                | (self.general_profile_idc & 0x1f),
+        ];
+
+        out.put_u32(self.general_profile_compatibility_flags);
+        out.put_uint(self.general_constraint_indicator_flags, 6);
+        out.put_u8(self.general_level_idc);
+        out.put_u16(0xf000 | (self.min_spatial_segmentation_idc & 0xfff));
+        out.put_u8(0xfc | (self.parallelism_type & 0x3));
+        out.put_u8(0xfc | (self.chroma_format_idc & 0x3));
+        out.put_u8(0xf8 | (self.bit_depth_luma_minus8 & 0x7));
+        out.put_u8(0xf8 | (self.bit_depth_chroma_minus8 & 0x7));
+        out.put_u16(self.avg_frame_rate);
+        out.put_u8(
+            (self.constant_frame_rate << 6)
+                | ((self.num_temporal_layers & 0x7) << 3)
+                | (u8::from(self.temporal_id_nested) << 2)
+                | (self.length_size_minus_one & 0x3),
+        );
+
+        out.put_u8(u8::try_from(self.arrays.len())?);
+        for array in &self.arrays {
+            out.put_u8((u8::from(array.array_completeness) << 7) | array.nal_unit_type as u8);
+            out.put_u16(u16::try_from(array.nalus.len())?);
+            for nalu in &array.nalus {
+                out.put_u16(u16::try_from(nalu.len())?);
+                out.extend_from_slice(nalu);
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// The size in bytes of the NALU length field of the described stream.
+    pub fn length_size(&self) -> usize {
+        usize::from(self.length_size_minus_one) + 1
+    }
+
+    /// Returns the NAL units of the record as an Annex B stream, suitable for
+    /// priming a decoder before sending it the converted samples.
+    pub fn to_annexb(&self) -> Vec<u8> {
+        let mut out = vec![];
+
+        for nalu in self.arrays.iter().flat_map(|a| a.nalus.iter()) {
+            out.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+            out.extend_from_slice(nalu);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::nalu::annexb_nalus;
+    use crate::codec::h264::nalu::annexb_to_length_prefixed;
+    use crate::codec::h264::nalu::length_prefixed_to_annexb;
+    use crate::codec::h265::hvcc::rbsp;
+    use crate::codec::h265::hvcc::HevcDecoderConfigurationRecord;
+    use crate::codec::h265::parser::NaluType;
+
+    const STREAM_TEST_25_FPS: &[u8] = include_bytes!("test_data/test-25fps.h265");
+    const STREAM_BEAR: &[u8] = include_bytes!("test_data/bear.h265");
+
+    #[test]
+    fn hvcc_from_annexb_roundtrip() {
+        for stream in [STREAM_TEST_25_FPS, STREAM_BEAR] {
+            let record = HevcDecoderConfigurationRecord::from_annexb(stream, 4).unwrap();
+            assert_eq!(record.configuration_version, 1);
+            assert_eq!(record.general_profile_idc, 1);
+            assert_eq!(record.chroma_format_idc, 1);
+            assert_eq!(record.length_size(), 4);
+
+            let types = record
+                .arrays
+                .iter()
+                .map(|a| a.nal_unit_type)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                types,
+                vec![NaluType::VpsNut, NaluType::SpsNut, NaluType::PpsNut]
+            );
+
+            let bytes = record.write().unwrap();
+            assert_eq!(
+                HevcDecoderConfigurationRecord::parse(&bytes).unwrap(),
+                record
+            );
+        }
+    }
+
+    #[test]
+    fn hvcc_ignores_sei() {
+        // Prefix SEI NAL units with a different payload for every frame.
+        let mut stream = STREAM_TEST_25_FPS.to_vec();
+        for i in 0..16u8 {
+            stream.extend_from_slice(&[0x00, 0x00, 0x01, 0x4e, 0x01, 0x05, 0x01, i, 0x80]);
+        }
+
+        let record = HevcDecoderConfigurationRecord::from_annexb(&stream, 4).unwrap();
+        assert_eq!(
+            record,
+            HevcDecoderConfigurationRecord::from_annexb(STREAM_TEST_25_FPS, 4).unwrap()
+        );
+        assert_eq!(record.arrays.len(), 3);
+    }
+
+    #[test]
+    fn hvcc_invalid() {
+        assert!(HevcDecoderConfigurationRecord::parse(&[]).is_err());
+        assert!(HevcDecoderConfigurationRecord::parse(&[0; 23]).is_err());
+    }
+
+    #[test]
+    fn rbsp_removes_emulation_prevention() {
+        assert_eq!(
+            rbsp(&[0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x03]),
+            [0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03]
+        );
+    }
+
+    #[test]
+    fn stream_roundtrip() {
+        let length_prefixed = annexb_to_length_prefixed(STREAM_TEST_25_FPS, 2).unwrap();
+        let annexb = length_prefixed_to_annexb(&length_prefixed, 2).unwrap();
+        assert_eq!(annexb_nalus(&annexb), annexb_nalus(STREAM_TEST_25_FPS));
+    }
+}
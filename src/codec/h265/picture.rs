@@ -59,8 +59,9 @@ impl PictureData {
         let nalu_type = slice.nalu.header.type_;
         let is_irap = nalu_type.is_irap();
 
-        // We assume HandleCraAsBlafFLag == 0, as it is only set through
-        // external means, which we do not provide.
+        // HandleCraAsBlaFlag is only set through external means. Decoders
+        // that need it, e.g. when resuming after a seek, pass
+        // `first_picture_after_eos` instead, which has the same effect.
 
         let mut pic_order_cnt_msb = 0;
         let slice_pic_order_cnt_lsb: i32 = hdr.pic_order_cnt_lsb.into();
@@ -0,0 +1,341 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! RTP payload format for H.265, as per RFC 7798.
+//!
+//! Single NAL unit packets, aggregation packets (APs) and fragmentation units
+//! (FUs) are supported, without decoding order numbers, i.e. with
+//! `sprop-max-don-diff=0`. PACI packets are not supported.
+
+use anyhow::anyhow;
+use bytes::Buf;
+
+use crate::codec::h264::nalu::annexb_nalus;
+use crate::codec::rtp::fragments;
+use crate::codec::rtp::AccessUnitAssembler;
+use crate::codec::rtp::RtpDepayloader;
+use crate::codec::rtp::RtpPacket;
+use crate::codec::rtp::RtpPayloader;
+
+/// NAL unit type of aggregation packets.
+const AP: u8 = 48;
+/// NAL unit type of fragmentation units.
+const FU: u8 = 49;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Returns the nal_unit_type of the NAL unit header starting with `byte`.
+fn nalu_type(byte: u8) -> u8 {
+    (byte >> 1) & 0x3f
+}
+
+/// Splits Annex B access units into RTP payloads.
+pub struct Payloader {
+    max_payload_size: usize,
+}
+
+impl Payloader {
+    /// Creates a payloader producing payloads of at most `max_payload_size`
+    /// bytes.
+    pub fn new(max_payload_size: usize) -> Self {
+        Self { max_payload_size }
+    }
+
+    /// Appends the NAL units of `ap` to `out` as a single NAL unit packet or
+    /// an aggregation packet, depending on their number.
+    fn flush_ap(ap: &mut Vec<&[u8]>, out: &mut Vec<Vec<u8>>) -> anyhow::Result<()> {
+        match ap.len() {
+            0 => (),
+            1 => out.push(ap[0].to_vec()),
+            _ => {
+                // F is the OR of the aggregated F bits, and LayerId and TID
+                // their minimum.
+                let f = ap.iter().fold(0, |f, nalu| f | (nalu[0] & 0x80));
+                let layer_id = ap
+                    .iter()
+                    .map(|nalu| ((nalu[0] & 0x1) << 5) | (nalu[1] >> 3))
+                    .min()
+                    .unwrap_or(0);
+                let tid = ap.iter().map(|nalu| nalu[1] & 0x7).min().unwrap_or(0);
+
+                let mut packet = vec![f | (AP << 1) | (layer_id >> 5), (layer_id << 3) | tid];
+                for nalu in ap.iter() {
+                    packet.extend_from_slice(&u16::try_from(nalu.len())?.to_be_bytes());
+                    packet.extend_from_slice(nalu);
+                }
+
+                out.push(packet);
+            }
+        }
+
+        ap.clear();
+
+        Ok(())
+    }
+}
+
+impl RtpPayloader for Payloader {
+    fn payload(&mut self, frame: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut packets = vec![];
+        let mut ap = vec![];
+        // Size of the AP being built, including its payload header.
+        let mut ap_size = 2;
+
+        for nalu in annexb_nalus(frame) {
+            if nalu.len() < 2 {
+                return Err(anyhow!("truncated NAL unit header"));
+            }
+
+            if nalu.len() > self.max_payload_size {
+                Self::flush_ap(&mut ap, &mut packets)?;
+                ap_size = 2;
+
+                let payload_header = [(nalu[0] & 0x81) | (FU << 1), nalu[1]];
+                let mut chunks = fragments(&nalu[2..], self.max_payload_size, 3)?.peekable();
+                let mut start = true;
+
+                while let Some(chunk) = chunks.next() {
+                    let mut header = nalu_type(nalu[0]);
+                    if start {
+                        header |= 0x80;
+                    }
+                    if chunks.peek().is_none() {
+                        header |= 0x40;
+                    }
+
+                    let mut packet = Vec::with_capacity(chunk.len() + 3);
+                    packet.extend_from_slice(&payload_header);
+                    packet.push(header);
+                    packet.extend_from_slice(chunk);
+                    packets.push(packet);
+                    start = false;
+                }
+
+                continue;
+            }
+
+            // NAL units too large for the 16-bit size field of aggregation packets are
+            // sent in single NAL unit packets.
+            if nalu.len() > usize::from(u16::MAX) {
+                Self::flush_ap(&mut ap, &mut packets)?;
+                ap_size = 2;
+                packets.push(nalu.to_vec());
+                continue;
+            }
+
+            if ap_size + 2 + nalu.len() > self.max_payload_size {
+                Self::flush_ap(&mut ap, &mut packets)?;
+                ap_size = 2;
+            }
+
+            ap.push(nalu);
+            ap_size += 2 + nalu.len();
+        }
+
+        Self::flush_ap(&mut ap, &mut packets)?;
+
+        Ok(packets)
+    }
+}
+
+/// Reassembles RTP payloads into Annex B access units.
+#[derive(Default)]
+pub struct Depayloader {
+    assembler: AccessUnitAssembler,
+    /// Whether a fragmented NAL unit is being reassembled.
+    in_fu: bool,
+}
+
+impl Depayloader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl RtpDepayloader for Depayloader {
+    fn depayload(&mut self, packet: &RtpPacket) -> anyhow::Result<Option<Vec<u8>>> {
+        let payload = packet.payload;
+        if payload.len() < 2 {
+            return Err(anyhow!("RTP payload is too short"));
+        }
+
+        let type_ = nalu_type(payload[0]);
+        let starts_frame = type_ != FU || payload.get(2).is_some_and(|h| h & 0x80 != 0);
+
+        let in_fu = &mut self.in_fu;
+        self.assembler.process(packet, starts_frame, |out| {
+            match type_ {
+                0..=47 => {
+                    *in_fu = false;
+                    out.extend_from_slice(&START_CODE);
+                    out.extend_from_slice(payload);
+                }
+                AP => {
+                    *in_fu = false;
+                    let mut reader = &payload[2..];
+
+                    while reader.has_remaining() {
+                        if reader.remaining() < 2 {
+                            return Err(anyhow!("truncated aggregation packet"));
+                        }
+
+                        let size = usize::from(reader.get_u16());
+                        if size < 2 || size > reader.remaining() {
+                            return Err(anyhow!("invalid aggregated NAL unit size {}", size));
+                        }
+
+                        out.extend_from_slice(&START_CODE);
+                        out.extend_from_slice(&reader[..size]);
+                        reader.advance(size);
+                    }
+                }
+                FU => {
+                    let header = *payload
+                        .get(2)
+                        .ok_or_else(|| anyhow!("truncated fragmentation unit"))?;
+
+                    if header & 0x80 != 0 {
+                        *in_fu = true;
+                        out.extend_from_slice(&START_CODE);
+                        out.push((payload[0] & 0x81) | ((header & 0x3f) << 1));
+                        out.push(payload[1]);
+                    } else if !*in_fu {
+                        return Err(anyhow!("FU continuation without a start fragment"));
+                    }
+
+                    out.extend_from_slice(&payload[3..]);
+
+                    if header & 0x40 != 0 {
+                        *in_fu = false;
+                    }
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "unsupported NAL unit type {} in RTP payload",
+                        type_
+                    ))
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::nalu::annexb_nalus;
+    use crate::codec::h265::parser::Nalu;
+    use crate::codec::h265::rtp::nalu_type;
+    use crate::codec::h265::rtp::Depayloader;
+    use crate::codec::h265::rtp::Payloader;
+    use crate::codec::h265::rtp::AP;
+    use crate::codec::h265::rtp::FU;
+    use crate::codec::rtp::tests::roundtrip;
+    use crate::codec::rtp::RtpDepayloader;
+    use crate::codec::rtp::RtpPacket;
+    use crate::utils::NalIterator;
+
+    const STREAM: &[u8] = include_bytes!("test_data/64x64-I-P-B-P.h265");
+
+    /// Returns the access units of `STREAM`, with 4-byte start codes.
+    fn access_units() -> Vec<Vec<u8>> {
+        let mut aus: Vec<Vec<u8>> = vec![];
+        let mut new_au = true;
+
+        for nalu in NalIterator::<Nalu>::new(STREAM) {
+            let nalu = annexb_nalus(&nalu)[0].to_vec();
+
+            if new_au {
+                aus.push(vec![]);
+            }
+
+            let au = aus.last_mut().unwrap();
+            au.extend_from_slice(&[0, 0, 0, 1]);
+            au.extend_from_slice(&nalu);
+            new_au = nalu_type(nalu[0]) < 32;
+        }
+
+        aus
+    }
+
+    #[test]
+    fn rtp_roundtrip() {
+        let aus = access_units();
+        assert_eq!(aus.len(), 3);
+
+        for max_payload_size in [20, 100, 1200] {
+            let (packets, depayloaded) = roundtrip(
+                &mut Payloader::new(max_payload_size),
+                &mut Depayloader::new(),
+                &aus,
+                &[],
+            );
+
+            assert!(packets.iter().all(|p| p.len() <= max_payload_size));
+            assert_eq!(depayloaded, aus);
+        }
+
+        // Parameter sets are aggregated, and larger NAL units fragmented.
+        let (packets, _) = roundtrip(&mut Payloader::new(100), &mut Depayloader::new(), &aus, &[]);
+        assert_eq!(nalu_type(packets[0][0]), AP);
+        assert!(packets.iter().any(|p| nalu_type(p[0]) == FU));
+    }
+
+    #[test]
+    fn rtp_large_nalu() {
+        // A NAL unit that fits in a payload but not in the 16-bit size field
+        // of an aggregation packet, between two small ones.
+        let mut au = vec![];
+        for nalu in [&[0x40, 0x01, 1][..], &[0x26, 0x01], &[0x44, 0x01, 3]] {
+            au.extend_from_slice(&[0, 0, 0, 1]);
+            au.extend_from_slice(nalu);
+            if nalu.len() < 3 {
+                au.resize(au.len() + 70000, 0xaa);
+            }
+        }
+        let aus = [au];
+
+        let (packets, depayloaded) = roundtrip(
+            &mut Payloader::new(100_000),
+            &mut Depayloader::new(),
+            &aus,
+            &[],
+        );
+        assert_eq!(depayloaded, aus);
+        // It is sent alone, in a single NAL unit packet.
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[1][..2], [0x26, 0x01]);
+        assert_eq!(packets[1].len(), 2 + 70000);
+    }
+
+    #[test]
+    fn rtp_packet_loss() {
+        let aus = access_units();
+
+        let (packets, _) = roundtrip(&mut Payloader::new(100), &mut Depayloader::new(), &aus, &[]);
+        // Lose the last fragment of the first fragmented NAL unit.
+        let lost = packets
+            .iter()
+            .position(|p| nalu_type(p[0]) == FU && p[2] & 0x40 != 0)
+            .unwrap() as u16;
+
+        let (_, depayloaded) = roundtrip(
+            &mut Payloader::new(100),
+            &mut Depayloader::new(),
+            &aus,
+            &[lost],
+        );
+        assert_eq!(depayloaded, aus[1..]);
+
+        // PACI packets are not supported.
+        let packet = RtpPacket {
+            sequence_number: 0,
+            timestamp: 0,
+            marker: true,
+            payload: &[50 << 1, 1, 0, 0],
+        };
+        assert!(Depayloader::new().depayload(&packet).is_err());
+    }
+}
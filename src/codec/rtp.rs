@@ -0,0 +1,207 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Common definitions for the RTP payload formats of the supported codecs.
+//!
+//! Only the payload of the RTP packets is handled here: building and parsing
+//! the RTP header itself, as well as choosing the RTP timestamps, is left to
+//! the client. The codec-specific implementations live in the `rtp` module of
+//! each codec.
+
+use anyhow::anyhow;
+use log::debug;
+
+/// The fields of an RTP packet that the depayloaders care about.
+#[derive(Clone, Copy, Debug)]
+pub struct RtpPacket<'a> {
+    /// The RTP sequence number, used to detect packet loss.
+    pub sequence_number: u16,
+    /// The RTP timestamp. All the packets of an access unit share the same
+    /// timestamp.
+    pub timestamp: u32,
+    /// The RTP marker bit, set on the last packet of an access unit.
+    pub marker: bool,
+    /// The RTP payload.
+    pub payload: &'a [u8],
+}
+
+/// Splits encoded frames into RTP payloads.
+pub trait RtpPayloader {
+    /// Packetizes one encoded frame, e.g. the bitstream of a
+    /// [`CodedBitstreamBuffer`](crate::encoder::CodedBitstreamBuffer), into
+    /// RTP payloads of at most the maximum payload size given at construction
+    /// time.
+    ///
+    /// All the returned payloads are to be sent with the same RTP timestamp,
+    /// and the marker bit set on the last one.
+    fn payload(&mut self, frame: &[u8]) -> anyhow::Result<Vec<Vec<u8>>>;
+}
+
+/// Reassembles RTP payloads into access units.
+pub trait RtpDepayloader {
+    /// Processes one RTP packet, returning a complete access unit, suitable
+    /// for [`StatelessVideoDecoder::decode`](crate::decoder::stateless::StatelessVideoDecoder::decode),
+    /// if `packet` completes one.
+    ///
+    /// Access units affected by packet loss are dropped, and depayloading
+    /// resumes at the start of the next access unit.
+    fn depayload(&mut self, packet: &RtpPacket) -> anyhow::Result<Option<Vec<u8>>>;
+}
+
+/// Loss and access unit boundary tracking shared by the depayloaders.
+#[derive(Debug, Default)]
+pub(crate) struct AccessUnitAssembler {
+    /// Data of the access unit being assembled.
+    data: Vec<u8>,
+    /// Timestamp of the access unit being assembled, if any.
+    timestamp: Option<u32>,
+    /// Sequence number of the next expected packet.
+    next_sequence_number: Option<u16>,
+    /// Whether the access unit being assembled is to be dropped.
+    discard: bool,
+}
+
+impl AccessUnitAssembler {
+    /// Processes `packet`, which starts a new frame, and therefore can begin
+    /// an access unit, if `starts_frame` is true. `f` is called to append the
+    /// contents of the packet to the access unit, unless the latter is being
+    /// dropped.
+    ///
+    /// Returns the access unit if `packet` completes it.
+    pub(crate) fn process(
+        &mut self,
+        packet: &RtpPacket,
+        starts_frame: bool,
+        f: impl FnOnce(&mut Vec<u8>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let lost = matches!(self.next_sequence_number, Some(seq) if seq != packet.sequence_number);
+        self.next_sequence_number = Some(packet.sequence_number.wrapping_add(1));
+
+        if self.timestamp != Some(packet.timestamp) {
+            if !self.data.is_empty() {
+                debug!("Dropping access unit without marker bit");
+            }
+
+            self.data.clear();
+            self.timestamp = Some(packet.timestamp);
+            self.discard = !starts_frame;
+        } else if lost {
+            debug!("Packet loss detected, dropping access unit");
+            self.discard = true;
+        }
+
+        let res = if self.discard {
+            Ok(())
+        } else {
+            f(&mut self.data)
+        };
+
+        if res.is_err() {
+            self.discard = true;
+        }
+
+        if !packet.marker {
+            return res.map(|()| None);
+        }
+
+        self.timestamp = None;
+        let data = std::mem::take(&mut self.data);
+
+        res.map(|()| (!self.discard && !data.is_empty()).then_some(data))
+    }
+}
+
+/// Splits `data` into chunks so that each chunk fits in a payload of
+/// `max_payload_size` bytes after a header of `header_size` bytes.
+pub(crate) fn fragments(
+    data: &[u8],
+    max_payload_size: usize,
+    header_size: usize,
+) -> anyhow::Result<std::slice::Chunks<'_, u8>> {
+    let chunk_size = max_payload_size
+        .checked_sub(header_size)
+        .filter(|&size| size > 0)
+        .ok_or_else(|| anyhow!("maximum payload size is too small"))?;
+
+    Ok(data.chunks(chunk_size))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::codec::rtp::AccessUnitAssembler;
+    use crate::codec::rtp::RtpDepayloader;
+    use crate::codec::rtp::RtpPacket;
+    use crate::codec::rtp::RtpPayloader;
+
+    /// Payloads `aus`, then depayloads the resulting packets, except those
+    /// whose sequence number is in `lost`.
+    pub(crate) fn roundtrip(
+        payloader: &mut impl RtpPayloader,
+        depayloader: &mut impl RtpDepayloader,
+        aus: &[Vec<u8>],
+        lost: &[u16],
+    ) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let mut packets = vec![];
+        let mut depayloaded = vec![];
+        let mut sequence_number = 0u16;
+
+        for (i, au) in aus.iter().enumerate() {
+            let payloads = payloader.payload(au).unwrap();
+            let num_payloads = payloads.len();
+
+            for (j, payload) in payloads.into_iter().enumerate() {
+                if !lost.contains(&sequence_number) {
+                    let packet = RtpPacket {
+                        sequence_number,
+                        timestamp: i as u32 * 3000,
+                        marker: j == num_payloads - 1,
+                        payload: &payload,
+                    };
+
+                    if let Some(au) = depayloader.depayload(&packet).unwrap() {
+                        depayloaded.push(au);
+                    }
+                }
+
+                sequence_number = sequence_number.wrapping_add(1);
+                packets.push(payload);
+            }
+        }
+
+        (packets, depayloaded)
+    }
+
+    #[test]
+    fn access_unit_assembler() {
+        let mut assembler = AccessUnitAssembler::default();
+        let mut process = |sequence_number, timestamp, marker, starts_frame| {
+            let packet = RtpPacket {
+                sequence_number,
+                timestamp,
+                marker,
+                payload: &[0xaa],
+            };
+
+            assembler
+                .process(&packet, starts_frame, |out| {
+                    out.extend_from_slice(packet.payload);
+                    Ok(())
+                })
+                .unwrap()
+        };
+
+        // Sequence numbers wrap around.
+        assert_eq!(process(u16::MAX, 0, false, true), None);
+        assert_eq!(process(0, 0, true, false), Some(vec![0xaa, 0xaa]));
+
+        // Access units not starting with the start of a frame are dropped.
+        assert_eq!(process(1, 1, true, false), None);
+
+        // So are access units with missing packets, or a missing marker bit.
+        assert_eq!(process(2, 2, false, true), None);
+        assert_eq!(process(4, 2, true, false), None);
+        assert_eq!(process(5, 3, false, true), None);
+        assert_eq!(process(6, 4, true, true), Some(vec![0xaa]));
+    }
+}
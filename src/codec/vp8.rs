@@ -5,3 +5,4 @@
 mod bool_decoder;
 pub mod parser;
 mod probs;
+pub mod rtp;
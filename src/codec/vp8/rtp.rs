@@ -0,0 +1,175 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! RTP payload format for VP8, as per RFC 7741.
+
+use anyhow::anyhow;
+
+use crate::codec::rtp::fragments;
+use crate::codec::rtp::AccessUnitAssembler;
+use crate::codec::rtp::RtpDepayloader;
+use crate::codec::rtp::RtpPacket;
+use crate::codec::rtp::RtpPayloader;
+
+/// Size of the payload descriptors we write: the mandatory byte, the
+/// extension byte and a 15-bit PictureID.
+const DESCRIPTOR_SIZE: usize = 4;
+
+/// Splits VP8 frames into RTP payloads.
+pub struct Payloader {
+    max_payload_size: usize,
+    /// PictureID of the next frame.
+    picture_id: u16,
+}
+
+impl Payloader {
+    /// Creates a payloader producing payloads of at most `max_payload_size`
+    /// bytes.
+    pub fn new(max_payload_size: usize) -> Self {
+        Self {
+            max_payload_size,
+            picture_id: 0,
+        }
+    }
+}
+
+impl RtpPayloader for Payloader {
+    fn payload(&mut self, frame: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut packets = vec![];
+
+        // The whole frame is sent as partition 0.
+        for (i, chunk) in fragments(frame, self.max_payload_size, DESCRIPTOR_SIZE)?.enumerate() {
+            let mut packet = Vec::with_capacity(DESCRIPTOR_SIZE + chunk.len());
+            // X, and S on the first packet.
+            packet.push(0x80 | if i == 0 { 0x10 } else { 0 });
+            // I.
+            packet.push(0x80);
+            // M and the 15-bit PictureID.
+            packet.extend_from_slice(&(0x8000 | self.picture_id).to_be_bytes());
+            packet.extend_from_slice(chunk);
+            packets.push(packet);
+        }
+
+        self.picture_id = (self.picture_id + 1) & 0x7fff;
+
+        Ok(packets)
+    }
+}
+
+/// Returns the size of the VP8 payload descriptor at the start of `payload`,
+/// and whether the payload starts a frame.
+fn parse_descriptor(payload: &[u8]) -> anyhow::Result<(usize, bool)> {
+    let first = *payload
+        .first()
+        .ok_or_else(|| anyhow!("empty RTP payload"))?;
+    let starts_frame = first & 0x10 != 0 && first & 0x7 == 0;
+    let mut size = 1;
+
+    if first & 0x80 != 0 {
+        let ext = *payload
+            .get(1)
+            .ok_or_else(|| anyhow!("truncated payload descriptor"))?;
+        size += 1;
+
+        // I, with M selecting a 15-bit PictureID.
+        if ext & 0x80 != 0 {
+            let picture_id = *payload
+                .get(size)
+                .ok_or_else(|| anyhow!("truncated payload descriptor"))?;
+            size += if picture_id & 0x80 != 0 { 2 } else { 1 };
+        }
+
+        // L.
+        if ext & 0x40 != 0 {
+            size += 1;
+        }
+
+        // T or K.
+        if ext & 0x30 != 0 {
+            size += 1;
+        }
+    }
+
+    if size >= payload.len() {
+        return Err(anyhow!("RTP payload without VP8 data"));
+    }
+
+    Ok((size, starts_frame))
+}
+
+/// Reassembles RTP payloads into VP8 frames.
+#[derive(Default)]
+pub struct Depayloader {
+    assembler: AccessUnitAssembler,
+}
+
+impl Depayloader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl RtpDepayloader for Depayloader {
+    fn depayload(&mut self, packet: &RtpPacket) -> anyhow::Result<Option<Vec<u8>>> {
+        let (descriptor_size, starts_frame) = parse_descriptor(packet.payload)?;
+
+        self.assembler.process(packet, starts_frame, |out| {
+            out.extend_from_slice(&packet.payload[descriptor_size..]);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::rtp::tests::roundtrip;
+    use crate::codec::vp8::rtp::parse_descriptor;
+    use crate::codec::vp8::rtp::Depayloader;
+    use crate::codec::vp8::rtp::Payloader;
+    use crate::utils::IvfIterator;
+
+    const STREAM: &[u8] = include_bytes!("test_data/test-25fps.vp8");
+
+    #[test]
+    fn rtp_roundtrip() {
+        let frames = IvfIterator::new(STREAM)
+            .take(10)
+            .map(|f| f.to_vec())
+            .collect::<Vec<_>>();
+
+        for max_payload_size in [100, 1200] {
+            let (packets, depayloaded) = roundtrip(
+                &mut Payloader::new(max_payload_size),
+                &mut Depayloader::new(),
+                &frames,
+                &[],
+            );
+
+            assert!(packets.iter().all(|p| p.len() <= max_payload_size));
+            assert_eq!(depayloaded, frames);
+        }
+
+        // Losing any packet of a frame drops it.
+        let (_, depayloaded) = roundtrip(
+            &mut Payloader::new(100),
+            &mut Depayloader::new(),
+            &frames,
+            &[1],
+        );
+        assert_eq!(depayloaded, frames[1..]);
+    }
+
+    #[test]
+    fn descriptor() {
+        // Minimal descriptor starting partition 0.
+        assert_eq!(parse_descriptor(&[0x10, 0xaa]).unwrap(), (1, true));
+        // Extended descriptor with a 7-bit PictureID, TL0PICIDX and
+        // TID/KEYIDX, starting partition 1.
+        assert_eq!(
+            parse_descriptor(&[0x91, 0xf0, 0x12, 0x34, 0x56, 0xaa]).unwrap(),
+            (5, false)
+        );
+        assert!(parse_descriptor(&[0x90, 0x80, 0x80]).is_err());
+    }
+}
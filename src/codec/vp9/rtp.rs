@@ -0,0 +1,390 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! RTP payload format for VP9, as per RFC 9628.
+//!
+//! The payloader uses flexible mode, with the picture references derived from
+//! the frame headers. The depayloader accepts both flexible and non-flexible
+//! modes.
+
+use anyhow::anyhow;
+
+use crate::codec::rtp::fragments;
+use crate::codec::rtp::AccessUnitAssembler;
+use crate::codec::rtp::RtpDepayloader;
+use crate::codec::rtp::RtpPacket;
+use crate::codec::rtp::RtpPayloader;
+use crate::codec::vp9::parser::FrameType;
+use crate::codec::vp9::parser::Parser;
+use crate::codec::vp9::parser::NUM_REF_FRAMES;
+
+/// Splits VP9 frames into RTP payloads.
+pub struct Payloader {
+    max_payload_size: usize,
+    parser: Parser,
+    /// PictureID of the next frame.
+    picture_id: u16,
+    /// PictureID of the frame held by each reference slot.
+    ref_picture_ids: [Option<u16>; NUM_REF_FRAMES],
+}
+
+impl Payloader {
+    /// Creates a payloader producing payloads of at most `max_payload_size`
+    /// bytes.
+    pub fn new(max_payload_size: usize) -> Self {
+        Self {
+            max_payload_size,
+            parser: Default::default(),
+            picture_id: 0,
+            ref_picture_ids: Default::default(),
+        }
+    }
+}
+
+impl RtpPayloader for Payloader {
+    fn payload(&mut self, frame: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut packets = vec![];
+
+        // Each frame of a superframe is sent as a picture of its own.
+        for frame in self.parser.parse_chunk(frame)? {
+            let hdr = &frame.header;
+            let is_inter = hdr.frame_type == FrameType::InterFrame
+                && !hdr.intra_only
+                && !hdr.show_existing_frame;
+
+            // P_DIFF of each distinct reference, as far as they can be coded.
+            let mut p_diffs = vec![];
+            if is_inter {
+                for idx in hdr.ref_frame_idx {
+                    let diff = self.ref_picture_ids[usize::from(idx)]
+                        .map(|id| self.picture_id.wrapping_sub(id) & 0x7fff);
+
+                    if let Some(diff @ 1..=127) = diff {
+                        if !p_diffs.contains(&(diff as u8)) {
+                            p_diffs.push(diff as u8);
+                        }
+                    }
+                }
+            }
+
+            // I and F, P if the frame has references.
+            let mut flags = 0x90;
+            if !p_diffs.is_empty() {
+                flags |= 0x40;
+            }
+
+            let mut descriptor = vec![flags];
+            descriptor.extend_from_slice(&(0x8000 | self.picture_id).to_be_bytes());
+            for (i, diff) in p_diffs.iter().enumerate() {
+                let n = u8::from(i < p_diffs.len() - 1);
+                descriptor.push((diff << 1) | n);
+            }
+
+            // Scalability structure with the resolution of the single spatial
+            // layer, on the first packet of keyframes.
+            let mut ss = vec![];
+            if hdr.frame_type == FrameType::KeyFrame && !hdr.show_existing_frame {
+                descriptor[0] |= 0x02;
+                ss.push(0x10);
+                ss.extend_from_slice(&u16::try_from(hdr.width)?.to_be_bytes());
+                ss.extend_from_slice(&u16::try_from(hdr.height)?.to_be_bytes());
+            }
+
+            let header_size = descriptor.len() + ss.len();
+            let mut chunks = fragments(frame.as_ref(), self.max_payload_size, header_size)?
+                .enumerate()
+                .peekable();
+
+            while let Some((i, chunk)) = chunks.next() {
+                let mut packet = Vec::with_capacity(header_size + chunk.len());
+                packet.extend_from_slice(&descriptor);
+
+                // B and V on the first packet, E on the last.
+                if i == 0 {
+                    packet[0] |= 0x08;
+                    packet.extend_from_slice(&ss);
+                } else {
+                    packet[0] &= !0x02;
+                }
+                if chunks.peek().is_none() {
+                    packet[0] |= 0x04;
+                }
+
+                packet.extend_from_slice(chunk);
+                packets.push(packet);
+            }
+
+            for (slot, id) in self.ref_picture_ids.iter_mut().enumerate() {
+                if hdr.refresh_frame_flags & (1 << slot) != 0 {
+                    *id = Some(self.picture_id);
+                }
+            }
+
+            self.picture_id = (self.picture_id + 1) & 0x7fff;
+        }
+
+        Ok(packets)
+    }
+}
+
+/// The fields of the VP9 payload descriptor the depayloader needs.
+#[derive(Debug, PartialEq, Eq)]
+struct Descriptor {
+    /// Size of the descriptor, in bytes.
+    size: usize,
+    /// Start of a frame.
+    begin: bool,
+}
+
+impl Descriptor {
+    fn parse(payload: &[u8]) -> anyhow::Result<Self> {
+        let byte = |pos: usize| {
+            payload
+                .get(pos)
+                .copied()
+                .ok_or_else(|| anyhow!("truncated payload descriptor"))
+        };
+
+        let flags = byte(0)?;
+        let has_picture_id = flags & 0x80 != 0;
+        let inter_predicted = flags & 0x40 != 0;
+        let has_layer_indices = flags & 0x20 != 0;
+        let flexible = flags & 0x10 != 0;
+        let has_ss = flags & 0x02 != 0;
+
+        let mut size = 1;
+
+        if has_picture_id {
+            size += if byte(size)? & 0x80 != 0 { 2 } else { 1 };
+        }
+
+        if has_layer_indices {
+            // TID/U/SID/D, followed by TL0PICIDX in non-flexible mode.
+            size += if flexible { 1 } else { 2 };
+        }
+
+        if flexible && inter_predicted {
+            // Up to 3 P_DIFF, the N bit signaling another one follows.
+            for _ in 0..3 {
+                let p_diff = byte(size)?;
+                size += 1;
+
+                if p_diff & 0x1 == 0 {
+                    break;
+                }
+            }
+        }
+
+        if has_ss {
+            let ss = byte(size)?;
+            size += 1;
+
+            let num_spatial_layers = usize::from(ss >> 5) + 1;
+            if ss & 0x10 != 0 {
+                size += 4 * num_spatial_layers;
+            }
+
+            if ss & 0x08 != 0 {
+                let num_pictures = byte(size)?;
+                size += 1;
+
+                for _ in 0..num_pictures {
+                    let num_refs = usize::from((byte(size)? >> 2) & 0x3);
+                    size += 1 + num_refs;
+                }
+            }
+        }
+
+        if size >= payload.len() {
+            return Err(anyhow!("RTP payload without VP9 data"));
+        }
+
+        Ok(Self {
+            size,
+            begin: flags & 0x08 != 0,
+        })
+    }
+}
+
+/// Reassembles RTP payloads into VP9 frames, or superframes if a picture is
+/// made of several frames, e.g. spatial layers.
+#[derive(Default)]
+pub struct Depayloader {
+    assembler: AccessUnitAssembler,
+    /// Offset of each frame in the access unit being assembled.
+    frame_offsets: Vec<usize>,
+}
+
+impl Depayloader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl RtpDepayloader for Depayloader {
+    fn depayload(&mut self, packet: &RtpPacket) -> anyhow::Result<Option<Vec<u8>>> {
+        let descriptor = Descriptor::parse(packet.payload)?;
+
+        let frame_offsets = &mut self.frame_offsets;
+        let au = self.assembler.process(packet, descriptor.begin, |out| {
+            if descriptor.begin {
+                if out.is_empty() {
+                    frame_offsets.clear();
+                }
+
+                frame_offsets.push(out.len());
+            } else if frame_offsets.is_empty() {
+                return Err(anyhow!("missing start of frame"));
+            }
+
+            out.extend_from_slice(&packet.payload[descriptor.size..]);
+            Ok(())
+        })?;
+
+        let Some(mut au) = au else {
+            return Ok(None);
+        };
+
+        if self.frame_offsets.len() > 1 {
+            let frame_sizes = self
+                .frame_offsets
+                .iter()
+                .zip(self.frame_offsets.iter().skip(1).chain([&au.len()]))
+                .map(|(start, end)| end - start)
+                .collect::<Vec<_>>();
+
+            append_superframe_index(&mut au, &frame_sizes)?;
+        }
+
+        Ok(Some(au))
+    }
+}
+
+/// Appends a superframe index describing frames of `frame_sizes` to `data`.
+fn append_superframe_index(data: &mut Vec<u8>, frame_sizes: &[usize]) -> anyhow::Result<()> {
+    if frame_sizes.len() > 8 {
+        return Err(anyhow!("too many frames for a superframe"));
+    }
+
+    let max_size = frame_sizes.iter().copied().max().unwrap_or(0);
+    let bytes_per_framesize = match max_size {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x10000..=0xff_ffff => 3,
+        _ => 4,
+    };
+
+    let marker = 0xc0 | ((bytes_per_framesize - 1) << 3) | (frame_sizes.len() as u8 - 1);
+    data.push(marker);
+    for size in frame_sizes {
+        let size = u32::try_from(*size)?.to_le_bytes();
+        data.extend_from_slice(&size[..usize::from(bytes_per_framesize)]);
+    }
+    data.push(marker);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::rtp::tests::roundtrip;
+    use crate::codec::rtp::RtpDepayloader;
+    use crate::codec::rtp::RtpPacket;
+    use crate::codec::rtp::RtpPayloader;
+    use crate::codec::vp9::parser::Parser;
+    use crate::codec::vp9::rtp::Depayloader;
+    use crate::codec::vp9::rtp::Descriptor;
+    use crate::codec::vp9::rtp::Payloader;
+    use crate::utils::IvfIterator;
+
+    const STREAM: &[u8] = include_bytes!("test_data/test-25fps.vp9");
+    const SUPERFRAME: &[u8] = include_bytes!("test_data/vp9-superframe.bin");
+
+    #[test]
+    fn rtp_roundtrip() {
+        let frames = IvfIterator::new(STREAM)
+            .take(10)
+            .map(|f| f.to_vec())
+            .collect::<Vec<_>>();
+
+        for max_payload_size in [100, 1200] {
+            let (packets, depayloaded) = roundtrip(
+                &mut Payloader::new(max_payload_size),
+                &mut Depayloader::new(),
+                &frames,
+                &[],
+            );
+
+            assert!(packets.iter().all(|p| p.len() <= max_payload_size));
+            assert_eq!(depayloaded, frames);
+        }
+
+        let (packets, _) = roundtrip(
+            &mut Payloader::new(1200),
+            &mut Depayloader::new(),
+            &frames,
+            &[],
+        );
+
+        // The keyframe starts with I, F, B and V, and carries the scalability
+        // structure.
+        let descriptor = Descriptor::parse(&packets[0]).unwrap();
+        assert_eq!(packets[0][0], 0x9a);
+        assert_eq!(descriptor.size, 8);
+        assert_eq!(&packets[0][3..8], &[0x10, 0x01, 0x40, 0, 0xf0]);
+
+        // The next frame references it.
+        let next = packets.iter().skip(1).find(|p| p[0] & 0x08 != 0).unwrap();
+        assert_eq!(next[0] & 0x40, 0x40);
+        assert_eq!(next[3], 1 << 1);
+    }
+
+    #[test]
+    fn rtp_superframe() {
+        let mut payloader = Payloader::new(1200);
+        let payloads = payloader.payload(SUPERFRAME).unwrap();
+        // One picture per frame.
+        assert_eq!(payloads.iter().filter(|p| p[0] & 0x08 != 0).count(), 2);
+
+        let mut depayloader = Depayloader::new();
+        let mut au = None;
+        for (i, payload) in payloads.iter().enumerate() {
+            let packet = RtpPacket {
+                sequence_number: i as u16,
+                timestamp: 0,
+                marker: i == payloads.len() - 1,
+                payload,
+            };
+            au = depayloader.depayload(&packet).unwrap();
+        }
+
+        // The superframe index is rebuilt.
+        let au = au.unwrap();
+        let original = Parser::default().parse_chunk(SUPERFRAME).unwrap();
+        let rebuilt = Parser::default().parse_chunk(&au).unwrap();
+        assert_eq!(rebuilt.len(), original.len());
+        for (rebuilt, original) in rebuilt.iter().zip(original.iter()) {
+            assert_eq!(rebuilt.as_ref(), original.as_ref());
+        }
+    }
+
+    #[test]
+    fn rtp_non_flexible_mode() {
+        // I with a 7-bit PictureID, L with TL0PICIDX, B and E.
+        let payload = [0xac, 0x05, 0x20, 0x07, 0xaa, 0xbb];
+        let descriptor = Descriptor::parse(&payload).unwrap();
+        assert_eq!(descriptor.size, 4);
+
+        let packet = RtpPacket {
+            sequence_number: 0,
+            timestamp: 0,
+            marker: true,
+            payload: &payload,
+        };
+        assert_eq!(
+            Depayloader::new().depayload(&packet).unwrap(),
+            Some(vec![0xaa, 0xbb])
+        );
+    }
+}
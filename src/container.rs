@@ -0,0 +1,12 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Lightweight readers and writers for the container formats encoded video
+//! commonly travels in.
+//!
+//! Only what is needed to move video samples in and out of the decoders and
+//! encoders of this crate is supported.
+
+pub mod fmp4;
+pub mod mp4;
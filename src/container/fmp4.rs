@@ -0,0 +1,770 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A fragmented MP4 (CMAF) writer for the output of the encoders.
+//!
+//! The writer produces a single video track. The initialization segment
+//! (`ftyp` and `moov`) is written before the first sample, and every
+//! [`CodedBitstreamBuffer`] then becomes a fragment of its own (`moof` and
+//! `mdat`).
+
+use std::io::Write;
+
+use anyhow::anyhow;
+
+use crate::codec::av1::annexb::RawObu;
+use crate::codec::av1::av1c::Av1CodecConfigurationRecord;
+use crate::codec::av1::parser::ObuType;
+use crate::codec::h264::avcc::AvcDecoderConfigurationRecord;
+use crate::codec::h264::nalu::annexb_nalus;
+use crate::codec::h265::hvcc::HevcDecoderConfigurationRecord;
+use crate::codec::vp9::parser::ColorRange;
+use crate::codec::vp9::parser::ColorSpace;
+use crate::codec::vp9::parser::FrameType;
+use crate::codec::vp9::parser::Parser as Vp9Parser;
+use crate::container::mp4::CodecConfig;
+use crate::encoder::CodedBitstreamBuffer;
+use crate::Resolution;
+
+/// Size of the NALU length field of the H.264 and H.265 samples we write.
+const NALU_LENGTH_SIZE: usize = 4;
+
+/// sample_flags of a sync sample: does not depend on other samples.
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+/// sample_flags of a non-sync sample: depends on other samples and is not a
+/// sync sample.
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+/// The unity matrix used by the `mvhd` and `tkhd` boxes.
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// The type of sample entry, and thus codec, of the written track.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleEntryType {
+    /// H.264, with parameter sets in the `avcC` box only.
+    Avc1,
+    /// H.265, with parameter sets in the `hvcC` box only.
+    Hvc1,
+    /// VP9.
+    Vp09,
+    /// AV1.
+    Av01,
+}
+
+impl SampleEntryType {
+    fn fourcc(&self) -> &'static [u8; 4] {
+        match self {
+            SampleEntryType::Avc1 => b"avc1",
+            SampleEntryType::Hvc1 => b"hvc1",
+            SampleEntryType::Vp09 => b"vp09",
+            SampleEntryType::Av01 => b"av01",
+        }
+    }
+}
+
+/// Appends a box of type `type_` to `out`, whose contents are written by `f`.
+fn write_box(out: &mut Vec<u8>, type_: &[u8; 4], f: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(type_);
+    f(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// Appends a FullBox of type `type_` to `out`, whose contents are written by
+/// `f`.
+fn write_full_box(
+    out: &mut Vec<u8>,
+    type_: &[u8; 4],
+    version: u8,
+    flags: u32,
+    f: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, type_, |out| {
+        put_u32(out, (u32::from(version) << 24) | (flags & 0xff_ffff));
+        f(out);
+    })
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Builds the payload of a `vpcC` box from a VP9 keyframe.
+fn vp9_codec_config(bitstream: &[u8], resolution: Resolution) -> anyhow::Result<Vec<u8>> {
+    let frames = Vp9Parser::default().parse_chunk(bitstream)?;
+    let header = &frames
+        .first()
+        .ok_or_else(|| anyhow!("no VP9 frame in bitstream"))?
+        .header;
+
+    // Table A.1 of the VP9 level definitions, by maximum picture size.
+    let picture_size = resolution.width * resolution.height;
+    let level = [
+        (36864, 10),
+        (73728, 11),
+        (122880, 20),
+        (245760, 21),
+        (552960, 30),
+        (983040, 31),
+        (2228224, 40),
+        (8912896, 50),
+        (35651584, 60),
+    ]
+    .iter()
+    .find(|(max_size, _)| picture_size <= *max_size)
+    .map(|(_, level)| *level)
+    .unwrap_or(62);
+
+    let chroma_subsampling = match (header.subsampling_x, header.subsampling_y) {
+        // 4:2:0 with chroma samples vertically between luma samples.
+        (true, true) => 0,
+        (true, false) => 2,
+        _ => 3,
+    };
+
+    // Colour primaries, transfer characteristics and matrix coefficients as
+    // per ISO/IEC 23091-4.
+    let (primaries, transfer, matrix) = match header.color_space {
+        ColorSpace::Bt601 | ColorSpace::Smpte170 => (6, 6, 6),
+        ColorSpace::Bt709 => (1, 1, 1),
+        ColorSpace::Smpte240 => (7, 7, 7),
+        ColorSpace::Bt2020 => (9, 14, 9),
+        ColorSpace::CsSrgb => (1, 13, 0),
+        ColorSpace::Unknown | ColorSpace::Reserved2 => (2, 2, 2),
+    };
+
+    let full_range = u8::from(header.color_range == ColorRange::FullSwing);
+
+    let mut payload = vec![];
+    // FullBox version 1, flags 0.
+    put_u32(&mut payload, 0x0100_0000);
+    payload.push(header.profile as u8);
+    payload.push(level);
+    payload.push(((header.bit_depth as u8) << 4) | (chroma_subsampling << 1) | full_range);
+    payload.extend_from_slice(&[primaries, transfer, matrix]);
+    // codecInitializationDataSize.
+    put_u16(&mut payload, 0);
+
+    Ok(payload)
+}
+
+/// Fragmented MP4 writer for encoded video.
+///
+/// The timestamps of the written buffers are expected to be in units of the
+/// timescale of the track, and buffers to be written in presentation order,
+/// i.e. without frame reordering.
+pub struct Fmp4Muxer<W: Write> {
+    writer: W,
+    sample_entry_type: SampleEntryType,
+    resolution: Resolution,
+    timescale: u32,
+    sample_duration: u32,
+    /// Codec configuration to write in the sample entry. Obtained from the
+    /// first buffer if not provided by the client.
+    config: Option<CodecConfig>,
+    /// Whether the initialization segment has been written.
+    init_written: bool,
+    sequence_number: u32,
+}
+
+impl<W: Write> Fmp4Muxer<W> {
+    /// Creates a new writer for a video track of `resolution` pixels with
+    /// samples lasting `sample_duration` units of `timescale`.
+    pub fn new(
+        writer: W,
+        sample_entry_type: SampleEntryType,
+        resolution: Resolution,
+        timescale: u32,
+        sample_duration: u32,
+    ) -> Self {
+        Self {
+            writer,
+            sample_entry_type,
+            resolution,
+            timescale,
+            sample_duration,
+            config: None,
+            init_written: false,
+            sequence_number: 0,
+        }
+    }
+
+    /// Sets the codec configuration to write in the sample entry, e.g. from
+    /// the parameter sets of the encoder. Without it, the configuration is
+    /// obtained from the first buffer, which must then be a keyframe carrying
+    /// the SPS/PPS or the sequence header.
+    ///
+    /// Has no effect once the first buffer has been written.
+    pub fn set_codec_config(&mut self, config: CodecConfig) {
+        self.config = Some(config);
+    }
+
+    /// Writes `buffer` as a new fragment, preceded by the initialization
+    /// segment if it has not been written yet.
+    pub fn write(&mut self, buffer: &CodedBitstreamBuffer) -> anyhow::Result<()> {
+        if !self.init_written {
+            if self.config.is_none() {
+                self.config = Some(self.codec_config_from(&buffer.bitstream)?);
+            }
+
+            let init = self.init_segment()?;
+            self.writer.write_all(&init)?;
+            self.init_written = true;
+        }
+
+        let (sample, is_sync) = self.sample_from(&buffer.bitstream)?;
+        self.sequence_number += 1;
+        let fragment = self.fragment(&sample, is_sync, buffer.metadata.timestamp)?;
+        self.writer.write_all(&fragment)?;
+
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(mut self) -> anyhow::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn codec_config_from(&self, bitstream: &[u8]) -> anyhow::Result<CodecConfig> {
+        Ok(match self.sample_entry_type {
+            SampleEntryType::Avc1 => CodecConfig::Avc(AvcDecoderConfigurationRecord::from_annexb(
+                bitstream,
+                NALU_LENGTH_SIZE,
+            )?),
+            SampleEntryType::Hvc1 => CodecConfig::Hevc(
+                HevcDecoderConfigurationRecord::from_annexb(bitstream, NALU_LENGTH_SIZE)?,
+            ),
+            SampleEntryType::Vp09 => {
+                CodecConfig::Vp9(vp9_codec_config(bitstream, self.resolution)?)
+            }
+            SampleEntryType::Av01 => {
+                CodecConfig::Av1(Av1CodecConfigurationRecord::from_temporal_unit(bitstream)?)
+            }
+        })
+    }
+
+    /// Converts the encoder output into an MP4 sample, and returns whether it
+    /// is a sync sample.
+    fn sample_from(&self, bitstream: &[u8]) -> anyhow::Result<(Vec<u8>, bool)> {
+        match self.sample_entry_type {
+            SampleEntryType::Avc1 | SampleEntryType::Hvc1 => {
+                let is_h264 = self.sample_entry_type == SampleEntryType::Avc1;
+                let mut sample = Vec::with_capacity(bitstream.len());
+                let mut is_sync = false;
+
+                for nalu in annexb_nalus(bitstream) {
+                    let (is_parameter_set, is_random_access) = if is_h264 {
+                        let nalu_type = nalu[0] & 0x1f;
+                        // SPS, PPS, AUD and SPS extension, then IDR slice.
+                        (matches!(nalu_type, 7..=9 | 13), nalu_type == 5)
+                    } else {
+                        let nalu_type = (nalu[0] >> 1) & 0x3f;
+                        // VPS, SPS, PPS and AUD, then IRAP slices.
+                        (matches!(nalu_type, 32..=35), (16..=23).contains(&nalu_type))
+                    };
+
+                    // avc1 and hvc1 carry their parameter sets in the sample
+                    // entry only.
+                    if is_parameter_set {
+                        continue;
+                    }
+
+                    is_sync |= is_random_access;
+                    put_u32(&mut sample, u32::try_from(nalu.len())?);
+                    sample.extend_from_slice(nalu);
+                }
+
+                Ok((sample, is_sync))
+            }
+            SampleEntryType::Vp09 => {
+                let frames = Vp9Parser::default().parse_chunk(bitstream)?;
+                let is_sync = frames
+                    .iter()
+                    .any(|f| f.header.frame_type == FrameType::KeyFrame);
+
+                Ok((bitstream.to_vec(), is_sync))
+            }
+            SampleEntryType::Av01 => {
+                // Temporal delimiters must be removed from samples, and
+                // keyframes are the only temporal units carrying a sequence
+                // header in the encoder output.
+                let mut sample = Vec::with_capacity(bitstream.len());
+                let mut is_sync = false;
+                let mut consumed = 0;
+
+                while consumed < bitstream.len() {
+                    let (obu, len) = RawObu::parse(&bitstream[consumed..])?;
+                    consumed += len;
+
+                    // Samples carry OBUs with an obu_size field, which
+                    // write_sized() adds to the last OBU if it lacks one.
+                    match obu.obu_type {
+                        ObuType::TemporalDelimiter => (),
+                        ObuType::SequenceHeader => {
+                            is_sync = true;
+                            obu.write_sized(&mut sample)?;
+                        }
+                        _ => obu.write_sized(&mut sample)?,
+                    }
+                }
+
+                Ok((sample, is_sync))
+            }
+        }
+    }
+
+    /// Returns the `ftyp` and `moov` boxes.
+    fn init_segment(&self) -> anyhow::Result<Vec<u8>> {
+        let width = u16::try_from(self.resolution.width)?;
+        let height = u16::try_from(self.resolution.height)?;
+        let config_box = self.config_box()?;
+
+        let mut out = vec![];
+
+        write_box(&mut out, b"ftyp", |out| {
+            out.extend_from_slice(b"iso6");
+            put_u32(out, 0);
+            out.extend_from_slice(b"iso6cmfcisom");
+            out.extend_from_slice(self.sample_entry_type.fourcc());
+        });
+
+        write_box(&mut out, b"moov", |out| {
+            write_full_box(out, b"mvhd", 0, 0, |out| {
+                // creation_time, modification_time.
+                put_u64(out, 0);
+                put_u32(out, self.timescale);
+                // duration: unknown for fragmented files.
+                put_u32(out, 0);
+                // rate and volume.
+                put_u32(out, 0x0001_0000);
+                put_u16(out, 0x0100);
+                out.extend_from_slice(&[0; 10]);
+                UNITY_MATRIX.iter().for_each(|v| put_u32(out, *v));
+                out.extend_from_slice(&[0; 24]);
+                // next_track_ID.
+                put_u32(out, 2);
+            });
+
+            write_box(out, b"trak", |out| {
+                // track_enabled | track_in_movie.
+                write_full_box(out, b"tkhd", 0, 0x3, |out| {
+                    put_u64(out, 0);
+                    // track_ID.
+                    put_u32(out, 1);
+                    put_u32(out, 0);
+                    // duration.
+                    put_u32(out, 0);
+                    out.extend_from_slice(&[0; 8]);
+                    // layer, alternate_group, volume, reserved.
+                    out.extend_from_slice(&[0; 8]);
+                    UNITY_MATRIX.iter().for_each(|v| put_u32(out, *v));
+                    put_u32(out, u32::from(width) << 16);
+                    put_u32(out, u32::from(height) << 16);
+                });
+
+                write_box(out, b"mdia", |out| {
+                    write_full_box(out, b"mdhd", 0, 0, |out| {
+                        put_u64(out, 0);
+                        put_u32(out, self.timescale);
+                        put_u32(out, 0);
+                        // Language "und".
+                        put_u16(out, 0x55c4);
+                        put_u16(out, 0);
+                    });
+
+                    write_full_box(out, b"hdlr", 0, 0, |out| {
+                        put_u32(out, 0);
+                        out.extend_from_slice(b"vide");
+                        out.extend_from_slice(&[0; 12]);
+                        out.extend_from_slice(b"VideoHandler\0");
+                    });
+
+                    write_box(out, b"minf", |out| {
+                        write_full_box(out, b"vmhd", 0, 1, |out| {
+                            out.extend_from_slice(&[0; 8]);
+                        });
+
+                        write_box(out, b"dinf", |out| {
+                            write_full_box(out, b"dref", 0, 0, |out| {
+                                put_u32(out, 1);
+                                // Media data is in the same file.
+                                write_full_box(out, b"url ", 0, 1, |_| ());
+                            });
+                        });
+
+                        write_box(out, b"stbl", |out| {
+                            write_full_box(out, b"stsd", 0, 0, |out| {
+                                put_u32(out, 1);
+                                self.write_sample_entry(out, width, height, &config_box);
+                            });
+
+                            // Samples are all in the fragments.
+                            for type_ in [b"stts", b"stsc", b"stco"] {
+                                write_full_box(out, type_, 0, 0, |out| put_u32(out, 0));
+                            }
+                            write_full_box(out, b"stsz", 0, 0, |out| put_u64(out, 0));
+                        });
+                    });
+                });
+            });
+
+            write_box(out, b"mvex", |out| {
+                write_full_box(out, b"trex", 0, 0, |out| {
+                    // track_ID and default_sample_description_index.
+                    put_u32(out, 1);
+                    put_u32(out, 1);
+                    put_u32(out, self.sample_duration);
+                    // default_sample_size and default_sample_flags.
+                    put_u32(out, 0);
+                    put_u32(out, NON_SYNC_SAMPLE_FLAGS);
+                });
+            });
+        });
+
+        Ok(out)
+    }
+
+    /// Returns the type and payload of the box carrying the codec
+    /// configuration, if any.
+    fn config_box(&self) -> anyhow::Result<Option<(&'static [u8; 4], Vec<u8>)>> {
+        Ok(match &self.config {
+            Some(CodecConfig::Avc(avcc)) => Some((b"avcC", avcc.write()?)),
+            Some(CodecConfig::Hevc(hvcc)) => Some((b"hvcC", hvcc.write()?)),
+            Some(CodecConfig::Vp8(vpcc) | CodecConfig::Vp9(vpcc)) => Some((b"vpcC", vpcc.clone())),
+            Some(CodecConfig::Av1(av1c)) => Some((b"av1C", av1c.write())),
+            None => None,
+        })
+    }
+
+    fn write_sample_entry(
+        &self,
+        out: &mut Vec<u8>,
+        width: u16,
+        height: u16,
+        config_box: &Option<(&'static [u8; 4], Vec<u8>)>,
+    ) {
+        write_box(out, self.sample_entry_type.fourcc(), |out| {
+            out.extend_from_slice(&[0; 6]);
+            // data_reference_index.
+            put_u16(out, 1);
+            out.extend_from_slice(&[0; 16]);
+            put_u16(out, width);
+            put_u16(out, height);
+            // 72 dpi horizontal and vertical resolution.
+            put_u32(out, 0x0048_0000);
+            put_u32(out, 0x0048_0000);
+            put_u32(out, 0);
+            // frame_count.
+            put_u16(out, 1);
+            // compressorname.
+            out.extend_from_slice(&[0; 32]);
+            // depth and pre_defined.
+            put_u16(out, 0x0018);
+            put_u16(out, 0xffff);
+
+            if let Some((type_, payload)) = config_box {
+                write_box(out, type_, |out| out.extend_from_slice(payload));
+            }
+        });
+    }
+
+    /// Returns the `moof` and `mdat` boxes of a fragment containing `sample`.
+    fn fragment(&self, sample: &[u8], is_sync: bool, timestamp: u64) -> anyhow::Result<Vec<u8>> {
+        // trun flags: data-offset, sample-duration, sample-size and
+        // sample-flags present.
+        const TRUN_FLAGS: u32 = 0x1 | 0x100 | 0x200 | 0x400;
+        // The sample size must fit in the trun box, and the sample and its
+        // header in the mdat box.
+        let sample_size = u32::try_from(sample.len())
+            .ok()
+            .filter(|&size| size <= u32::MAX - 8)
+            .ok_or_else(|| anyhow!("sample of {} bytes is too large", sample.len()))?;
+        // Offset of the data_offset field in the moof box.
+        let mut data_offset_pos = 0;
+
+        let mut out = vec![];
+        write_box(&mut out, b"moof", |out| {
+            write_full_box(out, b"mfhd", 0, 0, |out| put_u32(out, self.sequence_number));
+
+            write_box(out, b"traf", |out| {
+                // default-base-is-moof.
+                write_full_box(out, b"tfhd", 0, 0x2_0000, |out| put_u32(out, 1));
+                write_full_box(out, b"tfdt", 1, 0, |out| put_u64(out, timestamp));
+                write_full_box(out, b"trun", 0, TRUN_FLAGS, |out| {
+                    // sample_count.
+                    put_u32(out, 1);
+                    data_offset_pos = out.len();
+                    put_u32(out, 0);
+                    put_u32(out, self.sample_duration);
+                    put_u32(out, sample_size);
+                    put_u32(
+                        out,
+                        if is_sync {
+                            SYNC_SAMPLE_FLAGS
+                        } else {
+                            NON_SYNC_SAMPLE_FLAGS
+                        },
+                    );
+                });
+            });
+        });
+
+        // The sample starts right after the mdat header.
+        let data_offset = out.len() as u32 + 8;
+        out[data_offset_pos..data_offset_pos + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+        write_box(&mut out, b"mdat", |out| out.extend_from_slice(sample));
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::avcc::AvcDecoderConfigurationRecord;
+    use crate::codec::h264::nalu::annexb_nalus;
+    use crate::codec::h264::parser::Nalu as H264Nalu;
+    use crate::codec::h265::parser::Nalu as H265Nalu;
+    use crate::container::fmp4::Fmp4Muxer;
+    use crate::container::fmp4::SampleEntryType;
+    use crate::container::mp4::CodecConfig;
+    use crate::container::mp4::Mp4Demuxer;
+    use crate::encoder::CodedBitstreamBuffer;
+    use crate::encoder::FrameMetadata;
+    use crate::utils::IvfIterator;
+    use crate::utils::NalIterator;
+    use crate::FrameLayout;
+    use crate::Resolution;
+
+    const STREAM_H264: &[u8] = include_bytes!("../codec/h264/test_data/64x64-I-P-B-P.h264");
+    const STREAM_H265: &[u8] = include_bytes!("../codec/h265/test_data/64x64-I-P-B-P.h265");
+    const STREAM_VP9: &[u8] = include_bytes!("../codec/vp9/test_data/test-25fps.vp9");
+    const STREAM_AV1: &[u8] = include_bytes!("../codec/av1/test_data/test-25fps.ivf.av1");
+
+    fn coded_buffer(timestamp: u64, bitstream: &[u8]) -> CodedBitstreamBuffer {
+        CodedBitstreamBuffer::new(
+            FrameMetadata {
+                timestamp,
+                layout: FrameLayout {
+                    format: (b"NV12".into(), 0),
+                    size: Resolution {
+                        width: 64,
+                        height: 64,
+                    },
+                    planes: vec![],
+                },
+                force_keyframe: false,
+            },
+            bitstream.to_vec(),
+        )
+    }
+
+    /// Muxes `packets` and demuxes them back, returning the demuxed samples
+    /// with their sync flag.
+    fn mux_demux(
+        sample_entry_type: SampleEntryType,
+        packets: &[Vec<u8>],
+    ) -> (CodecConfig, Vec<(Vec<u8>, bool)>) {
+        let mut muxer = Fmp4Muxer::new(
+            vec![],
+            sample_entry_type,
+            Resolution {
+                width: 64,
+                height: 64,
+            },
+            25,
+            1,
+        );
+
+        for (i, packet) in packets.iter().enumerate() {
+            muxer.write(&coded_buffer(i as u64, packet)).unwrap();
+        }
+
+        let file = muxer.into_inner().unwrap();
+        let demuxer = Mp4Demuxer::new(&file).unwrap();
+        assert_eq!(&demuxer.track().sample_entry, sample_entry_type.fourcc());
+        assert_eq!(demuxer.track().timescale, 25);
+        assert_eq!(demuxer.track().width, 64);
+        let config = demuxer.track().config.clone();
+
+        let samples = demuxer
+            .enumerate()
+            .map(|(i, sample)| {
+                let sample = sample.unwrap();
+                assert_eq!(sample.dts, i as i64);
+                assert_eq!(sample.pts, i as i64);
+                assert_eq!(sample.duration, 1);
+                (sample.data.into_owned(), sample.is_sync)
+            })
+            .collect();
+
+        (config, samples)
+    }
+
+    /// Groups the NAL units of an Annex B stream into access units, starting a
+    /// new one at the first non-slice NAL unit following a slice.
+    fn access_units(nalus: &[&[u8]], is_slice: impl Fn(&[u8]) -> bool) -> Vec<Vec<u8>> {
+        let mut aus: Vec<Vec<u8>> = vec![vec![]];
+        let mut has_slice = false;
+        for nalu in nalus {
+            if !is_slice(nalu) && has_slice {
+                aus.push(vec![]);
+                has_slice = false;
+            }
+            has_slice |= is_slice(nalu);
+            let au = aus.last_mut().unwrap();
+            au.extend_from_slice(&[0, 0, 0, 1]);
+            au.extend_from_slice(nalu);
+        }
+        aus
+    }
+
+    #[test]
+    fn mux_h264() {
+        let nalus = NalIterator::<H264Nalu>::new(STREAM_H264)
+            .map(|n| annexb_nalus(&n)[0].to_vec())
+            .collect::<Vec<_>>();
+        let nalus = nalus.iter().map(|n| &n[..]).collect::<Vec<_>>();
+        let aus = access_units(&nalus, |n| matches!(n[0] & 0x1f, 1 | 5));
+
+        let (config, samples) = mux_demux(SampleEntryType::Avc1, &aus);
+        assert!(matches!(config, CodecConfig::Avc(_)));
+        assert_eq!(samples.len(), aus.len());
+
+        for ((sample, is_sync), au) in samples.iter().zip(aus.iter()) {
+            let au_nalus = annexb_nalus(au);
+            let expected_sync = au_nalus.iter().any(|n| n[0] & 0x1f == 5);
+            assert_eq!(*is_sync, expected_sync);
+
+            // The demuxer prepends the parameter sets to sync samples. Other
+            // than that, only the parameter sets and AUDs are removed.
+            let expected = au_nalus
+                .into_iter()
+                .filter(|n| !matches!(n[0] & 0x1f, 7..=9))
+                .collect::<Vec<_>>();
+            let sample_nalus = annexb_nalus(sample);
+            assert_eq!(
+                sample_nalus[sample_nalus.len() - expected.len()..],
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn mux_h265() {
+        let nalus = NalIterator::<H265Nalu>::new(STREAM_H265)
+            .map(|n| annexb_nalus(&n)[0].to_vec())
+            .collect::<Vec<_>>();
+        let nalus = nalus.iter().map(|n| &n[..]).collect::<Vec<_>>();
+        let aus = access_units(&nalus, |n| (n[0] >> 1) & 0x3f < 32);
+
+        let (config, samples) = mux_demux(SampleEntryType::Hvc1, &aus);
+        assert!(matches!(config, CodecConfig::Hevc(_)));
+        assert_eq!(samples.len(), aus.len());
+        assert!(samples[0].1);
+    }
+
+    #[test]
+    fn mux_vp9() {
+        let packets = IvfIterator::new(STREAM_VP9)
+            .take(10)
+            .map(|p| p.to_vec())
+            .collect::<Vec<_>>();
+
+        let (config, samples) = mux_demux(SampleEntryType::Vp09, &packets);
+        let CodecConfig::Vp9(vpcc) = config else {
+            panic!("unexpected codec configuration");
+        };
+        // Version 1, profile 0, level 1.0, 8 bits 4:2:0.
+        assert_eq!(&vpcc[..7], &[1, 0, 0, 0, 0, 10, 0x80]);
+
+        for ((sample, is_sync), packet) in samples.iter().zip(packets.iter()) {
+            assert_eq!(sample, packet);
+            assert_eq!(*is_sync, sample == &packets[0]);
+        }
+    }
+
+    #[test]
+    fn mux_av1() {
+        let packets = IvfIterator::new(STREAM_AV1)
+            .take(10)
+            .map(|p| p.to_vec())
+            .collect::<Vec<_>>();
+
+        let (config, samples) = mux_demux(SampleEntryType::Av01, &packets);
+        assert!(matches!(config, CodecConfig::Av1(_)));
+        assert_eq!(samples.len(), packets.len());
+        assert!(samples[0].1);
+        assert!(!samples[1].1);
+
+        for ((sample, _), packet) in samples.iter().zip(packets.iter()) {
+            // Only the 2-byte temporal delimiter is removed.
+            assert_eq!(&packet[..2], &[0x12, 0x00]);
+            assert_eq!(sample[..], packet[2..]);
+        }
+    }
+
+    #[test]
+    fn av1_sample_obus() {
+        let muxer = Fmp4Muxer::new(
+            vec![],
+            SampleEntryType::Av01,
+            Resolution {
+                width: 64,
+                height: 64,
+            },
+            25,
+            1,
+        );
+
+        // A temporal delimiter, a sized frame OBU, and a last frame OBU
+        // without obu_size field, which is given one.
+        let (sample, is_sync) = muxer
+            .sample_from(&[0x12, 0x00, 0x32, 0x01, 0xaa, 0x30, 0xbb, 0xcc])
+            .unwrap();
+        assert_eq!(sample, [0x32, 0x01, 0xaa, 0x32, 0x02, 0xbb, 0xcc]);
+        assert!(!is_sync);
+
+        // A sequence header makes the sample a sync one.
+        let (sample, is_sync) = muxer.sample_from(&[0x0a, 0x01, 0xdd]).unwrap();
+        assert_eq!(sample, [0x0a, 0x01, 0xdd]);
+        assert!(is_sync);
+
+        // An OBU larger than the bitstream.
+        assert!(muxer.sample_from(&[0x32, 0x05, 0xaa]).is_err());
+    }
+
+    #[test]
+    fn invalid_codec_config() {
+        let mut muxer = Fmp4Muxer::new(
+            vec![],
+            SampleEntryType::Avc1,
+            Resolution {
+                width: 64,
+                height: 64,
+            },
+            25,
+            1,
+        );
+        // An avcC record cannot hold more than 31 SPSs.
+        muxer.set_codec_config(CodecConfig::Avc(AvcDecoderConfigurationRecord {
+            sps: vec![vec![0x67]; 32],
+            ..Default::default()
+        }));
+
+        assert!(muxer.write(&coded_buffer(0, &[0, 0, 0, 1, 0x65])).is_err());
+        assert!(muxer.into_inner().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1258 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A minimal ISO BMFF (MP4) demuxer for the first video track of a file.
+//!
+//! Both regular files (`moov/trak/.../stbl`) and fragmented ones (`moof/traf`)
+//! are supported. Edit lists, encrypted tracks and multiple sample
+//! descriptions are not.
+
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use bytes::Buf;
+
+use crate::codec::av1::av1c::Av1CodecConfigurationRecord;
+use crate::codec::h264::avcc::AvcDecoderConfigurationRecord;
+use crate::codec::h264::nalu::length_prefixed_to_annexb;
+use crate::codec::h265::hvcc::HevcDecoderConfigurationRecord;
+use crate::EncodedFormat;
+
+/// Flag of the sample_flags field signaling a non-sync sample. See ISO/IEC
+/// 14496-12, 8.8.3.1.
+const SAMPLE_IS_NON_SYNC_SAMPLE: u32 = 0x0001_0000;
+
+/// Size of the VisualSampleEntry fields preceding its child boxes.
+const VISUAL_SAMPLE_ENTRY_SIZE: usize = 78;
+
+/// A box, as located in the file.
+#[derive(Clone, Copy)]
+struct Mp4Box<'a> {
+    type_: [u8; 4],
+    /// Absolute offset of the box in the file.
+    offset: usize,
+    /// The box contents, after the header.
+    payload: &'a [u8],
+    /// Absolute offset of the payload in the file.
+    payload_offset: usize,
+}
+
+impl<'a> Mp4Box<'a> {
+    fn name(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.type_)
+    }
+
+    /// Splits the payload of a FullBox into its version, flags and remaining
+    /// data.
+    fn full_box(&self) -> anyhow::Result<(u8, u32, Cursor<&'a [u8]>)> {
+        if self.payload.len() < 4 {
+            return Err(anyhow!("{} box is too short", self.name()));
+        }
+
+        let mut cursor = Cursor::new(self.payload);
+        let version_and_flags = cursor.get_u32();
+
+        Ok((
+            (version_and_flags >> 24) as u8,
+            version_and_flags & 0xff_ffff,
+            cursor,
+        ))
+    }
+
+    /// Returns all the child boxes, which start `skip` bytes into the payload.
+    fn all_children(&self, skip: usize) -> anyhow::Result<Vec<Mp4Box<'a>>> {
+        let payload = self
+            .payload
+            .get(skip..)
+            .ok_or_else(|| anyhow!("{} box is too short", self.name()))?;
+
+        BoxIterator::new(payload, self.payload_offset + skip).collect()
+    }
+
+    /// Returns the child boxes of type `type_`, which start `skip` bytes into
+    /// the payload.
+    fn children(&self, skip: usize, type_: &[u8; 4]) -> anyhow::Result<Vec<Mp4Box<'a>>> {
+        Ok(self
+            .all_children(skip)?
+            .into_iter()
+            .filter(|child| &child.type_ == type_)
+            .collect())
+    }
+
+    /// Returns the first child box of type `type_`, if any.
+    fn child(&self, type_: &[u8; 4]) -> anyhow::Result<Option<Mp4Box<'a>>> {
+        Ok(self.children(0, type_)?.into_iter().next())
+    }
+
+    /// Returns the first child box of type `type_`, failing if there is none.
+    fn expect_child(&self, type_: &[u8; 4]) -> anyhow::Result<Mp4Box<'a>> {
+        self.child(type_)?.ok_or_else(|| {
+            anyhow!(
+                "no {} box in {} box",
+                String::from_utf8_lossy(type_),
+                self.name()
+            )
+        })
+    }
+}
+
+/// Iterator over the consecutive boxes of a buffer.
+struct BoxIterator<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Absolute offset of `data` in the file.
+    base_offset: usize,
+}
+
+impl<'a> BoxIterator<'a> {
+    fn new(data: &'a [u8], base_offset: usize) -> Self {
+        Self {
+            data,
+            pos: 0,
+            base_offset,
+        }
+    }
+
+    fn parse_box(&mut self) -> anyhow::Result<Mp4Box<'a>> {
+        let mut cursor = Cursor::new(&self.data[self.pos..]);
+        if cursor.remaining() < 8 {
+            return Err(anyhow!("truncated box header"));
+        }
+
+        let size = cursor.get_u32();
+        let mut type_ = [0u8; 4];
+        cursor.copy_to_slice(&mut type_);
+
+        let size = match size {
+            // The box extends to the end of the file.
+            0 => cursor.get_ref().len() as u64,
+            1 => {
+                if cursor.remaining() < 8 {
+                    return Err(anyhow!("truncated box header"));
+                }
+                cursor.get_u64()
+            }
+            size => u64::from(size),
+        };
+
+        let header_len = cursor.position() as usize;
+        let size = usize::try_from(size)?;
+        if size < header_len || size > cursor.get_ref().len() {
+            return Err(anyhow!(
+                "invalid size {} for {} box",
+                size,
+                String::from_utf8_lossy(&type_)
+            ));
+        }
+
+        let start = self.pos;
+        self.pos += size;
+
+        Ok(Mp4Box {
+            type_,
+            offset: self.base_offset + start,
+            payload: &self.data[start + header_len..start + size],
+            payload_offset: self.base_offset + start + header_len,
+        })
+    }
+}
+
+impl<'a> Iterator for BoxIterator<'a> {
+    type Item = anyhow::Result<Mp4Box<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+
+        let parsed = self.parse_box();
+        if parsed.is_err() {
+            // Do not try to make sense of what follows a corrupted box.
+            self.pos = self.data.len();
+        }
+
+        Some(parsed)
+    }
+}
+
+/// The codec configuration found in the sample entry of a track.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodecConfig {
+    /// H.264, from the `avcC` box of an `avc1` or `avc3` sample entry.
+    Avc(AvcDecoderConfigurationRecord),
+    /// H.265, from the `hvcC` box of an `hvc1` or `hev1` sample entry.
+    Hevc(HevcDecoderConfigurationRecord),
+    /// VP8, with the payload of the `vpcC` box of a `vp08` sample entry.
+    Vp8(Vec<u8>),
+    /// VP9, with the payload of the `vpcC` box of a `vp09` sample entry.
+    Vp9(Vec<u8>),
+    /// AV1, from the `av1C` box of an `av01` sample entry.
+    Av1(Av1CodecConfigurationRecord),
+}
+
+impl CodecConfig {
+    /// Returns the codec the configuration is for.
+    pub fn encoded_format(&self) -> EncodedFormat {
+        match self {
+            CodecConfig::Avc(_) => EncodedFormat::H264,
+            CodecConfig::Hevc(_) => EncodedFormat::H265,
+            CodecConfig::Vp8(_) => EncodedFormat::VP8,
+            CodecConfig::Vp9(_) => EncodedFormat::VP9,
+            CodecConfig::Av1(_) => EncodedFormat::AV1,
+        }
+    }
+}
+
+/// Location and timing of a sample in the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SampleInfo {
+    offset: usize,
+    size: usize,
+    dts: i64,
+    /// Composition time offset, i.e. `pts - dts`.
+    cts_offset: i64,
+    duration: u32,
+    is_sync: bool,
+}
+
+/// The video track being demuxed.
+#[derive(Clone, Debug)]
+pub struct Mp4VideoTrack {
+    /// The `track_ID` of the track.
+    pub track_id: u32,
+    /// Number of time units per second for the timestamps of the samples.
+    pub timescale: u32,
+    /// Width of the video as given by the sample entry.
+    pub width: u16,
+    /// Height of the video as given by the sample entry.
+    pub height: u16,
+    /// The four character code of the sample entry, e.g. `avc1`.
+    pub sample_entry: [u8; 4],
+    /// The codec configuration record of the sample entry.
+    pub config: CodecConfig,
+    samples: Vec<SampleInfo>,
+}
+
+impl Mp4VideoTrack {
+    /// Number of samples in the track.
+    pub fn num_samples(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+/// A video sample read from the file.
+#[derive(Debug)]
+pub struct Mp4Sample<'a> {
+    /// The sample data, ready to be passed to a decoder. H.264 and H.265
+    /// samples are converted to Annex B.
+    pub data: Cow<'a, [u8]>,
+    /// Decoding timestamp, in units of the track timescale.
+    pub dts: i64,
+    /// Presentation timestamp, in units of the track timescale.
+    pub pts: i64,
+    /// Duration of the sample, in units of the track timescale.
+    pub duration: u32,
+    /// Whether the sample is a sync sample, i.e. a random access point.
+    pub is_sync: bool,
+}
+
+/// Default values for the samples of a track fragment, from `trex` and `tfhd`.
+#[derive(Clone, Copy, Debug, Default)]
+struct FragmentDefaults {
+    sample_duration: u32,
+    sample_size: u32,
+    sample_flags: u32,
+}
+
+/// Demuxer for the first video track of an MP4 file fully loaded in memory.
+///
+/// The demuxer is an iterator over the samples of the track, in decoding
+/// order. The parameter sets of H.264 and H.265 configuration records are
+/// prepended to every sync sample so decoding can start at any of them.
+pub struct Mp4Demuxer<'a> {
+    data: &'a [u8],
+    track: Mp4VideoTrack,
+    next_sample: usize,
+    /// Parameter sets to prepend to sync samples, in Annex B format.
+    parameter_sets: Vec<u8>,
+}
+
+impl<'a> Mp4Demuxer<'a> {
+    /// Parses the MP4 file contained in `data` and prepares to demux its first
+    /// video track.
+    pub fn new(data: &'a [u8]) -> anyhow::Result<Self> {
+        let mut moov = None;
+        let mut moofs = vec![];
+
+        for top_box in BoxIterator::new(data, 0) {
+            let top_box = top_box?;
+            match &top_box.type_ {
+                b"moov" => moov = Some(top_box),
+                b"moof" => moofs.push(top_box),
+                _ => (),
+            }
+        }
+
+        let moov = moov.ok_or_else(|| anyhow!("no moov box in file"))?;
+
+        let mut track = None;
+        for trak in moov.children(0, b"trak")? {
+            if let Some(video_track) = parse_video_trak(&trak, data.len())? {
+                track = Some(video_track);
+                break;
+            }
+        }
+        let mut track = track.ok_or_else(|| anyhow!("no video track in file"))?;
+
+        if !moofs.is_empty() {
+            let defaults = match moov.child(b"mvex")? {
+                Some(mvex) => parse_trex(&mvex, track.track_id)?,
+                None => Default::default(),
+            };
+
+            for moof in moofs {
+                parse_moof(&moof, &mut track, defaults, data.len())?;
+            }
+        }
+
+        if let Some(sample) = track
+            .samples
+            .iter()
+            .find(|s| !matches!(s.offset.checked_add(s.size), Some(end) if end <= data.len()))
+        {
+            return Err(anyhow!(
+                "sample at offset {} with size {} is out of the file bounds",
+                sample.offset,
+                sample.size
+            ));
+        }
+
+        let parameter_sets = match &track.config {
+            CodecConfig::Avc(avcc) => avcc.to_annexb(),
+            CodecConfig::Hevc(hvcc) => hvcc.to_annexb(),
+            _ => vec![],
+        };
+
+        Ok(Self {
+            data,
+            track,
+            next_sample: 0,
+            parameter_sets,
+        })
+    }
+
+    /// The video track being demuxed.
+    pub fn track(&self) -> &Mp4VideoTrack {
+        &self.track
+    }
+
+    /// Makes the next sample returned by the iterator the `index`th of the
+    /// track.
+    pub fn seek_to_sample(&mut self, index: usize) {
+        self.next_sample = index;
+    }
+
+    fn read_sample(&self, info: &SampleInfo) -> anyhow::Result<Mp4Sample<'a>> {
+        let raw = &self.data[info.offset..info.offset + info.size];
+
+        let data = match &self.track.config {
+            CodecConfig::Avc(AvcDecoderConfigurationRecord {
+                length_size_minus_one,
+                ..
+            })
+            | CodecConfig::Hevc(HevcDecoderConfigurationRecord {
+                length_size_minus_one,
+                ..
+            }) => {
+                let annexb =
+                    length_prefixed_to_annexb(raw, usize::from(*length_size_minus_one) + 1)?;
+
+                if info.is_sync {
+                    let mut data = self.parameter_sets.clone();
+                    data.extend_from_slice(&annexb);
+                    Cow::Owned(data)
+                } else {
+                    Cow::Owned(annexb)
+                }
+            }
+            _ => Cow::Borrowed(raw),
+        };
+
+        Ok(Mp4Sample {
+            data,
+            dts: info.dts,
+            pts: info
+                .dts
+                .checked_add(info.cts_offset)
+                .ok_or_else(|| anyhow!("presentation timestamp overflows"))?,
+            duration: info.duration,
+            is_sync: info.is_sync,
+        })
+    }
+}
+
+impl<'a> Iterator for Mp4Demuxer<'a> {
+    type Item = anyhow::Result<Mp4Sample<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let info = self.track.samples.get(self.next_sample)?;
+        self.next_sample += 1;
+
+        Some(self.read_sample(info))
+    }
+}
+
+/// Parses `trak`, returning `None` if it is not a video track. `file_len` is
+/// the size of the whole file.
+fn parse_video_trak(trak: &Mp4Box, file_len: usize) -> anyhow::Result<Option<Mp4VideoTrack>> {
+    let mdia = trak.expect_child(b"mdia")?;
+
+    let (_, _, mut hdlr) = mdia.expect_child(b"hdlr")?.full_box()?;
+    if hdlr.remaining() < 8 {
+        return Err(anyhow!("hdlr box is too short"));
+    }
+    // Skip pre_defined.
+    hdlr.advance(4);
+    if hdlr.chunk()[0..4] != *b"vide" {
+        return Ok(None);
+    }
+
+    let (version, _, mut tkhd) = trak.expect_child(b"tkhd")?.full_box()?;
+    let times_len = if version == 1 { 16 } else { 8 };
+    if tkhd.remaining() < times_len + 4 {
+        return Err(anyhow!("tkhd box is too short"));
+    }
+    tkhd.advance(times_len);
+    let track_id = tkhd.get_u32();
+
+    let (version, _, mut mdhd) = mdia.expect_child(b"mdhd")?.full_box()?;
+    let times_len = if version == 1 { 16 } else { 8 };
+    if mdhd.remaining() < times_len + 4 {
+        return Err(anyhow!("mdhd box is too short"));
+    }
+    mdhd.advance(times_len);
+    let timescale = mdhd.get_u32();
+
+    let stbl = mdia.expect_child(b"minf")?.expect_child(b"stbl")?;
+
+    // Skip the FullBox header and entry_count of stsd to get to the first
+    // sample entry.
+    let entry = stbl
+        .expect_child(b"stsd")?
+        .all_children(8)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no sample entry in stsd box"))?;
+
+    if entry.payload.len() < VISUAL_SAMPLE_ENTRY_SIZE {
+        return Err(anyhow!("{} sample entry is too short", entry.name()));
+    }
+    let width = u16::from_be_bytes([entry.payload[24], entry.payload[25]]);
+    let height = u16::from_be_bytes([entry.payload[26], entry.payload[27]]);
+
+    let config_box = |type_: &[u8; 4]| -> anyhow::Result<Mp4Box> {
+        entry
+            .children(VISUAL_SAMPLE_ENTRY_SIZE, type_)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                anyhow!(
+                    "no {} box in {} sample entry",
+                    String::from_utf8_lossy(type_),
+                    entry.name()
+                )
+            })
+    };
+
+    let config = match &entry.type_ {
+        b"avc1" | b"avc3" => CodecConfig::Avc(
+            AvcDecoderConfigurationRecord::parse(config_box(b"avcC")?.payload)
+                .context("while parsing avcC box")?,
+        ),
+        b"hvc1" | b"hev1" => CodecConfig::Hevc(
+            HevcDecoderConfigurationRecord::parse(config_box(b"hvcC")?.payload)
+                .context("while parsing hvcC box")?,
+        ),
+        b"vp08" => CodecConfig::Vp8(config_box(b"vpcC")?.payload.to_vec()),
+        b"vp09" => CodecConfig::Vp9(config_box(b"vpcC")?.payload.to_vec()),
+        b"av01" => CodecConfig::Av1(
+            Av1CodecConfigurationRecord::parse(config_box(b"av1C")?.payload)
+                .context("while parsing av1C box")?,
+        ),
+        _ => return Err(anyhow!("unsupported sample entry type {}", entry.name())),
+    };
+
+    let samples = parse_stbl(&stbl, file_len)?;
+
+    Ok(Some(Mp4VideoTrack {
+        track_id,
+        timescale,
+        width,
+        height,
+        sample_entry: entry.type_,
+        config,
+        samples,
+    }))
+}
+
+/// Reads the `entry_count` field of a FullBox and checks that `entry_size`
+/// bytes are available for each entry.
+fn read_entry_count(cursor: &mut Cursor<&[u8]>, entry_size: usize) -> anyhow::Result<usize> {
+    if cursor.remaining() < 4 {
+        return Err(anyhow!("missing entry_count"));
+    }
+
+    let count = cursor.get_u32() as usize;
+    if !matches!(count.checked_mul(entry_size), Some(len) if len <= cursor.remaining()) {
+        return Err(anyhow!("truncated table of {} entries", count));
+    }
+
+    Ok(count)
+}
+
+/// Checks that `count` samples of `sample_size` bytes each can fit in a file
+/// of `file_len` bytes.
+///
+/// Tables whose entries do not take any space in their box, e.g. `stsz` with
+/// a constant sample size, can otherwise claim billions of samples from a few
+/// bytes of input.
+fn check_sample_count(count: usize, sample_size: usize, file_len: usize) -> anyhow::Result<()> {
+    // Empty samples are not useful, so count them as taking one byte.
+    if !matches!(count.checked_mul(sample_size.max(1)), Some(len) if len <= file_len) {
+        return Err(anyhow!(
+            "{} samples of {} bytes cannot fit in a file of {} bytes",
+            count,
+            sample_size,
+            file_len
+        ));
+    }
+
+    Ok(())
+}
+
+/// Builds the sample table of a non-fragmented track contained in a file of
+/// `file_len` bytes.
+fn parse_stbl(stbl: &Mp4Box, file_len: usize) -> anyhow::Result<Vec<SampleInfo>> {
+    // Sample sizes.
+    let sizes = if let Some(stsz) = stbl.child(b"stsz")? {
+        let (_, _, mut stsz) = stsz.full_box()?;
+        if stsz.remaining() < 4 {
+            return Err(anyhow!("stsz box is too short"));
+        }
+
+        let sample_size = stsz.get_u32() as usize;
+        if sample_size == 0 {
+            let count = read_entry_count(&mut stsz, 4)?;
+            (0..count).map(|_| stsz.get_u32() as usize).collect()
+        } else {
+            let count = read_entry_count(&mut stsz, 0)?;
+            check_sample_count(count, sample_size, file_len)?;
+            vec![sample_size; count]
+        }
+    } else if let Some(stz2) = stbl.child(b"stz2")? {
+        let (_, _, mut stz2) = stz2.full_box()?;
+        if stz2.remaining() < 4 {
+            return Err(anyhow!("stz2 box is too short"));
+        }
+
+        stz2.advance(3);
+        let field_size = stz2.get_u8();
+        let count = read_entry_count(&mut stz2, 0)?;
+        let table_len = match field_size {
+            4 => Some(count.div_ceil(2)),
+            8 | 16 => count.checked_mul(usize::from(field_size / 8)),
+            _ => return Err(anyhow!("invalid stz2 field_size {}", field_size)),
+        };
+        if !matches!(table_len, Some(len) if len <= stz2.remaining()) {
+            return Err(anyhow!("stz2 box is too short"));
+        }
+
+        let mut sizes = Vec::with_capacity(count);
+        match field_size {
+            4 => {
+                let bytes = stz2.chunk();
+                for i in 0..count {
+                    let byte = bytes[i / 2];
+                    let size = if i % 2 == 0 { byte >> 4 } else { byte & 0xf };
+                    sizes.push(usize::from(size));
+                }
+            }
+            _ => {
+                let entry_size = usize::from(field_size / 8);
+                for _ in 0..count {
+                    sizes.push(stz2.get_uint(entry_size) as usize);
+                }
+            }
+        }
+        sizes
+    } else {
+        return Err(anyhow!("no stsz or stz2 box in stbl box"));
+    };
+
+    // Chunk offsets.
+    let chunk_offsets: Vec<u64> = if let Some(stco) = stbl.child(b"stco")? {
+        let (_, _, mut stco) = stco.full_box()?;
+        let count = read_entry_count(&mut stco, 4)?;
+        (0..count).map(|_| u64::from(stco.get_u32())).collect()
+    } else if let Some(co64) = stbl.child(b"co64")? {
+        let (_, _, mut co64) = co64.full_box()?;
+        let count = read_entry_count(&mut co64, 8)?;
+        (0..count).map(|_| co64.get_u64()).collect()
+    } else {
+        // Fragmented files have no samples in the moov box.
+        vec![]
+    };
+
+    // Sample to chunk mapping, as (first_chunk, samples_per_chunk).
+    let sample_to_chunk: Vec<(u32, u32)> = match stbl.child(b"stsc")? {
+        Some(stsc) => {
+            let (_, _, mut stsc) = stsc.full_box()?;
+            let count = read_entry_count(&mut stsc, 12)?;
+            (0..count)
+                .map(|_| {
+                    let first_chunk = stsc.get_u32();
+                    let samples_per_chunk = stsc.get_u32();
+                    let _sample_description_index = stsc.get_u32();
+                    (first_chunk, samples_per_chunk)
+                })
+                .collect()
+        }
+        None => vec![],
+    };
+
+    let mut samples = Vec::with_capacity(sizes.len());
+    let mut sizes_iter = sizes.iter();
+    // Both tables are sorted by chunk, so they are walked together.
+    let mut sample_to_chunk = sample_to_chunk.iter().peekable();
+    let mut samples_per_chunk = 0;
+    'chunks: for (chunk_index, &chunk_offset) in chunk_offsets.iter().enumerate() {
+        // Chunks are numbered from 1.
+        let chunk_number = chunk_index as u32 + 1;
+        while let Some((_, n)) =
+            sample_to_chunk.next_if(|(first_chunk, _)| *first_chunk <= chunk_number)
+        {
+            samples_per_chunk = *n;
+        }
+
+        let mut offset = usize::try_from(chunk_offset)?;
+        for _ in 0..samples_per_chunk {
+            let size = match sizes_iter.next() {
+                Some(size) => *size,
+                None => break 'chunks,
+            };
+
+            samples.push(SampleInfo {
+                offset,
+                size,
+                dts: 0,
+                cts_offset: 0,
+                duration: 0,
+                is_sync: true,
+            });
+            offset = offset
+                .checked_add(size)
+                .ok_or_else(|| anyhow!("sample offset overflows"))?;
+        }
+    }
+
+    if samples.len() != sizes.len() {
+        return Err(anyhow!(
+            "chunk tables describe {} samples, but stsz has {}",
+            samples.len(),
+            sizes.len()
+        ));
+    }
+
+    // Decoding timestamps.
+    if let Some(stts) = stbl.child(b"stts")? {
+        let (_, _, mut stts) = stts.full_box()?;
+        let count = read_entry_count(&mut stts, 8)?;
+        let mut samples_iter = samples.iter_mut();
+        let mut dts = 0i64;
+        'stts: for _ in 0..count {
+            let sample_count = stts.get_u32();
+            let sample_delta = stts.get_u32();
+            for _ in 0..sample_count {
+                let Some(sample) = samples_iter.next() else {
+                    break 'stts;
+                };
+                sample.dts = dts;
+                sample.duration = sample_delta;
+                dts = dts
+                    .checked_add(i64::from(sample_delta))
+                    .ok_or_else(|| anyhow!("decoding timestamp overflows"))?;
+            }
+        }
+    }
+
+    // Composition time offsets.
+    if let Some(ctts) = stbl.child(b"ctts")? {
+        let (version, _, mut ctts) = ctts.full_box()?;
+        let count = read_entry_count(&mut ctts, 8)?;
+        let mut samples_iter = samples.iter_mut();
+        'ctts: for _ in 0..count {
+            let sample_count = ctts.get_u32();
+            let sample_offset = if version == 0 {
+                i64::from(ctts.get_u32())
+            } else {
+                i64::from(ctts.get_i32())
+            };
+            for _ in 0..sample_count {
+                let Some(sample) = samples_iter.next() else {
+                    break 'ctts;
+                };
+                sample.cts_offset = sample_offset;
+            }
+        }
+    }
+
+    // Sync samples. If the box is absent, every sample is a sync sample.
+    if let Some(stss) = stbl.child(b"stss")? {
+        let (_, _, mut stss) = stss.full_box()?;
+        let count = read_entry_count(&mut stss, 4)?;
+        samples.iter_mut().for_each(|s| s.is_sync = false);
+        for _ in 0..count {
+            // Samples are numbered from 1.
+            let sample_number = stss.get_u32() as usize;
+            if let Some(sample) = sample_number
+                .checked_sub(1)
+                .and_then(|i| samples.get_mut(i))
+            {
+                sample.is_sync = true;
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Returns the fragment defaults of the `trex` box for `track_id`.
+fn parse_trex(mvex: &Mp4Box, track_id: u32) -> anyhow::Result<FragmentDefaults> {
+    for trex in mvex.children(0, b"trex")? {
+        let (_, _, mut trex) = trex.full_box()?;
+        if trex.remaining() < 20 {
+            return Err(anyhow!("trex box is too short"));
+        }
+
+        if trex.get_u32() != track_id {
+            continue;
+        }
+
+        let _default_sample_description_index = trex.get_u32();
+        return Ok(FragmentDefaults {
+            sample_duration: trex.get_u32(),
+            sample_size: trex.get_u32(),
+            sample_flags: trex.get_u32(),
+        });
+    }
+
+    Ok(Default::default())
+}
+
+/// Appends the samples of `track` described in `moof` to its sample table.
+/// `file_len` is the size of the whole file.
+fn parse_moof(
+    moof: &Mp4Box,
+    track: &mut Mp4VideoTrack,
+    trex_defaults: FragmentDefaults,
+    file_len: usize,
+) -> anyhow::Result<()> {
+    for traf in moof.children(0, b"traf")? {
+        let (_, tf_flags, mut tfhd) = traf.expect_child(b"tfhd")?.full_box()?;
+        if tfhd.remaining() < 4 {
+            return Err(anyhow!("tfhd box is too short"));
+        }
+        if tfhd.get_u32() != track.track_id {
+            continue;
+        }
+
+        let mut read_optional = |flag: u32, len: usize| -> anyhow::Result<Option<u64>> {
+            if tf_flags & flag == 0 {
+                return Ok(None);
+            }
+            if tfhd.remaining() < len {
+                return Err(anyhow!("tfhd box is too short"));
+            }
+            Ok(Some(tfhd.get_uint(len)))
+        };
+
+        let base_data_offset = read_optional(0x1, 8)?;
+        let _sample_description_index = read_optional(0x2, 4)?;
+        let mut defaults = trex_defaults;
+        if let Some(duration) = read_optional(0x8, 4)? {
+            defaults.sample_duration = duration as u32;
+        }
+        if let Some(size) = read_optional(0x10, 4)? {
+            defaults.sample_size = size as u32;
+        }
+        if let Some(flags) = read_optional(0x20, 4)? {
+            defaults.sample_flags = flags as u32;
+        }
+
+        // Without an explicit base offset, data offsets are relative to the
+        // start of the moof box.
+        let base_offset = match base_data_offset {
+            Some(offset) => usize::try_from(offset)?,
+            None => moof.offset,
+        };
+
+        let mut dts = match traf.child(b"tfdt")? {
+            Some(tfdt) => {
+                let (version, _, mut tfdt) = tfdt.full_box()?;
+                let len = if version == 1 { 8 } else { 4 };
+                if tfdt.remaining() < len {
+                    return Err(anyhow!("tfdt box is too short"));
+                }
+                i64::try_from(tfdt.get_uint(len))?
+            }
+            None => match track.samples.last() {
+                Some(s) => s
+                    .dts
+                    .checked_add(i64::from(s.duration))
+                    .ok_or_else(|| anyhow!("decoding timestamp overflows"))?,
+                None => 0,
+            },
+        };
+
+        let mut data_offset = base_offset;
+        for trun in traf.children(0, b"trun")? {
+            let (version, tr_flags, mut trun) = trun.full_box()?;
+            let sample_count = read_entry_count(&mut trun, 0)?;
+
+            if tr_flags & 0x1 != 0 {
+                if trun.remaining() < 4 {
+                    return Err(anyhow!("trun box is too short"));
+                }
+                let offset = i64::from(trun.get_i32());
+                data_offset = i64::try_from(base_offset)?
+                    .checked_add(offset)
+                    .ok_or_else(|| anyhow!("data offset overflows"))?
+                    .try_into()?;
+            }
+
+            let first_sample_flags = if tr_flags & 0x4 != 0 {
+                if trun.remaining() < 4 {
+                    return Err(anyhow!("trun box is too short"));
+                }
+                Some(trun.get_u32())
+            } else {
+                None
+            };
+
+            let entry_size = [0x100, 0x200, 0x400, 0x800]
+                .iter()
+                .filter(|&&flag| tr_flags & flag != 0)
+                .count()
+                * 4;
+            if sample_count * entry_size > trun.remaining() {
+                return Err(anyhow!("trun box is too short"));
+            }
+            if tr_flags & 0x200 == 0 {
+                check_sample_count(sample_count, defaults.sample_size as usize, file_len)?;
+            }
+
+            for i in 0..sample_count {
+                let duration = if tr_flags & 0x100 != 0 {
+                    trun.get_u32()
+                } else {
+                    defaults.sample_duration
+                };
+                let size = if tr_flags & 0x200 != 0 {
+                    trun.get_u32()
+                } else {
+                    defaults.sample_size
+                } as usize;
+                let flags = if tr_flags & 0x400 != 0 {
+                    trun.get_u32()
+                } else if i == 0 {
+                    first_sample_flags.unwrap_or(defaults.sample_flags)
+                } else {
+                    defaults.sample_flags
+                };
+                let cts_offset = if tr_flags & 0x800 != 0 {
+                    if version == 0 {
+                        i64::from(trun.get_u32())
+                    } else {
+                        i64::from(trun.get_i32())
+                    }
+                } else {
+                    0
+                };
+
+                track.samples.push(SampleInfo {
+                    offset: data_offset,
+                    size,
+                    dts,
+                    cts_offset,
+                    duration,
+                    is_sync: flags & SAMPLE_IS_NON_SYNC_SAMPLE == 0,
+                });
+
+                data_offset = data_offset
+                    .checked_add(size)
+                    .ok_or_else(|| anyhow!("data offset overflows"))?;
+                dts = dts
+                    .checked_add(i64::from(duration))
+                    .ok_or_else(|| anyhow!("decoding timestamp overflows"))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::avcc::AvcDecoderConfigurationRecord;
+    use crate::codec::h264::nalu::annexb_nalus;
+    use crate::codec::h264::nalu::annexb_to_length_prefixed;
+    use crate::container::mp4::CodecConfig;
+    use crate::container::mp4::Mp4Demuxer;
+    use crate::utils::sniff_encoded_format;
+    use crate::EncodedFormat;
+
+    const STREAM_64X64_IPBP: &[u8] = include_bytes!("../codec/h264/test_data/64x64-I-P-B-P.h264");
+
+    /// Time between two frames, in units of the 90kHz timescale we use.
+    const FRAME_DURATION: u32 = 3000;
+
+    fn mp4_box(type_: &[u8; 4], contents: &[&[u8]]) -> Vec<u8> {
+        let len = 8 + contents.iter().map(|c| c.len()).sum::<usize>();
+        let mut out = Vec::with_capacity(len);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+        out.extend_from_slice(type_);
+        for c in contents {
+            out.extend_from_slice(c);
+        }
+        out
+    }
+
+    fn full_box(type_: &[u8; 4], version: u8, flags: u32, contents: &[&[u8]]) -> Vec<u8> {
+        let version_and_flags = ((u32::from(version) << 24) | flags).to_be_bytes();
+        let mut all = vec![&version_and_flags[..]];
+        all.extend_from_slice(contents);
+        mp4_box(type_, &all)
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    /// Returns the slices of the test stream, the parameter sets and its
+    /// `avcC` record.
+    fn test_stream() -> (Vec<&'static [u8]>, AvcDecoderConfigurationRecord) {
+        let slices = annexb_nalus(STREAM_64X64_IPBP)
+            .into_iter()
+            .filter(|n| matches!(n[0] & 0x1f, 1 | 5))
+            .collect();
+        let avcc = AvcDecoderConfigurationRecord::from_annexb(STREAM_64X64_IPBP, 4).unwrap();
+
+        (slices, avcc)
+    }
+
+    /// Builds a `trak` box for a 64x64 H.264 track containing `stbl_boxes`.
+    fn video_trak(avcc: &AvcDecoderConfigurationRecord, stbl_boxes: &[&[u8]]) -> Vec<u8> {
+        let mut sample_entry = vec![0u8; 78];
+        sample_entry[7] = 1; // data_reference_index
+        sample_entry[24..28].copy_from_slice(&[0, 64, 0, 64]);
+        let avcc = mp4_box(b"avcC", &[&avcc.write().unwrap()]);
+        let avc1 = mp4_box(b"avc1", &[&sample_entry, &avcc]);
+        let stsd = full_box(b"stsd", 0, 0, &[&u32s(&[1]), &avc1]);
+
+        let mut all_stbl_boxes = vec![&stsd[..]];
+        all_stbl_boxes.extend_from_slice(stbl_boxes);
+        let stbl = mp4_box(b"stbl", &all_stbl_boxes);
+        let minf = mp4_box(b"minf", &[&stbl]);
+        let hdlr = full_box(b"hdlr", 0, 0, &[&u32s(&[0]), b"vide", &[0; 13]]);
+        let mdhd = full_box(b"mdhd", 0, 0, &[&u32s(&[0, 0, 90000, 0]), &[0; 4]]);
+        let mdia = mp4_box(b"mdia", &[&mdhd, &hdlr, &minf]);
+        let tkhd = full_box(b"tkhd", 0, 3, &[&u32s(&[0, 0, 1, 0, 0]), &[0; 60]]);
+
+        mp4_box(b"trak", &[&tkhd, &mdia])
+    }
+
+    /// Checks that the demuxed samples match the slices of the test stream.
+    fn check_samples(demuxer: Mp4Demuxer, slices: &[&[u8]], cts_offsets: &[u32]) {
+        let parameter_sets = match &demuxer.track().config {
+            CodecConfig::Avc(avcc) => [avcc.sps.clone(), avcc.pps.clone()].concat(),
+            _ => panic!("unexpected codec configuration"),
+        };
+        assert_eq!(demuxer.track().num_samples(), slices.len());
+        assert_eq!(demuxer.track().width, 64);
+        assert_eq!(demuxer.track().timescale, 90000);
+
+        for (i, sample) in demuxer.enumerate() {
+            let sample = sample.unwrap();
+            let is_idr = slices[i][0] & 0x1f == 5;
+
+            let mut expected = vec![];
+            if is_idr {
+                expected.extend(parameter_sets.iter().map(|n| &n[..]));
+            }
+            expected.push(slices[i]);
+
+            assert_eq!(annexb_nalus(&sample.data), expected);
+            assert_eq!(sample.is_sync, is_idr);
+            assert_eq!(sample.dts, i as i64 * i64::from(FRAME_DURATION));
+            assert_eq!(sample.pts, sample.dts + i64::from(cts_offsets[i]));
+            assert_eq!(sample.duration, FRAME_DURATION);
+        }
+    }
+
+    /// Builds a non-fragmented MP4 file containing the test stream. Returns
+    /// the file, the slices of the stream and their composition time offsets.
+    fn test_mp4() -> (Vec<u8>, Vec<&'static [u8]>, [u32; 3]) {
+        let (slices, avcc) = test_stream();
+        let samples = slices
+            .iter()
+            .map(|s| annexb_to_length_prefixed(&[&[0, 0, 1], *s].concat(), 4).unwrap())
+            .collect::<Vec<_>>();
+        let cts_offsets = [0, 2 * FRAME_DURATION, 0];
+
+        let ftyp = mp4_box(b"ftyp", &[b"isom", &u32s(&[0]), b"isomavc1"]);
+        let mdat = mp4_box(b"mdat", &samples.iter().map(|s| &s[..]).collect::<Vec<_>>());
+
+        let stts = full_box(b"stts", 0, 0, &[&u32s(&[1, 3, FRAME_DURATION])]);
+        let ctts = full_box(b"ctts", 0, 0, &[&u32s(&[3, 1, 0, 1, cts_offsets[1], 1, 0])]);
+        let stss = full_box(b"stss", 0, 0, &[&u32s(&[1, 1])]);
+        let mut stsz = vec![0, samples.len() as u32];
+        stsz.extend(samples.iter().map(|s| s.len() as u32));
+        let stsz = full_box(b"stsz", 0, 0, &[&u32s(&stsz)]);
+        // A chunk of two samples, then a chunk of one sample.
+        let stsc = full_box(b"stsc", 0, 0, &[&u32s(&[2, 1, 2, 1, 2, 1, 1])]);
+        let first_chunk = ftyp.len() as u32 + 8;
+        let second_chunk = first_chunk + (samples[0].len() + samples[1].len()) as u32;
+        let stco = full_box(b"stco", 0, 0, &[&u32s(&[2, first_chunk, second_chunk])]);
+        let trak = video_trak(&avcc, &[&stts, &ctts, &stss, &stsz, &stsc, &stco]);
+        let moov = mp4_box(b"moov", &[&trak]);
+
+        ([ftyp, mdat, moov].concat(), slices, cts_offsets)
+    }
+
+    #[test]
+    fn demux_mp4() {
+        let (file, slices, cts_offsets) = test_mp4();
+        let (_, avcc) = test_stream();
+
+        let demuxer = Mp4Demuxer::new(&file).unwrap();
+        assert_eq!(&demuxer.track().sample_entry, b"avc1");
+        assert!(matches!(&demuxer.track().config, CodecConfig::Avc(a) if *a == avcc));
+
+        check_samples(demuxer, &slices, &cts_offsets);
+    }
+
+    #[test]
+    fn sniff_mp4() {
+        let (file, _, _) = test_mp4();
+        assert_eq!(sniff_encoded_format(&file), Some(EncodedFormat::H264));
+        // The sample entry is in the truncated moov box.
+        assert_eq!(sniff_encoded_format(&file[..file.len() - 8]), None);
+    }
+
+    #[test]
+    fn demux_fragmented_mp4() {
+        let (slices, avcc) = test_stream();
+        let samples = slices
+            .iter()
+            .map(|s| annexb_to_length_prefixed(&[&[0, 0, 1], *s].concat(), 4).unwrap())
+            .collect::<Vec<_>>();
+        let cts_offsets = [0, 2 * FRAME_DURATION, 0];
+
+        let ftyp = mp4_box(b"ftyp", &[b"iso6", &u32s(&[0]), b"iso6cmfc"]);
+        let empty_tables = [b"stts", b"stsc", b"stco"]
+            .iter()
+            .map(|t| full_box(t, 0, 0, &[&u32s(&[0])]))
+            .collect::<Vec<_>>();
+        let stsz = full_box(b"stsz", 0, 0, &[&u32s(&[0, 0])]);
+        let trak = video_trak(
+            &avcc,
+            &[&empty_tables[0], &empty_tables[1], &stsz, &empty_tables[2]],
+        );
+        // Samples are non-sync and last FRAME_DURATION by default.
+        let trex = full_box(b"trex", 0, 0, &[&u32s(&[1, 1, FRAME_DURATION, 0, 0x10000])]);
+        let mvex = mp4_box(b"mvex", &[&trex]);
+        let moov = mp4_box(b"moov", &[&trak, &mvex]);
+
+        let mut file = [ftyp, moov].concat();
+
+        // One fragment per pair of samples.
+        for (i, pair) in samples.chunks(2).enumerate() {
+            let mfhd = full_box(b"mfhd", 0, 0, &[&u32s(&[i as u32 + 1])]);
+            // default-base-is-moof.
+            let tfhd = full_box(b"tfhd", 0, 0x20000, &[&u32s(&[1])]);
+            let base_time = (i as u64 * 2 * u64::from(FRAME_DURATION)).to_be_bytes();
+            let tfdt = full_box(b"tfdt", 1, 0, &[&base_time]);
+
+            // data-offset, first-sample-flags, sample-size and
+            // sample-composition-time-offset present.
+            let trun_len = 8 + 4 + 4 + 4 + 4 + 8 * pair.len();
+            let moof_len = 8 + 16 + 8 + 16 + 20 + trun_len;
+            let first_sample_flags = if i == 0 { 0 } else { 0x10000 };
+            let mut trun = vec![pair.len() as u32, moof_len as u32 + 8, first_sample_flags];
+            for (j, sample) in pair.iter().enumerate() {
+                trun.push(sample.len() as u32);
+                trun.push(cts_offsets[i * 2 + j]);
+            }
+            let trun = full_box(b"trun", 0, 0x1 | 0x4 | 0x200 | 0x800, &[&u32s(&trun)]);
+
+            let traf = mp4_box(b"traf", &[&tfhd, &tfdt, &trun]);
+            let moof = mp4_box(b"moof", &[&mfhd, &traf]);
+            assert_eq!(moof.len(), moof_len);
+            let mdat = mp4_box(b"mdat", &pair.iter().map(|s| &s[..]).collect::<Vec<_>>());
+
+            file.extend(moof);
+            file.extend(mdat);
+        }
+
+        let demuxer = Mp4Demuxer::new(&file).unwrap();
+        check_samples(demuxer, &slices, &cts_offsets);
+    }
+
+    #[test]
+    fn invalid_mp4() {
+        assert!(Mp4Demuxer::new(&[]).is_err());
+        // Box claiming to be larger than the file.
+        assert!(Mp4Demuxer::new(&[0, 0, 0, 16, b'm', b'o', b'o', b'v']).is_err());
+
+        let ftyp = mp4_box(b"ftyp", &[b"isom", &u32s(&[0])]);
+        let moov = mp4_box(b"moov", &[]);
+        assert!(Mp4Demuxer::new(&[ftyp, moov].concat()).is_err());
+    }
+
+    #[test]
+    fn huge_sample_counts() {
+        let (_, avcc) = test_stream();
+        let ftyp = mp4_box(b"ftyp", &[b"isom", &u32s(&[0])]);
+        let stco = full_box(b"stco", 0, 0, &[&u32s(&[1, 0])]);
+        let stsc = full_box(b"stsc", 0, 0, &[&u32s(&[1, 1, u32::MAX, 1])]);
+
+        // Constant sample size, with a count that would need 32 GiB of sample
+        // sizes.
+        let stsz = full_box(b"stsz", 0, 0, &[&u32s(&[1, u32::MAX])]);
+        let trak = video_trak(&avcc, &[&stsz, &stsc, &stco]);
+        let file = [ftyp.clone(), mp4_box(b"moov", &[&trak])].concat();
+        assert!(Mp4Demuxer::new(&file).is_err());
+
+        // Compact sample sizes, with a count larger than the table.
+        for field_size in [4u32, 8, 16] {
+            let stz2 = full_box(b"stz2", 0, 0, &[&u32s(&[field_size, u32::MAX]), &[0; 8]]);
+            let trak = video_trak(&avcc, &[&stz2, &stsc, &stco]);
+            let file = [ftyp.clone(), mp4_box(b"moov", &[&trak])].concat();
+            assert!(Mp4Demuxer::new(&file).is_err());
+        }
+
+        // Track fragment run without per-sample fields.
+        let empty_tables = [b"stts", b"stsc", b"stco"]
+            .iter()
+            .map(|t| full_box(t, 0, 0, &[&u32s(&[0])]))
+            .collect::<Vec<_>>();
+        let stsz = full_box(b"stsz", 0, 0, &[&u32s(&[0, 0])]);
+        let trak = video_trak(
+            &avcc,
+            &[&empty_tables[0], &empty_tables[1], &stsz, &empty_tables[2]],
+        );
+        let trex = full_box(b"trex", 0, 0, &[&u32s(&[1, 1, 0, 1, 0])]);
+        let moov = mp4_box(b"moov", &[&trak, &mp4_box(b"mvex", &[&trex])]);
+        let tfhd = full_box(b"tfhd", 0, 0x20000, &[&u32s(&[1])]);
+        let trun = full_box(b"trun", 0, 0, &[&u32s(&[u32::MAX])]);
+        let moof = mp4_box(b"moof", &[&mp4_box(b"traf", &[&tfhd, &trun])]);
+        let file = [ftyp, moov, moof].concat();
+        assert!(Mp4Demuxer::new(&file).is_err());
+    }
+
+    #[test]
+    fn corrupted_mp4() {
+        // Overwrite each byte of a valid file in turn, and check that parsing
+        // and demuxing fail gracefully.
+        let (file, _, _) = test_mp4();
+        for i in 0..file.len() {
+            for value in [0x00, 0x7f, 0x80, 0xff] {
+                let mut corrupted = file.clone();
+                corrupted[i] = value;
+
+                if let Ok(demuxer) = Mp4Demuxer::new(&corrupted) {
+                    demuxer.for_each(drop);
+                }
+            }
+        }
+    }
+
+    /// Builds a fragmented MP4 file with a single fragment made of `traf_boxes`.
+    fn fragmented_mp4(traf_boxes: &[&[u8]]) -> Vec<u8> {
+        let (_, avcc) = test_stream();
+        let ftyp = mp4_box(b"ftyp", &[b"iso6", &u32s(&[0]), b"iso6cmfc"]);
+        let empty_tables = [b"stts", b"stsc", b"stco"]
+            .iter()
+            .map(|t| full_box(t, 0, 0, &[&u32s(&[0])]))
+            .collect::<Vec<_>>();
+        let stsz = full_box(b"stsz", 0, 0, &[&u32s(&[0, 0])]);
+        let trak = video_trak(
+            &avcc,
+            &[&empty_tables[0], &empty_tables[1], &stsz, &empty_tables[2]],
+        );
+        let trex = full_box(b"trex", 0, 0, &[&u32s(&[1, 1, FRAME_DURATION, 0, 0])]);
+        let moov = mp4_box(b"moov", &[&trak, &mp4_box(b"mvex", &[&trex])]);
+
+        let mfhd = full_box(b"mfhd", 0, 0, &[&u32s(&[1])]);
+        let moof = mp4_box(b"moof", &[&mfhd, &mp4_box(b"traf", traf_boxes)]);
+        let mdat = mp4_box(b"mdat", &[&[0; 16]]);
+
+        [ftyp, moov, moof, mdat].concat()
+    }
+
+    #[test]
+    fn overflowing_mp4() {
+        let overflows = |file: &[u8]| {
+            let error = Mp4Demuxer::new(file)
+                .and_then(|demuxer| demuxer.collect::<anyhow::Result<Vec<_>>>())
+                .err();
+            matches!(error, Some(e) if e.to_string().contains("overflows"))
+        };
+
+        // Two 16-byte samples in a chunk starting right before the end of the address space.
+        let (_, avcc) = test_stream();
+        let stts = full_box(b"stts", 0, 0, &[&u32s(&[1, 2, FRAME_DURATION])]);
+        let stsz = full_box(b"stsz", 0, 0, &[&u32s(&[0, 2, 16, 16])]);
+        let stsc = full_box(b"stsc", 0, 0, &[&u32s(&[1, 1, 2, 1])]);
+        let co64 = full_box(b"co64", 0, 0, &[&u32s(&[1]), &(u64::MAX - 4).to_be_bytes()]);
+        let trak = video_trak(&avcc, &[&stts, &stsz, &stsc, &co64]);
+        let ftyp = mp4_box(b"ftyp", &[b"isom", &u32s(&[0]), b"isomavc1"]);
+        let mdat = mp4_box(b"mdat", &[&[0; 32]]);
+        assert!(overflows(
+            &[ftyp, mdat, mp4_box(b"moov", &[&trak])].concat()
+        ));
+
+        let tfhd = full_box(b"tfhd", 0, 0, &[&u32s(&[1])]);
+        let tfdt = |dts: i64| full_box(b"tfdt", 1, 0, &[&dts.to_be_bytes()]);
+
+        // Decoding timestamp of the second sample.
+        let trun = full_box(b"trun", 0, 0x100 | 0x200, &[&u32s(&[2, 1, 0, 1, 0])]);
+        assert!(overflows(&fragmented_mp4(&[&tfhd, &tfdt(i64::MAX), &trun])));
+
+        // Presentation timestamp of a sample.
+        let trun = full_box(b"trun", 0, 0x100 | 0x200 | 0x800, &[&u32s(&[1, 0, 0, 10])]);
+        assert!(overflows(&fragmented_mp4(&[
+            &tfhd,
+            &tfdt(i64::MAX - 1),
+            &trun
+        ])));
+
+        // Data offset of the second sample, from the base-data-offset of the fragment.
+        let base_tfhd = |base: u64| full_box(b"tfhd", 0, 0x1, &[&u32s(&[1]), &base.to_be_bytes()]);
+        let trun = full_box(b"trun", 0, 0x200, &[&u32s(&[2, 16, 16])]);
+        assert!(overflows(&fragmented_mp4(&[
+            &base_tfhd(u64::MAX - 1),
+            &trun
+        ])));
+
+        // Data offset of the run, relative to the base-data-offset of the fragment.
+        let trun = full_box(b"trun", 0, 0x1 | 0x200, &[&u32s(&[1, 1, 16])]);
+        assert!(overflows(&fragmented_mp4(&[
+            &base_tfhd(i64::MAX as u64),
+            &trun
+        ])));
+    }
+}
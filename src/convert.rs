@@ -0,0 +1,1146 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! CPU conversion and scaling of decoded frames.
+//!
+//! All the functions of this module work on frames laid out without padding, as returned by
+//! [`crate::decoder::MappableHandle::read`]. Conversions between planar and semi-planar formats
+//! with the same bit depth and subsampling, like NV12 and I420, copy samples directly. Other
+//! conversions go through an intermediate planar representation with 16 bits per sample. In both
+//! cases, inner loops work on whole lines so the compiler can vectorize them.
+
+use anyhow::anyhow;
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+
+use crate::DecodedFormat;
+
+/// Arrangement of the samples of a YUV frame in memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Packing {
+    /// Y, U and V planes.
+    Planar,
+    /// One Y and one interleaved UV plane.
+    SemiPlanar,
+    /// Single plane of interleaved Y0, U, Y1 and V samples.
+    Yuyv,
+    /// Single plane of V, U, Y and alpha bytes.
+    Ayuv,
+    /// Single plane of 32-bit LE words with U, Y, V and alpha in bits 0-9, 10-19, 20-29 and 30-31.
+    Y410,
+}
+
+/// Memory layout of a YUV frame format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct YuvLayout {
+    /// Number of significant bits per sample.
+    bit_depth: u32,
+    /// Horizontal and vertical chroma subsampling.
+    subsampling: (bool, bool),
+    /// How the samples are arranged in memory.
+    packing: Packing,
+    /// Whether samples are stored on 16 bits, with their significant bits in the MSBs. Samples of
+    /// other formats with more than 8 bits are stored in the LSBs of 16-bit words.
+    msb_aligned: bool,
+}
+
+impl YuvLayout {
+    const fn planar(bit_depth: u32, subsampling: (bool, bool)) -> Self {
+        Self {
+            bit_depth,
+            subsampling,
+            packing: Packing::Planar,
+            msb_aligned: false,
+        }
+    }
+
+    const fn msb_aligned(bit_depth: u32, subsampling: (bool, bool), packing: Packing) -> Self {
+        Self {
+            bit_depth,
+            subsampling,
+            packing,
+            msb_aligned: true,
+        }
+    }
+
+    fn sample_size(&self) -> usize {
+        if self.bit_depth > 8 {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Returns the size of the chroma planes of a `width`x`height` frame.
+    fn chroma_size(&self, width: usize, height: usize) -> (usize, usize) {
+        let (sub_x, sub_y) = self.subsampling;
+
+        (
+            if sub_x { width.div_ceil(2) } else { width },
+            if sub_y { height.div_ceil(2) } else { height },
+        )
+    }
+
+    /// Returns the shift to apply to the samples read from memory to get their value.
+    fn sample_shift(&self) -> u32 {
+        if self.msb_aligned {
+            16 - self.bit_depth
+        } else {
+            0
+        }
+    }
+
+    /// Returns the size in bytes of a `width`x`height` frame.
+    fn frame_size(&self, width: usize, height: usize) -> usize {
+        match self.packing {
+            Packing::Planar | Packing::SemiPlanar => {
+                let (uv_width, uv_height) = self.chroma_size(width, height);
+
+                (width * height + uv_width * uv_height * 2) * self.sample_size()
+            }
+            Packing::Yuyv => width.div_ceil(2) * 4 * height * self.sample_size(),
+            Packing::Ayuv | Packing::Y410 => width * height * 4,
+        }
+    }
+
+    /// Returns the size in bytes of a `width`x`height` frame, or an error if `buffer_len` is
+    /// smaller.
+    fn check_frame_size(
+        &self,
+        buffer_len: usize,
+        width: usize,
+        height: usize,
+    ) -> anyhow::Result<usize> {
+        let frame_size = self.frame_size(width, height);
+        if buffer_len < frame_size {
+            return Err(anyhow!(
+                "buffer of {} bytes is too small for a {}x{} frame of {} bytes",
+                buffer_len,
+                width,
+                height,
+                frame_size
+            ));
+        }
+
+        Ok(frame_size)
+    }
+}
+
+impl TryFrom<DecodedFormat> for YuvLayout {
+    type Error = anyhow::Error;
+
+    fn try_from(format: DecodedFormat) -> Result<Self, Self::Error> {
+        Ok(match format {
+            DecodedFormat::I420 => Self::planar(8, (true, true)),
+            DecodedFormat::NV12 => Self {
+                packing: Packing::SemiPlanar,
+                ..Self::planar(8, (true, true))
+            },
+            DecodedFormat::I422 => Self::planar(8, (true, false)),
+            DecodedFormat::I444 => Self::planar(8, (false, false)),
+            DecodedFormat::I010 => Self::planar(10, (true, true)),
+            DecodedFormat::I012 => Self::planar(12, (true, true)),
+            DecodedFormat::I210 => Self::planar(10, (true, false)),
+            DecodedFormat::I212 => Self::planar(12, (true, false)),
+            DecodedFormat::I410 => Self::planar(10, (false, false)),
+            DecodedFormat::I412 => Self::planar(12, (false, false)),
+            DecodedFormat::P010 => Self::msb_aligned(10, (true, true), Packing::SemiPlanar),
+            DecodedFormat::P012 => Self::msb_aligned(12, (true, true), Packing::SemiPlanar),
+            DecodedFormat::P016 => Self::msb_aligned(16, (true, true), Packing::SemiPlanar),
+            DecodedFormat::YUY2 => Self {
+                packing: Packing::Yuyv,
+                ..Self::planar(8, (true, false))
+            },
+            DecodedFormat::Y210 => Self::msb_aligned(10, (true, false), Packing::Yuyv),
+            DecodedFormat::Y410 => Self {
+                packing: Packing::Y410,
+                ..Self::planar(10, (false, false))
+            },
+            DecodedFormat::AYUV => Self {
+                packing: Packing::Ayuv,
+                ..Self::planar(8, (false, false))
+            },
+            DecodedFormat::ARGB | DecodedFormat::XRGB | DecodedFormat::AB30 => {
+                return Err(anyhow!("{:?} is not a YUV format", format))
+            }
+        })
+    }
+}
+
+/// A frame with its planes stored separately, using 16 bits per sample.
+struct PlanarFrame {
+    width: usize,
+    height: usize,
+    bit_depth: u32,
+    subsampling: (bool, bool),
+    /// Y, U and V planes, without padding.
+    planes: [Vec<u16>; 3],
+}
+
+impl PlanarFrame {
+    fn chroma_size(&self) -> (usize, usize) {
+        YuvLayout::planar(self.bit_depth, self.subsampling).chroma_size(self.width, self.height)
+    }
+
+    /// Unpacks the `width`x`height` frame `src` of `layout`.
+    fn unpack(layout: YuvLayout, src: &[u8], width: usize, height: usize) -> anyhow::Result<Self> {
+        let src = &src[..layout.check_frame_size(src.len(), width, height)?];
+
+        let (uv_width, uv_height) = layout.chroma_size(width, height);
+        let sample_size = layout.sample_size();
+        let shift = layout.sample_shift();
+        let read_samples = |src: &[u8]| -> Vec<u16> {
+            if sample_size == 1 {
+                src.iter().map(|&s| u16::from(s)).collect()
+            } else {
+                src.chunks_exact(2)
+                    .map(|s| LittleEndian::read_u16(s) >> shift)
+                    .collect()
+            }
+        };
+
+        let planes = match layout.packing {
+            Packing::Planar => {
+                let (y_src, uv_src) = src.split_at(width * height * sample_size);
+                let (u_src, v_src) = uv_src.split_at(uv_width * uv_height * sample_size);
+
+                [
+                    read_samples(y_src),
+                    read_samples(u_src),
+                    read_samples(v_src),
+                ]
+            }
+            Packing::SemiPlanar => {
+                let (y_src, uv_src) = src.split_at(width * height * sample_size);
+                let (u, v) = read_samples(uv_src)
+                    .chunks_exact(2)
+                    .map(|uv| (uv[0], uv[1]))
+                    .unzip();
+
+                [read_samples(y_src), u, v]
+            }
+            Packing::Yuyv => {
+                let mut y = Vec::with_capacity(width * height);
+                let mut u = Vec::with_capacity(uv_width * uv_height);
+                let mut v = Vec::with_capacity(uv_width * uv_height);
+                for line in read_samples(src).chunks_exact(uv_width * 4) {
+                    for (i, yuyv) in line.chunks_exact(4).enumerate() {
+                        y.push(yuyv[0]);
+                        // The last pixel pair of lines with an odd width only has one pixel.
+                        if i * 2 + 1 < width {
+                            y.push(yuyv[2]);
+                        }
+                        u.push(yuyv[1]);
+                        v.push(yuyv[3]);
+                    }
+                }
+
+                [y, u, v]
+            }
+            Packing::Ayuv => {
+                let pixels = src.chunks_exact(4);
+                let y = pixels.clone().map(|p| u16::from(p[2])).collect();
+                let u = pixels.clone().map(|p| u16::from(p[1])).collect();
+                let v = pixels.map(|p| u16::from(p[0])).collect();
+
+                [y, u, v]
+            }
+            Packing::Y410 => {
+                let words = src.chunks_exact(4).map(LittleEndian::read_u32);
+                let y = words.clone().map(|w| ((w >> 10) & 0x3ff) as u16).collect();
+                let u = words.clone().map(|w| (w & 0x3ff) as u16).collect();
+                let v = words.map(|w| ((w >> 20) & 0x3ff) as u16).collect();
+
+                [y, u, v]
+            }
+        };
+
+        Ok(Self {
+            width,
+            height,
+            bit_depth: layout.bit_depth,
+            subsampling: layout.subsampling,
+            planes,
+        })
+    }
+
+    /// Packs the frame into `dst` according to `layout`, which must have the bit depth and
+    /// subsampling of the frame. Alpha samples, if any, are set to their maximum value.
+    fn pack(&self, layout: YuvLayout, dst: &mut [u8]) -> anyhow::Result<()> {
+        let frame_size = layout.check_frame_size(dst.len(), self.width, self.height)?;
+        let dst = &mut dst[..frame_size];
+
+        let sample_size = layout.sample_size();
+        let shift = layout.sample_shift();
+        let write_samples = |samples: &mut dyn Iterator<Item = u16>, dst: &mut [u8]| {
+            if sample_size == 1 {
+                for (d, s) in dst.iter_mut().zip(samples) {
+                    *d = s as u8;
+                }
+            } else {
+                for (d, s) in dst.chunks_exact_mut(2).zip(samples) {
+                    LittleEndian::write_u16(d, s << shift);
+                }
+            }
+        };
+
+        let [y, u, v] = &self.planes;
+        match layout.packing {
+            Packing::Planar => {
+                let (y_dst, uv_dst) = dst.split_at_mut(y.len() * sample_size);
+                let (u_dst, v_dst) = uv_dst.split_at_mut(u.len() * sample_size);
+                write_samples(&mut y.iter().copied(), y_dst);
+                write_samples(&mut u.iter().copied(), u_dst);
+                write_samples(&mut v.iter().copied(), v_dst);
+            }
+            Packing::SemiPlanar => {
+                let (y_dst, uv_dst) = dst.split_at_mut(y.len() * sample_size);
+                write_samples(&mut y.iter().copied(), y_dst);
+                write_samples(&mut u.iter().zip(v).flat_map(|(&u, &v)| [u, v]), uv_dst);
+            }
+            Packing::Yuyv => {
+                let (uv_width, _) = self.chroma_size();
+                let mut samples = Vec::with_capacity(uv_width * 4 * self.height);
+                for ((y_line, u_line), v_line) in y
+                    .chunks_exact(self.width)
+                    .zip(u.chunks_exact(uv_width))
+                    .zip(v.chunks_exact(uv_width))
+                {
+                    for (i, (&u, &v)) in u_line.iter().zip(v_line).enumerate() {
+                        // Repeat the last pixel of lines with an odd width.
+                        let y1 = y_line[std::cmp::min(i * 2 + 1, self.width - 1)];
+                        samples.extend([y_line[i * 2], u, y1, v]);
+                    }
+                }
+                write_samples(&mut samples.into_iter(), dst);
+            }
+            Packing::Ayuv => {
+                for (((pixel, &y), &u), &v) in dst.chunks_exact_mut(4).zip(y).zip(u).zip(v) {
+                    pixel.copy_from_slice(&[v as u8, u as u8, y as u8, 0xff]);
+                }
+            }
+            Packing::Y410 => {
+                for (((pixel, &y), &u), &v) in dst.chunks_exact_mut(4).zip(y).zip(u).zip(v) {
+                    let word = u32::from(u) | u32::from(y) << 10 | u32::from(v) << 20 | 0b11 << 30;
+                    LittleEndian::write_u32(pixel, word);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resamples the chroma planes to `subsampling`. Upsampling replicates samples, and
+    /// downsampling averages pairs of samples, so downsampling an upsampled frame is lossless.
+    fn resample_chroma(&mut self, subsampling: (bool, bool)) {
+        let (width, height) = self.chroma_size();
+        let (target_width, target_height) =
+            YuvLayout::planar(self.bit_depth, subsampling).chroma_size(self.width, self.height);
+
+        for plane in &mut self.planes[1..] {
+            let resampled = resample_lines(plane, width, height, target_width);
+            *plane = resample_columns(&resampled, target_width, height, target_height);
+        }
+
+        self.subsampling = subsampling;
+    }
+
+    /// Changes the bit depth of the samples to `bit_depth`, rounding when reducing it.
+    fn set_bit_depth(&mut self, bit_depth: u32) {
+        if bit_depth > self.bit_depth {
+            let shift = bit_depth - self.bit_depth;
+            for sample in self.planes.iter_mut().flatten() {
+                *sample <<= shift;
+            }
+        } else if bit_depth < self.bit_depth {
+            let shift = self.bit_depth - bit_depth;
+            let max = (1u32 << bit_depth) - 1;
+            for sample in self.planes.iter_mut().flatten() {
+                *sample =
+                    std::cmp::min((u32::from(*sample) + (1 << (shift - 1))) >> shift, max) as u16;
+            }
+        }
+
+        self.bit_depth = bit_depth;
+    }
+}
+
+/// Resamples each of the `height` lines of `width` samples in `src` to `target_width` samples.
+/// `target_width` must be `width`, or its double or half rounded up.
+fn resample_lines(src: &[u16], width: usize, height: usize, target_width: usize) -> Vec<u16> {
+    if target_width == width {
+        return src.to_vec();
+    }
+
+    let mut dst = vec![0; target_width * height];
+    for (src_line, dst_line) in src
+        .chunks_exact(width)
+        .zip(dst.chunks_exact_mut(target_width))
+    {
+        if target_width > width {
+            for (i, d) in dst_line.iter_mut().enumerate() {
+                *d = src_line[i / 2];
+            }
+        } else {
+            for (i, d) in dst_line.iter_mut().enumerate() {
+                let a = u32::from(src_line[i * 2]);
+                let b = u32::from(src_line[std::cmp::min(i * 2 + 1, width - 1)]);
+                *d = ((a + b + 1) >> 1) as u16;
+            }
+        }
+    }
+
+    dst
+}
+
+/// Resamples the `width`x`height` plane `src` to `target_height` lines. `target_height` must be
+/// `height`, or its double or half rounded up.
+fn resample_columns(src: &[u16], width: usize, height: usize, target_height: usize) -> Vec<u16> {
+    if target_height == height {
+        return src.to_vec();
+    }
+
+    let mut dst = vec![0; width * target_height];
+    for (i, dst_line) in dst.chunks_exact_mut(width).enumerate() {
+        if target_height > height {
+            dst_line.copy_from_slice(&src[i / 2 * width..][..width]);
+        } else {
+            let a = &src[i * 2 * width..][..width];
+            let b = &src[std::cmp::min(i * 2 + 1, height - 1) * width..][..width];
+            for ((d, &a), &b) in dst_line.iter_mut().zip(a).zip(b) {
+                *d = ((u32::from(a) + u32::from(b) + 1) >> 1) as u16;
+            }
+        }
+    }
+
+    dst
+}
+
+/// Converts the `width`x`height` frame `src` of `src_layout` into `dst` with `dst_layout`, which
+/// must both be planar or semi-planar and have the same bit depth and subsampling. Samples are
+/// copied line by line without going through a [`PlanarFrame`].
+fn convert_planar(
+    src_layout: YuvLayout,
+    src: &[u8],
+    dst_layout: YuvLayout,
+    dst: &mut [u8],
+    width: usize,
+    height: usize,
+) -> anyhow::Result<()> {
+    let src = &src[..src_layout.check_frame_size(src.len(), width, height)?];
+    let frame_size = dst_layout.check_frame_size(dst.len(), width, height)?;
+    let dst = &mut dst[..frame_size];
+
+    let (uv_width, _) = src_layout.chroma_size(width, height);
+    let sample_size = src_layout.sample_size();
+    let (src_shift, dst_shift) = (src_layout.sample_shift(), dst_layout.sample_shift());
+    let copy_samples = |src: &[u8], dst: &mut [u8]| {
+        if sample_size == 1 || src_shift == dst_shift {
+            dst.copy_from_slice(src);
+        } else {
+            for (d, s) in dst.chunks_exact_mut(2).zip(src.chunks_exact(2)) {
+                LittleEndian::write_u16(d, LittleEndian::read_u16(s) >> src_shift << dst_shift);
+            }
+        }
+    };
+
+    let (y_src, uv_src) = src.split_at(width * height * sample_size);
+    let (y_dst, uv_dst) = dst.split_at_mut(width * height * sample_size);
+    copy_samples(y_src, y_dst);
+
+    let line_size = uv_width * sample_size;
+    match (src_layout.packing, dst_layout.packing) {
+        (Packing::SemiPlanar, Packing::Planar) => {
+            let (u_dst, v_dst) = uv_dst.split_at_mut(uv_dst.len() / 2);
+            for ((uv_line, u_line), v_line) in uv_src
+                .chunks_exact(line_size * 2)
+                .zip(u_dst.chunks_exact_mut(line_size))
+                .zip(v_dst.chunks_exact_mut(line_size))
+            {
+                for ((uv, u), v) in uv_line
+                    .chunks_exact(sample_size * 2)
+                    .zip(u_line.chunks_exact_mut(sample_size))
+                    .zip(v_line.chunks_exact_mut(sample_size))
+                {
+                    let (src_u, src_v) = uv.split_at(sample_size);
+                    copy_samples(src_u, u);
+                    copy_samples(src_v, v);
+                }
+            }
+        }
+        (Packing::Planar, Packing::SemiPlanar) => {
+            let (u_src, v_src) = uv_src.split_at(uv_src.len() / 2);
+            for ((uv_line, u_line), v_line) in uv_dst
+                .chunks_exact_mut(line_size * 2)
+                .zip(u_src.chunks_exact(line_size))
+                .zip(v_src.chunks_exact(line_size))
+            {
+                for ((uv, u), v) in uv_line
+                    .chunks_exact_mut(sample_size * 2)
+                    .zip(u_line.chunks_exact(sample_size))
+                    .zip(v_line.chunks_exact(sample_size))
+                {
+                    let (dst_u, dst_v) = uv.split_at_mut(sample_size);
+                    copy_samples(u, dst_u);
+                    copy_samples(v, dst_v);
+                }
+            }
+        }
+        _ => copy_samples(uv_src, uv_dst),
+    }
+
+    Ok(())
+}
+
+/// Converts the `width`x`height` frame `src` of `src_format` into `dst` with `dst_format`.
+///
+/// Any pair of YUV formats is supported: samples are packed or unpacked, chroma planes are
+/// upsampled or downsampled, and the bit depth is changed as needed. RGB formats are not
+/// supported.
+pub fn convert(
+    src_format: DecodedFormat,
+    src: &[u8],
+    dst_format: DecodedFormat,
+    dst: &mut [u8],
+    width: usize,
+    height: usize,
+) -> anyhow::Result<()> {
+    let src_layout = YuvLayout::try_from(src_format)?;
+    let dst_layout = YuvLayout::try_from(dst_format)?;
+
+    let is_planar =
+        |layout: YuvLayout| matches!(layout.packing, Packing::Planar | Packing::SemiPlanar);
+    if is_planar(src_layout)
+        && is_planar(dst_layout)
+        && src_layout.bit_depth == dst_layout.bit_depth
+        && src_layout.subsampling == dst_layout.subsampling
+    {
+        return convert_planar(src_layout, src, dst_layout, dst, width, height);
+    }
+
+    let mut frame = PlanarFrame::unpack(src_layout, src, width, height)?;
+    frame.resample_chroma(dst_layout.subsampling);
+    frame.set_bit_depth(dst_layout.bit_depth);
+
+    frame.pack(dst_layout, dst)
+}
+
+/// Converts the `width`x`height` P010 frame `src` into I010 in `dst`.
+pub fn p010_to_i010(src: &[u8], dst: &mut [u8], width: usize, height: usize) -> anyhow::Result<()> {
+    convert(
+        DecodedFormat::P010,
+        src,
+        DecodedFormat::I010,
+        dst,
+        width,
+        height,
+    )
+}
+
+/// Converts the `width`x`height` I010 frame `src` into P010 in `dst`.
+pub fn i010_to_p010(src: &[u8], dst: &mut [u8], width: usize, height: usize) -> anyhow::Result<()> {
+    convert(
+        DecodedFormat::I010,
+        src,
+        DecodedFormat::P010,
+        dst,
+        width,
+        height,
+    )
+}
+
+/// Matrix used to convert YUV samples to RGB.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMatrix {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl ColorMatrix {
+    /// Returns the `Kr` and `Kb` constants of the matrix.
+    fn constants(&self) -> (f64, f64) {
+        match self {
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+            ColorMatrix::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+/// Range of the YUV samples.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorRange {
+    /// Luma between 16 and 235 and chroma between 16 and 240, scaled to the bit depth.
+    Limited,
+    /// Samples use the whole range allowed by the bit depth.
+    Full,
+}
+
+/// Fractional bits of the fixed-point YUV to RGB coefficients.
+const RGB_SHIFT: u32 = 14;
+
+/// Converts the `width`x`height` frame `src` of `src_format` into 8-bit RGBA in `dst`, i.e. four
+/// bytes per pixel with the red component first and an opaque alpha.
+pub fn yuv_to_rgba(
+    src_format: DecodedFormat,
+    src: &[u8],
+    dst: &mut [u8],
+    width: usize,
+    height: usize,
+    matrix: ColorMatrix,
+    range: ColorRange,
+) -> anyhow::Result<()> {
+    if dst.len() < width * height * 4 {
+        return Err(anyhow!(
+            "buffer of {} bytes is too small for a {}x{} RGBA frame",
+            dst.len(),
+            width,
+            height
+        ));
+    }
+
+    let mut frame = PlanarFrame::unpack(src_format.try_into()?, src, width, height)?;
+    frame.resample_chroma((false, false));
+
+    let bit_depth = frame.bit_depth;
+    let (y_offset, y_range, c_range) = match range {
+        ColorRange::Limited => (
+            16 << (bit_depth - 8),
+            219 << (bit_depth - 8),
+            224 << (bit_depth - 8),
+        ),
+        ColorRange::Full => (0, (1 << bit_depth) - 1, (1 << bit_depth) - 1),
+    };
+    let c_offset = 1 << (bit_depth - 1);
+
+    let (kr, kb) = matrix.constants();
+    let kg = 1.0 - kr - kb;
+    let scale = f64::from(1 << RGB_SHIFT) * 255.0;
+    let fixed = |k: f64, range: i32| (k * scale / f64::from(range)).round() as i32;
+    let y_coeff = fixed(1.0, y_range);
+    let r_v = fixed(2.0 * (1.0 - kr), c_range);
+    let g_u = fixed(2.0 * kb * (1.0 - kb) / kg, c_range);
+    let g_v = fixed(2.0 * kr * (1.0 - kr) / kg, c_range);
+    let b_u = fixed(2.0 * (1.0 - kb), c_range);
+    let round = 1 << (RGB_SHIFT - 1);
+    let clip = |v: i32| ((v + round) >> RGB_SHIFT).clamp(0, 255) as u8;
+
+    let [y_plane, u_plane, v_plane] = &frame.planes;
+    for (((rgba, &y), &u), &v) in dst
+        .chunks_exact_mut(4)
+        .zip(y_plane)
+        .zip(u_plane)
+        .zip(v_plane)
+    {
+        let y = (i32::from(y) - y_offset) * y_coeff;
+        let u = i32::from(u) - c_offset;
+        let v = i32::from(v) - c_offset;
+
+        rgba[0] = clip(y + r_v * v);
+        rgba[1] = clip(y - g_u * u - g_v * v);
+        rgba[2] = clip(y + b_u * u);
+        rgba[3] = 255;
+    }
+
+    Ok(())
+}
+
+/// Fractional bits of the bilinear scaling weights.
+const WEIGHT_SHIFT: u32 = 8;
+
+/// Returns, for each of the `dst_size` output positions, the two input samples to blend and the
+/// weight of the second one.
+fn bilinear_taps(src_size: usize, dst_size: usize) -> Vec<(usize, usize, u32)> {
+    (0..dst_size)
+        .map(|i| {
+            // Align the centers of the samples of both sizes.
+            let pos = ((i as f64 + 0.5) * src_size as f64 / dst_size as f64 - 0.5).max(0.0);
+            let first = std::cmp::min(pos as usize, src_size - 1);
+            let second = std::cmp::min(first + 1, src_size - 1);
+            let weight = ((pos - first as f64) * f64::from(1 << WEIGHT_SHIFT)).round() as u32;
+
+            (first, second, weight)
+        })
+        .collect()
+}
+
+/// Scales the `src_width`x`src_height` plane `src` to `dst_width`x`dst_height` using bilinear
+/// interpolation.
+fn scale_plane(
+    src: &[u16],
+    src_width: usize,
+    src_height: usize,
+    dst_width: usize,
+    dst_height: usize,
+) -> Vec<u16> {
+    let one = 1 << WEIGHT_SHIFT;
+    let x_taps = bilinear_taps(src_width, dst_width);
+    let mut dst = vec![0; dst_width * dst_height];
+    let mut top = vec![0u32; dst_width];
+    let mut bottom = vec![0u32; dst_width];
+
+    let scale_line = |line: &[u16], out: &mut [u32]| {
+        for (o, &(a, b, w)) in out.iter_mut().zip(&x_taps) {
+            *o = u32::from(line[a]) * (one - w) + u32::from(line[b]) * w;
+        }
+    };
+
+    for (dst_line, (a, b, w)) in dst
+        .chunks_exact_mut(dst_width)
+        .zip(bilinear_taps(src_height, dst_height))
+    {
+        scale_line(&src[a * src_width..][..src_width], &mut top);
+        scale_line(&src[b * src_width..][..src_width], &mut bottom);
+
+        let round = 1 << (2 * WEIGHT_SHIFT - 1);
+        for ((d, &t), &b) in dst_line.iter_mut().zip(&top).zip(&bottom) {
+            *d = ((t * (one - w) + b * w + round) >> (2 * WEIGHT_SHIFT)) as u16;
+        }
+    }
+
+    dst
+}
+
+/// Scales the `src_width`x`src_height` frame `src` of `format` into the
+/// `dst_width`x`dst_height` frame `dst` of the same format, using bilinear interpolation. Only
+/// YUV formats are supported.
+pub fn scale(
+    format: DecodedFormat,
+    src: &[u8],
+    src_width: usize,
+    src_height: usize,
+    dst: &mut [u8],
+    dst_width: usize,
+    dst_height: usize,
+) -> anyhow::Result<()> {
+    if dst_width == 0 || dst_height == 0 || src_width == 0 || src_height == 0 {
+        return Err(anyhow!("cannot scale from or to an empty frame"));
+    }
+
+    let layout = YuvLayout::try_from(format)?;
+    let mut frame = PlanarFrame::unpack(layout, src, src_width, src_height)?;
+    let (src_uv_width, src_uv_height) = layout.chroma_size(src_width, src_height);
+    let (dst_uv_width, dst_uv_height) = layout.chroma_size(dst_width, dst_height);
+
+    let [y, u, v] = &frame.planes;
+    frame.planes = [
+        scale_plane(y, src_width, src_height, dst_width, dst_height),
+        scale_plane(u, src_uv_width, src_uv_height, dst_uv_width, dst_uv_height),
+        scale_plane(v, src_uv_width, src_uv_height, dst_uv_width, dst_uv_height),
+    ];
+    frame.width = dst_width;
+    frame.height = dst_height;
+
+    frame.pack(layout, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::convert::convert;
+    use crate::convert::i010_to_p010;
+    use crate::convert::p010_to_i010;
+    use crate::convert::scale;
+    use crate::convert::yuv_to_rgba;
+    use crate::convert::ColorMatrix;
+    use crate::convert::ColorRange;
+    use crate::convert::PlanarFrame;
+    use crate::convert::YuvLayout;
+    use crate::DecodedFormat;
+
+    const ALL_FORMATS: [DecodedFormat; 17] = [
+        DecodedFormat::I420,
+        DecodedFormat::NV12,
+        DecodedFormat::I422,
+        DecodedFormat::I444,
+        DecodedFormat::I010,
+        DecodedFormat::I012,
+        DecodedFormat::I210,
+        DecodedFormat::I212,
+        DecodedFormat::I410,
+        DecodedFormat::I412,
+        DecodedFormat::P010,
+        DecodedFormat::P012,
+        DecodedFormat::P016,
+        DecodedFormat::YUY2,
+        DecodedFormat::Y210,
+        DecodedFormat::Y410,
+        DecodedFormat::AYUV,
+    ];
+
+    /// Returns a `width`x`height` frame of `format` filled with a pattern.
+    fn test_frame(format: DecodedFormat, width: usize, height: usize) -> Vec<u8> {
+        let layout = YuvLayout::try_from(format).unwrap();
+        let (uv_width, uv_height) = layout.chroma_size(width, height);
+        let num_values = 1u32 << layout.bit_depth;
+        let pattern = |len: usize, seed: u32| {
+            (0..len as u32)
+                .map(|i| ((i * 37 + seed) % num_values) as u16)
+                .collect()
+        };
+        let frame = PlanarFrame {
+            width,
+            height,
+            bit_depth: layout.bit_depth,
+            subsampling: layout.subsampling,
+            planes: [
+                pattern(width * height, 11),
+                pattern(uv_width * uv_height, 5),
+                pattern(uv_width * uv_height, 23),
+            ],
+        };
+
+        let mut buffer = vec![0; layout.frame_size(width, height)];
+        frame.pack(layout, &mut buffer).unwrap();
+
+        buffer
+    }
+
+    fn frame_size(format: DecodedFormat, width: usize, height: usize) -> usize {
+        YuvLayout::try_from(format)
+            .unwrap()
+            .frame_size(width, height)
+    }
+
+    #[test]
+    fn nv12_i420() {
+        #[rustfmt::skip]
+        let i420 = [
+            0, 1, 2, 3, 4, 5, 6, 7, // Y
+            10, 11, // U
+            20, 21, // V
+        ];
+        let mut nv12 = [0; 12];
+        convert(
+            DecodedFormat::I420,
+            &i420,
+            DecodedFormat::NV12,
+            &mut nv12,
+            4,
+            2,
+        )
+        .unwrap();
+        assert_eq!(nv12, [0, 1, 2, 3, 4, 5, 6, 7, 10, 20, 11, 21]);
+
+        let mut back = [0; 12];
+        convert(
+            DecodedFormat::NV12,
+            &nv12,
+            DecodedFormat::I420,
+            &mut back,
+            4,
+            2,
+        )
+        .unwrap();
+        assert_eq!(back, i420);
+    }
+
+    #[test]
+    fn p010_i010() {
+        // 2x2 frame: 4 luma samples and one pair of chroma samples.
+        let i010 = [1u16, 2, 3, 1023, 512, 64]
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        let mut p010 = vec![0; 12];
+        i010_to_p010(&i010, &mut p010, 2, 2).unwrap();
+
+        let expected = [1u16 << 6, 2 << 6, 3 << 6, 1023 << 6, 512 << 6, 64 << 6]
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        assert_eq!(p010, expected);
+
+        let mut back = vec![0; 12];
+        p010_to_i010(&p010, &mut back, 2, 2).unwrap();
+        assert_eq!(back, i010);
+    }
+
+    #[test]
+    fn chroma_resampling() {
+        // 3x2 I444 frame, downsampled to 2x1 chroma planes.
+        #[rustfmt::skip]
+        let i444 = [
+            0, 0, 0, 0, 0, 0, // Y
+            10, 20, 30, 40, 50, 60, // U
+            100, 100, 100, 200, 200, 200, // V
+        ];
+        let mut i420 = [0; 10];
+        convert(
+            DecodedFormat::I444,
+            &i444,
+            DecodedFormat::I420,
+            &mut i420,
+            3,
+            2,
+        )
+        .unwrap();
+        assert_eq!(i420, [0, 0, 0, 0, 0, 0, 30, 45, 150, 150]);
+
+        // Upsampling replicates the chroma samples.
+        let mut i444 = [0; 18];
+        convert(
+            DecodedFormat::I420,
+            &i420,
+            DecodedFormat::I444,
+            &mut i444,
+            3,
+            2,
+        )
+        .unwrap();
+        assert_eq!(
+            i444,
+            [0, 0, 0, 0, 0, 0, 30, 30, 45, 30, 30, 45, 150, 150, 150, 150, 150, 150]
+        );
+    }
+
+    #[test]
+    fn bit_depth() {
+        let i010 = [0u16, 3, 1021, 1023, 514, 2]
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        let mut i420 = [0; 6];
+        convert(
+            DecodedFormat::I010,
+            &i010,
+            DecodedFormat::I420,
+            &mut i420,
+            2,
+            2,
+        )
+        .unwrap();
+        assert_eq!(i420, [0, 1, 255, 255, 129, 1]);
+    }
+
+    #[test]
+    fn all_format_pairs() {
+        let (width, height) = (6, 4);
+
+        for src_format in ALL_FORMATS {
+            let src = test_frame(src_format, width, height);
+
+            for dst_format in ALL_FORMATS {
+                let mut dst = vec![0; frame_size(dst_format, width, height)];
+                convert(src_format, &src, dst_format, &mut dst, width, height).unwrap();
+
+                // Converting to a format with at least the same bit depth and chroma resolution
+                // is lossless.
+                let src_layout = YuvLayout::try_from(src_format).unwrap();
+                let dst_layout = YuvLayout::try_from(dst_format).unwrap();
+                let lossless = dst_layout.bit_depth >= src_layout.bit_depth
+                    && (dst_layout.subsampling.0 <= src_layout.subsampling.0)
+                    && (dst_layout.subsampling.1 <= src_layout.subsampling.1);
+                if lossless {
+                    let mut back = vec![0; src.len()];
+                    convert(dst_format, &dst, src_format, &mut back, width, height).unwrap();
+                    assert_eq!(back, src, "{:?} -> {:?}", src_format, dst_format);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn direct_planar_conversion() {
+        // Conversions between planar and semi-planar formats of the same bit depth and
+        // subsampling skip the intermediate planar frame, and must give the same result.
+        let (width, height) = (5, 3);
+
+        for src_format in ALL_FORMATS {
+            let src_layout = YuvLayout::try_from(src_format).unwrap();
+            let src = test_frame(src_format, width, height);
+
+            for dst_format in ALL_FORMATS {
+                let dst_layout = YuvLayout::try_from(dst_format).unwrap();
+                let mut dst = vec![0; frame_size(dst_format, width, height)];
+                convert(src_format, &src, dst_format, &mut dst, width, height).unwrap();
+
+                let mut frame = PlanarFrame::unpack(src_layout, &src, width, height).unwrap();
+                frame.resample_chroma(dst_layout.subsampling);
+                frame.set_bit_depth(dst_layout.bit_depth);
+                let mut expected = vec![0; dst.len()];
+                frame.pack(dst_layout, &mut expected).unwrap();
+
+                assert_eq!(dst, expected, "{:?} -> {:?}", src_format, dst_format);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_formats() {
+        // 3x1 I422 frame, whose last pixel pair only has one pixel.
+        let i422 = [1, 2, 3, 10, 11, 20, 21];
+        let mut yuy2 = [0; 8];
+        convert(
+            DecodedFormat::I422,
+            &i422,
+            DecodedFormat::YUY2,
+            &mut yuy2,
+            3,
+            1,
+        )
+        .unwrap();
+        assert_eq!(yuy2, [1, 10, 2, 20, 3, 11, 3, 21]);
+
+        let mut back = [0; 7];
+        convert(
+            DecodedFormat::YUY2,
+            &yuy2,
+            DecodedFormat::I422,
+            &mut back,
+            3,
+            1,
+        )
+        .unwrap();
+        assert_eq!(back, i422);
+
+        let mut ayuv = [0; 4];
+        convert(
+            DecodedFormat::I444,
+            &[1, 2, 3],
+            DecodedFormat::AYUV,
+            &mut ayuv,
+            1,
+            1,
+        )
+        .unwrap();
+        assert_eq!(ayuv, [3, 2, 1, 0xff]);
+
+        let i410 = [1u16, 2, 3]
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        let mut y410 = [0; 4];
+        convert(
+            DecodedFormat::I410,
+            &i410,
+            DecodedFormat::Y410,
+            &mut y410,
+            1,
+            1,
+        )
+        .unwrap();
+        assert_eq!(u32::from_le_bytes(y410), 2 | 1 << 10 | 3 << 20 | 0b11 << 30);
+    }
+
+    #[test]
+    fn rgb_formats_rejected() {
+        let src = test_frame(DecodedFormat::NV12, 2, 2);
+        let mut dst = [0; 16];
+
+        for format in [
+            DecodedFormat::ARGB,
+            DecodedFormat::XRGB,
+            DecodedFormat::AB30,
+        ] {
+            assert!(convert(DecodedFormat::NV12, &src, format, &mut dst, 2, 2).is_err());
+            assert!(convert(format, &dst, DecodedFormat::NV12, &mut [0; 6], 2, 2).is_err());
+        }
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let src = test_frame(DecodedFormat::NV12, 4, 4);
+        let mut dst = vec![0; frame_size(DecodedFormat::I420, 4, 4)];
+
+        assert!(convert(
+            DecodedFormat::NV12,
+            &src[..src.len() - 1],
+            DecodedFormat::I420,
+            &mut dst,
+            4,
+            4
+        )
+        .is_err());
+        assert!(convert(
+            DecodedFormat::NV12,
+            &src,
+            DecodedFormat::I420,
+            &mut dst[1..],
+            4,
+            4
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rgba() {
+        // Black, white, red and blue pixels in limited range BT.601.
+        let pixels = [
+            (16, 128, 128),
+            (235, 128, 128),
+            (81, 90, 240),
+            (41, 240, 110),
+        ];
+        let expected = [
+            [0, 0, 0, 255],
+            [255, 255, 255, 255],
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+        ];
+        for ((y, u, v), expected) in pixels.into_iter().zip(expected) {
+            let mut rgba = [0; 4];
+            yuv_to_rgba(
+                DecodedFormat::I444,
+                &[y, u, v],
+                &mut rgba,
+                1,
+                1,
+                ColorMatrix::Bt601,
+                ColorRange::Limited,
+            )
+            .unwrap();
+            for (c, e) in rgba.into_iter().zip(expected) {
+                assert!(c.abs_diff(e) <= 1, "{:?} != {:?}", rgba, expected);
+            }
+        }
+
+        // Full range 10-bit BT.709 and BT.2020 grays.
+        let gray = [512u16, 512, 512]
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        for matrix in [ColorMatrix::Bt709, ColorMatrix::Bt2020] {
+            let mut rgba = [0; 4];
+            yuv_to_rgba(
+                DecodedFormat::I410,
+                &gray,
+                &mut rgba,
+                1,
+                1,
+                matrix,
+                ColorRange::Full,
+            )
+            .unwrap();
+            assert_eq!(rgba, [128, 128, 128, 255]);
+        }
+    }
+
+    #[test]
+    fn bilinear_scaling() {
+        // Halving the size averages each 2x2 block.
+        #[rustfmt::skip]
+        let src = [
+            0, 2, 4, 6, // Y
+            2, 4, 6, 8,
+            10, 20, // U
+            30, 40, // V
+        ];
+        let mut dst = [0; 4];
+        scale(DecodedFormat::I420, &src, 4, 2, &mut dst, 2, 1).unwrap();
+        assert_eq!(dst, [2, 6, 15, 35]);
+
+        // Scaling a constant frame keeps it constant.
+        let src = vec![77; frame_size(DecodedFormat::NV12, 10, 6)];
+        let mut dst = vec![0; frame_size(DecodedFormat::NV12, 17, 9)];
+        scale(DecodedFormat::NV12, &src, 10, 6, &mut dst, 17, 9).unwrap();
+        assert!(dst.iter().all(|&s| s == 77));
+
+        // Scaling to the same size is lossless.
+        let src = test_frame(DecodedFormat::I012, 8, 6);
+        let mut dst = vec![0; src.len()];
+        scale(DecodedFormat::I012, &src, 8, 6, &mut dst, 8, 6).unwrap();
+        assert_eq!(dst, src);
+    }
+}
@@ -8,9 +8,17 @@
 //! several decoders for various codecs and backends.
 //!
 //! At the moment, only a [stateless] decoder interface is provided.
+//!
+//! # Threading
+//!
+//! Decoders and the [`DecodedHandle`]s they output are not [`Send`]. To decode on a worker thread
+//! and hand the decoded frames to other threads, use a [`threaded::ThreadedDecoder`], which owns the
+//! decoder and its frames on a thread of its own.
 
 pub mod stateless;
+pub mod threaded;
 
+use std::any::Any;
 use std::collections::VecDeque;
 use std::os::fd::AsFd;
 use std::os::fd::BorrowedFd;
@@ -20,8 +28,11 @@ use nix::sys::eventfd::EventFd;
 
 pub use crate::BlockingMode;
 
+use crate::codec::av1::film_grain::FilmGrain;
 use crate::decoder::stateless::PoolLayer;
+use crate::utils::DmabufFrame;
 use crate::DecodedFormat;
+use crate::FrameLayout;
 use crate::Resolution;
 
 /// Trait for a pool of frames in a particular format.
@@ -95,6 +106,58 @@ pub trait DecoderFormatNegotiator {
     fn try_format(&mut self, format: DecodedFormat) -> anyhow::Result<()>;
 }
 
+/// How a decoder reacts to errors in the stream, e.g. caused by lossy network input.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorResilience {
+    /// Any stream or backend error is returned to the client.
+    #[default]
+    Strict,
+    /// Corrupted parts of the stream are skipped and missing reference frames are replaced by the
+    /// nearest available frame. Frames decoded from the point of the error until the next
+    /// recovery point of the stream (e.g. a key frame) are marked as corrupted, see
+    /// [`DecodedHandle::is_corrupted`].
+    Conceal,
+}
+
+/// Which frames of the stream a decoder actually decodes. Frames that are not decoded are not
+/// output, but the state of the decoder is kept consistent so the frames that are decoded are
+/// correct.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// All frames are decoded.
+    #[default]
+    All,
+    /// Only key frames are decoded: IDR pictures for H.264, IRAP pictures for H.265, and key
+    /// frames for VP8, VP9 and AV1. Useful for thumbnail generation or trick play.
+    KeyFramesOnly,
+    /// Frames that are not used as reference by other frames are skipped: pictures with a
+    /// `nal_ref_idc` of 0 for H.264, sub-layer non-reference pictures of the highest temporal
+    /// sub-layer for H.265, and frames that do not refresh any reference frame for VP8, VP9 and
+    /// AV1.
+    SkipNonReference,
+}
+
+/// When a decoder outputs the frames it has decoded, for codecs in which frames can be displayed in
+/// a different order than they are decoded, i.e. H.264 and H.265. Other codecs always output frames
+/// as soon as they are decoded.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Frames are output as described by the bumping process of the specification. For H.264 this
+    /// means that frames are only output once the DPB is full, which can add several frames of
+    /// latency.
+    #[default]
+    Dpb,
+    /// Frames are output as soon as more frames than the reordering depth signaled by the stream
+    /// (`max_num_reorder_frames` for H.264, `sps_max_num_reorder_pics` for H.265) are waiting for
+    /// output, regardless of how full the DPB is. H.265 decoders already do this as part of the
+    /// bumping process.
+    ReorderLimit,
+    /// Frames are output in decoding order as soon as they are decoded. The client asserts that
+    /// the stream does not reorder frames, e.g. for cloud gaming or remote desktop streams, as
+    /// frames would be output in the wrong order otherwise.
+    DecodeOrder,
+}
+
 /// Events that can be retrieved using the `next_event` method of a decoder.
 pub enum DecoderEvent<'a, H: DecodedHandle> {
     /// The next frame has been decoded.
@@ -118,6 +181,26 @@ pub trait MappableHandle {
 
     /// Returns the size of the `buffer` argument required to call `read` on this handle.
     fn image_size(&mut self) -> usize;
+
+    /// Returns the planes of `self` in the native format of the frame, without copying them.
+    ///
+    /// Contrary to `read`, the planes keep the layout of the backend's memory, including any
+    /// padding, as described by the returned [`MappedPlanes::layout`].
+    ///
+    /// The default implementation is for handles whose memory cannot be mapped as is, and always
+    /// returns an error.
+    fn map_planes(&self) -> anyhow::Result<MappedPlanes> {
+        Err(anyhow::anyhow!("this handle cannot map its planes"))
+    }
+}
+
+/// A CPU mapping of the planes of a decoded frame, in the native format of the frame.
+pub struct MappedPlanes<'a> {
+    /// Format and layout of the frame. Plane offsets are relative to the start of the mapped
+    /// memory, and strides should be used to access each line of a plane.
+    pub layout: FrameLayout,
+    /// Data of each plane of `layout`, starting at the first sample of the plane.
+    pub planes: Vec<&'a [u8]>,
 }
 
 /// The handle type used by the decoder backend. The only requirement from implementors is that
@@ -149,6 +232,75 @@ pub trait DecodedHandle {
     /// Returns a reference to the internal [`DecodedHandle::Descriptor`]. Can be leveraged by
     /// platform-specific code,
     fn resource(&self) -> std::cell::Ref<Self::Descriptor>;
+
+    /// Returns `true` if errors have been concealed while decoding this frame or the frames it
+    /// depends on, or if it has been decoded after a seek before the stream recovered. In both
+    /// cases its content is likely to contain artifacts and it should not be displayed.
+    ///
+    /// The default implementation is for handles that cannot be marked, and always returns
+    /// `false`.
+    fn is_corrupted(&self) -> bool {
+        false
+    }
+
+    /// Marks this frame as corrupted. Used by decoders when concealing errors.
+    ///
+    /// The default implementation does nothing.
+    fn set_corrupted(&self) {}
+
+    /// Returns the film grain signaled by the stream for this frame if the backend has not
+    /// applied it, in which case the frame is grain-free and [`crate::apply_film_grain`] can be
+    /// used to add the grain after reading it. Returns `None` if the frame has no film grain or if
+    /// it has already been applied.
+    ///
+    /// The film grain is set when the frame is output, so for frames output several times it is
+    /// the one of their latest output.
+    ///
+    /// The default implementation is for handles that cannot carry film grain, and always returns
+    /// `None`.
+    fn pending_film_grain(&self) -> Option<FilmGrain> {
+        None
+    }
+
+    /// Sets the film grain that remains to be applied to this frame. Used by decoders for codecs
+    /// supporting film grain synthesis.
+    ///
+    /// The default implementation does nothing.
+    fn set_pending_film_grain(&self, _film_grain: Option<FilmGrain>) {}
+
+    /// Exports the frame as DMA-BUF objects without copying it, e.g. so it can be scanned out or
+    /// imported as a texture. The frame is synced before being exported.
+    ///
+    /// The default implementation is for handles that are not backed by DMA-BUF objects, and
+    /// always returns an error.
+    fn export_dmabuf(&self) -> anyhow::Result<ExportedFrame> {
+        Err(anyhow::anyhow!("this handle cannot be exported as DMA-BUF"))
+    }
+}
+
+/// A decoded frame exported as DMA-BUF objects.
+///
+/// The exported frame keeps the handle it comes from alive, so the decoder does not reuse the
+/// frame's memory for as long as the exported frame exists.
+pub struct ExportedFrame {
+    frame: DmabufFrame,
+    /// Dropped after `frame`, so its objects are closed before the memory can be reused.
+    _handle: Box<dyn Any>,
+}
+
+impl ExportedFrame {
+    /// Creates a new exported frame from `frame`, keeping `handle` alive for as long as it exists.
+    pub fn new(frame: DmabufFrame, handle: Box<dyn Any>) -> Self {
+        Self {
+            frame,
+            _handle: handle,
+        }
+    }
+
+    /// Returns the DMA-BUF objects and layout of the frame.
+    pub fn frame(&self) -> &DmabufFrame {
+        &self.frame
+    }
 }
 
 /// Implementation for any boxed [`DecodedHandle`], including trait objects.
@@ -185,6 +337,26 @@ where
     fn resource(&self) -> std::cell::Ref<Self::Descriptor> {
         self.as_ref().resource()
     }
+
+    fn is_corrupted(&self) -> bool {
+        self.as_ref().is_corrupted()
+    }
+
+    fn set_corrupted(&self) {
+        self.as_ref().set_corrupted()
+    }
+
+    fn pending_film_grain(&self) -> Option<FilmGrain> {
+        self.as_ref().pending_film_grain()
+    }
+
+    fn set_pending_film_grain(&self, film_grain: Option<FilmGrain>) {
+        self.as_ref().set_pending_film_grain(film_grain)
+    }
+
+    fn export_dmabuf(&self) -> anyhow::Result<ExportedFrame> {
+        self.as_ref().export_dmabuf()
+    }
 }
 
 /// Trait object for [`DecodedHandle`]s using a specific `Descriptor`.
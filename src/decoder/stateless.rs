@@ -29,11 +29,14 @@ use nix::sys::eventfd::EventFd;
 use thiserror::Error;
 
 use crate::decoder::BlockingMode;
+use crate::decoder::DecodeMode;
 use crate::decoder::DecodedHandle;
 use crate::decoder::DecoderEvent;
 use crate::decoder::DecoderFormatNegotiator;
 use crate::decoder::DynDecodedHandle;
+use crate::decoder::ErrorResilience;
 use crate::decoder::FramePool;
+use crate::decoder::OutputMode;
 use crate::decoder::ReadyFramesQueue;
 use crate::decoder::StreamInfo;
 use crate::DecodedFormat;
@@ -65,7 +68,7 @@ enum DecodingState<F> {
     /// Decoder is currently decoding input.
     Decoding,
     /// Decoder has been reset after a flush, and can resume with the current parameters after
-    /// seeing a key frame.
+    /// seeing a random access point, e.g. a key frame.
     Reset,
 }
 
@@ -264,11 +267,41 @@ pub trait StatelessVideoDecoder {
     /// Flush the decoder i.e. finish processing all pending decode requests and make sure the
     /// resulting frames are ready to be retrieved via [`next_event`].
     ///
-    /// Note that after flushing, a key frame must be submitted before decoding can resume.
+    /// Note that after flushing, a key frame must be submitted before decoding can resume. This
+    /// makes it possible to seek in the stream: for H.264, decoding can also resume at a recovery
+    /// point SEI message or a non-IDR I picture, and the frames decoded before the stream
+    /// recovers are reported with [`DecodedHandle::is_corrupted`]. For H.265, decoding resumes at
+    /// any IRAP picture and the RASL pictures associated with it are skipped.
     ///
     /// [`next_event`]: StatelessVideoDecoder::next_event
     fn flush(&mut self) -> Result<(), DecodeError>;
 
+    /// Sets which frames of the stream are decoded. The default is [`DecodeMode::All`].
+    ///
+    /// Switching back to a mode that decodes more frames should be done right after a call to
+    /// [`flush`](StatelessVideoDecoder::flush), as the skipped frames may be needed as
+    /// references.
+    ///
+    /// The default implementation is for decoders that cannot skip frames, and ignores `mode`.
+    fn set_decode_mode(&mut self, _mode: DecodeMode) {}
+
+    /// Sets how the decoder reacts to errors in the stream. The default is
+    /// [`ErrorResilience::Strict`].
+    ///
+    /// The default implementation is for decoders that cannot conceal errors, and ignores
+    /// `error_resilience`.
+    fn set_error_resilience(&mut self, _error_resilience: ErrorResilience) {}
+
+    /// Sets when decoded frames are output. The default is [`OutputMode::Dpb`].
+    ///
+    /// The mode should be set before decoding starts, or after a call to
+    /// [`flush`](StatelessVideoDecoder::flush), as frames waiting for output when switching to
+    /// [`OutputMode::DecodeOrder`] may otherwise never be output.
+    ///
+    /// The default implementation is for codecs whose frames are output as soon as they are
+    /// decoded, and ignores `output_mode`.
+    fn set_output_mode(&mut self, _output_mode: OutputMode) {}
+
     /// Returns the frame pool for `resolution` in use with the decoder. If
     /// `resolution` is None, the pool of the highest resolution is returned.
     ///
@@ -325,6 +358,18 @@ where
         self.0.flush()
     }
 
+    fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.0.set_decode_mode(mode)
+    }
+
+    fn set_error_resilience(&mut self, error_resilience: ErrorResilience) {
+        self.0.set_error_resilience(error_resilience)
+    }
+
+    fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.0.set_output_mode(output_mode)
+    }
+
     fn frame_pool(&mut self, layer: PoolLayer) -> Vec<&mut Self::FramePool> {
         self.0
             .frame_pool(layer)
@@ -413,6 +458,24 @@ where
     /// Union of `awaiting_format_event` and `ready_queue` to signal whenever there is an event
     /// (frame ready or format change) pending.
     epoll_fd: Epoll,
+
+    /// How the decoder reacts to errors in the stream.
+    error_resilience: ErrorResilience,
+
+    /// Whether an error has been concealed since the last recovery point of the stream. Frames
+    /// decoded while this is set are marked as corrupted.
+    corrupted: bool,
+
+    /// Whether the picture being decoded follows a seek and precedes the recovery point of the
+    /// stream. Such pictures may reference pictures that have not been decoded, so their errors
+    /// are concealed and they are marked as corrupted.
+    recovering: bool,
+
+    /// Which frames of the stream are decoded.
+    decode_mode: DecodeMode,
+
+    /// When decoded frames are output.
+    output_mode: OutputMode,
 }
 
 #[derive(Debug, Error)]
@@ -462,6 +525,11 @@ where
             codec: Default::default(),
             awaiting_format_event,
             epoll_fd,
+            error_resilience: Default::default(),
+            corrupted: false,
+            recovering: false,
+            decode_mode: Default::default(),
+            output_mode: Default::default(),
         })
     }
 
@@ -507,6 +575,118 @@ where
     }
 }
 
+impl<C, B> StatelessDecoder<C, B>
+where
+    C: StatelessCodec,
+    B: StatelessDecoderBackend + StatelessDecoderBackendPicture<C>,
+{
+    /// Returns how the decoder currently reacts to errors in the stream.
+    pub fn error_resilience(&self) -> ErrorResilience {
+        self.error_resilience
+    }
+
+    /// Returns when decoded frames are currently output.
+    pub fn output_mode(&self) -> OutputMode {
+        self.output_mode
+    }
+
+    /// Returns whether errors are to be concealed rather than returned.
+    fn conceal_errors(&self) -> bool {
+        self.error_resilience == ErrorResilience::Conceal
+    }
+
+    /// Applies the error resilience policy to `res`. In [`ErrorResilience::Conceal`] mode, stream
+    /// and backend errors are logged and dropped so the input that caused them can be skipped,
+    /// and the frames decoded until the next recovery point are marked as corrupted.
+    ///
+    /// Pictures decoded while recovering from a seek are marked as corrupted anyway, and may be
+    /// leading pictures that the following ones do not reference, so their errors do not mark
+    /// the following frames.
+    ///
+    /// Errors that the client is expected to act upon are always returned.
+    fn conceal_error(&mut self, res: Result<(), DecodeError>) -> Result<(), DecodeError> {
+        match res {
+            Err(DecodeError::DecoderError(e)) if self.conceal_errors() => {
+                log::warn!("concealing decoding error: {:#}", e);
+                self.corrupted |= !self.recovering;
+                Ok(())
+            }
+            Err(DecodeError::BackendError(StatelessBackendError::Other(e)))
+                if self.conceal_errors() =>
+            {
+                log::warn!("concealing backend error: {:#}", e);
+                self.corrupted |= !self.recovering;
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// Signals that the picture being decoded is a recovery point of the stream, i.e. that it and
+    /// the pictures following it can be decoded correctly regardless of what came before.
+    fn recovery_point(&mut self) {
+        if self.corrupted {
+            log::debug!("recovery point reached, output is no longer corrupted");
+        }
+        self.corrupted = false;
+        self.recovering = false;
+    }
+
+    /// Signals that decoding resumes after a seek at a picture that is not a clean random access
+    /// point. The pictures decoded until [`Self::recovery_point`] is called are not displayable.
+    fn start_recovery(&mut self) {
+        log::debug!("resuming decoding, output is corrupted until the next recovery point");
+        self.recovering = true;
+    }
+
+    /// Returns whether a frame should be decoded according to the current [`DecodeMode`].
+    fn should_decode(&self, is_key_frame: bool, is_reference: bool) -> bool {
+        match self.decode_mode {
+            DecodeMode::All => true,
+            DecodeMode::KeyFramesOnly => is_key_frame,
+            DecodeMode::SkipNonReference => is_key_frame || is_reference,
+        }
+    }
+
+    /// Marks `handle` as corrupted if an error has been concealed since the last recovery point,
+    /// or if it has been decoded while recovering from a seek.
+    fn mark_if_corrupted(&self, handle: &B::Handle) {
+        if self.corrupted || self.recovering {
+            handle.set_corrupted();
+        }
+    }
+}
+
+/// Replaces the missing entries of `reference_frames` listed in `indices` with the available
+/// reference frame closest in time to `timestamp`, if any.
+///
+/// Returns `true` if a missing entry has been found.
+fn substitute_missing_references<H: DecodedHandle + Clone>(
+    reference_frames: &mut [Option<H>],
+    indices: impl IntoIterator<Item = usize>,
+    timestamp: u64,
+) -> bool {
+    let nearest = reference_frames
+        .iter()
+        .flatten()
+        .min_by_key(|h| h.timestamp().abs_diff(timestamp))
+        .cloned();
+
+    let mut missing = false;
+    for idx in indices {
+        if let Some(slot @ None) = reference_frames.get_mut(idx) {
+            log::warn!(
+                "reference frame {} is missing, substituting nearest frame",
+                idx
+            );
+            missing = true;
+            *slot = nearest.clone();
+        }
+    }
+
+    missing
+}
+
 impl<C, B> private::StatelessVideoDecoder for StatelessDecoder<C, B>
 where
     C: StatelessCodec,
@@ -522,11 +702,136 @@ where
     }
 }
 
+/// Creates a decoder for `requirements.codec` using the first available backend satisfying
+/// `requirements`, and returns it as a [`DynStatelessVideoDecoder`] producing frames backed by
+/// memory of type `M`.
+///
+/// This spares applications from matching over the codec and backend themselves. Backends are
+/// tried in order of preference, and skipped if their [capabilities](crate::backend::capabilities)
+/// do not satisfy `requirements` or cannot be queried. VA-API is currently the only stateless
+/// decoding backend.
+#[cfg(feature = "vaapi")]
+pub fn new_dyn_decoder<M>(
+    requirements: &crate::backend::capabilities::Requirements,
+    blocking_mode: BlockingMode,
+) -> anyhow::Result<DynStatelessVideoDecoder<M>>
+where
+    M: libva::SurfaceMemoryDescriptor + 'static,
+{
+    use crate::backend::capabilities::CapabilityQuery;
+    use crate::backend::vaapi::decoder::VaapiBackend;
+    use crate::EncodedFormat;
+
+    let display =
+        libva::Display::open().ok_or_else(|| anyhow::anyhow!("failed to open a VA display"))?;
+    match display.capabilities() {
+        Ok(capabilities) if capabilities.supports_decoding(requirements) => (),
+        Ok(_) => {
+            return Err(anyhow::anyhow!(
+                "no available backend satisfies {:?}",
+                requirements
+            ))
+        }
+        Err(e) => {
+            log::warn!("failed to query the VA-API capabilities: {:#}", e);
+            return Err(anyhow::anyhow!(
+                "no available backend satisfies {:?}",
+                requirements
+            ));
+        }
+    }
+
+    let decoder = match requirements.codec {
+        EncodedFormat::H264 => {
+            StatelessDecoder::<h264::H264, VaapiBackend<M>>::new_vaapi::<M>(display, blocking_mode)?
+                .into_trait_object()
+        }
+        EncodedFormat::H265 => {
+            StatelessDecoder::<h265::H265, VaapiBackend<M>>::new_vaapi::<M>(display, blocking_mode)?
+                .into_trait_object()
+        }
+        EncodedFormat::VP8 => {
+            StatelessDecoder::<vp8::Vp8, VaapiBackend<M>>::new_vaapi::<M>(display, blocking_mode)?
+                .into_trait_object()
+        }
+        EncodedFormat::VP9 => {
+            StatelessDecoder::<vp9::Vp9, VaapiBackend<M>>::new_vaapi::<M>(display, blocking_mode)?
+                .into_trait_object()
+        }
+        EncodedFormat::AV1 => {
+            StatelessDecoder::<av1::Av1, VaapiBackend<M>>::new_vaapi::<M>(display, blocking_mode)?
+                .into_trait_object()
+        }
+    };
+
+    Ok(decoder)
+}
+
+/// Creates a decoder for `codec` using the dummy backend, and returns it as a
+/// [`DynStatelessVideoDecoder`].
+///
+/// The dummy backend parses the stream and outputs frames without decoding them, which can be
+/// used to exercise an application without hardware support. It does not use any memory, hence
+/// the `()` descriptor.
+pub fn new_dummy_dyn_decoder(
+    codec: crate::EncodedFormat,
+    blocking_mode: BlockingMode,
+) -> anyhow::Result<DynStatelessVideoDecoder<()>> {
+    use crate::EncodedFormat;
+
+    let decoder = match codec {
+        EncodedFormat::H264 => {
+            StatelessDecoder::<h264::H264, _>::new_dummy(blocking_mode)?.into_trait_object()
+        }
+        EncodedFormat::H265 => {
+            StatelessDecoder::<h265::H265, _>::new_dummy(blocking_mode)?.into_trait_object()
+        }
+        EncodedFormat::VP8 => {
+            StatelessDecoder::<vp8::Vp8, _>::new_dummy(blocking_mode)?.into_trait_object()
+        }
+        EncodedFormat::VP9 => {
+            StatelessDecoder::<vp9::Vp9, _>::new_dummy(blocking_mode)?.into_trait_object()
+        }
+        EncodedFormat::AV1 => {
+            StatelessDecoder::<av1::Av1, _>::new_dummy(blocking_mode)?.into_trait_object()
+        }
+    };
+
+    Ok(decoder)
+}
+
+/// Creates a decoder for `requirements.codec` producing frames allocated by the backend, falling
+/// back to a [dummy decoder](new_dummy_dyn_decoder) if no backend can create one, e.g. because
+/// none is compiled in, none satisfies `requirements`, or the hardware cannot be opened.
+pub fn new_dyn_decoder_or_dummy(
+    requirements: &crate::backend::capabilities::Requirements,
+    blocking_mode: BlockingMode,
+) -> anyhow::Result<DynStatelessVideoDecoder<()>> {
+    #[cfg(feature = "vaapi")]
+    match new_dyn_decoder::<()>(requirements, blocking_mode) {
+        Ok(decoder) => return Ok(decoder),
+        Err(e) => log::warn!("{:#}, falling back to the dummy backend", e),
+    }
+
+    new_dummy_dyn_decoder(requirements.codec, blocking_mode)
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
+    use crate::backend::capabilities::Requirements;
+    use crate::codec::h264::parser::Nalu;
+    use crate::decoder::stateless::h264::tests::DECODE_64X64_PROGRESSIVE_I_P_B_P;
+    use crate::decoder::stateless::new_dummy_dyn_decoder;
+    use crate::decoder::stateless::new_dyn_decoder_or_dummy;
+    use crate::decoder::stateless::DecodeError;
     use crate::decoder::stateless::StatelessVideoDecoder;
+    use crate::decoder::BlockingMode;
     use crate::decoder::DecodedHandle;
+    use crate::decoder::DecoderEvent;
     use crate::decoder::FramePool;
+    use crate::utils::NalIterator;
+    use crate::EncodedFormat;
+    use crate::Resolution;
 
     /// Stream that can be used in tests, along with the CRC32 of all of its frames.
     pub struct TestStream {
@@ -586,4 +891,68 @@ pub(crate) mod tests {
 
         assert_eq!(crcs.next(), None, "decoded less frames than expected");
     }
+
+    /// Decodes `units` one after the other, accepting any format change, then flushes `decoder`
+    /// if `flush` is `true`.
+    ///
+    /// Returns whether each output frame is corrupted, in output order, along with the number of
+    /// frames output before the flush.
+    pub fn decode_units<'a, D: StatelessVideoDecoder + ?Sized>(
+        decoder: &mut D,
+        units: impl IntoIterator<Item = &'a [u8]>,
+        flush: bool,
+    ) -> Result<(Vec<bool>, usize), DecodeError> {
+        let mut corrupted = vec![];
+        let mut collect_frames = |decoder: &mut D| {
+            while let Some(event) = decoder.next_event() {
+                if let DecoderEvent::FrameReady(handle) = event {
+                    corrupted.push(handle.is_corrupted());
+                }
+            }
+            corrupted.len()
+        };
+
+        for unit in units {
+            loop {
+                let res = decoder.decode(0, unit);
+                collect_frames(decoder);
+
+                match res {
+                    Ok(_) => break,
+                    Err(DecodeError::CheckEvents) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let before_flush = collect_frames(decoder);
+        if flush {
+            decoder.flush()?;
+            collect_frames(decoder);
+        }
+
+        Ok((corrupted, before_flush))
+    }
+
+    #[test]
+    fn dummy_dyn_decoder() {
+        let nalus: Vec<_> =
+            NalIterator::<Nalu>::new(DECODE_64X64_PROGRESSIVE_I_P_B_P.stream).collect();
+        let nalus: Vec<&[u8]> = nalus.iter().map(|nalu| nalu.as_ref()).collect();
+
+        let mut decoder =
+            new_dummy_dyn_decoder(EncodedFormat::H264, BlockingMode::Blocking).unwrap();
+        let (corrupted, _) = decode_units(decoder.as_mut(), nalus.iter().copied(), true).unwrap();
+        assert_eq!(corrupted.len(), 3);
+
+        // Without VA-API support or hardware, the factory falls back to the dummy backend.
+        if cfg!(not(feature = "vaapi")) {
+            let requirements = Requirements::new(EncodedFormat::H264, Resolution::from((64, 64)));
+            let mut decoder =
+                new_dyn_decoder_or_dummy(&requirements, BlockingMode::Blocking).unwrap();
+            let (corrupted, _) =
+                decode_units(decoder.as_mut(), nalus.iter().copied(), true).unwrap();
+            assert_eq!(corrupted.len(), 3);
+        }
+    }
 }
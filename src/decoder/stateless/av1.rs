@@ -2,6 +2,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::collections::VecDeque;
 use std::os::fd::AsFd;
 use std::os::fd::BorrowedFd;
 use std::rc::Rc;
@@ -9,9 +10,11 @@ use std::rc::Rc;
 use anyhow::anyhow;
 use anyhow::Context;
 
+use crate::codec::av1::film_grain::FilmGrain;
 use crate::codec::av1::parser::FrameHeaderObu;
 use crate::codec::av1::parser::FrameObu;
 use crate::codec::av1::parser::FrameType;
+use crate::codec::av1::parser::Obu;
 use crate::codec::av1::parser::ObuType;
 use crate::codec::av1::parser::ParsedObu;
 use crate::codec::av1::parser::Parser;
@@ -20,6 +23,7 @@ use crate::codec::av1::parser::NUM_REF_FRAMES;
 use crate::Resolution;
 
 use crate::codec::av1::parser::TileGroupObu;
+use crate::decoder::stateless::substitute_missing_references;
 use crate::decoder::stateless::DecodeError;
 use crate::decoder::stateless::DecodingState;
 use crate::decoder::stateless::StatelessBackendResult;
@@ -30,11 +34,13 @@ use crate::decoder::stateless::StatelessDecoderBackendPicture;
 use crate::decoder::stateless::StatelessVideoDecoder;
 use crate::decoder::stateless::TryFormat;
 use crate::decoder::BlockingMode;
+use crate::decoder::DecodeMode;
 use crate::decoder::DecodedHandle;
+use crate::decoder::DecoderEvent;
+use crate::decoder::ErrorResilience;
 use crate::decoder::FramePool;
 use crate::decoder::PoolLayer;
 
-#[cfg(test)]
 mod dummy;
 #[cfg(feature = "vaapi")]
 mod vaapi;
@@ -45,8 +51,11 @@ pub trait StatelessAV1DecoderBackend:
 {
     /// Called when a new Sequence Header OBU is parsed. The
     /// `highest_spatial_layer` argument refers to the maximum layer selected by
-    /// the client through `set_operating_point()` and the scalability
-    /// information present in the stream, if any.
+    /// the client through [`StatelessDecoder::set_operating_point`] and the
+    /// scalability information present in the stream, if any.
+    ///
+    /// This is also called if the client selects an operating point with a
+    /// different highest spatial layer while a sequence is active.
     fn new_sequence(
         &mut self,
         sequence: &Rc<SequenceHeaderObu>,
@@ -97,6 +106,12 @@ enum CurrentPicState<H: DecodedHandle, P> {
         /// The handle of the reference frame that this frame points to.
         handle: H,
     },
+
+    /// A frame that is not decoded as per the decode mode.
+    Skipped {
+        /// Data for the current picture as extracted from the stream.
+        header: FrameHeaderObu,
+    },
 }
 
 pub struct AV1DecoderState<H: DecodedHandle, P> {
@@ -120,6 +135,16 @@ pub struct AV1DecoderState<H: DecodedHandle, P> {
     /// For SVC streams, we only want to output the highest layer possible given
     /// the choice of operating point.
     highest_spatial_layer: Option<u32>,
+
+    /// The operating point requested by the client through
+    /// [`StatelessDecoder::set_operating_point`].
+    operating_point: u32,
+
+    /// Film grain of each frame of the ready queue, in the same order. It is
+    /// attached to the handles as they are output rather than when the frames
+    /// are shown, as a frame shown again through `show_existing_frame` shares
+    /// its handle with the reference it comes from.
+    output_film_grain: VecDeque<Option<FilmGrain>>,
 }
 
 impl<H, P> Default for AV1DecoderState<H, P>
@@ -134,6 +159,8 @@ where
             current_pic: Default::default(),
             frame_count: Default::default(),
             highest_spatial_layer: Default::default(),
+            operating_point: Default::default(),
+            output_film_grain: Default::default(),
         }
     }
 }
@@ -157,6 +184,20 @@ where
     B: StatelessAV1DecoderBackend,
     B::Handle: Clone,
 {
+    /// Applies the operating point requested by the client to the sequence
+    /// that has just been parsed, falling back to operating point 0 if the
+    /// sequence does not define it.
+    fn apply_operating_point(&mut self) -> anyhow::Result<()> {
+        let operating_point = self.codec.operating_point;
+
+        if let Err(e) = self.codec.parser.choose_operating_point(operating_point) {
+            log::warn!("{:#}, using operating point 0 instead", e);
+            self.codec.parser.choose_operating_point(0)?;
+        }
+
+        Ok(())
+    }
+
     fn count_frames(&mut self, bitstream: &[u8]) -> usize {
         let mut nframes = 0;
         let mut consumed = 0;
@@ -191,6 +232,40 @@ where
             timestamp
         );
 
+        let is_key_frame = frame_header.frame_type == FrameType::KeyFrame;
+        let skip = if frame_header.show_existing_frame {
+            // The frame to show may not have been decoded.
+            self.decode_mode == DecodeMode::KeyFramesOnly && !is_key_frame
+        } else {
+            !self.should_decode(is_key_frame, frame_header.refresh_frame_flags != 0)
+        };
+
+        if skip {
+            log::debug!("Skipping frame as per the decode mode");
+            self.codec.current_pic = Some(CurrentPicState::Skipped {
+                header: frame_header,
+            });
+            return Ok(());
+        }
+
+        if !frame_header.show_existing_frame {
+            if is_key_frame {
+                self.recovery_point();
+            } else if !frame_header.frame_is_intra
+                && self.conceal_errors()
+                && substitute_missing_references(
+                    &mut self.codec.reference_frames,
+                    frame_header
+                        .ref_frame_idx
+                        .iter()
+                        .filter_map(|&idx| usize::try_from(idx).ok()),
+                    timestamp,
+                )
+            {
+                self.corrupted = true;
+            }
+        }
+
         if frame_header.show_existing_frame {
             let idx = usize::try_from(frame_header.frame_to_show_map_idx)
                 .context("Broken stream: invalid frame_to_show_map_idx")?;
@@ -229,6 +304,7 @@ where
             Some(CurrentPicState::ShowExistingFrame { .. }) => {
                 return Err(anyhow!("Broken stream: cannot decode a tile group for a frame with show_existing_frame set"));
             }
+            Some(CurrentPicState::Skipped { .. }) => return Ok(()),
             None => {
                 return Err(anyhow!(
                 "Broken stream: cannot decode a tile group without first decoding a frame header"
@@ -247,6 +323,47 @@ where
         Ok(())
     }
 
+    /// Processes an OBU carrying a frame header or tile group.
+    fn decode_frame_obu(&mut self, obu: Obu, timestamp: u64) -> anyhow::Result<()> {
+        match obu.header.obu_type {
+            ObuType::FrameHeader => {
+                if self.codec.current_pic.is_some() {
+                    /* submit this frame immediately, as we need to update the
+                     * DPB and the reference info state *before* processing the
+                     * next frame */
+                    self.submit_frame(timestamp)?;
+                }
+                let frame_header = self.codec.parser.parse_frame_header_obu(&obu)?;
+                self.decode_frame_header(frame_header, timestamp)?;
+            }
+            ObuType::TileGroup => {
+                let tile_group = self.codec.parser.parse_tile_group_obu(obu)?;
+                self.decode_tile_group(tile_group)?;
+            }
+            ObuType::Frame => {
+                let frame = self.codec.parser.parse_frame_obu(obu)?;
+                self.decode_frame(frame, timestamp)?;
+                /* submit this frame immediately, as we need to update the
+                 * DPB and the reference info state *before* processing the
+                 * next frame */
+                self.submit_frame(timestamp)?;
+            }
+            other => return Err(anyhow!("Unexpected OBU of type {:?}", other)),
+        }
+
+        Ok(())
+    }
+
+    /// Applies the error resilience policy to an error that occurred while decoding the current
+    /// frame. The frame is dropped if the error is concealed.
+    fn conceal_frame_error(&mut self, res: anyhow::Result<()>) -> Result<(), DecodeError> {
+        if res.is_err() && self.conceal_errors() {
+            self.codec.current_pic = None;
+        }
+
+        self.conceal_error(res.map_err(DecodeError::from))
+    }
+
     fn submit_frame(&mut self, timestamp: u64) -> anyhow::Result<()> {
         log::debug!(
             "Finishing frame {} with timestamp: {}",
@@ -262,6 +379,7 @@ where
                 backend_picture,
             }) => {
                 let handle = self.backend.submit_picture(backend_picture)?;
+                self.mark_if_corrupted(&handle);
 
                 if self.blocking_mode == BlockingMode::Blocking {
                     handle.sync()?;
@@ -269,6 +387,12 @@ where
                 (handle, header)
             }
             Some(CurrentPicState::ShowExistingFrame { header, handle }) => (handle, header),
+            // The parser still needs to know about the references the frame would have updated.
+            Some(CurrentPicState::Skipped { header }) => {
+                self.codec.parser.ref_frame_update(&header)?;
+                self.codec.frame_count += 1;
+                return Ok(());
+            }
             None => return Err(anyhow!("Broken stream: no picture to submit")),
         };
 
@@ -299,11 +423,24 @@ where
 
         let show_existing_frame = header.show_existing_frame;
         if header.show_frame || show_existing_frame {
+            // None of our backends synthesize film grain, the VA-API one disabling it in the
+            // driver, so leave it to the client. Shown existing frames carry the film grain
+            // parameters loaded from their reference.
+            let film_grain = self
+                .codec
+                .sequence
+                .as_deref()
+                .and_then(|sequence| FilmGrain::new(sequence, &header));
+
             match self.codec.highest_spatial_layer {
-                None => self.ready_queue.push(handle),
+                None => {
+                    self.ready_queue.push(handle);
+                    self.codec.output_film_grain.push_back(film_grain);
+                }
                 Some(highest_spatial_layer) => {
                     if header.obu_header.spatial_id >= highest_spatial_layer {
                         self.ready_queue.push(handle);
+                        self.codec.output_film_grain.push_back(film_grain);
                     } else {
                         log::debug!(
                             "Dropping frame with spatial_id {}",
@@ -320,6 +457,66 @@ where
     }
 }
 
+impl<B> StatelessDecoder<Av1, B>
+where
+    B: StatelessAV1DecoderBackend + TryFormat<Av1>,
+    B::Handle: Clone,
+{
+    /// Selects the operating point to decode, i.e. which spatial and temporal
+    /// layers of a scalable stream are decoded. OBUs that are not part of the
+    /// operating point are dropped, and only frames of its highest spatial
+    /// layer are output.
+    ///
+    /// The operating point can be selected before or during decoding and is
+    /// kept across sequence headers. Operating point 0, the default, usually
+    /// contains all the layers of the stream. If a sequence header does not
+    /// define the requested operating point, operating point 0 is used for
+    /// that sequence.
+    ///
+    /// If a sequence is active, this returns an error if `operating_point` is
+    /// not defined by its sequence header. If the highest spatial layer
+    /// changes as a result, a new format change event is raised.
+    pub fn set_operating_point(&mut self, operating_point: u32) -> anyhow::Result<()> {
+        let (sequence, awaiting_format) = match &self.decoding_state {
+            DecodingState::AwaitingStreamInfo => (None, false),
+            DecodingState::AwaitingFormat(sequence) => (Some(Rc::clone(sequence)), true),
+            DecodingState::Decoding | DecodingState::Reset => (self.codec.sequence.clone(), false),
+        };
+
+        let Some(sequence) = sequence else {
+            /* Will be applied when the next sequence header is parsed. */
+            self.codec.operating_point = operating_point;
+            return Ok(());
+        };
+
+        if self.codec.current_pic.is_some() {
+            return Err(anyhow!(
+                "cannot change the operating point while a picture is being decoded"
+            ));
+        }
+
+        self.codec.parser.choose_operating_point(operating_point)?;
+        self.codec.operating_point = operating_point;
+
+        let highest_spatial_layer = self.codec.parser.highest_operating_point();
+        if highest_spatial_layer != self.codec.highest_spatial_layer {
+            for f in &mut self.ready_queue.queue {
+                f.sync()?;
+            }
+
+            self.codec.highest_spatial_layer = highest_spatial_layer;
+            self.backend
+                .new_sequence(&sequence, highest_spatial_layer)?;
+            /* A pending format change already covers the new layer. */
+            if !awaiting_format {
+                self.await_format_change(sequence);
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl<B> StatelessVideoDecoder for StatelessDecoder<Av1, B>
 where
     B: StatelessAV1DecoderBackend + TryFormat<Av1>,
@@ -408,6 +605,7 @@ where
             match obu.header.obu_type {
                 ObuType::SequenceHeader => {
                     let sequence = self.codec.parser.parse_sequence_header_obu(&obu)?;
+                    self.apply_operating_point()?;
                     let sequence_differs = match &self.codec.sequence {
                         Some(old_sequence) => **old_sequence != *sequence,
                         None => true,
@@ -453,27 +651,9 @@ where
                 ObuType::TemporalDelimiter => {
                     self.codec.parser.parse_temporal_delimiter_obu(&obu)?
                 }
-                ObuType::FrameHeader => {
-                    if self.codec.current_pic.is_some() {
-                        /* submit this frame immediately, as we need to update the
-                         * DPB and the reference info state *before* processing the
-                         * next frame */
-                        self.submit_frame(timestamp)?;
-                    }
-                    let frame_header = self.codec.parser.parse_frame_header_obu(&obu)?;
-                    self.decode_frame_header(frame_header, timestamp)?;
-                }
-                ObuType::TileGroup => {
-                    let tile_group = self.codec.parser.parse_tile_group_obu(obu)?;
-                    self.decode_tile_group(tile_group)?;
-                }
-                ObuType::Frame => {
-                    let frame = self.codec.parser.parse_frame_obu(obu)?;
-                    self.decode_frame(frame, timestamp)?;
-                    /* submit this frame immediately, as we need to update the
-                     * DPB and the reference info state *before* processing the
-                     * next frame */
-                    self.submit_frame(timestamp)?;
+                ObuType::FrameHeader | ObuType::TileGroup | ObuType::Frame => {
+                    let res = self.decode_frame_obu(obu, timestamp);
+                    self.conceal_frame_error(res)?;
                 }
                 ObuType::TileList => {
                     return Err(DecodeError::DecoderError(anyhow!(
@@ -491,7 +671,8 @@ where
         /* we may already have dispatched work if we got ObuType::Frame */
         if self.codec.current_pic.is_some() {
             /* dispatch work to the backend */
-            self.submit_frame(timestamp)?;
+            let res = self.submit_frame(timestamp);
+            self.conceal_frame_error(res)?;
         }
 
         Ok(consumed)
@@ -513,7 +694,21 @@ where
         self.backend.stream_info()
     }
 
-    fn next_event(&mut self) -> Option<crate::decoder::DecoderEvent<B::Handle>> {
+    fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    fn set_error_resilience(&mut self, error_resilience: ErrorResilience) {
+        self.error_resilience = error_resilience;
+    }
+
+    fn next_event(&mut self) -> Option<DecoderEvent<B::Handle>> {
+        if let Some(handle) = self.ready_queue.next() {
+            let film_grain = self.codec.output_film_grain.pop_front().flatten();
+            handle.set_pending_film_grain(film_grain);
+            return Some(DecoderEvent::FrameReady(handle));
+        }
+
         self.query_next_event(|decoder, sequence| {
             decoder.codec.sequence = Some(Rc::clone(sequence));
         })
@@ -530,6 +725,7 @@ pub mod tests {
     use crate::decoder::stateless::tests::test_decode_stream;
     use crate::decoder::stateless::tests::TestStream;
     use crate::decoder::stateless::StatelessDecoder;
+    use crate::decoder::stateless::StatelessVideoDecoder;
     use crate::decoder::BlockingMode;
     use crate::utils::simple_playback_loop;
     use crate::utils::simple_playback_loop_owned_frames;
@@ -573,4 +769,21 @@ pub mod tests {
     fn test_25fps_nonblock() {
         test_decoder_dummy(&DECODE_TEST_25FPS, BlockingMode::NonBlocking);
     }
+
+    #[test]
+    fn test_set_operating_point() {
+        let mut decoder = StatelessDecoder::<Av1, _>::new_dummy(BlockingMode::Blocking).unwrap();
+
+        /* Cannot be validated before a sequence header is parsed. */
+        decoder.set_operating_point(1).unwrap();
+
+        /* The stream only has operating point 0, which is used as a fallback. */
+        let frame = IvfIterator::new(DECODE_TEST_25FPS.stream).next().unwrap();
+        decoder.decode(0, frame).unwrap_err();
+        assert_eq!(decoder.codec.parser.highest_operating_point(), None);
+
+        decoder.set_operating_point(1).unwrap_err();
+        decoder.set_operating_point(0).unwrap();
+        assert_eq!(decoder.codec.operating_point, 0);
+    }
 }
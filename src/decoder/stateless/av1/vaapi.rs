@@ -107,12 +107,10 @@ impl VaStreamInfo for &Rc<SequenceHeaderObu> {
 fn build_fg_info(hdr: &FrameHeaderObu) -> anyhow::Result<libva::AV1FilmGrain> {
     let fg = &hdr.film_grain_params;
 
-    if fg.apply_grain {
-        log::warn!("Film grain is not officially supported yet.")
-    }
-
+    // The film grain is attached to the decoded handles for the client to synthesize, see
+    // `DecodedHandle::pending_film_grain`, so the driver must not apply it as well.
     let film_grain_fields = libva::AV1FilmGrainFields::new(
-        u32::from(fg.apply_grain),
+        0,
         u32::from(fg.chroma_scaling_from_luma),
         fg.grain_scaling_minus_8,
         fg.ar_coeff_lag,
@@ -2,12 +2,12 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-#[cfg(test)]
 mod dummy;
 #[cfg(feature = "vaapi")]
 mod vaapi;
 
 use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
 use std::io::Cursor;
 use std::os::fd::AsFd;
 use std::os::fd::BorrowedFd;
@@ -24,6 +24,7 @@ use crate::codec::h264::dpb::MmcoError;
 use crate::codec::h264::dpb::ReferencePicLists;
 use crate::codec::h264::parser::MaxLongTermFrameIdx;
 use crate::codec::h264::parser::Nalu;
+use crate::codec::h264::parser::NaluHeaderMvcExtension;
 use crate::codec::h264::parser::NaluType;
 use crate::codec::h264::parser::Parser;
 use crate::codec::h264::parser::Pps;
@@ -48,9 +49,12 @@ use crate::decoder::stateless::StatelessDecoderBackend;
 use crate::decoder::stateless::StatelessVideoDecoder;
 use crate::decoder::stateless::TryFormat;
 use crate::decoder::BlockingMode;
+use crate::decoder::DecodeMode;
 use crate::decoder::DecodedHandle;
 use crate::decoder::DecoderEvent;
+use crate::decoder::ErrorResilience;
 use crate::decoder::FramePool;
+use crate::decoder::OutputMode;
 use crate::decoder::StreamInfo;
 use crate::Resolution;
 
@@ -81,7 +85,7 @@ pub trait StatelessH264DecoderBackend:
     StatelessDecoderBackend + StatelessDecoderBackendPicture<H264>
 {
     /// Called when a new SPS is parsed.
-    fn new_sequence(&mut self, sps: &Rc<Sps>) -> StatelessBackendResult<()>;
+    fn new_sequence(&mut self, stream_sps: &StreamSps) -> StatelessBackendResult<()>;
 
     /// Called when the decoder determines that a frame or field was found.
     fn new_picture(
@@ -102,6 +106,10 @@ pub trait StatelessH264DecoderBackend:
     ) -> StatelessBackendResult<Self::Picture>;
 
     /// Called by the decoder when starting a new frame or field.
+    ///
+    /// `inter_view_refs` contains the pictures of the other views of the access unit that the
+    /// picture may use as references, for the non-base views of MVC streams.
+    #[allow(clippy::too_many_arguments)]
     fn start_picture(
         &mut self,
         picture: &mut Self::Picture,
@@ -109,6 +117,7 @@ pub trait StatelessH264DecoderBackend:
         sps: &Sps,
         pps: &Pps,
         dpb: &Dpb<Self::Handle>,
+        inter_view_refs: &[&DpbEntry<Self::Handle>],
         hdr: &SliceHeader,
     ) -> StatelessBackendResult<()>;
 
@@ -150,10 +159,14 @@ struct NegotiationInfo {
     max_dpb_frames: usize,
     /// Whether this is an interlaced stream
     interlaced: bool,
+    /// The number of views of MVC streams, each of which has its own DPB.
+    num_views: usize,
 }
 
-impl From<&Sps> for NegotiationInfo {
-    fn from(sps: &Sps) -> Self {
+impl From<&StreamSps> for NegotiationInfo {
+    fn from(stream_sps: &StreamSps) -> Self {
+        let sps = &stream_sps.sps;
+
         NegotiationInfo {
             coded_resolution: Resolution::from((sps.width, sps.height)),
             profile_idc: sps.profile_idc,
@@ -162,10 +175,22 @@ impl From<&Sps> for NegotiationInfo {
             chroma_format_idc: sps.chroma_format_idc,
             max_dpb_frames: sps.max_dpb_frames(),
             interlaced: !sps.frame_mbs_only_flag,
+            num_views: stream_sps.num_views,
         }
     }
 }
 
+/// The SPS a stream is negotiated with, along with the number of views it has.
+#[derive(Clone, Debug)]
+pub struct StreamSps {
+    /// The SPS of the stream, or the one of the subset SPS of MVC streams, which covers all the
+    /// views.
+    pub sps: Rc<Sps>,
+    /// The number of views of MVC streams, as signaled by `num_views_minus1` in their subset SPS,
+    /// or 1 for other streams.
+    pub num_views: usize,
+}
+
 #[derive(Copy, Clone, Debug)]
 enum RefPicList {
     RefPicList0,
@@ -231,6 +256,31 @@ struct RefPicLists<'a, T> {
     ref_pic_list1: DpbPicRefList<'a, T>,
 }
 
+/// Identifies the view a picture belongs to. Streams without MVC extensions only have a base
+/// view.
+#[derive(Clone, Copy, Debug, Default)]
+struct ViewInfo {
+    view_id: u16,
+    /// Position of the view in the decoding order of an access unit, 0 being the base view.
+    order_idx: usize,
+    anchor_pic_flag: bool,
+    /// Whether the other views of the access unit may use the picture as reference.
+    inter_view_flag: bool,
+}
+
+/// Decoding state of a view of a MVC stream. Each view has its own DPB and reference picture
+/// marking.
+///
+/// The state of the view being decoded lives in [`H264DecoderState`], the state of the other
+/// views is parked here until their next picture.
+struct ViewState<H> {
+    dpb: Dpb<H>,
+    prev_ref_pic_info: PrevReferencePicInfo,
+    prev_pic_info: PrevPicInfo,
+    max_long_term_frame_idx: MaxLongTermFrameIdx,
+    last_field: Option<(RcPictureData, H)>,
+}
+
 /// Used to track that first_mb_in_slice increases monotonically.
 enum CurrentMacroblockTracking {
     SeparateColorPlane(std::collections::BTreeMap<u8, u32>),
@@ -251,6 +301,21 @@ struct CurrentPicState<P> {
     ref_pic_lists: ReferencePicLists,
     /// The current macroblock we are processing
     current_macroblock: CurrentMacroblockTracking,
+    /// The view the picture belongs to.
+    view: ViewInfo,
+}
+
+/// Progress of the recovery of the stream after decoding resumed at a picture that is not an IDR.
+#[derive(Clone, Copy, Debug)]
+enum SeekRecovery {
+    /// Decoding resumed at a recovery point SEI message or an I picture, the output will be
+    /// correct after `recovery_frame_cnt` frames.
+    Resumed { recovery_frame_cnt: u32 },
+    /// Waiting for the picture with the given `frame_num`, after which the output is correct.
+    AwaitingFrameNum(u32),
+    /// The picture with POC `poc` has been reached. The pictures following it in decoding order
+    /// but preceding it in output order may still reference pictures that have not been decoded.
+    Recovered { poc: i32 },
 }
 
 /// State of the H.264 decoder.
@@ -284,6 +349,18 @@ pub struct H264DecoderState<H: DecodedHandle, P> {
     /// The picture currently being decoded. We need to preserve it between calls to `decode`
     /// because multiple slices will be processed in different calls to `decode`.
     current_pic: Option<CurrentPicState<P>>,
+
+    /// View order index of the view which state is held by the fields above.
+    current_view: usize,
+    /// State of the other views of MVC streams, by view order index.
+    other_views: BTreeMap<usize, ViewState<H>>,
+    /// MVC extension of the last prefix NAL unit, which applies to the next base view slice.
+    prefix_mvc_extension: Option<NaluHeaderMvcExtension>,
+    /// Pictures of the current access unit that the other views may use as inter-view
+    /// references, along with their view_id.
+    inter_view_refs: Vec<(u16, DpbEntry<H>)>,
+    /// Recovery of the stream after a seek, if decoding did not resume at an IDR picture.
+    seek_recovery: Option<SeekRecovery>,
 }
 
 impl<H, P> Default for H264DecoderState<H, P>
@@ -300,6 +377,11 @@ where
             max_long_term_frame_idx: Default::default(),
             last_field: Default::default(),
             current_pic: None,
+            current_view: 0,
+            other_views: Default::default(),
+            prefix_mvc_extension: None,
+            inter_view_refs: Default::default(),
+            seek_recovery: None,
         }
     }
 }
@@ -316,7 +398,7 @@ where
 pub struct H264;
 
 impl StatelessCodec for H264 {
-    type FormatInfo = Rc<Sps>;
+    type FormatInfo = StreamSps;
     type DecoderState<H: DecodedHandle, P> = H264DecoderState<H, P>;
 }
 
@@ -501,6 +583,12 @@ where
         self.dpb.bump_as_needed(current_pic).into_iter().flatten()
     }
 
+    /// Returns an iterator of the handles of the frames that need to be bumped into the ready
+    /// queue for no more than `max_num_reorder_frames` frames to be waiting for output.
+    fn bump_to_reorder_limit(&mut self) -> impl Iterator<Item = H> {
+        self.dpb.bump_to_reorder_limit().into_iter().flatten()
+    }
+
     /// Returns an iterator of the handles of all the frames still present in the DPB.
     fn drain(&mut self) -> impl Iterator<Item = H> {
         let pics = self.dpb.drain();
@@ -510,6 +598,110 @@ where
         pics.into_iter().flatten()
     }
 
+    /// Returns an iterator of the handles of all the frames still present in the DPBs of the views
+    /// other than the current one.
+    fn drain_other_views(&mut self) -> impl Iterator<Item = H> {
+        let mut pics = vec![];
+
+        for view in self.other_views.values_mut() {
+            pics.extend(view.dpb.drain());
+            view.last_field = None;
+        }
+
+        self.inter_view_refs.clear();
+
+        pics.into_iter().flatten()
+    }
+
+    /// Returns the view `slice` belongs to.
+    fn view_info(&mut self, slice: &Slice) -> anyhow::Result<ViewInfo> {
+        let Some((_, mvc_ext)) = self.parser.mvc_extension() else {
+            self.prefix_mvc_extension = None;
+            return Ok(Default::default());
+        };
+
+        // Base view slices get their MVC extension from the prefix NAL unit preceding them, if
+        // any.
+        let nalu_ext = match slice.nalu.header.mvc_extension() {
+            Some(ext) => Some(*ext),
+            None => self.prefix_mvc_extension.take(),
+        };
+
+        match nalu_ext {
+            Some(ext) => Ok(ViewInfo {
+                view_id: ext.view_id,
+                order_idx: mvc_ext.view_order_idx(ext.view_id).with_context(|| {
+                    format!("view_id {} is not part of the stream", ext.view_id)
+                })?,
+                anchor_pic_flag: ext.anchor_pic_flag,
+                inter_view_flag: ext.inter_view_flag,
+            }),
+            // H.7.4.1.1: the base view may be used as inter-view reference in the absence of
+            // prefix NAL unit.
+            None => Ok(ViewInfo {
+                view_id: mvc_ext.view_id[0],
+                order_idx: 0,
+                anchor_pic_flag: slice.nalu.header.idr_pic_flag,
+                inter_view_flag: true,
+            }),
+        }
+    }
+
+    /// Makes the view with view order index `order_idx` the current one, parking the state of
+    /// the previous one.
+    fn switch_view(&mut self, order_idx: usize) {
+        if order_idx == self.current_view {
+            return;
+        }
+
+        let mut view = self.other_views.remove(&order_idx).unwrap_or_else(|| {
+            let mut dpb = Dpb::default();
+            dpb.set_limits(self.dpb.max_num_pics(), self.dpb.max_num_reorder_frames());
+            dpb.set_interlaced(self.dpb.interlaced());
+
+            ViewState {
+                dpb,
+                prev_ref_pic_info: Default::default(),
+                prev_pic_info: Default::default(),
+                max_long_term_frame_idx: Default::default(),
+                last_field: None,
+            }
+        });
+
+        std::mem::swap(&mut self.dpb, &mut view.dpb);
+        std::mem::swap(&mut self.prev_ref_pic_info, &mut view.prev_ref_pic_info);
+        std::mem::swap(&mut self.prev_pic_info, &mut view.prev_pic_info);
+        std::mem::swap(
+            &mut self.max_long_term_frame_idx,
+            &mut view.max_long_term_frame_idx,
+        );
+        std::mem::swap(&mut self.last_field, &mut view.last_field);
+
+        self.other_views.insert(self.current_view, view);
+        self.current_view = order_idx;
+    }
+
+    /// Returns the inter-view references of the picture of `view`, for reference picture list 0
+    /// or 1 depending on `list1`, in the order given by the subset SPS.
+    fn inter_view_refs(&self, view: &ViewInfo, list1: bool) -> Vec<&DpbEntry<H>> {
+        let view_ids = match self.parser.mvc_extension() {
+            Some((_, ext)) if view.order_idx > 0 => {
+                ext.inter_view_refs(view.order_idx, view.anchor_pic_flag, list1)
+            }
+            _ => return vec![],
+        };
+
+        view_ids
+            .iter()
+            .filter_map(|view_id| {
+                self.inter_view_refs
+                    .iter()
+                    .find(|(id, _)| id == view_id)
+                    .map(|(_, entry)| entry)
+            })
+            .collect()
+    }
+
     /// Find the first field for the picture started by `slice`, if any.
     #[allow(clippy::type_complexity)]
     fn find_first_field(&self, hdr: &SliceHeader) -> anyhow::Result<Option<(RcPictureData, H)>> {
@@ -692,9 +884,67 @@ where
         Ok(())
     }
 
+    // H.8.2.2.3 Modification process for inter-view reference components
+    fn inter_view_pic_list_modification<'a>(
+        inter_view_refs: &[&'a DpbEntry<H>],
+        ref_pic_list_x: &mut DpbPicRefList<'a, H>,
+        num_ref_idx_lx_active_minus1: u8,
+        rplm: &RefPicListModification,
+        pic_view_idx_lx_pred: &mut i32,
+        ref_idx_lx: &mut usize,
+    ) -> anyhow::Result<()> {
+        let num_inter_view_refs = inter_view_refs.len() as i32;
+        let abs_diff_view_idx = rplm.abs_diff_view_idx_minus1 as i32 + 1;
+
+        let mut pic_view_idx_lx = match rplm.modification_of_pic_nums_idc {
+            4 => *pic_view_idx_lx_pred - abs_diff_view_idx,
+            5 => *pic_view_idx_lx_pred + abs_diff_view_idx,
+            idc => anyhow::bail!(
+                "unexpected value for modification_of_pic_nums_idc {:?}",
+                idc
+            ),
+        };
+
+        if pic_view_idx_lx < 0 {
+            pic_view_idx_lx += num_inter_view_refs;
+        } else if pic_view_idx_lx >= num_inter_view_refs {
+            pic_view_idx_lx -= num_inter_view_refs;
+        }
+
+        *pic_view_idx_lx_pred = pic_view_idx_lx;
+
+        let entry = usize::try_from(pic_view_idx_lx)
+            .ok()
+            .and_then(|idx| inter_view_refs.get(idx))
+            .with_context(|| format!("No inter-view reference with index {}", pic_view_idx_lx))?;
+
+        ref_pic_list_x.insert(*ref_idx_lx, entry);
+        *ref_idx_lx += 1;
+
+        let mut nidx = *ref_idx_lx;
+
+        for cidx in *ref_idx_lx..=usize::from(num_ref_idx_lx_active_minus1) + 1 {
+            if cidx == ref_pic_list_x.len() {
+                break;
+            }
+
+            if !std::ptr::eq(ref_pic_list_x[cidx], *entry) {
+                ref_pic_list_x[nidx] = ref_pic_list_x[cidx];
+                nidx += 1;
+            }
+        }
+
+        while ref_pic_list_x.len() > (usize::from(num_ref_idx_lx_active_minus1) + 1) {
+            ref_pic_list_x.pop();
+        }
+
+        Ok(())
+    }
+
     fn modify_ref_pic_list(
         &self,
         cur_pic: &PictureData,
+        view: &ViewInfo,
         hdr: &SliceHeader,
         ref_pic_list_type: RefPicList,
         ref_pic_list_indices: &[usize],
@@ -713,9 +963,15 @@ where
                 ),
             };
 
+        let inter_view_refs =
+            self.inter_view_refs(view, matches!(ref_pic_list_type, RefPicList::RefPicList1));
+
+        // H.8.2.1: inter-view references are appended to the initial reference picture list
+        // before it gets truncated.
         let mut ref_pic_list: Vec<_> = ref_pic_list_indices
             .iter()
             .map(|&i| &self.dpb.entries()[i])
+            .chain(inter_view_refs.iter().copied())
             .take(usize::from(num_ref_idx_lx_active_minus1) + 1)
             .collect();
 
@@ -724,6 +980,7 @@ where
         }
 
         let mut pic_num_lx_pred = cur_pic.pic_num;
+        let mut pic_view_idx_lx_pred = -1;
         let mut ref_idx_lx = 0;
 
         for modification in rplm {
@@ -751,6 +1008,14 @@ where
                     &mut ref_idx_lx,
                 )?,
                 3 => break,
+                4 | 5 => Self::inter_view_pic_list_modification(
+                    &inter_view_refs,
+                    &mut ref_pic_list,
+                    num_ref_idx_lx_active_minus1,
+                    modification,
+                    &mut pic_view_idx_lx_pred,
+                    &mut ref_idx_lx,
+                )?,
                 _ => anyhow::bail!("unexpected modification_of_pic_nums_idc {:?}", idc),
             }
         }
@@ -763,18 +1028,21 @@ where
     fn create_ref_pic_lists(
         &mut self,
         cur_pic: &PictureData,
+        view: &ViewInfo,
         hdr: &SliceHeader,
         ref_pic_lists: &ReferencePicLists,
     ) -> anyhow::Result<RefPicLists<H>> {
         let ref_pic_list0 = match hdr.slice_type {
             SliceType::P | SliceType::Sp => self.modify_ref_pic_list(
                 cur_pic,
+                view,
                 hdr,
                 RefPicList::RefPicList0,
                 &ref_pic_lists.ref_pic_list_p0,
             )?,
             SliceType::B => self.modify_ref_pic_list(
                 cur_pic,
+                view,
                 hdr,
                 RefPicList::RefPicList0,
                 &ref_pic_lists.ref_pic_list_b0,
@@ -785,6 +1053,7 @@ where
         let ref_pic_list1 = match hdr.slice_type {
             SliceType::B => self.modify_ref_pic_list(
                 cur_pic,
+                view,
                 hdr,
                 RefPicList::RefPicList1,
                 &ref_pic_lists.ref_pic_list_b1,
@@ -843,10 +1112,11 @@ where
         Ok(())
     }
 
-    // Apply the parameters of `sps` to the decoding state.
-    fn apply_sps(&mut self, sps: &Sps) {
-        self.negotiation_info = NegotiationInfo::from(sps);
+    // Apply the parameters of `stream_sps` to the decoding state.
+    fn apply_sps(&mut self, stream_sps: &StreamSps) {
+        self.negotiation_info = NegotiationInfo::from(stream_sps);
 
+        let sps = &stream_sps.sps;
         let max_dpb_frames = sps.max_dpb_frames();
         let interlaced = !sps.frame_mbs_only_flag;
         let max_num_order_frames = sps.max_num_order_frames() as usize;
@@ -858,6 +1128,26 @@ where
 
         self.dpb.set_limits(max_dpb_frames, max_num_reorder_frames);
         self.dpb.set_interlaced(interlaced);
+
+        for view in self.other_views.values_mut() {
+            view.dpb.set_limits(max_dpb_frames, max_num_reorder_frames);
+            view.dpb.set_interlaced(interlaced);
+        }
+    }
+
+    /// Returns the SPS to negotiate the stream format with: the one of the subset SPS of MVC
+    /// streams, which covers all the views, or `sps` otherwise.
+    fn stream_sps(&self, sps: &Rc<Sps>) -> StreamSps {
+        match self.parser.mvc_extension() {
+            Some((subset_sps, mvc_ext)) => StreamSps {
+                sps: Rc::clone(&subset_sps.sps),
+                num_views: usize::from(mvc_ext.num_views_minus1) + 1,
+            },
+            None => StreamSps {
+                sps: Rc::clone(sps),
+                num_views: 1,
+            },
+        }
     }
 }
 
@@ -866,29 +1156,32 @@ where
     B: StatelessH264DecoderBackend + TryFormat<H264>,
     B::Handle: Clone,
 {
-    fn negotiation_possible(sps: &Sps, old_negotiation_info: &NegotiationInfo) -> bool {
-        let negotiation_info = NegotiationInfo::from(sps);
+    fn negotiation_possible(
+        stream_sps: &StreamSps,
+        old_negotiation_info: &NegotiationInfo,
+    ) -> bool {
+        let negotiation_info = NegotiationInfo::from(stream_sps);
         *old_negotiation_info != negotiation_info
     }
 
-    fn renegotiate_if_needed(&mut self, sps: &Rc<Sps>) -> anyhow::Result<()> {
-        if Self::negotiation_possible(sps, &self.codec.negotiation_info) {
+    fn renegotiate_if_needed(&mut self, stream_sps: &StreamSps) -> anyhow::Result<()> {
+        if Self::negotiation_possible(stream_sps, &self.codec.negotiation_info) {
             // Make sure all the frames we decoded so far are in the ready queue.
-            self.drain()?;
-            self.backend.new_sequence(sps)?;
-            self.await_format_change(sps.clone());
+            self.drain_all_views()?;
+            self.backend.new_sequence(stream_sps)?;
+            self.await_format_change(stream_sps.clone());
         }
 
         Ok(())
     }
 
-    // Apply the parameters of `sps` to the decoder.
-    fn apply_sps(&mut self, sps: &Sps) {
-        self.codec.apply_sps(sps);
+    // Apply the parameters of `stream_sps` to the decoder.
+    fn apply_sps(&mut self, stream_sps: &StreamSps) {
+        self.codec.apply_sps(stream_sps);
 
         self.coded_resolution = Resolution {
-            width: sps.width,
-            height: sps.height,
+            width: stream_sps.sps.width,
+            height: stream_sps.sps.height,
         };
     }
 
@@ -903,6 +1196,14 @@ where
         Ok(())
     }
 
+    /// Like [`Self::drain`], but also drains the other views of MVC streams.
+    fn drain_all_views(&mut self) -> anyhow::Result<()> {
+        self.drain()?;
+        self.ready_queue.extend(self.codec.drain_other_views());
+
+        Ok(())
+    }
+
     /// Adds picture to the ready queue if it could not be added to the DPB.
     fn add_to_ready_queue(&mut self, pic: PictureData, handle: B::Handle) {
         if matches!(pic.field, Field::Frame) {
@@ -933,8 +1234,17 @@ where
         // Submit the picture to the backend.
         let handle = self.submit_picture(pic.backend_pic)?;
         let pps = pic.pps;
+        let view = pic.view;
         let mut pic = pic.pic;
 
+        if view.inter_view_flag && self.codec.parser.mvc_extension().is_some() {
+            let inter_view_ref =
+                DpbEntry::new_inter_view_ref(pic.new_inter_view_ref().into_rc(), handle.clone());
+            self.codec
+                .inter_view_refs
+                .push((view.view_id, inter_view_ref));
+        }
+
         if matches!(pic.reference(), Reference::ShortTerm | Reference::LongTerm) {
             self.codec.reference_pic_marking(&mut pic, &pps.sps)?;
             self.codec.prev_ref_pic_info.fill(&pic);
@@ -943,6 +1253,15 @@ where
         self.codec.prev_pic_info.fill(&pic);
 
         if pic.has_mmco_5 {
+            // The POCs of the following pictures cannot be compared with the recovery point
+            // anymore.
+            if matches!(
+                self.codec.seek_recovery,
+                Some(SeekRecovery::Recovered { .. })
+            ) {
+                self.codec.seek_recovery = None;
+            }
+
             // C.4.5.3 "Bumping process"
             // The bumping process is invoked in the following cases:
             // Clause 3:
@@ -970,6 +1289,14 @@ where
             || pic.is_ref()
             || self.codec.dpb.has_empty_frame_buffer()
         {
+            // In decoding order mode, complete frames are output right away and only kept in the
+            // DPB for reference.
+            let output_now = self.output_mode == OutputMode::DecodeOrder
+                && (matches!(pic.field, Field::Frame) || pic.is_second_field());
+            if output_now {
+                self.ready_queue.push(handle.clone());
+            }
+
             if self.codec.dpb.interlaced() && matches!(pic.field, Field::Frame) {
                 // Split the Frame into two complementary fields so reference
                 // marking is easier. This is inspired by the GStreamer implementation.
@@ -992,10 +1319,18 @@ where
                     &mut self.codec.last_field,
                 )?;
             }
+
+            if self.output_mode == OutputMode::DecodeOrder {
+                self.codec.dpb.mark_all_as_output();
+            }
         } else {
             self.add_to_ready_queue(pic, handle);
         }
 
+        if self.output_mode == OutputMode::ReorderLimit {
+            self.ready_queue.extend(self.codec.bump_to_reorder_limit());
+        }
+
         Ok(())
     }
 
@@ -1011,19 +1346,45 @@ where
 
         debug!("frame_num gap detected.");
 
-        if !sps.gaps_in_frame_num_value_allowed_flag {
-            return Err(anyhow!(
-                "Invalid frame_num: {}. Assuming unintentional loss of pictures",
+        // When concealing errors, the missing frames are replaced by the most recently decoded
+        // one so the pictures referencing them can still be decoded.
+        let substitute = if !sps.gaps_in_frame_num_value_allowed_flag {
+            if !self.conceal_errors() {
+                return Err(anyhow!(
+                    "Invalid frame_num: {}. Assuming unintentional loss of pictures",
+                    frame_num
+                ));
+            }
+
+            log::warn!(
+                "Invalid frame_num: {}, concealing the loss of pictures",
                 frame_num
-            ));
-        }
+            );
+            self.corrupted = true;
+
+            self.codec
+                .dpb
+                .entries()
+                .iter()
+                .filter(|entry| entry.handle.is_some())
+                .max_by_key(|entry| entry.pic.borrow().frame_num_wrap)
+                .and_then(|entry| entry.handle.clone())
+        } else {
+            None
+        };
 
         let mut unused_short_term_frame_num =
             (self.codec.prev_ref_pic_info.frame_num + 1) % sps.max_frame_num();
         while unused_short_term_frame_num != frame_num {
             let max_frame_num = sps.max_frame_num();
 
-            let mut pic = PictureData::new_non_existing(unused_short_term_frame_num, timestamp);
+            // Concealed pictures are used as references, contrary to non-existing ones which are
+            // left out of the reference picture lists.
+            let mut pic = if substitute.is_some() {
+                PictureData::new_concealed(unused_short_term_frame_num, timestamp)
+            } else {
+                PictureData::new_non_existing(unused_short_term_frame_num, timestamp)
+            };
             self.codec.compute_pic_order_count(&mut pic, sps)?;
 
             self.codec
@@ -1037,16 +1398,22 @@ where
             if self.codec.dpb.interlaced() {
                 let (first_field, second_field) = PictureData::split_frame(pic);
 
-                self.codec
-                    .dpb
-                    .add_picture(first_field, None, &mut self.codec.last_field)?;
-                self.codec
-                    .dpb
-                    .add_picture(second_field, None, &mut self.codec.last_field)?;
+                self.codec.dpb.add_picture(
+                    first_field,
+                    substitute.clone(),
+                    &mut self.codec.last_field,
+                )?;
+                self.codec.dpb.add_picture(
+                    second_field,
+                    substitute.clone(),
+                    &mut self.codec.last_field,
+                )?;
             } else {
-                self.codec
-                    .dpb
-                    .add_picture(pic.into_rc(), None, &mut self.codec.last_field)?;
+                self.codec.dpb.add_picture(
+                    pic.into_rc(),
+                    substitute.clone(),
+                    &mut self.codec.last_field,
+                )?;
             }
 
             unused_short_term_frame_num += 1;
@@ -1066,7 +1433,7 @@ where
         let pps = self
             .codec
             .parser
-            .get_pps(slice.header.pic_parameter_set_id)
+            .get_slice_pps(&slice.nalu.header, slice.header.pic_parameter_set_id)
             .context("Invalid SPS in init_current_pic")?;
 
         let sps = Rc::clone(&pps.sps);
@@ -1101,16 +1468,43 @@ where
         Ok(pic)
     }
 
+    /// Tracks the recovery of the stream after a seek, and decides whether `pic` can be displayed.
+    /// See D.2.8 in the specification.
+    fn update_seek_recovery(&mut self, pic: &PictureData, max_frame_num: u32) {
+        if let Some(SeekRecovery::Resumed { recovery_frame_cnt }) = self.codec.seek_recovery {
+            let frame_num = (pic.frame_num + recovery_frame_cnt) % max_frame_num;
+            self.codec.seek_recovery = Some(SeekRecovery::AwaitingFrameNum(frame_num));
+        }
+
+        match self.codec.seek_recovery {
+            Some(SeekRecovery::AwaitingFrameNum(frame_num)) if frame_num == pic.frame_num => {
+                self.codec.seek_recovery = Some(SeekRecovery::Recovered {
+                    poc: pic.pic_order_cnt,
+                });
+                self.recovery_point();
+            }
+            // Leading pictures of the recovery point may reference pictures from before the seek.
+            Some(SeekRecovery::Recovered { poc }) => self.recovering = pic.pic_order_cnt < poc,
+            _ => (),
+        }
+    }
+
     /// Called once per picture to start it.
     fn begin_picture(
         &mut self,
         timestamp: u64,
         slice: &Slice,
+        view: ViewInfo,
     ) -> Result<CurrentPicState<B::Picture>, DecodeError> {
         let nalu_hdr = &slice.nalu.header;
 
         if nalu_hdr.idr_pic_flag {
             self.codec.prev_ref_pic_info.frame_num = 0;
+
+            if view.order_idx == 0 {
+                self.codec.seek_recovery = None;
+                self.recovery_point();
+            }
         }
 
         let hdr = &slice.header;
@@ -1119,12 +1513,12 @@ where
         let pps = Rc::clone(
             self.codec
                 .parser
-                .get_pps(hdr.pic_parameter_set_id)
+                .get_slice_pps(nalu_hdr, hdr.pic_parameter_set_id)
                 .context("Invalid PPS in handle_picture")?,
         );
 
         // A picture's SPS may require negociation.
-        self.renegotiate_if_needed(&pps.sps)?;
+        self.renegotiate_if_needed(&self.codec.stream_sps(&pps.sps))?;
         if let DecodingState::AwaitingFormat(_) = &self.decoding_state {
             return Err(DecodeError::CheckEvents);
         }
@@ -1145,14 +1539,30 @@ where
             false => CurrentMacroblockTracking::NonSeparateColorPlane(0),
         };
 
+        // When resuming after a seek, there is no previous reference picture to detect gaps in
+        // frame_num against.
+        if view.order_idx == 0
+            && matches!(self.codec.seek_recovery, Some(SeekRecovery::Resumed { .. }))
+        {
+            self.codec.prev_ref_pic_info.frame_num = frame_num;
+        }
+
         if frame_num != self.codec.prev_ref_pic_info.frame_num
             && frame_num != (self.codec.prev_ref_pic_info.frame_num + 1) % pps.sps.max_frame_num()
         {
             self.handle_frame_num_gap(&pps.sps, frame_num, timestamp)?;
         }
 
+        // The base view starts a new access unit.
+        if view.order_idx == 0 {
+            self.codec.inter_view_refs.clear();
+        }
+
         let first_field = self.codec.find_first_field(&slice.header)?;
         let pic = self.init_current_pic(slice, first_field.as_ref().map(|f| &f.0), timestamp)?;
+        if view.order_idx == 0 && !pic.is_second_field() {
+            self.update_seek_recovery(&pic, pps.sps.max_frame_num());
+        }
         let ref_pic_lists = self.codec.dpb.build_ref_pic_lists(&pic);
 
         debug!("Decode picture POC {:?}", pic.pic_order_cnt);
@@ -1164,12 +1574,20 @@ where
             self.backend.new_picture(&pic, timestamp)?
         };
 
+        let mut inter_view_refs = self.codec.inter_view_refs(&view, false);
+        for entry in self.codec.inter_view_refs(&view, true) {
+            if !inter_view_refs.iter().any(|e| std::ptr::eq(*e, entry)) {
+                inter_view_refs.push(entry);
+            }
+        }
+
         self.backend.start_picture(
             &mut backend_pic,
             &pic,
             pps.sps.as_ref(),
             pps.as_ref(),
             &self.codec.dpb,
+            &inter_view_refs,
             &slice.header,
         )?;
 
@@ -1179,6 +1597,7 @@ where
             backend_pic,
             ref_pic_lists,
             current_macroblock,
+            view,
         })
     }
 
@@ -1227,22 +1646,28 @@ where
         let pps = self
             .codec
             .parser
-            .get_pps(slice.header.pic_parameter_set_id)
+            .get_slice_pps(&slice.nalu.header, slice.header.pic_parameter_set_id)
             .context("Invalid PPS")?;
         cur_pic.pps = Rc::clone(pps);
 
         // Make sure that no negotiation is possible mid-picture. How could it?
         // We'd lose the context with the previous slices on it.
-        if Self::negotiation_possible(&cur_pic.pps.sps, &self.codec.negotiation_info) {
+        if Self::negotiation_possible(
+            &self.codec.stream_sps(&cur_pic.pps.sps),
+            &self.codec.negotiation_info,
+        ) {
             anyhow::bail!("invalid stream: inter-frame renegotiation requested");
         }
 
         let RefPicLists {
             ref_pic_list0,
             ref_pic_list1,
-        } = self
-            .codec
-            .create_ref_pic_lists(&cur_pic.pic, &slice.header, &cur_pic.ref_pic_lists)?;
+        } = self.codec.create_ref_pic_lists(
+            &cur_pic.pic,
+            &cur_pic.view,
+            &slice.header,
+            &cur_pic.ref_pic_lists,
+        )?;
 
         self.backend.decode_slice(
             &mut cur_pic.backend_pic,
@@ -1256,9 +1681,41 @@ where
         Ok(())
     }
 
+    /// Returns whether `nalu` is a slice of a picture that is not decoded in the current
+    /// [`DecodeMode`].
+    fn skip_slice(&mut self, nalu: &Nalu) -> bool {
+        let hdr = &nalu.header;
+        if !matches!(
+            hdr.type_,
+            NaluType::Slice
+                | NaluType::SliceDpa
+                | NaluType::SliceDpb
+                | NaluType::SliceDpc
+                | NaluType::SliceIdr
+                | NaluType::SliceExt
+        ) {
+            return false;
+        }
+
+        // Non-reference pictures of MVC streams may still be used for inter-view prediction.
+        let mvc_ext = hdr
+            .mvc_extension()
+            .or(self.codec.prefix_mvc_extension.as_ref());
+        let is_reference = hdr.ref_idc != 0 || mvc_ext.is_some_and(|ext| ext.inter_view_flag);
+
+        let skip = !self.should_decode(hdr.idr_pic_flag, is_reference);
+        if skip && hdr.mvc_extension().is_none() {
+            // The prefix NAL unit only applies to the skipped slice.
+            self.codec.prefix_mvc_extension = None;
+        }
+
+        skip
+    }
+
     /// Submits the picture to the accelerator.
     fn submit_picture(&mut self, backend_pic: B::Picture) -> Result<B::Handle, DecodeError> {
         let handle = self.backend.submit_picture(backend_pic)?;
+        self.mark_if_corrupted(&handle);
 
         if self.blocking_mode == BlockingMode::Blocking {
             handle.sync()?;
@@ -1275,6 +1732,17 @@ where
             NaluType::Pps => {
                 self.codec.parser.parse_pps(&nalu)?;
             }
+            NaluType::SubsetSps => {
+                self.codec.parser.parse_subset_sps(&nalu)?;
+            }
+            NaluType::PrefixUnit => {
+                // Only the base layer of SVC streams is decoded, so SVC prefix NAL units are
+                // ignored.
+                self.codec.prefix_mvc_extension = nalu.header.mvc_extension().copied();
+            }
+            NaluType::SliceExt if nalu.header.svc_extension().is_some() => {
+                debug!("Skipping SVC enhancement layer slice");
+            }
             NaluType::Slice
             | NaluType::SliceDpa
             | NaluType::SliceDpb
@@ -1282,9 +1750,21 @@ where
             | NaluType::SliceIdr
             | NaluType::SliceExt => {
                 let slice = self.codec.parser.parse_slice_header(nalu)?;
+                let view = self.codec.view_info(&slice)?;
+
+                // The pictures of the different views of MVC streams are interleaved, each view
+                // using its own DPB.
+                if view.order_idx != self.codec.current_view {
+                    if let Some(cur_pic) = self.codec.current_pic.take() {
+                        self.finish_picture(cur_pic)?;
+                    }
+
+                    self.codec.switch_view(view.order_idx);
+                }
+
                 let mut cur_pic = match self.codec.current_pic.take() {
                     // No current picture, start a new one.
-                    None => self.begin_picture(timestamp, &slice)?,
+                    None => self.begin_picture(timestamp, &slice, view)?,
                     // We have a current picture but are starting a new field, or first_mb_in_slice
                     // indicates that a new picture is starting: finish the current picture and
                     // start a new one.
@@ -1296,7 +1776,7 @@ where
                             || (slice.header.first_mb_in_slice == 0) =>
                     {
                         self.finish_picture(cur_pic)?;
-                        self.begin_picture(timestamp, &slice)?
+                        self.begin_picture(timestamp, &slice, view)?
                     }
                     // This slice is part of the current picture.
                     Some(cur_pic) => cur_pic,
@@ -1330,37 +1810,67 @@ where
             let sps = self.codec.parser.parse_sps(&nalu)?.clone();
             if matches!(self.decoding_state, DecodingState::AwaitingStreamInfo) {
                 // If more SPS come along we will renegotiate in begin_picture().
-                self.renegotiate_if_needed(&sps)?;
-            } else if matches!(self.decoding_state, DecodingState::Reset) {
-                // We can resume decoding since the decoding parameters have not changed.
-                self.decoding_state = DecodingState::Decoding;
+                let stream_sps = self.codec.stream_sps(&sps);
+                self.renegotiate_if_needed(&stream_sps)?;
             }
         } else if matches!(self.decoding_state, DecodingState::Reset) {
-            let mut cursor = Cursor::new(bitstream);
-
-            while let Ok(nalu) = Nalu::next(&mut cursor) {
-                // In the Reset state we can resume decoding from any key frame.
-                if matches!(nalu.header.type_, NaluType::SliceIdr) {
+            // In the Reset state we can resume decoding from any random access point: an IDR
+            // picture, a recovery point SEI message, or the I picture of an open GOP.
+            let recovery_frame_cnt = match nalu.header.type_ {
+                NaluType::SliceIdr => {
                     self.decoding_state = DecodingState::Decoding;
-                    break;
+                    None
                 }
+                NaluType::Sei => self
+                    .codec
+                    .parser
+                    .parse_recovery_point(&nalu)
+                    .ok()
+                    .flatten()
+                    .map(|recovery_point| recovery_point.recovery_frame_cnt),
+                NaluType::Slice => {
+                    let nalu = Nalu::next(&mut Cursor::new(bitstream))?;
+                    self.codec
+                        .parser
+                        .parse_slice_header(nalu)
+                        .ok()
+                        .filter(|slice| {
+                            let hdr = &slice.header;
+                            hdr.first_mb_in_slice == 0
+                                && (hdr.slice_type.is_i() || hdr.slice_type.is_si())
+                        })
+                        .map(|_| 0)
+                }
+                _ => None,
+            };
+
+            if let Some(recovery_frame_cnt) = recovery_frame_cnt {
+                self.codec.seek_recovery = Some(SeekRecovery::Resumed { recovery_frame_cnt });
+                self.start_recovery();
+                self.decoding_state = DecodingState::Decoding;
             }
         }
 
         let nalu_len = nalu.offset + nalu.size;
+        let skip = matches!(self.decoding_state, DecodingState::Decoding) && self.skip_slice(&nalu);
 
         match &mut self.decoding_state {
             // Process parameter sets, but skip input until we get information
             // from the stream.
             DecodingState::AwaitingStreamInfo | DecodingState::Reset => {
-                if matches!(nalu.header.type_, NaluType::Pps) {
+                if matches!(nalu.header.type_, NaluType::Pps | NaluType::SubsetSps) {
                     self.process_nalu(timestamp, nalu)?;
                 }
             }
             // Ask the client to confirm the format before we can process this.
             DecodingState::AwaitingFormat(_) => return Err(DecodeError::CheckEvents),
+            DecodingState::Decoding if skip => {
+                debug!("Skipping slice as per the decode mode");
+            }
             DecodingState::Decoding => {
-                self.process_nalu(timestamp, nalu)?;
+                // When concealing errors, a NAL unit that cannot be processed is skipped.
+                let res = self.process_nalu(timestamp, nalu);
+                self.conceal_error(res)?;
             }
         }
 
@@ -1368,12 +1878,25 @@ where
     }
 
     fn flush(&mut self) -> Result<(), DecodeError> {
-        self.drain()?;
+        self.drain_all_views()?;
+        self.codec.seek_recovery = None;
         self.decoding_state = DecodingState::Reset;
 
         Ok(())
     }
 
+    fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    fn set_error_resilience(&mut self, error_resilience: ErrorResilience) {
+        self.error_resilience = error_resilience;
+    }
+
+    fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.output_mode = output_mode;
+    }
+
     fn next_event(&mut self) -> Option<DecoderEvent<B::Handle>> {
         self.query_next_event(|decoder, sps| {
             // Apply the SPS settings to the decoder so we don't enter the AwaitingFormat state
@@ -1397,12 +1920,19 @@ where
 
 #[cfg(test)]
 pub mod tests {
+    use crate::codec::h264::nalu_writer::NaluWriter;
     use crate::codec::h264::parser::Nalu;
+    use crate::codec::h264::parser::NaluType;
     use crate::decoder::stateless::h264::H264;
+    use crate::decoder::stateless::tests::decode_units;
     use crate::decoder::stateless::tests::test_decode_stream;
     use crate::decoder::stateless::tests::TestStream;
     use crate::decoder::stateless::StatelessDecoder;
+    use crate::decoder::stateless::StatelessVideoDecoder;
     use crate::decoder::BlockingMode;
+    use crate::decoder::DecodeMode;
+    use crate::decoder::ErrorResilience;
+    use crate::decoder::OutputMode;
     use crate::utils::simple_playback_loop;
     use crate::utils::simple_playback_loop_owned_frames;
     use crate::utils::NalIterator;
@@ -1554,4 +2084,360 @@ pub mod tests {
     fn test_25fps_interlaced_nonblock() {
         test_decoder_dummy(&DECODE_TEST_25FPS_INTERLACED, BlockingMode::NonBlocking);
     }
+
+    #[test]
+    fn test_seek() {
+        let nalus: Vec<_> = NalIterator::<Nalu>::new(DECODE_TEST_25FPS.stream).collect();
+        let nalus: Vec<&[u8]> = nalus.iter().map(|nalu| nalu.as_ref()).collect();
+
+        let mut decoder = StatelessDecoder::<H264, _>::new_dummy(BlockingMode::Blocking).unwrap();
+        let (corrupted, _) =
+            decode_units(&mut decoder, nalus[..100].iter().copied(), true).unwrap();
+        assert!(!corrupted.is_empty());
+        assert!(corrupted.iter().all(|c| !c));
+
+        // Decoding cannot resume at a P picture...
+        let (corrupted, _) =
+            decode_units(&mut decoder, nalus[100..196].iter().copied(), true).unwrap();
+        assert!(corrupted.is_empty());
+
+        // ... but can at an IDR picture.
+        let (corrupted, _) =
+            decode_units(&mut decoder, nalus[196..250].iter().copied(), true).unwrap();
+        assert!(!corrupted.is_empty());
+        assert!(corrupted.iter().all(|c| !c));
+
+        // Replace the SEI message preceding a P picture with a recovery point SEI message with a
+        // recovery_frame_cnt of 2. Decoding resumes, but the three pictures decoded before the
+        // recovery point (P, B, P) are corrupted.
+        let recovery_point = [0x00, 0x00, 0x00, 0x01, 0x06, 0x06, 0x01, 0x71, 0x80];
+        let (corrupted, _) = decode_units(
+            &mut decoder,
+            std::iter::once(recovery_point.as_ref()).chain(nalus[303..350].iter().copied()),
+            true,
+        )
+        .unwrap();
+        assert_eq!(corrupted.iter().filter(|c| **c).count(), 3);
+        assert!(corrupted[4..].iter().all(|c| !c));
+    }
+
+    #[test]
+    fn test_seek_recovery_errors() {
+        let nalus: Vec<_> = NalIterator::<Nalu>::new(DECODE_TEST_25FPS.stream).collect();
+        let nalus: Vec<&[u8]> = nalus.iter().map(|nalu| nalu.as_ref()).collect();
+        let recovery_point = [0x00, 0x00, 0x00, 0x01, 0x06, 0x06, 0x01, 0x71, 0x80];
+        // A truncated slice of the picture following the recovery point SEI message.
+        let broken_slice = &nalus[303][..6];
+
+        for error_resilience in [ErrorResilience::Strict, ErrorResilience::Conceal] {
+            let mut decoder =
+                StatelessDecoder::<H264, _>::new_dummy(BlockingMode::Blocking).unwrap();
+            decoder.set_error_resilience(error_resilience);
+            decode_units(&mut decoder, nalus[..100].iter().copied(), true).unwrap();
+
+            // Errors are only concealed in `Conceal` mode, even while recovering from a seek.
+            let units = [recovery_point.as_ref(), nalus[303], broken_slice];
+            let res = decode_units(&mut decoder, units, false);
+            assert_eq!(
+                res.is_ok(),
+                error_resilience == ErrorResilience::Conceal,
+                "{:?}",
+                error_resilience
+            );
+        }
+    }
+
+    #[test]
+    fn test_conceal_lost_reference() {
+        let nalus: Vec<_> = NalIterator::<Nalu>::new(DECODE_TEST_25FPS.stream).collect();
+        let nalus: Vec<&[u8]> = nalus.iter().map(|nalu| nalu.as_ref()).collect();
+
+        let mut decoder = StatelessDecoder::<H264, _>::new_dummy(BlockingMode::Blocking).unwrap();
+        decoder.set_error_resilience(ErrorResilience::Conceal);
+
+        // Lose the first P picture (NALUs 6 to 8), which the following B picture references.
+        let lossy_nalus = nalus[..6].iter().chain(&nalus[9..12]).copied();
+        let (corrupted, _) = decode_units(&mut decoder, lossy_nalus, false).unwrap();
+        assert!(corrupted.is_empty());
+
+        // The B picture predicts from both the IDR picture and the picture concealing the lost
+        // one.
+        let dpb = &decoder.codec.dpb;
+        let ref_pic_lists = &decoder.codec.current_pic.as_ref().unwrap().ref_pic_lists;
+        let concealed: Vec<_> = dpb
+            .entries()
+            .iter()
+            .map(|entry| entry.pic.borrow().concealed)
+            .collect();
+        assert_eq!(concealed.iter().filter(|c| **c).count(), 1);
+        for list in [
+            &ref_pic_lists.ref_pic_list_b0,
+            &ref_pic_lists.ref_pic_list_b1,
+        ] {
+            assert_eq!(list.len(), 2);
+            assert!(list.iter().any(|&i| concealed[i]));
+        }
+
+        // The concealed picture is not output, and the pictures following the IDR one are
+        // corrupted until the next IDR.
+        let (corrupted, _) =
+            decode_units(&mut decoder, nalus[12..196].iter().copied(), true).unwrap();
+        assert_eq!(corrupted.len(), 63);
+        assert!(!corrupted[0]);
+        assert!(corrupted[1..].iter().all(|c| *c));
+    }
+
+    #[test]
+    fn test_decode_mode() {
+        let nalus: Vec<_> = NalIterator::<Nalu>::new(DECODE_TEST_25FPS.stream).collect();
+        let nalus: Vec<&[u8]> = nalus.iter().map(|nalu| nalu.as_ref()).collect();
+        let mut decoder = StatelessDecoder::<H264, _>::new_dummy(BlockingMode::Blocking).unwrap();
+        decoder.set_decode_mode(DecodeMode::KeyFramesOnly);
+        let (frames, _) = decode_units(&mut decoder, nalus.iter().copied(), true).unwrap();
+        // The stream contains 4 IDR pictures.
+        assert_eq!(frames.len(), 4);
+
+        let mut decoder = StatelessDecoder::<H264, _>::new_dummy(BlockingMode::Blocking).unwrap();
+        decoder.set_decode_mode(DecodeMode::SkipNonReference);
+        let (frames, _) = decode_units(&mut decoder, nalus.iter().copied(), true).unwrap();
+        // Half of the 250 frames of the stream are non-reference B pictures.
+        assert_eq!(frames.len(), 125);
+    }
+
+    #[test]
+    fn test_output_mode() {
+        let stream = DECODE_TEST_25FPS.stream;
+        let nalus: Vec<_> = NalIterator::<Nalu>::new(stream).collect();
+        let nalus: Vec<&[u8]> = nalus.iter().map(|nalu| nalu.as_ref()).collect();
+
+        let count_with_mode = |mode| {
+            let mut decoder =
+                StatelessDecoder::<H264, _>::new_dummy(BlockingMode::Blocking).unwrap();
+            decoder.set_output_mode(mode);
+            let (frames, before_flush) =
+                decode_units(&mut decoder, nalus.iter().copied(), true).unwrap();
+            (before_flush, frames.len())
+        };
+
+        // Frames are held until the DPB is full, even though the stream only reorders one frame.
+        assert_eq!(count_with_mode(OutputMode::Dpb), (242, 250));
+        // The last picture is only complete once the decoder is flushed.
+        assert_eq!(count_with_mode(OutputMode::ReorderLimit), (249, 250));
+        assert_eq!(count_with_mode(OutputMode::DecodeOrder), (249, 250));
+    }
+
+    fn write_rbsp_trailing_bits(w: &mut NaluWriter<&mut Vec<u8>>) {
+        w.write_u(1, 1u32).unwrap();
+        while !w.aligned() {
+            w.write_u(1, 0u32).unwrap();
+        }
+    }
+
+    /// Writes the header of a NAL unit of `type_` with a MVC extension for `view_id`. IDR view
+    /// components are also anchor ones.
+    fn write_mvc_header(
+        w: &mut NaluWriter<&mut Vec<u8>>,
+        type_: NaluType,
+        view_id: u32,
+        idr: bool,
+        inter_view: bool,
+    ) {
+        w.write_header(3, type_ as u8).unwrap();
+        // svc_extension_flag, non_idr_flag and priority_id.
+        w.write_u(1, 0u32).unwrap();
+        w.write_u(1, u32::from(!idr)).unwrap();
+        w.write_u(6, 0u32).unwrap();
+        w.write_u(10, view_id).unwrap();
+        // temporal_id, anchor_pic_flag, inter_view_flag and reserved_one_bit.
+        w.write_u(3, 0u32).unwrap();
+        w.write_u(1, u32::from(idr)).unwrap();
+        w.write_u(1, u32::from(inter_view)).unwrap();
+        w.write_u(1, 1u32).unwrap();
+    }
+
+    /// Writes the end of the header of a slice referencing the SPS and PPS of [`mvc_stream`], from
+    /// `frame_num` on, then a stub of slice data.
+    fn write_slice_header_end(
+        w: &mut NaluWriter<&mut Vec<u8>>,
+        frame_num: u32,
+        idr: bool,
+        inter_view_modification: bool,
+    ) {
+        w.write_u(4, frame_num).unwrap();
+        if idr {
+            // idr_pic_id.
+            w.write_ue(0u32).unwrap();
+        }
+        // num_ref_idx_active_override_flag.
+        w.write_u(1, 0u32).unwrap();
+        if inter_view_modification {
+            // Move the first inter-view reference to the front of list 0.
+            w.write_u(1, 1u32).unwrap();
+            for value in [5u32, 0, 3] {
+                w.write_ue(value).unwrap();
+            }
+        } else {
+            w.write_u(1, 0u32).unwrap();
+        }
+        // dec_ref_pic_marking().
+        w.write_u(if idr { 2 } else { 1 }, 0u32).unwrap();
+        // slice_qp_delta, disable_deblocking_filter_idc.
+        w.write_se(0i32).unwrap();
+        w.write_ue(1u32).unwrap();
+        write_rbsp_trailing_bits(w);
+    }
+
+    /// Returns a 64x64 Multiview High stream with three views and two access units. Each non-base
+    /// view predicts from the previous view, and the second access unit also predicts from the
+    /// first one.
+    fn mvc_stream() -> Vec<u8> {
+        let mut stream = vec![];
+        let mut w = NaluWriter::new(&mut stream, true);
+
+        // Main profile SPS of the base view, then the subset SPS of the other views.
+        w.write_header(3, NaluType::Sps as u8).unwrap();
+        w.write_u(8, 77u32).unwrap();
+        w.write_u(8, 0u32).unwrap();
+        w.write_u(8, 30u32).unwrap();
+        // seq_parameter_set_id, log2_max_frame_num_minus4, pic_order_cnt_type,
+        // max_num_ref_frames.
+        for value in [0u32, 0, 2, 1] {
+            w.write_ue(value).unwrap();
+        }
+        w.write_u(1, 0u32).unwrap();
+        // 64x64.
+        w.write_ue(3u32).unwrap();
+        w.write_ue(3u32).unwrap();
+        // frame_mbs_only_flag, direct_8x8_inference_flag, frame_cropping_flag and
+        // vui_parameters_present_flag.
+        w.write_u(4, 0b1100u32).unwrap();
+        write_rbsp_trailing_bits(&mut w);
+
+        w.write_header(3, NaluType::SubsetSps as u8).unwrap();
+        w.write_u(8, 118u32).unwrap();
+        w.write_u(8, 0u32).unwrap();
+        w.write_u(8, 30u32).unwrap();
+        // seq_parameter_set_id, chroma_format_idc, bit depths.
+        for value in [0u32, 1, 0, 0] {
+            w.write_ue(value).unwrap();
+        }
+        // qpprime_y_zero_transform_bypass_flag, seq_scaling_matrix_present_flag.
+        w.write_u(2, 0u32).unwrap();
+        for value in [0u32, 2, 1] {
+            w.write_ue(value).unwrap();
+        }
+        w.write_u(1, 0u32).unwrap();
+        w.write_ue(3u32).unwrap();
+        w.write_ue(3u32).unwrap();
+        // Same flags as the SPS, and bit_equal_to_one.
+        w.write_u(5, 0b11001u32).unwrap();
+        // num_views_minus1 and view_id.
+        for value in [2u32, 0, 1, 2] {
+            w.write_ue(value).unwrap();
+        }
+        // Anchor, then non-anchor references: view 0 in list 0 of view 1, and view 1 in list 0
+        // of view 2.
+        for _ in 0..2 {
+            for value in [1u32, 0, 0, 1, 1, 0] {
+                w.write_ue(value).unwrap();
+            }
+        }
+        // One level value, for one operation point with all the views.
+        w.write_ue(0u32).unwrap();
+        w.write_u(8, 30u32).unwrap();
+        w.write_ue(0u32).unwrap();
+        w.write_u(3, 0u32).unwrap();
+        for value in [2u32, 0, 1, 2, 2] {
+            w.write_ue(value).unwrap();
+        }
+        // mvc_vui_parameters_present_flag, additional_extension2_flag.
+        w.write_u(2, 0u32).unwrap();
+        write_rbsp_trailing_bits(&mut w);
+
+        // The PPS applies to the SPS and the subset SPS, which share the same ID.
+        w.write_header(3, NaluType::Pps as u8).unwrap();
+        w.write_ue(0u32).unwrap();
+        w.write_ue(0u32).unwrap();
+        w.write_u(2, 0u32).unwrap();
+        for value in [0u32, 0, 0] {
+            w.write_ue(value).unwrap();
+        }
+        w.write_u(3, 0u32).unwrap();
+        for value in [0i32, 0, 0] {
+            w.write_se(value).unwrap();
+        }
+        // deblocking_filter_control_present_flag only.
+        w.write_u(3, 0b100u32).unwrap();
+        write_rbsp_trailing_bits(&mut w);
+
+        for frame_num in 0..2 {
+            let idr = frame_num == 0;
+
+            // Base view, with its MVC extension in a prefix NAL unit.
+            write_mvc_header(&mut w, NaluType::PrefixUnit, 0, idr, true);
+            let type_ = if idr {
+                NaluType::SliceIdr
+            } else {
+                NaluType::Slice
+            };
+            w.write_header(3, type_ as u8).unwrap();
+            // first_mb_in_slice, slice_type (I or P), pic_parameter_set_id.
+            for value in [0u32, if idr { 7 } else { 5 }, 0] {
+                w.write_ue(value).unwrap();
+            }
+            if idr {
+                w.write_u(4, 0u32).unwrap();
+                w.write_ue(0u32).unwrap();
+                w.write_u(2, 0u32).unwrap();
+                w.write_se(0i32).unwrap();
+                w.write_ue(1u32).unwrap();
+                write_rbsp_trailing_bits(&mut w);
+            } else {
+                write_slice_header_end(&mut w, frame_num, false, false);
+            }
+
+            // P slices of the other views. Their only reference in the first access unit is the
+            // inter-view one, which needs to be moved before the temporal one in the second.
+            for view_id in 1..3 {
+                write_mvc_header(&mut w, NaluType::SliceExt, view_id, idr, view_id < 2);
+                for value in [0u32, 5, 0] {
+                    w.write_ue(value).unwrap();
+                }
+                write_slice_header_end(&mut w, frame_num, idr, !idr);
+            }
+        }
+        drop(w);
+
+        stream
+    }
+
+    #[test]
+    fn test_mvc() {
+        let stream = mvc_stream();
+        let nalus: Vec<_> = NalIterator::<Nalu>::new(&stream).collect();
+        let nalus: Vec<&[u8]> = nalus.iter().map(|nalu| nalu.as_ref()).collect();
+
+        let mut decoder = StatelessDecoder::<H264, _>::new_dummy(BlockingMode::Blocking).unwrap();
+        let (corrupted, _) = decode_units(&mut decoder, nalus.iter().copied(), false).unwrap();
+        assert!(corrupted.iter().all(|c| !c));
+
+        // The stream is negotiated with the subset SPS, and each view has its own DPB.
+        assert_eq!(decoder.codec.negotiation_info.profile_idc, 118);
+        assert_eq!(decoder.codec.negotiation_info.num_views, 3);
+        assert_eq!(decoder.codec.current_view, 2);
+        assert_eq!(decoder.codec.other_views.len(), 2);
+
+        // The last picture of view 2 predicts from the one of view 1 of the same access unit.
+        let (_, inter_view_ref) = decoder
+            .codec
+            .inter_view_refs
+            .iter()
+            .find(|(view_id, _)| *view_id == 1)
+            .unwrap();
+        assert_eq!(inter_view_ref.pic.borrow().frame_num, 1);
+
+        // Flushing outputs the pictures of all the views.
+        let (flushed, _) = decode_units(&mut decoder, [], true).unwrap();
+        assert_eq!(corrupted.len() + flushed.len(), 6);
+    }
 }
@@ -18,6 +18,7 @@ use crate::codec::h264::parser::SliceHeader;
 use crate::codec::h264::parser::Sps;
 use crate::codec::h264::picture::PictureData;
 use crate::decoder::stateless::h264::StatelessH264DecoderBackend;
+use crate::decoder::stateless::h264::StreamSps;
 use crate::decoder::stateless::h264::H264;
 use crate::decoder::stateless::NewStatelessDecoderError;
 use crate::decoder::stateless::StatelessBackendResult;
@@ -25,7 +26,7 @@ use crate::decoder::stateless::StatelessDecoder;
 use crate::decoder::BlockingMode;
 
 impl StatelessH264DecoderBackend for Backend {
-    fn new_sequence(&mut self, _: &Rc<Sps>) -> StatelessBackendResult<()> {
+    fn new_sequence(&mut self, _: &StreamSps) -> StatelessBackendResult<()> {
         Ok(())
     }
 
@@ -36,6 +37,7 @@ impl StatelessH264DecoderBackend for Backend {
         _: &Sps,
         _: &Pps,
         _: &Dpb<Self::Handle>,
+        _: &[&DpbEntry<Self::Handle>],
         _: &SliceHeader,
     ) -> StatelessBackendResult<()> {
         Ok(())
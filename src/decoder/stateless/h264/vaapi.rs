@@ -34,6 +34,7 @@ use crate::codec::h264::picture::Field;
 use crate::codec::h264::picture::PictureData;
 use crate::codec::h264::picture::Reference;
 use crate::decoder::stateless::h264::StatelessH264DecoderBackend;
+use crate::decoder::stateless::h264::StreamSps;
 use crate::decoder::stateless::h264::H264;
 use crate::decoder::stateless::NewStatelessDecoderError;
 use crate::decoder::stateless::StatelessBackendError;
@@ -42,15 +43,39 @@ use crate::decoder::stateless::StatelessDecoder;
 use crate::decoder::stateless::StatelessDecoderBackendPicture;
 use crate::decoder::BlockingMode;
 
-impl VaStreamInfo for &Rc<Sps> {
+impl VaStreamInfo for &StreamSps {
     fn va_profile(&self) -> anyhow::Result<i32> {
-        let profile_idc = self.profile_idc;
-        let profile = Profile::n(profile_idc)
+        let profile_idc = self.sps.profile_idc;
+
+        // MVC streams are negotiated with the SPS of their subset SPS.
+        match profile_idc {
+            118 => return Ok(libva::VAProfile::VAProfileH264MultiviewHigh),
+            128 => return Ok(libva::VAProfile::VAProfileH264StereoHigh),
+            _ => (),
+        }
+
+        let profile = self
+            .sps
+            .profile()
             .with_context(|| format!("Invalid profile_idc {:?}", profile_idc))?;
 
+        // The High 4:4:4 profiles allow lossless coding and coding the colour planes separately,
+        // none of which VA-API decoders can signal.
+        if self.sps.qpprime_y_zero_transform_bypass_flag {
+            return Err(anyhow!(
+                "Unsupported stream: qpprime_y_zero_transform_bypass_flag is set"
+            ));
+        }
+        if self.sps.separate_colour_plane_flag {
+            return Err(anyhow!(
+                "Unsupported stream: separate_colour_plane_flag is set"
+            ));
+        }
+
         match profile {
+            Profile::ConstrainedBaseline => Ok(libva::VAProfile::VAProfileH264ConstrainedBaseline),
             Profile::Baseline => {
-                if self.constraint_set0_flag {
+                if self.sps.constraint_set0_flag {
                     Ok(libva::VAProfile::VAProfileH264ConstrainedBaseline)
                 } else {
                     Err(anyhow!(
@@ -60,7 +85,7 @@ impl VaStreamInfo for &Rc<Sps> {
             }
             Profile::Main => Ok(libva::VAProfile::VAProfileH264Main),
             Profile::Extended => {
-                if self.constraint_set1_flag {
+                if self.sps.constraint_set1_flag {
                     Ok(libva::VAProfile::VAProfileH264Main)
                 } else {
                     Err(anyhow!(
@@ -68,15 +93,23 @@ impl VaStreamInfo for &Rc<Sps> {
                     ))
                 }
             }
-            Profile::High | Profile::High422P | Profile::High10 => {
-                Ok(libva::VAProfile::VAProfileH264High)
-            }
+            // VA-API has no dedicated profiles for the high bit depth and chroma formats, which
+            // are selected through the RT format instead.
+            Profile::High
+            | Profile::ConstrainedHigh
+            | Profile::High10
+            | Profile::High10Intra
+            | Profile::High422P
+            | Profile::High422Intra
+            | Profile::High444P
+            | Profile::High444Intra
+            | Profile::Cavlc444Intra => Ok(libva::VAProfile::VAProfileH264High),
         }
     }
 
     fn rt_format(&self) -> anyhow::Result<u32> {
-        let bit_depth_luma = self.bit_depth_chroma_minus8 + 8;
-        let chroma_format_idc = self.chroma_format_idc;
+        let bit_depth_luma = self.sps.bit_depth_chroma_minus8 + 8;
+        let chroma_format_idc = self.sps.chroma_format_idc;
 
         match (bit_depth_luma, chroma_format_idc) {
             (8, 0) | (8, 1) => Ok(libva::constants::VA_RT_FORMAT_YUV420),
@@ -97,15 +130,16 @@ impl VaStreamInfo for &Rc<Sps> {
     }
 
     fn min_num_surfaces(&self) -> usize {
-        self.max_dpb_frames() + 4
+        // Each view of MVC streams has its own DPB.
+        self.num_views * self.sps.max_dpb_frames() + 4
     }
 
     fn coded_size(&self) -> (u32, u32) {
-        (self.width, self.height)
+        (self.sps.width, self.sps.height)
     }
 
     fn visible_rect(&self) -> ((u32, u32), (u32, u32)) {
-        let rect = self.visible_rectangle();
+        let rect = self.sps.visible_rectangle();
 
         ((rect.min.x, rect.min.y), (rect.max.x, rect.max.y))
     }
@@ -209,6 +243,7 @@ fn build_pic_param<M: SurfaceMemoryDescriptor>(
     current_picture: &PictureData,
     current_surface_id: libva::VASurfaceID,
     dpb: &Dpb<VADecodedHandle<M>>,
+    inter_view_refs: &[&DpbEntry<VADecodedHandle<M>>],
     sps: &Sps,
     pps: &Pps,
 ) -> anyhow::Result<BufferType> {
@@ -250,6 +285,18 @@ fn build_pic_param<M: SurfaceMemoryDescriptor>(
         va_refs.push(pic);
     }
 
+    // Inter-view references are not marked as reference, as they do not belong to the DPB of the
+    // current view.
+    for handle in inter_view_refs
+        .iter()
+        .take(16usize.saturating_sub(va_refs.len()))
+    {
+        let surface_id = va_surface_id(&handle.handle);
+        let ref_pic = handle.pic.borrow();
+        let pic = fill_va_h264_pic(&ref_pic, surface_id, true);
+        va_refs.push(pic);
+    }
+
     for _ in va_refs.len()..16 {
         va_refs.push(build_invalid_va_h264_pic());
     }
@@ -468,8 +515,8 @@ impl<M: SurfaceMemoryDescriptor + 'static> StatelessDecoderBackendPicture<H264>
 }
 
 impl<M: SurfaceMemoryDescriptor + 'static> StatelessH264DecoderBackend for VaapiBackend<M> {
-    fn new_sequence(&mut self, sps: &Rc<Sps>) -> StatelessBackendResult<()> {
-        self.new_sequence(sps, PoolCreationMode::Highest)
+    fn new_sequence(&mut self, stream_sps: &StreamSps) -> StatelessBackendResult<()> {
+        self.new_sequence(stream_sps, PoolCreationMode::Highest)
     }
 
     fn start_picture(
@@ -479,6 +526,7 @@ impl<M: SurfaceMemoryDescriptor + 'static> StatelessH264DecoderBackend for Vaapi
         sps: &Sps,
         pps: &Pps,
         dpb: &Dpb<Self::Handle>,
+        inter_view_refs: &[&DpbEntry<Self::Handle>],
         hdr: &SliceHeader,
     ) -> StatelessBackendResult<()> {
         let metadata = self.metadata_state.get_parsed()?;
@@ -486,7 +534,15 @@ impl<M: SurfaceMemoryDescriptor + 'static> StatelessH264DecoderBackend for Vaapi
 
         let surface_id = picture.surface().id();
 
-        let pic_param = build_pic_param(hdr, picture_data, surface_id, dpb, sps, pps)?;
+        let pic_param = build_pic_param(
+            hdr,
+            picture_data,
+            surface_id,
+            dpb,
+            inter_view_refs,
+            sps,
+            pps,
+        )?;
         let pic_param = context
             .create_buffer(pic_param)
             .context("while creating picture parameter buffer")?;
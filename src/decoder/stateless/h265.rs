@@ -2,7 +2,6 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-#[cfg(test)]
 mod dummy;
 #[cfg(feature = "vaapi")]
 mod vaapi;
@@ -38,9 +37,12 @@ use crate::decoder::stateless::StatelessDecoderBackend;
 use crate::decoder::stateless::StatelessVideoDecoder;
 use crate::decoder::stateless::TryFormat;
 use crate::decoder::BlockingMode;
+use crate::decoder::DecodeMode;
 use crate::decoder::DecodedHandle;
 use crate::decoder::DecoderEvent;
+use crate::decoder::ErrorResilience;
 use crate::decoder::FramePool;
+use crate::decoder::OutputMode;
 use crate::decoder::StreamInfo;
 use crate::Resolution;
 
@@ -587,6 +589,10 @@ where
             self.codec.rps.ref_pic_set_st_foll[i] = reference;
         }
 
+        if self.conceal_errors() {
+            self.substitute_missing_references();
+        }
+
         // 4. All reference pictures in the DPB that are not included in
         // RefPicSetLtCurr, RefPicSetLtFoll, RefPicSetStCurrBefore,
         // RefPicSetStCurrAfter, or RefPicSetStFoll and have nuh_layer_id equal
@@ -643,6 +649,45 @@ where
         Ok(())
     }
 
+    /// Replaces the missing pictures of the RPS of the current picture with the picture of the
+    /// DPB closest in output order, marking the current picture as corrupted if any is found.
+    fn substitute_missing_references(&mut self) {
+        let rps = &mut self.codec.rps;
+        let lists = [
+            (
+                &mut rps.ref_pic_set_st_curr_before[..rps.num_poc_st_curr_before],
+                &rps.poc_st_curr_before[..rps.num_poc_st_curr_before],
+            ),
+            (
+                &mut rps.ref_pic_set_st_curr_after[..rps.num_poc_st_curr_after],
+                &rps.poc_st_curr_after[..rps.num_poc_st_curr_after],
+            ),
+            (
+                &mut rps.ref_pic_set_lt_curr[..rps.num_poc_lt_curr],
+                &rps.poc_lt_curr[..rps.num_poc_lt_curr],
+            ),
+        ];
+
+        for (refs, pocs) in lists {
+            for (reference, &poc) in refs.iter_mut().zip(pocs) {
+                if reference.is_some() {
+                    continue;
+                }
+
+                *reference = self
+                    .codec
+                    .dpb
+                    .entries()
+                    .iter()
+                    .min_by_key(|entry| entry.0.borrow().pic_order_cnt_val.abs_diff(poc))
+                    .cloned();
+
+                log::warn!("Concealing missing reference with POC {}", poc);
+                self.corrupted = true;
+            }
+        }
+    }
+
     // See 8.3.4.
     // Builds the reference picture list for `hdr` for P and B slices.
     fn build_ref_pic_lists(
@@ -955,6 +1000,12 @@ where
             return Err(DecodeError::CheckEvents);
         }
 
+        // When only decoding IRAP pictures, each of them starts a new coded video sequence so the
+        // state left by the previous one is not used.
+        if self.decode_mode == DecodeMode::KeyFramesOnly {
+            self.codec.first_picture_after_eos = true;
+        }
+
         let pic = PictureData::new_from_slice(
             slice,
             self.codec
@@ -973,6 +1024,10 @@ where
 
         if pic.is_irap {
             self.codec.irap_no_rasl_output_flag = pic.no_rasl_output_flag;
+
+            if pic.no_rasl_output_flag {
+                self.recovery_point();
+            }
         } else if pic.nalu_type.is_rasl() && self.codec.irap_no_rasl_output_flag {
             // NOTE – All RASL pictures are leading pictures of an associated
             // BLA or CRA picture. When the associated IRAP picture has
@@ -1088,11 +1143,20 @@ where
 
         self.clear_ref_lists();
 
+        // In decoding order mode, the picture is output right away and only kept in the DPB for
+        // reference.
+        let output_now = self.output_mode == OutputMode::DecodeOrder;
+        if output_now && pic.pic_output_flag {
+            self.ready_queue.push(handle.clone());
+        }
+
         // First store the current picture in the DPB, only then we should
         // decide whether to bump.
-        self.codec
-            .dpb
-            .store_picture(Rc::new(RefCell::new(pic)), handle)?;
+        let pic = Rc::new(RefCell::new(pic));
+        self.codec.dpb.store_picture(pic.clone(), handle)?;
+        if output_now {
+            pic.borrow_mut().needed_for_output = false;
+        }
         let bumped = self.bump_as_needed(BumpingType::AfterDecoding)?;
 
         log::debug!(
@@ -1237,9 +1301,32 @@ where
         Ok(())
     }
 
+    /// Returns whether `nalu` is a slice segment of a picture that is not decoded in the current
+    /// [`DecodeMode`].
+    fn skip_slice(&self, nalu: &Nalu) -> bool {
+        let type_ = nalu.header.type_;
+        // Only VCL NAL units carry pictures.
+        if type_ as u32 >= NaluType::VpsNut as u32 {
+            return false;
+        }
+
+        // Sub-layer non-reference pictures of the highest sub-layer are not used as reference by
+        // any other picture.
+        let max_sub_layers_minus1 = self
+            .codec
+            .parser
+            .get_sps(self.codec.cur_sps_id)
+            .map_or(0, |sps| sps.max_sub_layers_minus1);
+        let is_reference =
+            !type_.is_slnr() || nalu.header.nuh_temporal_id_plus1 <= max_sub_layers_minus1;
+
+        !self.should_decode(type_.is_irap(), is_reference)
+    }
+
     /// Submits the picture to the accelerator.
     fn submit_picture(&mut self, backend_pic: B::Picture) -> Result<B::Handle, DecodeError> {
         let handle = self.backend.submit_picture(backend_pic)?;
+        self.mark_if_corrupted(&handle);
 
         if self.blocking_mode == BlockingMode::Blocking {
             handle.sync()?;
@@ -1266,23 +1353,17 @@ where
             if matches!(self.decoding_state, DecodingState::AwaitingStreamInfo) {
                 // If more SPS come along we will renegotiate in begin_picture().
                 self.renegotiate_if_needed(RenegotiationType::NewSps(&sps))?;
-            } else if matches!(self.decoding_state, DecodingState::Reset) {
-                // We can resume decoding since the decoding parameters have not changed.
-                self.decoding_state = DecodingState::Decoding;
             }
         } else if matches!(self.decoding_state, DecodingState::Reset) {
-            let mut cursor = Cursor::new(bitstream);
-
-            while let Ok(nalu) = Nalu::next(&mut cursor) {
-                // In the Reset state we can resume decoding from any key frame.
-                if nalu.header.type_.is_idr() {
-                    self.decoding_state = DecodingState::Decoding;
-                    break;
-                }
+            // In the Reset state we can resume decoding from any IRAP picture. The RASL pictures
+            // associated with a CRA picture are skipped since it gets NoRaslOutputFlag set.
+            if nalu.header.type_.is_irap() && nalu.header.nuh_layer_id == 0 {
+                self.decoding_state = DecodingState::Decoding;
             }
         }
 
         let nalu_len = nalu.offset + nalu.size;
+        let skip = matches!(self.decoding_state, DecodingState::Decoding) && self.skip_slice(&nalu);
 
         match &mut self.decoding_state {
             // Process parameter sets, but skip input until we get information
@@ -1297,8 +1378,13 @@ where
             }
             // Ask the client to confirm the format before we can process this.
             DecodingState::AwaitingFormat(_) => return Err(DecodeError::CheckEvents),
+            DecodingState::Decoding if skip => {
+                log::debug!("Skipping slice as per the decode mode");
+            }
             DecodingState::Decoding => {
-                self.process_nalu(timestamp, nalu)?;
+                // When concealing errors, a NAL unit that cannot be processed is skipped.
+                let res = self.process_nalu(timestamp, nalu);
+                self.conceal_error(res)?;
             }
         }
 
@@ -1307,11 +1393,26 @@ where
 
     fn flush(&mut self) -> Result<(), DecodeError> {
         self.drain()?;
+        // The picture decoding resumes at is handled like the first picture following an end of
+        // sequence, i.e. as if HandleCraAsBlaFlag was set for CRA pictures.
+        self.codec.first_picture_after_eos = true;
         self.decoding_state = DecodingState::Reset;
 
         Ok(())
     }
 
+    fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    fn set_error_resilience(&mut self, error_resilience: ErrorResilience) {
+        self.error_resilience = error_resilience;
+    }
+
+    fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.output_mode = output_mode;
+    }
+
     fn next_event(&mut self) -> Option<DecoderEvent<B::Handle>> {
         self.query_next_event(|decoder, sps| {
             // Apply the SPS settings to the decoder so we don't enter the AwaitingFormat state
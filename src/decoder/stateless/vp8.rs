@@ -2,7 +2,6 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-#[cfg(test)]
 mod dummy;
 #[cfg(feature = "vaapi")]
 mod vaapi;
@@ -27,8 +26,10 @@ use crate::decoder::stateless::StatelessDecoderBackend;
 use crate::decoder::stateless::StatelessVideoDecoder;
 use crate::decoder::stateless::TryFormat;
 use crate::decoder::BlockingMode;
+use crate::decoder::DecodeMode;
 use crate::decoder::DecodedHandle;
 use crate::decoder::DecoderEvent;
+use crate::decoder::ErrorResilience;
 use crate::decoder::FramePool;
 use crate::decoder::StreamInfo;
 use crate::Resolution;
@@ -178,6 +179,19 @@ where
 {
     /// Handle a single frame.
     fn handle_frame(&mut self, frame: Frame, timestamp: u64) -> Result<(), DecodeError> {
+        let hdr = &frame.header;
+        // The parser has already updated its state, so the frame can be skipped safely if it
+        // does not update any reference.
+        let is_reference = hdr.refresh_last
+            || hdr.refresh_golden_frame
+            || hdr.refresh_alternate_frame
+            || hdr.copy_buffer_to_golden != 0
+            || hdr.copy_buffer_to_alternate != 0;
+        if !self.should_decode(hdr.key_frame, is_reference) {
+            log::debug!("Skipping frame as per the decode mode");
+            return Ok(());
+        }
+
         if self
             .backend
             .frame_pool(PoolLayer::Highest)
@@ -191,6 +205,10 @@ where
 
         let show_frame = frame.header.show_frame;
 
+        if frame.header.key_frame {
+            self.recovery_point();
+        }
+
         let decoded_handle = self.backend.submit_picture(
             &frame.header,
             &self.codec.last_picture,
@@ -201,6 +219,7 @@ where
             self.codec.parser.mb_lf_adjust(),
             timestamp,
         )?;
+        self.mark_if_corrupted(&decoded_handle);
 
         if self.blocking_mode == BlockingMode::Blocking {
             decoded_handle.sync()?;
@@ -236,7 +255,15 @@ where
     type FramePool = B::FramePool;
 
     fn decode(&mut self, timestamp: u64, bitstream: &[u8]) -> Result<usize, DecodeError> {
-        let frame = self.codec.parser.parse_frame(bitstream)?;
+        let frame = match self.codec.parser.parse_frame(bitstream) {
+            Ok(frame) => frame,
+            // When concealing errors, a frame that cannot be parsed is skipped.
+            Err(e) if matches!(self.decoding_state, DecodingState::Decoding) => {
+                self.conceal_error(Err(e.into()))?;
+                return Ok(bitstream.len());
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         if frame.header.key_frame {
             if self.negotiation_possible(&frame) {
@@ -255,7 +282,9 @@ where
             DecodingState::AwaitingFormat(_) => Err(DecodeError::CheckEvents),
             DecodingState::Decoding => {
                 let len = frame.header.frame_len();
-                self.handle_frame(frame, timestamp)?;
+                // When concealing errors, a frame that cannot be decoded is skipped.
+                let res = self.handle_frame(frame, timestamp);
+                self.conceal_error(res)?;
                 Ok(len)
             }
         }
@@ -271,6 +300,14 @@ where
         Ok(())
     }
 
+    fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    fn set_error_resilience(&mut self, error_resilience: ErrorResilience) {
+        self.error_resilience = error_resilience;
+    }
+
     fn next_event(&mut self) -> Option<DecoderEvent<B::Handle>> {
         self.query_next_event(|decoder, hdr| {
             decoder.coded_resolution = Resolution {
@@ -295,11 +332,15 @@ where
 
 #[cfg(test)]
 pub mod tests {
+    use crate::decoder::stateless::tests::decode_units;
     use crate::decoder::stateless::tests::test_decode_stream;
     use crate::decoder::stateless::tests::TestStream;
     use crate::decoder::stateless::vp8::Vp8;
     use crate::decoder::stateless::StatelessDecoder;
+    use crate::decoder::stateless::StatelessVideoDecoder;
     use crate::decoder::BlockingMode;
+    use crate::decoder::DecodeMode;
+    use crate::decoder::ErrorResilience;
     use crate::utils::simple_playback_loop;
     use crate::utils::simple_playback_loop_owned_frames;
     use crate::utils::IvfIterator;
@@ -342,4 +383,56 @@ pub mod tests {
     fn test_25fps_nonblock() {
         test_decoder_dummy(&DECODE_TEST_25FPS, BlockingMode::NonBlocking);
     }
+
+    #[test]
+    fn test_conceal_errors() {
+        let frames: Vec<_> = IvfIterator::new(DECODE_TEST_25FPS.stream).collect();
+        let garbage = [0u8; 16];
+
+        let mut decoder = StatelessDecoder::<Vp8, _>::new_dummy(BlockingMode::Blocking).unwrap();
+        decode_units(&mut decoder, [frames[0]], false).unwrap();
+        assert!(decode_units(&mut decoder, [&garbage[..]], false).is_err());
+
+        let mut decoder = StatelessDecoder::<Vp8, _>::new_dummy(BlockingMode::Blocking).unwrap();
+        decoder.set_error_resilience(ErrorResilience::Conceal);
+        assert_eq!(
+            decode_units(&mut decoder, [frames[0]], false).unwrap().0,
+            vec![false]
+        );
+        assert_eq!(
+            decode_units(&mut decoder, [frames[1]], false).unwrap().0,
+            vec![false]
+        );
+        // The corrupted frame is skipped, and the frames depending on it marked as corrupted.
+        assert_eq!(
+            decode_units(&mut decoder, [&garbage[..]], false).unwrap().0,
+            vec![]
+        );
+        assert_eq!(
+            decode_units(&mut decoder, [frames[2]], false).unwrap().0,
+            vec![true]
+        );
+        assert_eq!(
+            decode_units(&mut decoder, [frames[3]], false).unwrap().0,
+            vec![true]
+        );
+        // Key frames are recovery points.
+        assert_eq!(
+            decode_units(&mut decoder, [frames[0]], false).unwrap().0,
+            vec![false]
+        );
+    }
+
+    #[test]
+    fn test_decode_mode() {
+        let mut decoder = StatelessDecoder::<Vp8, _>::new_dummy(BlockingMode::Blocking).unwrap();
+        decoder.set_decode_mode(DecodeMode::KeyFramesOnly);
+
+        let mut num_frames = 0;
+        for frame in IvfIterator::new(DECODE_TEST_25FPS.stream) {
+            num_frames += decode_units(&mut decoder, [frame], false).unwrap().0.len();
+        }
+        // Only the 2 key frames of the stream are decoded.
+        assert_eq!(num_frames, 2);
+    }
 }
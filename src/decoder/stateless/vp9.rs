@@ -2,7 +2,6 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-#[cfg(test)]
 mod dummy;
 #[cfg(feature = "vaapi")]
 mod vaapi;
@@ -15,12 +14,14 @@ use log::debug;
 
 use crate::codec::vp9::parser::BitDepth;
 use crate::codec::vp9::parser::Frame;
+use crate::codec::vp9::parser::FrameType;
 use crate::codec::vp9::parser::Header;
 use crate::codec::vp9::parser::Parser;
 use crate::codec::vp9::parser::Profile;
 use crate::codec::vp9::parser::Segmentation;
 use crate::codec::vp9::parser::MAX_SEGMENTS;
 use crate::codec::vp9::parser::NUM_REF_FRAMES;
+use crate::decoder::stateless::substitute_missing_references;
 use crate::decoder::stateless::DecodeError;
 use crate::decoder::stateless::DecodingState;
 use crate::decoder::stateless::PoolLayer;
@@ -31,8 +32,10 @@ use crate::decoder::stateless::StatelessDecoderBackend;
 use crate::decoder::stateless::StatelessVideoDecoder;
 use crate::decoder::stateless::TryFormat;
 use crate::decoder::BlockingMode;
+use crate::decoder::DecodeMode;
 use crate::decoder::DecodedHandle;
 use crate::decoder::DecoderEvent;
+use crate::decoder::ErrorResilience;
 use crate::decoder::FramePool;
 use crate::decoder::StreamInfo;
 use crate::Resolution;
@@ -153,6 +156,12 @@ where
     /// Handle a single frame.
     fn handle_frame(&mut self, frame: &Frame, timestamp: u64) -> Result<(), DecodeError> {
         let decoded_handle = if frame.header.show_existing_frame {
+            // The frame to show may not have been decoded.
+            if self.decode_mode == DecodeMode::KeyFramesOnly {
+                debug!("Skipping frame as per the decode mode");
+                return Ok(());
+            }
+
             // Frame to be shown. Because the spec mandates that frame_to_show_map_idx references a
             // valid entry in the DPB, an non-existing index means that the stream is invalid.
             let idx = usize::from(frame.header.frame_to_show_map_idx);
@@ -172,7 +181,36 @@ where
             // Otherwise, we must actually arrange to decode a frame
             let refresh_frame_flags = frame.header.refresh_frame_flags;
 
+            // The segmentation parameters persist across frames, so they are updated even for
+            // frames that are not decoded.
             Segmentation::update_segmentation(&mut self.codec.segmentation, &frame.header)?;
+
+            let is_key_frame = frame.header.frame_type == FrameType::KeyFrame;
+            // Frames refreshing a probability context are needed by the following frames that
+            // load it, even if they do not refresh any reference frame.
+            let is_reference = refresh_frame_flags != 0 || frame.header.refresh_frame_context;
+            if !self.should_decode(is_key_frame, is_reference) {
+                debug!("Skipping frame as per the decode mode");
+                return Ok(());
+            }
+
+            if is_key_frame {
+                self.recovery_point();
+            } else if !frame.header.intra_only
+                && self.conceal_errors()
+                && substitute_missing_references(
+                    &mut self.codec.reference_frames,
+                    frame
+                        .header
+                        .ref_frame_idx
+                        .iter()
+                        .map(|&idx| usize::from(idx)),
+                    timestamp,
+                )
+            {
+                self.corrupted = true;
+            }
+
             let decoded_handle = self.backend.submit_picture(
                 &frame.header,
                 &self.codec.reference_frames,
@@ -180,6 +218,7 @@ where
                 timestamp,
                 &self.codec.segmentation,
             )?;
+            self.mark_if_corrupted(&decoded_handle);
 
             if self.blocking_mode == BlockingMode::Blocking {
                 decoded_handle.sync()?;
@@ -225,7 +264,15 @@ where
     type FramePool = B::FramePool;
 
     fn decode(&mut self, timestamp: u64, bitstream: &[u8]) -> Result<usize, DecodeError> {
-        let frames = self.codec.parser.parse_chunk(bitstream)?;
+        let frames = match self.codec.parser.parse_chunk(bitstream) {
+            Ok(frames) => frames,
+            // When concealing errors, a chunk that cannot be parsed is skipped.
+            Err(e) if matches!(self.decoding_state, DecodingState::Decoding) => {
+                self.conceal_error(Err(e.into()))?;
+                return Ok(bitstream.len());
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         let num_free_frames = self
             .backend
@@ -278,7 +325,11 @@ where
                 DecodingState::AwaitingStreamInfo | DecodingState::Reset => (),
                 // Ask the client to confirm the format before we can process this.
                 DecodingState::AwaitingFormat(_) => return Err(DecodeError::CheckEvents),
-                DecodingState::Decoding => self.handle_frame(&frame, timestamp)?,
+                DecodingState::Decoding => {
+                    // When concealing errors, a frame that cannot be decoded is skipped.
+                    let res = self.handle_frame(&frame, timestamp);
+                    self.conceal_error(res)?
+                }
             }
         }
 
@@ -293,6 +344,14 @@ where
         Ok(())
     }
 
+    fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    fn set_error_resilience(&mut self, error_resilience: ErrorResilience) {
+        self.error_resilience = error_resilience;
+    }
+
     fn next_event(&mut self) -> Option<DecoderEvent<B::Handle>> {
         self.query_next_event(|decoder, hdr| {
             decoder.codec.negotiation_info = hdr.into();
@@ -314,11 +373,14 @@ where
 
 #[cfg(test)]
 pub mod tests {
+    use crate::decoder::stateless::tests::decode_units;
     use crate::decoder::stateless::tests::test_decode_stream;
     use crate::decoder::stateless::tests::TestStream;
     use crate::decoder::stateless::vp9::Vp9;
     use crate::decoder::stateless::StatelessDecoder;
+    use crate::decoder::stateless::StatelessVideoDecoder;
     use crate::decoder::BlockingMode;
+    use crate::decoder::DecodeMode;
     use crate::utils::simple_playback_loop;
     use crate::utils::simple_playback_loop_owned_frames;
     use crate::utils::IvfIterator;
@@ -362,6 +424,43 @@ pub mod tests {
         test_decoder_dummy(&DECODE_TEST_25FPS, BlockingMode::NonBlocking);
     }
 
+    #[test]
+    fn test_decode_mode() {
+        let frames: Vec<_> = IvfIterator::new(DECODE_TEST_25FPS.stream).collect();
+        let count_frames = |frames: &[&[u8]], mode| {
+            let mut decoder =
+                StatelessDecoder::<Vp9, _>::new_dummy(BlockingMode::Blocking).unwrap();
+            decoder.set_decode_mode(mode);
+            let (frames, _) = decode_units(&mut decoder, frames.iter().copied(), true).unwrap();
+            frames.len()
+        };
+
+        // Only the 2 key frames of the stream are decoded.
+        assert_eq!(count_frames(&frames, DecodeMode::KeyFramesOnly), 2);
+        // All the frames of the stream refresh a reference frame.
+        assert_eq!(count_frames(&frames, DecodeMode::SkipNonReference), 250);
+
+        // Clear the refresh_frame_flags of the sixth frame. It still refreshes the probability
+        // context used by the following frames, so it is decoded.
+        let mut frame = frames[5].to_vec();
+        frame[2] &= !0x40;
+        let mut patched_frames = frames.clone();
+        patched_frames[5] = &frame;
+        assert_eq!(
+            count_frames(&patched_frames, DecodeMode::SkipNonReference),
+            250
+        );
+
+        // Once it does not refresh its probability context either, it is skipped.
+        let mut frame = frame.clone();
+        frame[4] &= !0x08;
+        patched_frames[5] = &frame;
+        assert_eq!(
+            count_frames(&patched_frames, DecodeMode::SkipNonReference),
+            249
+        );
+    }
+
     // Remuxed from the original matroska source in libvpx using ffmpeg:
     // ffmpeg -i vp90-2-10-show-existing-frame.webm/vp90-2-10-show-existing-frame.webm -c:v copy /tmp/vp90-2-10-show-existing-frame.vp9.ivf
     pub const DECODE_TEST_25FPS_SHOW_EXISTING_FRAME: TestStream = TestStream {
@@ -0,0 +1,379 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Decoder running on a thread of its own.
+//!
+//! Decoders and their handles are not [`Send`]: the decoding state keeps its pictures in
+//! `Rc<RefCell<...>>`, and the VA-API handles wrap libva objects sharing their display through
+//! `Rc`. [`ThreadedDecoder`] creates the decoder on a worker thread which owns it, along with all the
+//! frames it outputs, for its whole lifetime. The decoder and the [`ThreadedHandle`]s of its frames
+//! only send requests to that thread, so they can be moved to other threads, e.g. to hand decoded
+//! frames to a render thread.
+
+use std::collections::HashMap;
+use std::os::fd::AsFd;
+use std::os::fd::BorrowedFd;
+use std::os::fd::OwnedFd;
+use std::sync::mpsc;
+
+use crate::decoder::stateless::DecodeError;
+use crate::decoder::stateless::StatelessVideoDecoder;
+use crate::decoder::DecodedHandle;
+use crate::decoder::DecoderEvent;
+use crate::decoder::DecoderFormatNegotiator;
+use crate::decoder::StreamInfo;
+use crate::Resolution;
+
+type Reply<T> = mpsc::Sender<T>;
+
+/// Requests sent to the worker thread.
+enum Request {
+    Decode(u64, Vec<u8>, Reply<Result<usize, DecodeError>>),
+    Flush(Reply<Result<(), DecodeError>>),
+    NextEvent(Reply<Option<Result<WorkerEvent, DecodeError>>>),
+    IsReady(u64, Reply<bool>),
+    Sync(u64, Reply<anyhow::Result<()>>),
+    ImageSize(u64, Reply<anyhow::Result<usize>>),
+    Read(u64, Vec<u8>, Reply<anyhow::Result<Vec<u8>>>),
+    /// The client is done with a frame, which is dropped and returns to the pool of the decoder.
+    Release(u64),
+}
+
+/// Properties of a decoded frame, obtained once on the worker thread.
+struct FrameInfo {
+    id: u64,
+    timestamp: u64,
+    coded_resolution: Resolution,
+    display_resolution: Resolution,
+    corrupted: bool,
+}
+
+enum WorkerEvent {
+    FrameReady(FrameInfo),
+    FormatChanged(StreamInfo),
+}
+
+/// Events returned by [`ThreadedDecoder::next_event`].
+pub enum ThreadedDecoderEvent {
+    /// The next frame has been decoded.
+    FrameReady(ThreadedHandle),
+    /// The format of the stream has changed and has been negotiated by the callback passed to
+    /// [`ThreadedDecoder::new`].
+    FormatChanged(StreamInfo),
+}
+
+/// Error returned when the worker thread is not running anymore, e.g. because the decoder failed
+/// to be created.
+fn worker_stopped() -> anyhow::Error {
+    anyhow::anyhow!("the decoder thread has stopped")
+}
+
+/// Sends `request`, built around a reply sender, to the worker thread and waits for its reply.
+fn call<T>(
+    requests: &mpsc::Sender<Request>,
+    request: impl FnOnce(Reply<T>) -> Request,
+) -> anyhow::Result<T> {
+    let (reply, receiver) = mpsc::channel();
+    requests
+        .send(request(reply))
+        .map_err(|_| worker_stopped())?;
+    receiver.recv().map_err(|_| worker_stopped())
+}
+
+/// Decoder owned by a worker thread, which can be moved across threads. See the
+/// [module-level documentation](self).
+pub struct ThreadedDecoder {
+    requests: mpsc::Sender<Request>,
+    /// Duplicate of the poll FD of the decoder.
+    poll_fd: OwnedFd,
+}
+
+impl ThreadedDecoder {
+    /// Spawns the worker thread, creating the decoder on it with `create_decoder`.
+    /// `negotiate_format` is called on the worker thread to negotiate the format of the decoder
+    /// whenever the stream requires it, e.g. by adding frames to its pools.
+    pub fn new<D, C, N>(create_decoder: C, mut negotiate_format: N) -> anyhow::Result<Self>
+    where
+        D: StatelessVideoDecoder + 'static,
+        C: FnOnce() -> anyhow::Result<D> + Send + 'static,
+        N: FnMut(
+                &mut dyn DecoderFormatNegotiator<
+                    Descriptor = <D::Handle as DecodedHandle>::Descriptor,
+                >,
+            ) -> anyhow::Result<()>
+            + Send
+            + 'static,
+    {
+        let (requests, receiver) = mpsc::channel();
+        let (poll_fd_sender, poll_fd_receiver) = mpsc::channel();
+
+        std::thread::Builder::new()
+            .name("cros-codecs decoder".into())
+            .spawn(move || {
+                let decoder = create_decoder().and_then(|decoder| {
+                    let poll_fd = decoder.poll_fd().try_clone_to_owned()?;
+                    Ok((decoder, poll_fd))
+                });
+                let mut decoder = match decoder {
+                    Ok((decoder, poll_fd)) => {
+                        let _ = poll_fd_sender.send(Ok(poll_fd));
+                        decoder
+                    }
+                    Err(e) => {
+                        let _ = poll_fd_sender.send(Err(e));
+                        return;
+                    }
+                };
+
+                let mut frames = HashMap::new();
+                let mut next_id = 0;
+                // Runs until the decoder and all its handles are dropped.
+                while let Ok(request) = receiver.recv() {
+                    match request {
+                        Request::Decode(timestamp, bitstream, reply) => {
+                            let _ = reply.send(decoder.decode(timestamp, &bitstream));
+                        }
+                        Request::Flush(reply) => {
+                            let _ = reply.send(decoder.flush());
+                        }
+                        Request::NextEvent(reply) => {
+                            let event = match decoder.next_event() {
+                                None => None,
+                                Some(DecoderEvent::FrameReady(handle)) => {
+                                    let info = FrameInfo {
+                                        id: next_id,
+                                        timestamp: handle.timestamp(),
+                                        coded_resolution: handle.coded_resolution(),
+                                        display_resolution: handle.display_resolution(),
+                                        corrupted: handle.is_corrupted(),
+                                    };
+                                    frames.insert(next_id, handle);
+                                    next_id += 1;
+                                    Some(Ok(WorkerEvent::FrameReady(info)))
+                                }
+                                Some(DecoderEvent::FormatChanged(mut negotiator)) => {
+                                    Some(match negotiate_format(negotiator.as_mut()) {
+                                        Ok(()) => Ok(WorkerEvent::FormatChanged(
+                                            negotiator.stream_info().clone(),
+                                        )),
+                                        Err(e) => Err(DecodeError::DecoderError(e)),
+                                    })
+                                }
+                            };
+                            let _ = reply.send(event);
+                        }
+                        Request::IsReady(id, reply) => {
+                            let _ = reply.send(frames.get(&id).is_some_and(|h| h.is_ready()));
+                        }
+                        Request::Sync(id, reply) => {
+                            let _ = reply.send(match frames.get(&id) {
+                                Some(handle) => handle.sync(),
+                                None => Err(anyhow::anyhow!("unknown frame {}", id)),
+                            });
+                        }
+                        Request::ImageSize(id, reply) => {
+                            let _ = reply.send(match frames.get(&id) {
+                                Some(handle) => handle
+                                    .dyn_picture()
+                                    .dyn_mappable_handle()
+                                    .map(|mut mappable| mappable.image_size()),
+                                None => Err(anyhow::anyhow!("unknown frame {}", id)),
+                            });
+                        }
+                        Request::Read(id, mut buffer, reply) => {
+                            let _ = reply.send(match frames.get(&id) {
+                                Some(handle) => handle
+                                    .dyn_picture()
+                                    .dyn_mappable_handle()
+                                    .and_then(|mut mappable| mappable.read(&mut buffer))
+                                    .map(|()| buffer),
+                                None => Err(anyhow::anyhow!("unknown frame {}", id)),
+                            });
+                        }
+                        Request::Release(id) => {
+                            frames.remove(&id);
+                        }
+                    }
+                }
+            })?;
+
+        let poll_fd = poll_fd_receiver.recv().map_err(|_| worker_stopped())??;
+
+        Ok(Self { requests, poll_fd })
+    }
+
+    /// Decodes `bitstream`, see [`StatelessVideoDecoder::decode`].
+    pub fn decode(&mut self, timestamp: u64, bitstream: &[u8]) -> Result<usize, DecodeError> {
+        call(&self.requests, |reply| {
+            Request::Decode(timestamp, bitstream.to_vec(), reply)
+        })?
+    }
+
+    /// Flushes the decoder, see [`StatelessVideoDecoder::flush`].
+    pub fn flush(&mut self) -> Result<(), DecodeError> {
+        call(&self.requests, Request::Flush)?
+    }
+
+    /// Returns the next event of the decoder, if any, see [`StatelessVideoDecoder::next_event`].
+    /// Format changes are negotiated on the worker thread before being reported.
+    pub fn next_event(&mut self) -> Option<Result<ThreadedDecoderEvent, DecodeError>> {
+        let event = match call(&self.requests, Request::NextEvent) {
+            Ok(event) => event?,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        Some(event.map(|event| match event {
+            WorkerEvent::FrameReady(info) => ThreadedDecoderEvent::FrameReady(ThreadedHandle {
+                requests: self.requests.clone(),
+                info,
+            }),
+            WorkerEvent::FormatChanged(stream_info) => {
+                ThreadedDecoderEvent::FormatChanged(stream_info)
+            }
+        }))
+    }
+
+    /// Returns a file descriptor that signals `POLLIN` whenever an event is pending on the
+    /// decoder, see [`StatelessVideoDecoder::poll_fd`].
+    pub fn poll_fd(&self) -> BorrowedFd<'_> {
+        self.poll_fd.as_fd()
+    }
+}
+
+/// Handle to a frame decoded by a [`ThreadedDecoder`].
+///
+/// The frame itself stays on the worker thread, and returns to the pool of the decoder once its
+/// handle is dropped.
+pub struct ThreadedHandle {
+    requests: mpsc::Sender<Request>,
+    info: FrameInfo,
+}
+
+impl ThreadedHandle {
+    /// Returns the timestamp of the frame.
+    pub fn timestamp(&self) -> u64 {
+        self.info.timestamp
+    }
+
+    /// Returns the coded resolution at the time the frame was decoded.
+    pub fn coded_resolution(&self) -> Resolution {
+        self.info.coded_resolution
+    }
+
+    /// Returns the display resolution at the time the frame was decoded.
+    pub fn display_resolution(&self) -> Resolution {
+        self.info.display_resolution
+    }
+
+    /// Returns whether the frame may contain artifacts, see [`DecodedHandle::is_corrupted`].
+    pub fn is_corrupted(&self) -> bool {
+        self.info.corrupted
+    }
+
+    /// Returns whether the frame is ready to be presented.
+    pub fn is_ready(&self) -> bool {
+        call(&self.requests, |reply| {
+            Request::IsReady(self.info.id, reply)
+        })
+        .unwrap_or(false)
+    }
+
+    /// Waits until the frame is ready to be presented.
+    pub fn sync(&self) -> anyhow::Result<()> {
+        call(&self.requests, |reply| Request::Sync(self.info.id, reply))?
+    }
+
+    /// Returns the size of the buffer required to read the frame with [`ThreadedHandle::read`].
+    pub fn image_size(&self) -> anyhow::Result<usize> {
+        call(&self.requests, |reply| {
+            Request::ImageSize(self.info.id, reply)
+        })?
+    }
+
+    /// Reads the content of the frame into `buffer`, see [`crate::decoder::MappableHandle::read`].
+    pub fn read(&self, buffer: &mut [u8]) -> anyhow::Result<()> {
+        let data = call(&self.requests, |reply| {
+            Request::Read(self.info.id, vec![0; buffer.len()], reply)
+        })??;
+        buffer.copy_from_slice(&data);
+
+        Ok(())
+    }
+}
+
+impl Drop for ThreadedHandle {
+    fn drop(&mut self) {
+        let _ = self.requests.send(Request::Release(self.info.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::h264::parser::Nalu;
+    use crate::decoder::stateless::h264::tests::DECODE_64X64_PROGRESSIVE_I_P_B_P;
+    use crate::decoder::stateless::h264::H264;
+    use crate::decoder::stateless::DecodeError;
+    use crate::decoder::stateless::StatelessDecoder;
+    use crate::decoder::threaded::ThreadedDecoder;
+    use crate::decoder::threaded::ThreadedDecoderEvent;
+    use crate::decoder::BlockingMode;
+    use crate::utils::NalIterator;
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn decode_on_worker_thread() {
+        let mut decoder = ThreadedDecoder::new(
+            || {
+                Ok(StatelessDecoder::<H264, _>::new_dummy(
+                    BlockingMode::Blocking,
+                )?)
+            },
+            |_| Ok(()),
+        )
+        .unwrap();
+        assert_send(&decoder);
+
+        let mut frames = vec![];
+        let mut num_format_changes = 0;
+        let mut process_events = |decoder: &mut ThreadedDecoder| {
+            while let Some(event) = decoder.next_event() {
+                match event.unwrap() {
+                    ThreadedDecoderEvent::FrameReady(frame) => frames.push(frame),
+                    ThreadedDecoderEvent::FormatChanged(_) => num_format_changes += 1,
+                }
+            }
+        };
+
+        for nalu in NalIterator::<Nalu>::new(DECODE_64X64_PROGRESSIVE_I_P_B_P.stream) {
+            loop {
+                match decoder.decode(0, nalu.as_ref()) {
+                    Err(DecodeError::CheckEvents) => process_events(&mut decoder),
+                    res => {
+                        res.unwrap();
+                        break;
+                    }
+                }
+            }
+        }
+        decoder.flush().unwrap();
+        process_events(&mut decoder);
+
+        assert_eq!(num_format_changes, 1);
+        assert_eq!(frames.len(), 3);
+        assert_send(&frames[0]);
+
+        // The frames can be used and released from another thread, even once the decoder is gone.
+        drop(decoder);
+        std::thread::spawn(move || {
+            for frame in frames {
+                frame.sync().unwrap();
+                let mut buffer = vec![0; frame.image_size().unwrap()];
+                frame.read(&mut buffer).unwrap();
+            }
+        })
+        .join()
+        .unwrap();
+    }
+}
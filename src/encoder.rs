@@ -2,6 +2,9 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::os::fd::BorrowedFd;
+
+use anyhow::anyhow;
 use thiserror::Error;
 
 pub mod av1;
@@ -17,7 +20,10 @@ use crate::codec::av1::synthesizer::SynthesizerError as AV1SynthesizerError;
 use crate::codec::h264::synthesizer::SynthesizerError as H264SynthesizerError;
 use crate::encoder::stateful::StatefulBackendError;
 use crate::encoder::stateless::StatelessBackendError;
+use crate::Fourcc;
 use crate::FrameLayout;
+use crate::PlaneLayout;
+use crate::Resolution;
 
 /// Specifies the encoder operation
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -157,6 +163,15 @@ pub trait VideoEncoder<Handle> {
     /// The call may also trigger a further processing aside of returning output. Therefore it
     /// *recommended* that this function is called frequently.
     fn poll(&mut self) -> EncodeResult<Option<CodedBitstreamBuffer>>;
+
+    /// Returns a file descriptor that signals `POLLIN` whenever [`Self::poll`] may return a new
+    /// coded buffer.
+    ///
+    /// The default implementation is for encoders that complete their frames synchronously, and
+    /// returns `None`.
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
 }
 
 pub fn simple_encode_loop<H>(
@@ -179,16 +194,142 @@ pub fn simple_encode_loop<H>(
     Ok(())
 }
 
+/// Callback writing a frame, called with its layout and the memory of each of its buffers.
+pub type WriteFrameFn<'a> = dyn FnMut(&FrameLayout, &mut [&mut [u8]]) -> anyhow::Result<()> + 'a;
+
+/// Frames whose memory can be written from the CPU, so raw frames can be imported into a
+/// [`VideoEncoder`] without knowing the layout its backend uses.
+pub trait WritableFrame {
+    /// Maps the frame for writing and calls `f` with its layout and the memory of each of its
+    /// buffers, indexed by [`PlaneLayout::buffer_index`]. What `f` writes is part of the frame once
+    /// this method returns.
+    fn write_with(&mut self, f: &mut WriteFrameFn) -> anyhow::Result<()>;
+
+    /// Copies the `size` frame of `format` stored without padding in `src` into this frame. See
+    /// [`copy_packed_frame`] for the supported formats.
+    fn copy_from_packed(
+        &mut self,
+        format: Fourcc,
+        size: Resolution,
+        src: &[u8],
+    ) -> anyhow::Result<()> {
+        self.write_with(&mut |layout, buffers| {
+            copy_packed_frame(format, size, src, layout, buffers)
+        })
+    }
+}
+
+/// Returns the line `row` of `plane`, `len` bytes long, from `buffers`.
+fn plane_line<'a>(
+    buffers: &'a mut [&mut [u8]],
+    plane: &PlaneLayout,
+    row: usize,
+    len: usize,
+) -> anyhow::Result<&'a mut [u8]> {
+    let start = plane.offset + row * plane.stride;
+
+    buffers
+        .get_mut(plane.buffer_index)
+        .and_then(|buffer| buffer.get_mut(start..start + len))
+        .ok_or_else(|| anyhow!("line {} of plane {:?} is outside of the frame", row, plane))
+}
+
+/// Copies the `size` frame of `format` stored without padding in `src`, into the frame memory
+/// `buffers` laid out according to `layout`.
+///
+/// `I420` and `NV12` frames can be copied into `NV12` or `NM12` frames, and `P010` frames into
+/// `P010` frames. Frames with a single plane in their layout are assumed to have their chroma
+/// plane right after the luma one, with the same stride.
+pub fn copy_packed_frame(
+    format: Fourcc,
+    size: Resolution,
+    src: &[u8],
+    layout: &FrameLayout,
+    buffers: &mut [&mut [u8]],
+) -> anyhow::Result<()> {
+    let dst_format = layout.format.0;
+    let sample_size = match (&format.0.to_le_bytes(), &dst_format.0.to_le_bytes()) {
+        (b"I420" | b"NV12", b"NV12" | b"NM12") => 1,
+        (b"P010", b"P010") => 2,
+        _ => {
+            return Err(anyhow!(
+                "cannot copy a {} frame into a {} frame",
+                format,
+                dst_format
+            ))
+        }
+    };
+
+    if !layout.size.can_contain(size) {
+        return Err(anyhow!(
+            "{:?} frame cannot hold a {:?} frame",
+            layout.size,
+            size
+        ));
+    }
+
+    let width = size.width as usize;
+    let height = size.height as usize;
+    let y_line_size = width * sample_size;
+    let uv_line_size = width.div_ceil(2) * 2 * sample_size;
+    let uv_height = height.div_ceil(2);
+    let y_size = y_line_size * height;
+    if src.len() < y_size + uv_line_size * uv_height {
+        return Err(anyhow!(
+            "source buffer of {} bytes is too small for a {:?} frame",
+            src.len(),
+            size
+        ));
+    }
+
+    let (y_plane, uv_plane) = match layout.planes.as_slice() {
+        [y_plane] => (
+            y_plane.clone(),
+            PlaneLayout {
+                offset: y_plane.offset + y_plane.stride * layout.size.height as usize,
+                ..y_plane.clone()
+            },
+        ),
+        [y_plane, uv_plane, ..] => (y_plane.clone(), uv_plane.clone()),
+        [] => return Err(anyhow!("frame layout has no planes")),
+    };
+
+    for (row, src_line) in src[..y_size].chunks(y_line_size).enumerate() {
+        plane_line(buffers, &y_plane, row, y_line_size)?.copy_from_slice(src_line);
+    }
+
+    let src_uv = &src[y_size..];
+    for row in 0..uv_height {
+        let dst_line = plane_line(buffers, &uv_plane, row, uv_line_size)?;
+
+        if format == Fourcc::from(b"I420") {
+            // Interleave the U and V planes.
+            let u_width = width.div_ceil(2);
+            let u_line = &src_uv[row * u_width..][..u_width];
+            let v_line = &src_uv[(uv_height + row) * u_width..][..u_width];
+            for (uv, (u, v)) in dst_line.chunks_exact_mut(2).zip(u_line.iter().zip(v_line)) {
+                uv[0] = *u;
+                uv[1] = *v;
+            }
+        } else {
+            dst_line.copy_from_slice(&src_uv[row * uv_line_size..][..uv_line_size]);
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
+    use crate::encoder::copy_packed_frame;
     #[cfg(feature = "v4l2")]
     use crate::encoder::FrameMetadata;
-    #[cfg(feature = "v4l2")]
+    use crate::encoder::WritableFrame;
     use crate::utils::UserPtrFrame;
-    #[cfg(feature = "v4l2")]
     use crate::Fourcc;
-    #[cfg(feature = "v4l2")]
     use crate::FrameLayout;
+    use crate::PlaneLayout;
+    use crate::Resolution;
 
     pub fn get_test_frame_t(ts: u64, max_ts: u64) -> f32 {
         2.0 * std::f32::consts::PI * (ts as f32) / (max_ts as f32)
@@ -391,4 +532,158 @@ pub(crate) mod tests {
             (meta, frame)
         })
     }
+
+    #[test]
+    fn copy_i420_into_nv12() {
+        // 3x3 frame with 2x2 chroma planes.
+        let src = [
+            0, 1, 2, 3, 4, 5, 6, 7, 8, // Y
+            10, 11, 12, 13, // U
+            20, 21, 22, 23, // V
+        ];
+        let layout = FrameLayout {
+            format: (Fourcc::from(b"NV12"), 0),
+            size: Resolution::from((4, 4)),
+            planes: vec![PlaneLayout {
+                buffer_index: 0,
+                offset: 0,
+                stride: 8,
+            }],
+        };
+        let mut buffer = vec![0xff; 8 * 6];
+
+        copy_packed_frame(
+            Fourcc::from(b"I420"),
+            Resolution::from((3, 3)),
+            &src,
+            &layout,
+            &mut [&mut buffer],
+        )
+        .unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            0, 1, 2, 0xff, 0xff, 0xff, 0xff, 0xff,
+            3, 4, 5, 0xff, 0xff, 0xff, 0xff, 0xff,
+            6, 7, 8, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            10, 20, 11, 21, 0xff, 0xff, 0xff, 0xff,
+            12, 22, 13, 23, 0xff, 0xff, 0xff, 0xff,
+        ];
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn copy_p010_into_p010() {
+        let src = (0..(2 * 2 + 2) * 2).collect::<Vec<u8>>();
+        let layout = FrameLayout {
+            format: (Fourcc::from(b"P010"), 0),
+            size: Resolution::from((2, 2)),
+            planes: vec![
+                PlaneLayout {
+                    buffer_index: 0,
+                    offset: 0,
+                    stride: 6,
+                },
+                PlaneLayout {
+                    buffer_index: 1,
+                    offset: 2,
+                    stride: 4,
+                },
+            ],
+        };
+        let mut y_buffer = vec![0xff; 12];
+        let mut uv_buffer = vec![0xff; 6];
+
+        copy_packed_frame(
+            Fourcc::from(b"P010"),
+            Resolution::from((2, 2)),
+            &src,
+            &layout,
+            &mut [&mut y_buffer, &mut uv_buffer],
+        )
+        .unwrap();
+
+        assert_eq!(y_buffer, [0, 1, 2, 3, 0xff, 0xff, 4, 5, 6, 7, 0xff, 0xff]);
+        assert_eq!(uv_buffer, [0xff, 0xff, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn copy_packed_frame_errors() {
+        let layout = FrameLayout {
+            format: (Fourcc::from(b"NV12"), 0),
+            size: Resolution::from((2, 2)),
+            planes: vec![PlaneLayout {
+                buffer_index: 0,
+                offset: 0,
+                stride: 2,
+            }],
+        };
+        let src = [0; 6];
+        let mut buffer = [0; 6];
+
+        // Unsupported conversion.
+        assert!(copy_packed_frame(
+            Fourcc::from(b"P010"),
+            Resolution::from((2, 2)),
+            &src,
+            &layout,
+            &mut [&mut buffer],
+        )
+        .is_err());
+        // Source frame larger than the destination.
+        assert!(copy_packed_frame(
+            Fourcc::from(b"NV12"),
+            Resolution::from((4, 2)),
+            &src,
+            &layout,
+            &mut [&mut buffer],
+        )
+        .is_err());
+        // Source buffer too small.
+        assert!(copy_packed_frame(
+            Fourcc::from(b"NV12"),
+            Resolution::from((2, 2)),
+            &src[..5],
+            &layout,
+            &mut [&mut buffer],
+        )
+        .is_err());
+        // Destination buffer too small.
+        assert!(copy_packed_frame(
+            Fourcc::from(b"NV12"),
+            Resolution::from((2, 2)),
+            &src,
+            &layout,
+            &mut [&mut buffer[..5]],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn userptr_frame_copy_from_packed() {
+        let size = Resolution::from((20, 10));
+        let src = (0..20 * 10 * 3 / 2).map(|i| i as u8).collect::<Vec<_>>();
+        let mut frame = UserPtrFrame::new_nv12(size);
+
+        frame
+            .copy_from_packed(Fourcc::from(b"NV12"), size, &src)
+            .unwrap();
+
+        frame
+            .write_with(&mut |layout, buffers| {
+                let y_plane = &layout.planes[0];
+                let uv_plane = &layout.planes[1];
+                for row in 0..10 {
+                    let line = &buffers[0][y_plane.offset + row * y_plane.stride..][..20];
+                    assert_eq!(line, &src[row * 20..][..20]);
+                }
+                for row in 0..5 {
+                    let line = &buffers[0][uv_plane.offset + row * uv_plane.stride..][..20];
+                    assert_eq!(line, &src[200 + row * 20..][..20]);
+                }
+                Ok(())
+            })
+            .unwrap();
+    }
 }
@@ -4,6 +4,7 @@
 
 use std::collections::BTreeSet;
 use std::collections::VecDeque;
+use std::os::fd::BorrowedFd;
 
 use thiserror::Error;
 
@@ -82,6 +83,15 @@ pub trait StatefulVideoEncoderBackend<Handle> {
     ///
     /// [`consume_request`]: StatefulVideoEncoderBackend::consume_request
     fn poll(&mut self) -> StatefulBackendResult<Option<BackendOutput>>;
+
+    /// Returns a file descriptor that signals `POLLIN` whenever the processing of a
+    /// [`BackendRequest`] is finished, see [`VideoEncoder::poll_fd`].
+    ///
+    /// The default implementation is for backends without such a file descriptor, and returns
+    /// `None`.
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
 }
 
 pub struct StatefulEncoder<Handle, Backend>
@@ -265,4 +275,8 @@ where
 
         Ok(())
     }
+
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.backend.poll_fd()
+    }
 }
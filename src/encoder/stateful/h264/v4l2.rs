@@ -67,11 +67,18 @@ impl From<Profile> for VideoH264Profile {
     fn from(value: Profile) -> Self {
         match value {
             Profile::Baseline => Self::Baseline,
+            Profile::ConstrainedBaseline => Self::ConstrainedBaseline,
             Profile::Main => Self::Main,
             Profile::Extended => Self::Extended,
             Profile::High => Self::High,
+            Profile::ConstrainedHigh => Self::ConstrainedHigh,
             Profile::High10 => Self::High10,
+            Profile::High10Intra => Self::High10Intra,
             Profile::High422P => Self::High422,
+            Profile::High422Intra => Self::High422Intra,
+            Profile::High444P => Self::High444Predictive,
+            Profile::High444Intra => Self::High444Intra,
+            Profile::Cavlc444Intra => Self::Cavlc444Intra,
         }
     }
 }
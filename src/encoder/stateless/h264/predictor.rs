@@ -81,7 +81,11 @@ impl<Picture, Reference> LowDelayH264<Picture, Reference> {
         // H.264 Table 6-1
         sps = match config.profile {
             // 4:2:2 subsampling
-            Profile::High422P => sps.chroma_format_idc(2),
+            Profile::High422P | Profile::High422Intra => sps.chroma_format_idc(2),
+            // 4:4:4 subsampling
+            Profile::High444P | Profile::High444Intra | Profile::Cavlc444Intra => {
+                sps.chroma_format_idc(3)
+            }
             // 4:2:0 subsampling
             _ => sps.chroma_format_idc(1),
         };
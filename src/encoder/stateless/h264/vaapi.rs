@@ -455,9 +455,11 @@ where
         blocking_mode: BlockingMode,
     ) -> EncodeResult<Self> {
         let va_profile = match config.profile {
-            Profile::Baseline => VAProfile::VAProfileH264ConstrainedBaseline,
+            Profile::Baseline | Profile::ConstrainedBaseline => {
+                VAProfile::VAProfileH264ConstrainedBaseline
+            }
             Profile::Main => VAProfile::VAProfileH264Main,
-            Profile::High => VAProfile::VAProfileH264High,
+            Profile::High | Profile::ConstrainedHigh => VAProfile::VAProfileH264High,
             _ => return Err(StatelessBackendError::UnsupportedProfile.into()),
         };
 
@@ -8,20 +8,33 @@
 //! The [codec] module contains tools to parse encoded video streams like H.264 or VP9 and extract
 //! the information useful in order to perform e.g. hardware-accelerated decoding.
 //!
+//! The [container] module contains minimal readers and writers for the container formats encoded
+//! video streams are usually stored in, like MP4.
+//!
 //! The [backend] module contains common backend code. A backend is a provider of some way to
 //! decode or encode a particular codec, like VAAPI.
 //!
+//! The [convert] module contains CPU helpers to convert decoded frames between formats, to RGBA,
+//! and to scale them.
+//!
 //! The [decoder] module contains decoders that can turn an encoded video stream into a sequence of
 //! decoded frames using the hardware acceleration available on the host.
 //!
 //! The [encoder] module contains encoder that can turn a picture sequence into a compressed
 //! sequence of decodable encoded packets using the hardware acceleration available on the host.
 //!
+//! The `asynchronous` module, enabled by the `async` feature, contains wrappers exposing decoders
+//! and encoders to async code independently of the runtime.
+//!
 //! The [utils] module contains some useful code that is shared between different parts of this
 //! crate and didn't fit any of the modules above.
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
 pub mod backend;
 pub mod codec;
+pub mod container;
+pub mod convert;
 pub mod decoder;
 pub mod encoder;
 pub mod utils;
@@ -35,6 +48,9 @@ pub use libva;
 #[cfg(feature = "v4l2")]
 pub use v4l2r;
 
+use crate::codec::av1::film_grain::FilmGrain;
+use crate::codec::av1::film_grain::Plane;
+
 /// Rounding modes for `Resolution`
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ResolutionRoundMode {
@@ -140,6 +156,31 @@ impl std::fmt::Debug for Fourcc {
     }
 }
 
+/// Coded formats, i.e. the codecs supported by this crate.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum EncodedFormat {
+    H264,
+    H265,
+    VP8,
+    VP9,
+    AV1,
+}
+
+impl FromStr for EncodedFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "h264" | "H264" => Ok(EncodedFormat::H264),
+            "h265" | "H265" => Ok(EncodedFormat::H265),
+            "vp8" | "VP8" => Ok(EncodedFormat::VP8),
+            "vp9" | "VP9" => Ok(EncodedFormat::VP9),
+            "av1" | "AV1" => Ok(EncodedFormat::AV1),
+            _ => Err("unrecognized input format. Valid values: h264, h265, vp8, vp9, av1"),
+        }
+    }
+}
+
 /// Formats that buffers can be mapped into for the CPU to read.
 ///
 /// The conventions here largely follow these of libyuv.
@@ -165,6 +206,31 @@ pub enum DecodedFormat {
     I410,
     /// Y, U and V planes, 4:4:4 sampling, 16 bits per sample, LE. Only the 12 LSBs are used.
     I412,
+    /// One Y and one interleaved UV plane, 4:2:0 sampling, 16 bits per sample, LE. Only the 10
+    /// MSBs are used.
+    P010,
+    /// One Y and one interleaved UV plane, 4:2:0 sampling, 16 bits per sample, LE. Only the 12
+    /// MSBs are used.
+    P012,
+    /// One Y and one interleaved UV plane, 4:2:0 sampling, 16 bits per sample, LE.
+    P016,
+    /// Single plane of interleaved Y0, U, Y1 and V samples, 4:2:2 sampling, 8 bits per sample.
+    YUY2,
+    /// Single plane of interleaved Y0, U, Y1 and V samples, 4:2:2 sampling, 16 bits per sample,
+    /// LE. Only the 10 MSBs are used.
+    Y210,
+    /// Single plane of 32-bit LE words, 4:4:4 sampling, with U, Y, V and alpha in bits 0-9, 10-19,
+    /// 20-29 and 30-31 respectively.
+    Y410,
+    /// Single plane of V, U, Y and alpha samples, 4:4:4 sampling, 8 bits per sample.
+    AYUV,
+    /// Single plane of B, G, R and alpha samples, 8 bits per sample.
+    ARGB,
+    /// Single plane of B, G and R samples followed by an unused byte, 8 bits per sample.
+    XRGB,
+    /// Single plane of 32-bit LE words with R, G, B and alpha in bits 0-9, 10-19, 20-29 and 30-31
+    /// respectively.
+    AB30,
 }
 
 impl FromStr for DecodedFormat {
@@ -182,8 +248,18 @@ impl FromStr for DecodedFormat {
             "i212" | "I212" => Ok(DecodedFormat::I212),
             "i410" | "I410" => Ok(DecodedFormat::I410),
             "i412" | "I412" => Ok(DecodedFormat::I412),
+            "p010" | "P010" => Ok(DecodedFormat::P010),
+            "p012" | "P012" => Ok(DecodedFormat::P012),
+            "p016" | "P016" => Ok(DecodedFormat::P016),
+            "yuy2" | "YUY2" => Ok(DecodedFormat::YUY2),
+            "y210" | "Y210" => Ok(DecodedFormat::Y210),
+            "y410" | "Y410" => Ok(DecodedFormat::Y410),
+            "ayuv" | "AYUV" => Ok(DecodedFormat::AYUV),
+            "argb" | "ARGB" => Ok(DecodedFormat::ARGB),
+            "xrgb" | "XRGB" => Ok(DecodedFormat::XRGB),
+            "ab30" | "AB30" => Ok(DecodedFormat::AB30),
             _ => {
-                Err("unrecognized output format. Valid values: i420, nv12, i422, i444, i010, i012, i210, i212, i410, i412")
+                Err("unrecognized output format. Valid values: i420, nv12, i422, i444, i010, i012, i210, i212, i410, i412, p010, p012, p016, yuy2, y210, y410, ayuv, argb, xrgb, ab30")
             }
         }
     }
@@ -217,6 +293,28 @@ pub struct FrameLayout {
     pub planes: Vec<PlaneLayout>,
 }
 
+impl FrameLayout {
+    /// Returns the number of memory buffers the planes of the frame are spread across.
+    pub fn num_buffers(&self) -> usize {
+        self.planes
+            .iter()
+            .map(|plane| plane.buffer_index + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the offset at which the data of buffer `buffer_index` starts, i.e. the offset of
+    /// the first plane it contains.
+    pub fn buffer_offset(&self, buffer_index: usize) -> usize {
+        self.planes
+            .iter()
+            .filter(|plane| plane.buffer_index == buffer_index)
+            .map(|plane| plane.offset)
+            .min()
+            .unwrap_or(0)
+    }
+}
+
 /// Build a frame memory descriptor enum that supports multiple descriptor types.
 ///
 /// This is useful for the case where the frames' memory backing is not decided at compile-time.
@@ -291,6 +389,81 @@ pub fn nv12_copy(
     }
 }
 
+/// Copies `src` into `dst` as P010, P012 or P016, removing any extra padding.
+///
+/// This is the same as [`nv12_copy`] with 16-bit samples, which are copied as-is.
+pub fn p01x_copy(
+    src: &[u8],
+    dst: &mut [u8],
+    width: usize,
+    height: usize,
+    strides: [usize; 3],
+    offsets: [usize; 3],
+) {
+    // Copy Y.
+    let y_line_size = width * 2;
+    let src_y_lines = src[offsets[0]..]
+        .chunks(strides[0])
+        .map(|line| &line[..y_line_size]);
+    let dst_y_lines = dst.chunks_mut(y_line_size);
+
+    for (src_line, dst_line) in src_y_lines.zip(dst_y_lines).take(height) {
+        dst_line.copy_from_slice(src_line);
+    }
+
+    let dst_uv_offset = y_line_size * height;
+
+    // Each line of the UV plane contains two 16-bit samples per pair of pixels.
+    let uv_line_size = width.div_ceil(2) * 4;
+    let uv_height = height.div_ceil(2);
+
+    // Copy UV.
+    let src_uv_lines = src[offsets[1]..]
+        .chunks(strides[1])
+        .map(|line| &line[..uv_line_size]);
+    let dst_uv_lines = dst[dst_uv_offset..].chunks_mut(uv_line_size);
+    for (src_line, dst_line) in src_uv_lines.zip(dst_uv_lines).take(uv_height) {
+        dst_line.copy_from_slice(src_line);
+    }
+}
+
+/// Copies the single-plane frame `src` into `dst`, removing any extra padding.
+///
+/// `line_size` is the number of useful bytes in each of the `height` lines of `src`, which start
+/// at `offset` and are `stride` bytes apart. This is suitable for all packed formats, e.g. YUY2 or
+/// ARGB.
+pub fn packed_copy(
+    src: &[u8],
+    dst: &mut [u8],
+    line_size: usize,
+    height: usize,
+    stride: usize,
+    offset: usize,
+) {
+    let src_lines = src[offset..].chunks(stride).map(|line| &line[..line_size]);
+    let dst_lines = dst.chunks_mut(line_size);
+
+    for (src_line, dst_line) in src_lines.zip(dst_lines).take(height) {
+        dst_line.copy_from_slice(src_line);
+    }
+}
+
+/// Returns the number of useful bytes in each line of a `width` pixels wide frame of the
+/// single-plane `format`, or `None` if `format` has several planes.
+pub fn packed_line_size(format: DecodedFormat, width: usize) -> Option<usize> {
+    match format {
+        // Two pixels share one chroma pair, so lines always contain an even number of pixels.
+        DecodedFormat::YUY2 => Some(width.div_ceil(2) * 4),
+        DecodedFormat::Y210 => Some(width.div_ceil(2) * 8),
+        DecodedFormat::Y410
+        | DecodedFormat::AYUV
+        | DecodedFormat::ARGB
+        | DecodedFormat::XRGB
+        | DecodedFormat::AB30 => Some(width * 4),
+        _ => None,
+    }
+}
+
 /// Copies `src` into `dst` as I4xx (YUV tri-planar).
 ///
 /// This function does not change the data layout beyond removing any padding in the source, i.e.
@@ -379,7 +552,176 @@ pub fn decoded_frame_size(format: DecodedFormat, width: usize, height: usize) ->
             u_size + uv_size
         }
         DecodedFormat::I410 | DecodedFormat::I412 => (width * height * 2) * 3,
+        DecodedFormat::P010 | DecodedFormat::P012 | DecodedFormat::P016 => {
+            decoded_frame_size(DecodedFormat::NV12, width, height) * 2
+        }
+        DecodedFormat::YUY2
+        | DecodedFormat::Y210
+        | DecodedFormat::Y410
+        | DecodedFormat::AYUV
+        | DecodedFormat::ARGB
+        | DecodedFormat::XRGB
+        | DecodedFormat::AB30 => packed_line_size(format, width).unwrap() * height,
+    }
+}
+
+/// Applies `film_grain` to the `width`x`height` frame of `format` in `buffer`, laid out without
+/// padding as returned by [`decoder::MappableHandle::read`].
+///
+/// This is meant for frames whose [`decoder::DecodedHandle::pending_film_grain`] is set, i.e.
+/// that the backend has output without the film grain signaled by the stream.
+pub fn apply_film_grain(
+    film_grain: &FilmGrain,
+    format: DecodedFormat,
+    buffer: &mut [u8],
+    width: usize,
+    height: usize,
+) -> anyhow::Result<()> {
+    let (bit_depth, subsampling) = match format {
+        DecodedFormat::I420 | DecodedFormat::NV12 => (8, (true, true)),
+        DecodedFormat::I422 => (8, (true, false)),
+        DecodedFormat::I444 => (8, (false, false)),
+        DecodedFormat::I010 => (10, (true, true)),
+        DecodedFormat::I012 => (12, (true, true)),
+        DecodedFormat::I210 => (10, (true, false)),
+        DecodedFormat::I212 => (12, (true, false)),
+        DecodedFormat::I410 => (10, (false, false)),
+        DecodedFormat::I412 => (12, (false, false)),
+        _ => {
+            // Apply the grain to the planar equivalent of the frame, to and from which conversions
+            // are lossless.
+            let planar_format = match format {
+                DecodedFormat::P010 => DecodedFormat::I010,
+                DecodedFormat::P012 => DecodedFormat::I012,
+                DecodedFormat::YUY2 => DecodedFormat::I422,
+                DecodedFormat::Y210 => DecodedFormat::I210,
+                DecodedFormat::Y410 => DecodedFormat::I410,
+                DecodedFormat::AYUV => DecodedFormat::I444,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "film grain cannot be applied to {:?} frames",
+                        format
+                    ))
+                }
+            };
+            let mut planar = vec![0; decoded_frame_size(planar_format, width, height)];
+            convert::convert(format, buffer, planar_format, &mut planar, width, height)?;
+            apply_film_grain(film_grain, planar_format, &mut planar, width, height)?;
+
+            return convert::convert(planar_format, &planar, format, buffer, width, height);
+        }
+    };
+    if bit_depth != film_grain.bit_depth() || subsampling != film_grain.subsampling() {
+        return Err(anyhow::anyhow!(
+            "cannot apply film grain of a {}-bit {:?} subsampled stream to a {:?} frame",
+            film_grain.bit_depth(),
+            film_grain.subsampling(),
+            format
+        ));
+    }
+
+    let uv_width = if subsampling.0 {
+        width.div_ceil(2)
+    } else {
+        width
+    };
+    let uv_height = if subsampling.1 {
+        height.div_ceil(2)
+    } else {
+        height
+    };
+    let y_size = width * height;
+    let uv_size = uv_width * uv_height;
+    let sample_size = if bit_depth == 8 { 1 } else { 2 };
+    let frame_size = (y_size + uv_size * 2) * sample_size;
+    if buffer.len() < frame_size {
+        return Err(anyhow::anyhow!(
+            "buffer of {} bytes is too small for a {}x{} {:?} frame",
+            buffer.len(),
+            width,
+            height,
+            format
+        ));
     }
+    let buffer = &mut buffer[..frame_size];
+
+    if bit_depth == 8 {
+        if format == DecodedFormat::NV12 {
+            // Deinterleave the chroma samples, apply the grain, then interleave them back.
+            let (y_plane, uv_plane) = buffer.split_at_mut(y_size);
+            let (mut u_plane, mut v_plane): (Vec<u8>, Vec<u8>) =
+                uv_plane.chunks_exact(2).map(|uv| (uv[0], uv[1])).unzip();
+            film_grain.apply(
+                width,
+                height,
+                [
+                    Plane {
+                        data: y_plane,
+                        stride: width,
+                    },
+                    Plane {
+                        data: &mut u_plane,
+                        stride: uv_width,
+                    },
+                    Plane {
+                        data: &mut v_plane,
+                        stride: uv_width,
+                    },
+                ],
+            )?;
+            for ((uv, u), v) in uv_plane.chunks_exact_mut(2).zip(u_plane).zip(v_plane) {
+                uv[0] = u;
+                uv[1] = v;
+            }
+        } else {
+            let (y_plane, uv_planes) = buffer.split_at_mut(y_size);
+            let (u_plane, v_plane) = uv_planes.split_at_mut(uv_size);
+            film_grain.apply(
+                width,
+                height,
+                [
+                    Plane {
+                        data: y_plane,
+                        stride: width,
+                    },
+                    Plane {
+                        data: u_plane,
+                        stride: uv_width,
+                    },
+                    Plane {
+                        data: v_plane,
+                        stride: uv_width,
+                    },
+                ],
+            )?;
+        }
+    } else {
+        let mut samples = vec![0u16; buffer.len() / 2];
+        LittleEndian::read_u16_into(buffer, &mut samples);
+        let (y_plane, uv_planes) = samples.split_at_mut(y_size);
+        let (u_plane, v_plane) = uv_planes.split_at_mut(uv_size);
+        film_grain.apply(
+            width,
+            height,
+            [
+                Plane {
+                    data: y_plane,
+                    stride: width,
+                },
+                Plane {
+                    data: u_plane,
+                    stride: uv_width,
+                },
+                Plane {
+                    data: v_plane,
+                    stride: uv_width,
+                },
+            ],
+        )?;
+        LittleEndian::write_u16_into(&samples, buffer);
+    }
+
+    Ok(())
 }
 
 /// Copies `src` into `dst` as I410, removing all padding and changing the layout from packed to
@@ -434,7 +776,15 @@ pub enum BlockingMode {
 
 #[cfg(test)]
 mod tests {
+    use super::decoded_frame_size;
+    use super::p01x_copy;
+    use super::packed_copy;
+    use super::packed_line_size;
+    use super::DecodedFormat;
     use super::Fourcc;
+    use super::FrameLayout;
+    use super::PlaneLayout;
+    use super::Resolution;
 
     const NV12_FOURCC: u32 = 0x3231564E;
 
@@ -463,4 +813,76 @@ mod tests {
         let fourcc = Fourcc::from(NV12_FOURCC);
         assert_eq!(format!("{:?}", fourcc), "0x3231564e (NV12)");
     }
+
+    #[test]
+    fn frame_layout_buffers() {
+        let plane = |buffer_index, offset| PlaneLayout {
+            buffer_index,
+            offset,
+            stride: 64,
+        };
+        let nv12 = FrameLayout {
+            format: (Fourcc::from(b"NV12"), 0),
+            size: Resolution::from((64, 64)),
+            planes: vec![plane(0, 128), plane(0, 4224)],
+        };
+        let nm12 = FrameLayout {
+            format: (Fourcc::from(b"NM12"), 0),
+            planes: vec![plane(0, 0), plane(1, 256)],
+            ..nv12.clone()
+        };
+
+        assert_eq!(nv12.num_buffers(), 1);
+        assert_eq!(nv12.buffer_offset(0), 128);
+        assert_eq!(nm12.num_buffers(), 2);
+        assert_eq!(nm12.buffer_offset(0), 0);
+        assert_eq!(nm12.buffer_offset(1), 256);
+    }
+
+    #[test]
+    fn packed_frame_sizes() {
+        assert_eq!(decoded_frame_size(DecodedFormat::P010, 3, 3), 17 * 2);
+        assert_eq!(decoded_frame_size(DecodedFormat::P016, 4, 2), 12 * 2);
+        assert_eq!(decoded_frame_size(DecodedFormat::YUY2, 3, 2), 16);
+        assert_eq!(decoded_frame_size(DecodedFormat::Y210, 4, 2), 32);
+        assert_eq!(decoded_frame_size(DecodedFormat::AB30, 3, 2), 24);
+        assert_eq!(packed_line_size(DecodedFormat::NV12, 4), None);
+    }
+
+    #[test]
+    fn copy_p010() {
+        // 3x3 frame with 8 bytes of padding per line, and a gap between the planes.
+        let strides = [14, 16, 0];
+        let offsets = [0, 48, 0];
+        let src = (0..80u8).collect::<Vec<_>>();
+        let mut dst = vec![0; decoded_frame_size(DecodedFormat::P010, 3, 3)];
+
+        p01x_copy(&src, &mut dst, 3, 3, strides, offsets);
+
+        #[rustfmt::skip]
+        assert_eq!(
+            dst,
+            [
+                0, 1, 2, 3, 4, 5,
+                14, 15, 16, 17, 18, 19,
+                28, 29, 30, 31, 32, 33,
+                48, 49, 50, 51, 52, 53, 54, 55,
+                64, 65, 66, 67, 68, 69, 70, 71,
+            ]
+        );
+    }
+
+    #[test]
+    fn copy_packed() {
+        let src = (0..24u8).collect::<Vec<_>>();
+        let line_size = packed_line_size(DecodedFormat::YUY2, 3).unwrap();
+        let mut dst = vec![0; decoded_frame_size(DecodedFormat::YUY2, 3, 2)];
+
+        packed_copy(&src, &mut dst, line_size, 2, 12, 4);
+
+        assert_eq!(
+            dst,
+            [4, 5, 6, 7, 8, 9, 10, 11, 16, 17, 18, 19, 20, 21, 22, 23]
+        );
+    }
 }
@@ -19,6 +19,7 @@ use thiserror::Error;
 
 use crate::codec::h264::parser::Nalu as H264Nalu;
 use crate::codec::h265::parser::Nalu as H265Nalu;
+use crate::container::mp4::Mp4Demuxer;
 use crate::decoder::stateless::DecodeError;
 use crate::decoder::stateless::PoolLayer;
 use crate::decoder::stateless::StatelessVideoDecoder;
@@ -27,7 +28,10 @@ use crate::decoder::DecodedHandle;
 use crate::decoder::DecoderEvent;
 use crate::decoder::FramePool;
 use crate::decoder::StreamInfo;
+use crate::encoder::WritableFrame;
+use crate::encoder::WriteFrameFn;
 use crate::DecodedFormat;
+use crate::EncodedFormat;
 use crate::Fourcc;
 use crate::FrameLayout;
 use crate::PlaneLayout;
@@ -183,6 +187,48 @@ impl<'a> Iterator for NalIterator<'a, H265Nalu<'a>> {
     }
 }
 
+/// Tries to guess the codec of `stream` from its first bytes.
+///
+/// IVF files are identified by their header. H.264 and H.265 Annex B streams are identified by
+/// their first NAL unit, which is expected to be an access unit delimiter or a parameter set.
+///
+/// MP4 files are identified by their `ftyp` box, and their codec is given by the sample entry of
+/// their video track. Since the `moov` box holding it can be anywhere in the file, `stream` must
+/// contain the whole file.
+pub fn sniff_encoded_format(stream: &[u8]) -> Option<EncodedFormat> {
+    if stream.get(4..8) == Some(b"ftyp") {
+        return Mp4Demuxer::new(stream)
+            .ok()
+            .map(|demuxer| demuxer.track().config.encoded_format());
+    }
+
+    if stream.starts_with(&IvfFileHeader::MAGIC) {
+        return match stream.get(8..12)? {
+            codec if codec == IvfFileHeader::CODEC_VP8 => Some(EncodedFormat::VP8),
+            codec if codec == IvfFileHeader::CODEC_VP9 => Some(EncodedFormat::VP9),
+            codec if codec == IvfFileHeader::CODEC_AV1 => Some(EncodedFormat::AV1),
+            _ => None,
+        };
+    }
+
+    let start = stream.windows(3).position(|w| w == [0, 0, 1])? + 3;
+    let header = stream.get(start..start + 2)?;
+
+    // H.265 NAL headers are two bytes long, with a zero nuh_layer_id for the base layer and a
+    // non-zero nuh_temporal_id_plus1.
+    let h265_type = (header[0] >> 1) & 0x3f;
+    if header[0] & 0x81 == 0 && header[1] & 0x7 != 0 && matches!(h265_type, 32..=35) {
+        return Some(EncodedFormat::H265);
+    }
+
+    let h264_type = header[0] & 0x1f;
+    if header[0] & 0x80 == 0 && matches!(h264_type, 7..=9) {
+        return Some(EncodedFormat::H264);
+    }
+
+    None
+}
+
 #[derive(Error, Debug)]
 pub enum BitWriterError {
     #[error("invalid bit count")]
@@ -423,17 +469,12 @@ impl UserPtrFrame {
                     },
                 ],
             },
-            uv_start.max(uv_size),
+            uv_start + uv_size,
         )
     }
 
     pub fn alloc(layout: FrameLayout, buffer_size: usize) -> Self {
-        let buffer_count = layout
-            .planes
-            .iter()
-            .map(|plane| plane.buffer_index)
-            .collect::<std::collections::HashSet<usize>>()
-            .len();
+        let buffer_count = layout.num_buffers();
 
         // SAFETY: the invariants of `Layout` are respected.
         let mem_layout =
@@ -454,12 +495,46 @@ impl UserPtrFrame {
     }
 }
 
+/// A frame backed by one or several DMA-BUF objects, as well as its layout.
 #[derive(Debug)]
 pub struct DmabufFrame {
+    /// The DMA-BUF objects of the frame. The planes of `layout` refer to them through their
+    /// `buffer_index`, so several planes can share an object or each have their own.
     pub fds: Vec<OwnedFd>,
     pub layout: FrameLayout,
 }
 
+impl DmabufFrame {
+    /// Checks that the planes of the frame only refer to objects present in `fds`. Backends call
+    /// this before importing the frame.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.fds.len() < self.layout.num_buffers() {
+            return Err(anyhow::anyhow!(
+                "DMA-BUF frame has {} fds but its layout uses {} buffers",
+                self.fds.len(),
+                self.layout.num_buffers()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl WritableFrame for UserPtrFrame {
+    fn write_with(&mut self, f: &mut WriteFrameFn) -> anyhow::Result<()> {
+        let size = self.mem_layout.size();
+        let mut buffers = self
+            .buffers
+            .iter()
+            // SAFETY: each buffer has been allocated with `mem_layout`, and we borrow `self`
+            // mutably.
+            .map(|&buffer| unsafe { std::slice::from_raw_parts_mut(buffer, size) })
+            .collect::<Vec<_>>();
+
+        f(&self.layout, &mut buffers)
+    }
+}
+
 impl Drop for UserPtrFrame {
     fn drop(&mut self) {
         for buffer in std::mem::take(&mut self.buffers).into_iter() {
@@ -516,6 +591,54 @@ mod tests {
         assert_eq!(&buf, &EXPECTED2);
     }
 
+    #[test]
+    fn test_sniff_encoded_format() {
+        let ivf_header = |codec| {
+            let mut buf = Vec::new();
+            IvfFileHeader::new(codec, 320, 240, 30, 1)
+                .writo_into(&mut buf)
+                .unwrap();
+            buf
+        };
+
+        assert_eq!(
+            sniff_encoded_format(&ivf_header(IvfFileHeader::CODEC_VP8)),
+            Some(EncodedFormat::VP8)
+        );
+        assert_eq!(
+            sniff_encoded_format(&ivf_header(IvfFileHeader::CODEC_VP9)),
+            Some(EncodedFormat::VP9)
+        );
+        assert_eq!(
+            sniff_encoded_format(&ivf_header(IvfFileHeader::CODEC_AV1)),
+            Some(EncodedFormat::AV1)
+        );
+        assert_eq!(sniff_encoded_format(&ivf_header(*b"XXXX")), None);
+
+        // H.264 SPS and AUD.
+        assert_eq!(
+            sniff_encoded_format(&[0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x1f]),
+            Some(EncodedFormat::H264)
+        );
+        assert_eq!(
+            sniff_encoded_format(&[0x00, 0x00, 0x01, 0x09, 0xf0]),
+            Some(EncodedFormat::H264)
+        );
+        // H.265 VPS and AUD.
+        assert_eq!(
+            sniff_encoded_format(&[0x00, 0x00, 0x00, 0x01, 0x40, 0x01, 0x0c, 0x01]),
+            Some(EncodedFormat::H265)
+        );
+        assert_eq!(
+            sniff_encoded_format(&[0x00, 0x00, 0x01, 0x46, 0x01, 0x50]),
+            Some(EncodedFormat::H265)
+        );
+
+        assert_eq!(sniff_encoded_format(&[]), None);
+        assert_eq!(sniff_encoded_format(&[0x00, 0x00, 0x01]), None);
+        assert_eq!(sniff_encoded_format(&[0x1a, 0x45, 0xdf, 0xa3]), None);
+    }
+
     #[test]
     fn test_ivf_frame_header() {
         let mut hdr = IvfFrameHeader {
@@ -584,4 +707,33 @@ mod tests {
         }
         assert_eq!(buf, vec![0b10001011u8]);
     }
+
+    #[test]
+    fn test_dmabuf_frame_num_fds() {
+        let layout = FrameLayout {
+            format: (Fourcc::from(b"NM12"), 0),
+            size: Resolution::from((64, 64)),
+            planes: vec![
+                PlaneLayout {
+                    buffer_index: 0,
+                    offset: 0,
+                    stride: 64,
+                },
+                PlaneLayout {
+                    buffer_index: 1,
+                    offset: 0,
+                    stride: 64,
+                },
+            ],
+        };
+        let fd = || OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+
+        let mut frame = DmabufFrame {
+            fds: vec![fd()],
+            layout,
+        };
+        assert!(frame.validate().is_err());
+        frame.fds.push(fd());
+        assert!(frame.validate().is_ok());
+    }
 }